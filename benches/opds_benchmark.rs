@@ -1,15 +1,12 @@
-use abs_opds::api::AbsClient;
-use abs_opds::models::{
-    AbsItemResult, AbsItemsResponse, AbsLibrary, AbsMedia, AbsMetadata, AppConfig, InternalUser,
-};
+use abs_opds::models::{AbsItemsResponse, AbsLibrary, AppConfig, InternalUser};
 use abs_opds::service::LibraryService;
+use abs_opds::opds::model::{Entry, Feed, FeedKind};
 use abs_opds::xml::OpdsBuilder;
 use abs_opds::handlers::LibraryQuery;
 use abs_opds::i18n::I18n;
-use abs_opds::build_app_state_with_mock;
+use abs_opds::test_util::{build_app_state_with_mock, create_items, MockAbsClient};
 use abs_opds::build_router;
 use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId, Throughput};
-use mockall::mock;
 use std::sync::Arc;
 use tokio::runtime::Runtime;
 use std::fs::File;
@@ -20,63 +17,10 @@
     http::{Request, StatusCode},
 };
 use tower::ServiceExt;
-use async_trait::async_trait;
 use std::time::Duration;
 use wiremock::{MockServer, Mock, ResponseTemplate};
 use wiremock::matchers::{method, path};
 
-mock! {
-    pub AbsClient {}
-    #[async_trait]
-    impl AbsClient for AbsClient {
-        async fn login(&self, username: &str, password: &str) -> anyhow::Result<InternalUser>;
-        async fn get_libraries(&self, user: &InternalUser) -> anyhow::Result<Vec<AbsLibrary>>;
-        async fn get_library(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsLibrary>;
-        async fn get_items(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsItemsResponse>;
-    }
-}
-
-fn create_item(
-    id: &str,
-    title: &str,
-    author: Option<&str>,
-    genre: Option<&str>,
-) -> AbsItemResult {
-    AbsItemResult {
-        id: id.to_string(),
-        media: AbsMedia {
-            ebook_format: Some("epub".to_string()),
-            metadata: AbsMetadata {
-                title: Some(title.to_string()),
-                subtitle: None,
-                description: None,
-                genres: genre.map(|g| vec![g.to_string()]),
-                tags: None,
-                publisher: None,
-                isbn: None,
-                language: Some("en".to_string()),
-                published_year: None,
-                author_name: author.map(|a| a.to_string()),
-                narrator_name: None,
-                series_name: None,
-            },
-        },
-    }
-}
-
-fn generate_data(n_items: usize, n_authors: usize, n_genres: usize) -> Vec<AbsItemResult> {
-    let mut items = Vec::with_capacity(n_items);
-    for i in 0..n_items {
-        items.push(create_item(
-            &format!("{}", i),
-            &format!("Book Title {}", i),
-            Some(&format!("Author {}", i % n_authors)),
-            Some(&format!("Genre {}", i % n_genres)),
-        ));
-    }
-    items
-}
-
 fn mock_user() -> InternalUser {
     InternalUser {
         name: "bench_user".to_string(),
@@ -86,19 +30,14 @@ fn mock_user() -> InternalUser {
 }
 
 fn mock_config() -> AppConfig {
-    AppConfig {
-        port: 3000,
-        use_proxy: false,
-        abs_url: "http://localhost:3000".to_string(),
-        opds_users: "bench_user:bench_token:pass".to_string(),
-        internal_users: vec![],
-        show_audiobooks: true,
-        show_char_cards: true,
-        opds_no_auth: false,
-        abs_noauth_username: "".to_string(),
-        abs_noauth_password: "".to_string(),
-        opds_page_size: 100,
-    }
+    AppConfig::builder()
+        .port(3000)
+        .user("bench_user", "bench_token", "pass")
+        .show_audiobooks(true)
+        .show_char_cards(true)
+        .page_size(100)
+        .build()
+        .unwrap()
 }
 
 fn mock_i18n() -> I18n {
@@ -165,7 +104,7 @@ fn bench_service_layer(c: &mut Criterion) {
         let n_authors = std::cmp::max(1, n_items / 40);
         let n_genres = std::cmp::max(1, n_items / 4000);
 
-        let items = generate_data(n_items, n_authors, n_genres);
+        let items = create_items(n_items, n_authors, n_genres);
         let items_response = AbsItemsResponse { results: items.clone() };
 
         let mut mock_client = MockAbsClient::new();
@@ -174,7 +113,7 @@ fn bench_service_layer(c: &mut Criterion) {
             .returning(move |_, _| Ok(items_response.clone()));
         mock_client
             .expect_get_library()
-            .returning(|_, _| Ok(AbsLibrary { id: "lib1".to_string(), name: "Test Lib".to_string(), icon: None }));
+            .returning(|_, _| Ok(AbsLibrary { id: "lib1".to_string(), name: "Test Lib".to_string(), icon: None, media_type: Default::default() }));
 
         let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
         let user = mock_user();
@@ -184,7 +123,7 @@ fn bench_service_layer(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::new("get_filtered_items", n_items), &n_items, |b, &_| {
             b.to_async(&rt).iter(|| async {
                  service.get_filtered_items(&user, "lib1", &LibraryQuery {
-                    q: None, page: 0, categories: None, author: None, title: None, name: None, type_: None, start: None
+                    q: None, page: 0, categories: None, author: None, narrator: None, title: None, name: None, type_: None, start: None, limit: None, shelf: None, updated_since: None, sort: None, format: None, recently_updated: false, recently_added: false, in_progress: false, collection: None
                  }).await.unwrap()
             })
         });
@@ -192,7 +131,7 @@ fn bench_service_layer(c: &mut Criterion) {
         let start = std::time::Instant::now();
         rt.block_on(async {
              service.get_filtered_items(&user, "lib1", &LibraryQuery {
-                q: None, page: 0, categories: None, author: None, title: None, name: None, type_: None, start: None
+                q: None, page: 0, categories: None, author: None, narrator: None, title: None, name: None, type_: None, start: None, limit: None, shelf: None, updated_since: None, sort: None, format: None, recently_updated: false, recently_added: false, in_progress: false, collection: None
              }).await.unwrap();
         });
         let duration = start.elapsed().as_nanos() as f64;
@@ -201,16 +140,16 @@ fn bench_service_layer(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::new("get_categories_authors", n_items), &n_items, |b, &_| {
             b.to_async(&rt).iter(|| async {
                  service.get_categories(&user, "lib1", "authors", &LibraryQuery {
-                    q: None, page: 0, categories: None, author: None, title: None, name: None, type_: None, start: None
-                 }).await.unwrap()
+                    q: None, page: 0, categories: None, author: None, narrator: None, title: None, name: None, type_: None, start: None, limit: None, shelf: None, updated_since: None, sort: None, format: None, recently_updated: false, recently_added: false, in_progress: false, collection: None
+                 }, None).await.unwrap()
             })
         });
 
         let start = std::time::Instant::now();
         rt.block_on(async {
              service.get_categories(&user, "lib1", "authors", &LibraryQuery {
-                q: None, page: 0, categories: None, author: None, title: None, name: None, type_: None, start: None
-             }).await.unwrap();
+                q: None, page: 0, categories: None, author: None, narrator: None, title: None, name: None, type_: None, start: None, limit: None, shelf: None, updated_since: None, sort: None, format: None, recently_updated: false, recently_added: false, in_progress: false, collection: None
+             }, None).await.unwrap();
         });
         let duration = start.elapsed().as_nanos() as f64;
         REPORTER.add_entry("get_categories_authors", n_items, n_authors, n_genres, duration);
@@ -233,7 +172,7 @@ fn bench_handlers(c: &mut Criterion) {
         let n_authors = std::cmp::max(1, n_items / 40);
         let n_genres = std::cmp::max(1, n_items / 4000);
 
-        let items = generate_data(n_items, n_authors, n_genres);
+        let items = create_items(n_items, n_authors, n_genres);
         let items_response = AbsItemsResponse { results: items.clone() };
 
         let mut mock_client = MockAbsClient::new();
@@ -242,7 +181,7 @@ fn bench_handlers(c: &mut Criterion) {
             .returning(move |_, _| Ok(items_response.clone()));
         mock_client
             .expect_get_library()
-            .returning(|_, _| Ok(AbsLibrary { id: "lib1".to_string(), name: "Test Lib".to_string(), icon: None }));
+            .returning(|_, _| Ok(AbsLibrary { id: "lib1".to_string(), name: "Test Lib".to_string(), icon: None, media_type: Default::default() }));
         mock_client
             .expect_login()
             .returning(|_, _| Ok(mock_user()));
@@ -355,7 +294,7 @@ fn bench_xml_layer(c: &mut Criterion) {
         let n_authors = std::cmp::max(1, n_items / 40);
         let n_genres = std::cmp::max(1, n_items / 4000);
 
-        let abs_items = generate_data(n_items, n_authors, n_genres);
+        let abs_items = create_items(n_items, n_authors, n_genres);
         let library_items: Vec<abs_opds::models::LibraryItem> = abs_items.into_iter().map(|i| {
              abs_opds::models::LibraryItem {
                  id: i.id,
@@ -371,53 +310,39 @@ fn bench_xml_layer(c: &mut Criterion) {
                  authors: i.media.metadata.author_name.map(|s| s.split(',').map(|n| abs_opds::models::Author { name: n.trim().to_string() }).collect()).unwrap_or_default(),
                  narrators: i.media.metadata.narrator_name.map(|s| s.split(',').map(|n| abs_opds::models::Author { name: n.trim().to_string() }).collect()).unwrap_or_default(),
                  series: i.media.metadata.series_name.map(|s| s.split(',').map(|n| n.trim().to_string()).collect()).unwrap_or_default(),
+                 series_sequence: None,
                  format: i.media.ebook_format,
+                 audio_file_count: 0,
+                 editions: vec![],
+                 updated: None,
              }
         }).collect();
 
         let user = mock_user();
-        let lib = abs_opds::models::Library { id: "lib1".to_string(), name: "Lib".to_string(), icon: None };
+        let i18n = mock_i18n();
+        let lib = abs_opds::models::Library { id: "lib1".to_string(), name: "Lib".to_string(), icon: None, media_type: Default::default() };
 
         group.throughput(Throughput::Elements(n_items as u64));
 
-        group.bench_with_input(BenchmarkId::new("xml_build_entries", n_items), &n_items, |b, &_| {
+        let build_xml = || {
             let updated_time = chrono::Utc::now().to_rfc3339();
-            let mut url_buf = String::with_capacity(256);
-            b.iter(|| {
-                 OpdsBuilder::build_opds_skeleton(
-                        "urn:uuid:lib1",
-                        "Lib",
-                        |writer| {
-                            for item in &library_items {
-                                OpdsBuilder::build_item_entry(writer, item, &user, "/opds", &updated_time, &mut url_buf)?;
-                            }
-                            Ok(())
-                        },
-                        Some(&lib),
-                        Some(&user),
-                        Some((0, 100, n_items, n_items/100)),
-                        "/opds"
-                    ).unwrap()
-            })
+            let entries = library_items
+                .iter()
+                .map(|item| Entry::publication(item, &user, "lib1", "/opds", &abs_opds::models::AcquisitionLink::DEFAULT_ORDER, &updated_time))
+                .collect();
+            let feed = Feed::new("urn:uuid:lib1", "Lib", FeedKind::Acquisition, "/opds", &i18n, None)
+                .with_library_links(&lib, &i18n, None)
+                .with_pagination(Some((0, 100, n_items, n_items / 100)))
+                .with_entries(entries);
+            OpdsBuilder::build_feed(&feed).unwrap()
+        };
+
+        group.bench_with_input(BenchmarkId::new("xml_build_entries", n_items), &n_items, |b, &_| {
+            b.iter(build_xml)
         });
 
         let start = std::time::Instant::now();
-        let updated_time = chrono::Utc::now().to_rfc3339();
-        let mut url_buf = String::with_capacity(256);
-         OpdsBuilder::build_opds_skeleton(
-                "urn:uuid:lib1",
-                "Lib",
-                |writer| {
-                    for item in &library_items {
-                        OpdsBuilder::build_item_entry(writer, item, &user, "/opds", &updated_time, &mut url_buf)?;
-                    }
-                    Ok(())
-                },
-                Some(&lib),
-                Some(&user),
-                Some((0, 100, n_items, n_items/100)),
-                "/opds"
-            ).unwrap();
+        build_xml();
         let duration = start.elapsed().as_nanos() as f64;
         REPORTER.add_entry("xml_build_entries", n_items, n_authors, n_genres, duration);
     }
@@ -1,75 +1,275 @@
-use crate::models::{AbsItemsResponse, AbsLibrariesResponse, AbsLibrary, AbsLoginResponse, InternalUser};
-use reqwest::Client;
+use crate::error::{AppError, Result};
+use crate::models::{AbsCollection, AbsCollectionsResponse, AbsEpisodesResponse, AbsItemResult, AbsItemsResponse, AbsLibrariesResponse, AbsLibrary, AbsLoginResponse, InternalUser};
+use crate::cache::{self, CacheStore, MemoryStore, ValueStore};
+use crate::metrics;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::time::Duration;
 use async_trait::async_trait;
 
+/// Logging in to ABS.
 #[async_trait]
-pub trait AbsClient: Send + Sync {
-    async fn login(&self, username: &str, password: &str) -> anyhow::Result<InternalUser>;
-    async fn get_libraries(&self, user: &InternalUser) -> anyhow::Result<Vec<AbsLibrary>>;
-    async fn get_library(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsLibrary>;
-    async fn get_items(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsItemsResponse>;
+pub trait AuthClient: Send + Sync {
+    async fn login(&self, username: &str, password: &str) -> Result<InternalUser>;
+    /// The user an API token belongs to, asked from `/api/me`.
+    async fn verify_token(&self, token: &str) -> Result<InternalUser>;
+    /// Drops the login session cached for `username`, so their next login asks ABS,
+    /// returning whether there was one. Clients without a cache have nothing to drop.
+    async fn forget_login(&self, _username: &str) -> bool {
+        false
+    }
+    /// A new access token in place of `token`, which ABS rejected as expired, or `None`
+    /// if it can't be renewed. Clients whose tokens don't expire have none.
+    async fn refresh(&self, _token: &str) -> Option<String> {
+        None
+    }
 }
 
-#[derive(Clone)]
+/// Reading libraries, items and covers.
+#[async_trait]
+pub trait CatalogClient: Send + Sync {
+    async fn get_libraries(&self, user: &InternalUser) -> Result<Vec<AbsLibrary>>;
+    async fn get_library(&self, user: &InternalUser, library_id: &str) -> Result<AbsLibrary>;
+    async fn get_items(&self, user: &InternalUser, library_id: &str) -> Result<AbsItemsResponse>;
+    async fn get_item(&self, user: &InternalUser, item_id: &str) -> Result<AbsItemResult>;
+    /// Returns the raw cover image bytes, or `None` if ABS has no cover for the item.
+    async fn get_cover(&self, user: &InternalUser, item_id: &str) -> Result<Option<Vec<u8>>>;
+    /// Drops the items of `library_id` cached for `user`, so the next `get_items` reads
+    /// them from ABS. Clients without a cache have nothing to drop.
+    async fn forget_items(&self, _user: &InternalUser, _library_id: &str) {}
+}
+
+/// Writing activity back to ABS.
+#[async_trait]
+pub trait ProgressClient: Send + Sync {
+    /// Records an OPDS download as a zero-length session so it shows up in the ABS activity view.
+    async fn report_download(&self, user: &InternalUser, item: &AbsItemResult) -> Result<()>;
+    /// The user's progress per item id, from 0 to 1; finished items count as 1.
+    async fn get_progress(&self, user: &InternalUser) -> Result<HashMap<String, f64>>;
+    /// The ids of the items the user started and hasn't finished, most recently read first.
+    async fn get_items_in_progress(&self, user: &InternalUser) -> Result<Vec<String>>;
+}
+
+/// Reading podcast episodes.
+#[async_trait]
+pub trait PodcastClient: Send + Sync {
+    /// One page of a podcast library's episodes, newest first; pages count from 0.
+    async fn get_recent_episodes(&self, user: &InternalUser, library_id: &str, limit: usize, page: usize) -> Result<AbsEpisodesResponse>;
+}
+
+/// Reading the collections users put together in ABS.
+#[async_trait]
+pub trait CollectionClient: Send + Sync {
+    /// The collections of a library, each with its books in order.
+    async fn get_collections(&self, user: &InternalUser, library_id: &str) -> Result<Vec<AbsCollection>>;
+}
+
+/// Everything the catalog needs from ABS. Optional capabilities are reached through
+/// accessors that return `None` when the backend does not have them.
+pub trait AbsClient: AuthClient + CatalogClient {
+    fn progress(&self) -> Option<&dyn ProgressClient> {
+        None
+    }
+
+    fn podcasts(&self) -> Option<&dyn PodcastClient> {
+        None
+    }
+
+    fn collections(&self) -> Option<&dyn CollectionClient> {
+        None
+    }
+}
+
+#[derive(Deserialize)]
+struct MeResponse {
+    #[serde(default)]
+    username: String,
+    #[serde(rename = "mediaProgress", default)]
+    media_progress: Vec<MediaProgress>,
+}
+
+#[derive(Deserialize)]
+struct MediaProgress {
+    #[serde(rename = "libraryItemId")]
+    library_item_id: String,
+    #[serde(default)]
+    progress: f64,
+    #[serde(rename = "ebookProgress", default)]
+    ebook_progress: Option<f64>,
+    #[serde(rename = "isFinished", default)]
+    is_finished: bool,
+}
+
+#[derive(Deserialize)]
+struct ItemsInProgressResponse {
+    #[serde(rename = "libraryItems", default)]
+    library_items: Vec<ItemInProgress>,
+}
+
+#[derive(Deserialize)]
+struct ItemInProgress {
+    id: String,
+    /// When the progress last changed, in milliseconds since the epoch.
+    #[serde(rename = "progressLastUpdate", default)]
+    progress_last_update: i64,
+}
+
+#[derive(Serialize, Deserialize)]
 struct CachedSession {
     token: String,
     password_hash: String,
-    expires: Instant,
 }
 
-#[derive(Clone)]
-struct CachedItems {
-    response: AbsItemsResponse,
-    expires: Instant,
+/// How an expiring access token is renewed, by the hash of the token.
+#[derive(Serialize, Deserialize)]
+struct CachedRefresh {
+    /// The key of the session the token was cached under.
+    session_key: String,
+    refresh_token: String,
+    /// The token it was renewed with, once it has been, for requests still holding it.
+    #[serde(default)]
+    replaced_by: Option<String>,
 }
 
+/// Login sessions are cached for 10 minutes by default, sparing ABS a login per request.
+pub const DEFAULT_TOKEN_CACHE_TTL: Duration = Duration::from_secs(600);
+/// How many users' sessions are kept by default.
+pub const DEFAULT_TOKEN_CACHE_MAX_USERS: usize = 1000;
+/// Library items are cached briefly by default, so paging through a feed fetches them once.
+pub const DEFAULT_ITEMS_CACHE_TTL: Duration = Duration::from_secs(60);
+/// How many item lists, one per user and library, are kept by default.
+pub const DEFAULT_ITEMS_CACHE_MAX_ENTRIES: usize = 100;
+/// How long a password ABS rejected is rejected again without asking ABS.
+const LOGIN_FAILURE_TTL: Duration = Duration::from_secs(60);
+/// How long a renewed access token still leads to the token that replaced it.
+const REPLACED_TOKEN_TTL: Duration = Duration::from_secs(60);
+/// Upper bound of the in-memory store for rejected logins and refresh tokens.
+const MEMORY_CACHE_MAX_ENTRIES: usize = 10_000;
+
 #[derive(Clone)]
 pub struct ApiClient {
     base_url: String,
     client: Client,
-    token_cache: Arc<RwLock<HashMap<String, CachedSession>>>,
-    items_cache: Arc<RwLock<HashMap<String, CachedItems>>>,
+    cache: Arc<dyn CacheStore>,
+    /// Login sessions by username, always in memory: they hold a hash of the ABS
+    /// password, which must not end up in a store others can read.
+    sessions: Arc<dyn CacheStore>,
+    /// Passwords ABS rejected, by username and password hash; in memory for the same reason.
+    failed_logins: Arc<dyn CacheStore>,
     cache_ttl: Duration,
+    /// Item lists as parsed, in front of `cache` when it is shared.
+    items: Arc<ValueStore<AbsItemsResponse>>,
+    items_ttl: Duration,
+    /// Whether `cache` is a store shared with other instances.
+    shared: bool,
+    /// Held while renewing a token, so concurrent requests don't spend its refresh
+    /// token twice; ABS rotates them.
+    refreshing: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl ApiClient {
     pub fn new(base_url: String, client: Client) -> Self {
-        let token_cache: Arc<RwLock<HashMap<String, CachedSession>>> = Arc::new(RwLock::new(HashMap::new()));
-        let items_cache: Arc<RwLock<HashMap<String, CachedItems>>> = Arc::new(RwLock::new(HashMap::new()));
-
-        // Active cache purge in a background task
-        let token_cache_clone = token_cache.clone();
-        let items_cache_clone = items_cache.clone();
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(60));
-            loop {
-                interval.tick().await;
-                let now = Instant::now();
-                if let Ok(mut cache) = token_cache_clone.write() {
-                    cache.retain(|_, session| now < session.expires);
-                }
-                if let Ok(mut cache) = items_cache_clone.write() {
-                    cache.retain(|_, cached| now < cached.expires);
-                }
-            }
-        });
+        Self::build(base_url, client, Arc::new(MemoryStore::new(MEMORY_CACHE_MAX_ENTRIES)), false)
+    }
 
+    /// A client keeping item lists in `cache` as well as in memory. Login sessions and
+    /// rejected passwords stay in memory only.
+    pub fn with_cache(base_url: String, client: Client, cache: Arc<dyn CacheStore>) -> Self {
+        Self::build(base_url, client, cache, true)
+    }
+
+    fn build(base_url: String, client: Client, cache: Arc<dyn CacheStore>, shared: bool) -> Self {
+        if shared {
+            cache::register("items", "items:", &cache);
+        }
         Self {
             base_url,
             client,
-            token_cache,
-            items_cache,
-            cache_ttl: Duration::from_secs(600), // 10 minutes
+            sessions: Arc::new(MemoryStore::lru(DEFAULT_TOKEN_CACHE_MAX_USERS)),
+            failed_logins: Arc::new(MemoryStore::new(MEMORY_CACHE_MAX_ENTRIES)),
+            cache,
+            cache_ttl: DEFAULT_TOKEN_CACHE_TTL,
+            items: Arc::new(ValueStore::new(0)),
+            items_ttl: DEFAULT_ITEMS_CACHE_TTL,
+            shared,
+            refreshing: Arc::new(tokio::sync::Mutex::new(())),
+        }
+        .with_token_cache(DEFAULT_TOKEN_CACHE_TTL, DEFAULT_TOKEN_CACHE_MAX_USERS)
+        .with_items_cache(DEFAULT_ITEMS_CACHE_TTL, DEFAULT_ITEMS_CACHE_MAX_ENTRIES)
+    }
+
+    /// Keeps the login sessions of up to `max_users`, evicting the least recently used,
+    /// for `ttl`; a zero TTL logs in to ABS every time.
+    pub fn with_token_cache(mut self, ttl: Duration, max_users: usize) -> Self {
+        self.cache_ttl = ttl;
+        self.sessions = Arc::new(MemoryStore::lru(max_users));
+        cache::register("token", "session:", &self.sessions);
+        self
+    }
+
+    /// Keeps up to `max_entries` item lists for `ttl`; a zero TTL reads every list from ABS.
+    pub fn with_items_cache(mut self, ttl: Duration, max_entries: usize) -> Self {
+        self.items = Arc::new(ValueStore::new(max_entries));
+        self.items_ttl = ttl;
+        if !self.shared {
+            let store: Arc<dyn CacheStore> = self.items.clone();
+            cache::register("items", "items:", &store);
+        }
+        self
+    }
+
+    fn items_key(user: &InternalUser, library_id: &str) -> String {
+        format!("items:{}:{}", cache::token_key(&user.api_key), library_id)
+    }
+
+    fn refresh_key(token: &str) -> String {
+        format!("refresh:{}", cache::token_key(token))
+    }
+
+    /// Sends the request `build` makes with `user`'s token. If ABS rejects the token as
+    /// expired and it came with a refresh token, it is renewed and the request sent again.
+    async fn send(&self, user: &InternalUser, build: impl Fn(&str) -> RequestBuilder) -> Result<Response> {
+        let response = build(&user.api_key).send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        match self.refresh(&user.api_key).await {
+            Some(token) => Ok(build(&token).send().await?),
+            None => Ok(response),
+        }
+    }
+
+    /// Keeps the refresh token of `token` for as long as its session is cached.
+    async fn remember_refresh(&self, token: &str, session_key: &str, refresh_token: Option<String>) {
+        let Some(refresh_token) = refresh_token.filter(|_| !self.cache_ttl.is_zero()) else {
+            return;
+        };
+        let entry = CachedRefresh { session_key: session_key.to_string(), refresh_token, replaced_by: None };
+        if let Ok(value) = serde_json::to_vec(&entry) {
+            self.cache.set(&Self::refresh_key(token), value, self.cache_ttl).await;
         }
     }
 }
 
-#[async_trait]
 impl AbsClient for ApiClient {
-    async fn login(&self, username: &str, password: &str) -> anyhow::Result<InternalUser> {
+    fn progress(&self) -> Option<&dyn ProgressClient> {
+        Some(self)
+    }
+
+    fn podcasts(&self) -> Option<&dyn PodcastClient> {
+        Some(self)
+    }
+
+    fn collections(&self) -> Option<&dyn CollectionClient> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl AuthClient for ApiClient {
+    async fn login(&self, username: &str, password: &str) -> Result<InternalUser> {
         let incoming_hash = {
             let mut hasher = sha1_smol::Sha1::new();
             hasher.update(password.as_bytes());
@@ -77,122 +277,316 @@ async fn login(&self, username: &str, password: &str) -> anyhow::Result<Internal
         };
 
         // Check cache
-        {
-            let cache = self.token_cache.read().unwrap();
-            if let Some(session) = cache.get(username) {
-                if Instant::now() < session.expires && session.password_hash == incoming_hash {
-                    return Ok(InternalUser {
-                        name: username.to_string(),
-                        api_key: session.token.clone(),
-                        password: None,
-                    });
-                }
-            }
+        let cache_key = format!("session:{}", username);
+        let cached = self.sessions.get(&cache_key).await.and_then(|v| serde_json::from_slice::<CachedSession>(&v).ok());
+        if let Some(session) = cached.filter(|session| session.password_hash == incoming_hash) {
+            metrics::cache_lookup("token", true);
+            return Ok(InternalUser {
+                name: username.to_string(),
+                api_key: session.token,
+                password: None,
+            });
+        }
+        metrics::cache_lookup("token", false);
+
+        // Readers retry a wrong password with every request
+        let failure_key = format!("login-failed:{}:{}", username, incoming_hash);
+        if self.failed_logins.get(&failure_key).await.is_some() {
+            return Err(AppError::upstream("Login failed", reqwest::StatusCode::UNAUTHORIZED));
         }
 
         let url = format!("{}/login", self.base_url);
         let body = HashMap::from([("username", username), ("password", password)]);
 
-        match self.client.post(&url).json(&body).send().await {
+        // Newer ABS versions only put the refresh token in the body when asked
+        match self.client.post(&url).header("x-return-tokens", "true").json(&body).send().await {
             Ok(response) => {
                 if response.status().is_success() {
                     let data = response.json::<AbsLoginResponse>().await?;
-                    {
-                        let mut cache = self.token_cache.write().unwrap();
-                        let now = Instant::now();
-                        // Suggestion 9: Evict expired sessions to prevent memory leaks
-                        cache.retain(|_, session| now < session.expires);
-                        cache.insert(
-                            username.to_string(),
-                            CachedSession {
-                                token: data.user.access_token.clone(),
-                                password_hash: incoming_hash,
-                                expires: now + self.cache_ttl,
-                            },
-                        );
+                    let session = CachedSession {
+                        token: data.user.access_token.clone(),
+                        password_hash: incoming_hash,
+                    };
+                    if let Ok(value) = serde_json::to_vec(&session) {
+                        if !self.cache_ttl.is_zero() {
+                            self.sessions.set(&cache_key, value, self.cache_ttl).await;
+                        }
                     }
+                    self.remember_refresh(&data.user.access_token, &cache_key, data.user.refresh_token).await;
                     return Ok(InternalUser {
                         name: data.user.username,
                         api_key: data.user.access_token,
                         password: None,
                     });
                 } else {
-                    return Err(anyhow::anyhow!("Invalid credentials or server error"));
+                    let status = response.status();
+                    if matches!(status, reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) {
+                        self.failed_logins.set(&failure_key, Vec::new(), LOGIN_FAILURE_TTL).await;
+                    }
+                    return Err(AppError::upstream("Login failed", status));
                 }
             }
             Err(e) => return Err(e.into()),
         }
     }
 
-    async fn get_libraries(&self, user: &InternalUser) -> anyhow::Result<Vec<AbsLibrary>> {
+    async fn verify_token(&self, token: &str) -> Result<InternalUser> {
+        let url = format!("{}/api/me", self.base_url);
+        let response = self.client.get(&url).bearer_auth(token).send().await?;
+        if !response.status().is_success() {
+            return Err(AppError::upstream("Token rejected", response.status()));
+        }
+        let me: MeResponse = response.json().await?;
+        Ok(InternalUser { name: me.username, api_key: token.to_string(), password: None })
+    }
+
+    async fn forget_login(&self, username: &str) -> bool {
+        let cache_key = format!("session:{}", username);
+        let cached = self.sessions.get(&cache_key).await.is_some();
+        self.sessions.remove(&cache_key).await;
+        cached
+    }
+
+    /// Renews `token` at `/auth/refresh` with the refresh token it came with. The cached
+    /// session gets the new token, so later requests use it.
+    async fn refresh(&self, token: &str) -> Option<String> {
+        let _refreshing = self.refreshing.lock().await;
+        let key = Self::refresh_key(token);
+        let entry: CachedRefresh = serde_json::from_slice(&self.cache.get(&key).await?).ok()?;
+        if let Some(replaced_by) = entry.replaced_by {
+            return Some(replaced_by);
+        }
+
+        let url = format!("{}/auth/refresh", self.base_url);
+        let response = match self.client.post(&url).header("x-refresh-token", &entry.refresh_token).send().await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                tracing::debug!("ABS refused to renew an access token: {}", response.status());
+                self.cache.remove(&key).await;
+                return None;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to renew an ABS access token: {}", e);
+                return None;
+            }
+        };
+        let data = match response.json::<AbsLoginResponse>().await {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!("ABS sent a renewed access token that can't be read: {}", e);
+                return None;
+            }
+        };
+        let renewed = data.user.access_token;
+        tracing::debug!("Renewed the ABS access token of {}", data.user.username);
+
+        let replaced = CachedRefresh { replaced_by: Some(renewed.clone()), ..entry };
+        if let Ok(value) = serde_json::to_vec(&replaced) {
+            self.cache.set(&key, value, REPLACED_TOKEN_TTL).await;
+        }
+        self.remember_refresh(&renewed, &replaced.session_key, data.user.refresh_token).await;
+        let session = self.sessions.get(&replaced.session_key).await.and_then(|v| serde_json::from_slice::<CachedSession>(&v).ok());
+        if let Some(session) = session.filter(|session| session.token == token) {
+            let session = CachedSession { token: renewed.clone(), ..session };
+            if let Ok(value) = serde_json::to_vec(&session) {
+                self.sessions.set(&replaced.session_key, value, self.cache_ttl).await;
+            }
+        }
+        Some(renewed)
+    }
+}
+
+#[async_trait]
+impl CatalogClient for ApiClient {
+    async fn get_libraries(&self, user: &InternalUser) -> Result<Vec<AbsLibrary>> {
         let url = format!("{}/api/libraries", self.base_url);
-        let response = self
-            .client
-            .get(&url)
-            .bearer_auth(&user.api_key)
-            .send()
-            .await?;
+        let response = self.send(user, |token| self.client.get(&url).bearer_auth(token)).await?;
 
         if !response.status().is_success() {
-            return Err(anyhow::anyhow!("Failed to fetch libraries: status {}", response.status()));
+            return Err(AppError::upstream("Failed to fetch libraries", response.status()));
         }
 
         let data = response.json::<AbsLibrariesResponse>().await?;
         Ok(data.libraries)
     }
 
-    async fn get_library(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsLibrary> {
+    async fn get_library(&self, user: &InternalUser, library_id: &str) -> Result<AbsLibrary> {
          let url = format!("{}/api/libraries/{}", self.base_url, library_id);
-        let response = self
-            .client
-            .get(&url)
-            .bearer_auth(&user.api_key)
-            .send()
-            .await?;
+        let response = self.send(user, |token| self.client.get(&url).bearer_auth(token)).await?;
 
         if !response.status().is_success() {
-            return Err(anyhow::anyhow!("Failed to fetch library details: status {}", response.status()));
+            return Err(AppError::upstream("Failed to fetch library details", response.status()));
         }
 
         Ok(response.json::<AbsLibrary>().await?)
     }
 
-    async fn get_items(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsItemsResponse> {
-        let cache_key = format!("{}:{}", user.api_key, library_id);
-        {
-            let cache = self.items_cache.read().unwrap();
-            if let Some(cached) = cache.get(&cache_key) {
-                if Instant::now() < cached.expires {
-                    return Ok(cached.response.clone());
-                }
+    async fn get_items(&self, user: &InternalUser, library_id: &str) -> Result<AbsItemsResponse> {
+        let cache_key = Self::items_key(user, library_id);
+        if let Some(items) = self.items.get_value(&cache_key) {
+            metrics::cache_lookup("items", true);
+            return Ok(AbsItemsResponse::clone(&items));
+        }
+        if self.shared {
+            // Another instance may have read the list already
+            let cached = self.cache.get(&cache_key).await;
+            if let Some((items, size)) = cached.and_then(|v| Some((serde_json::from_slice::<AbsItemsResponse>(&v).ok()?, v.len()))) {
+                metrics::cache_lookup("items", true);
+                self.items.set_value(&cache_key, Arc::new(items.clone()), size, self.items_ttl);
+                return Ok(items);
             }
         }
+        metrics::cache_lookup("items", false);
 
         let url = format!("{}/api/libraries/{}/items", self.base_url, library_id);
+        let response = self.send(user, |token| self.client.get(&url).bearer_auth(token)).await?;
+
+        if !response.status().is_success() {
+            return Err(AppError::upstream("Failed to fetch library items", response.status()));
+        }
+
+        let body = response.bytes().await?;
+        let data: AbsItemsResponse = serde_json::from_slice(&body)
+            .map_err(|e| anyhow::anyhow!("ABS sent library items that can't be read: {}", e))?;
+        if !self.items_ttl.is_zero() {
+            self.items.set_value(&cache_key, Arc::new(data.clone()), body.len(), self.items_ttl);
+            if self.shared {
+                if let Ok(value) = serde_json::to_vec(&data) {
+                    self.cache.set(&cache_key, value, self.items_ttl).await;
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    async fn forget_items(&self, user: &InternalUser, library_id: &str) {
+        let cache_key = Self::items_key(user, library_id);
+        self.items.remove_value(&cache_key);
+        if self.shared {
+            self.cache.remove(&cache_key).await;
+        }
+    }
+
+    async fn get_item(&self, user: &InternalUser, item_id: &str) -> Result<AbsItemResult> {
+        // The expanded form carries the same flattened metadata (authorName, seriesName, ...) as the items list
+        let url = format!("{}/api/items/{}?expanded=1", self.base_url, item_id);
+        let response = self.send(user, |token| self.client.get(&url).bearer_auth(token)).await?;
+
+        if !response.status().is_success() {
+            return Err(AppError::upstream("Failed to fetch library item", response.status()));
+        }
+
+        Ok(response.json::<AbsItemResult>().await?)
+    }
+
+    async fn get_cover(&self, user: &InternalUser, item_id: &str) -> Result<Option<Vec<u8>>> {
+        let url = format!("{}/api/items/{}/cover", self.base_url, item_id);
+        let response = self.send(user, |token| self.client.get(&url).bearer_auth(token)).await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(AppError::upstream("Failed to fetch cover", response.status()));
+        }
+
+        Ok(Some(response.bytes().await?.to_vec()))
+    }
+
+}
+
+#[async_trait]
+impl PodcastClient for ApiClient {
+    async fn get_recent_episodes(&self, user: &InternalUser, library_id: &str, limit: usize, page: usize) -> Result<AbsEpisodesResponse> {
+        let url = format!("{}/api/libraries/{}/recent-episodes", self.base_url, library_id);
         let response = self
-            .client
-            .get(&url)
-            .bearer_auth(&user.api_key)
-            .send()
+            .send(user, |token| self.client.get(&url).query(&[("limit", limit), ("page", page)]).bearer_auth(token))
             .await?;
+        if !response.status().is_success() {
+            return Err(AppError::upstream("Failed to fetch episodes", response.status()));
+        }
+        Ok(response.json().await?)
+    }
+}
 
+#[async_trait]
+impl CollectionClient for ApiClient {
+    async fn get_collections(&self, user: &InternalUser, library_id: &str) -> Result<Vec<AbsCollection>> {
+        let url = format!("{}/api/libraries/{}/collections", self.base_url, library_id);
+        let response = self.send(user, |token| self.client.get(&url).bearer_auth(token)).await?;
         if !response.status().is_success() {
-            return Err(anyhow::anyhow!("Failed to fetch library items: status {}", response.status()));
-        }
-
-        let data = response.json::<AbsItemsResponse>().await?;
-        {
-            let mut cache = self.items_cache.write().unwrap();
-            let now = Instant::now();
-            cache.retain(|_, cached| now < cached.expires);
-            cache.insert(
-                cache_key,
-                CachedItems {
-                    response: data.clone(),
-                    expires: now + Duration::from_secs(60), // Cache for 60 seconds
-                },
-            );
+            return Err(AppError::upstream("Failed to fetch collections", response.status()));
         }
-        Ok(data)
+        Ok(response.json::<AbsCollectionsResponse>().await?.results)
+    }
+}
+
+#[async_trait]
+impl ProgressClient for ApiClient {
+    async fn report_download(&self, user: &InternalUser, item: &AbsItemResult) -> Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let session_id = {
+            let mut hasher = sha1_smol::Sha1::new();
+            hasher.update(format!("{}:{}:{}", user.name, item.id, now).as_bytes());
+            format!("opds-{}", hasher.digest())
+        };
+        let metadata = &item.media.metadata;
+
+        // ABS accepts sessions from offline players through this endpoint; we report one
+        // with no listening time, which is exactly what a download is.
+        let session = serde_json::json!({
+            "id": session_id,
+            "libraryItemId": item.id,
+            "mediaType": "book",
+            "displayTitle": metadata.title.as_deref().unwrap_or(&item.id),
+            "displayAuthor": metadata.author_name.as_deref().unwrap_or(""),
+            "duration": 0,
+            "playMethod": 3,
+            "mediaPlayer": "abs-opds",
+            "deviceInfo": { "clientName": "abs-opds", "deviceName": "OPDS" },
+            "timeListening": 0,
+            "startTime": 0,
+            "currentTime": 0,
+            "startedAt": now,
+            "updatedAt": now,
+        });
+
+        let url = format!("{}/api/session/local", self.base_url);
+        let response = self.send(user, |token| self.client.post(&url).bearer_auth(token).json(&session)).await?;
+
+        if !response.status().is_success() {
+            return Err(AppError::upstream("Failed to report download", response.status()));
+        }
+
+        Ok(())
+    }
+
+    async fn get_progress(&self, user: &InternalUser) -> Result<HashMap<String, f64>> {
+        let url = format!("{}/api/me", self.base_url);
+        let response = self.send(user, |token| self.client.get(&url).bearer_auth(token)).await?;
+        if !response.status().is_success() {
+            return Err(AppError::upstream("Failed to fetch progress", response.status()));
+        }
+        let me: MeResponse = response.json().await?;
+        // Audiobooks report `progress`, ebooks `ebookProgress`
+        Ok(me
+            .media_progress
+            .into_iter()
+            .map(|p| {
+                let progress = if p.is_finished { 1.0 } else { p.progress.max(p.ebook_progress.unwrap_or(0.0)) };
+                (p.library_item_id, progress.clamp(0.0, 1.0))
+            })
+            .collect())
+    }
+
+    async fn get_items_in_progress(&self, user: &InternalUser) -> Result<Vec<String>> {
+        let url = format!("{}/api/me/items-in-progress", self.base_url);
+        let response = self.send(user, |token| self.client.get(&url).bearer_auth(token)).await?;
+        if !response.status().is_success() {
+            return Err(AppError::upstream("Failed to fetch items in progress", response.status()));
+        }
+        let mut items = response.json::<ItemsInProgressResponse>().await?.library_items;
+        items.sort_by_key(|item| std::cmp::Reverse(item.progress_last_update));
+        Ok(items.into_iter().map(|item| item.id).collect())
     }
 }
@@ -0,0 +1,173 @@
+//! Minimal streaming ZIP writer. Entries are STORED (audio is already compressed) and
+//! use data descriptors, so each file can be streamed through without knowing its CRC
+//! up front. ZIP64 records are only emitted when sizes or offsets need them.
+
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIG: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const ZIP64_END_SIG: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIG: u32 = 0x0706_4b50;
+const END_SIG: u32 = 0x0605_4b50;
+
+// Bit 3: sizes and CRC follow in a data descriptor. Bit 11: names are UTF-8.
+const FLAGS: u16 = (1 << 3) | (1 << 11);
+const VERSION_STORED: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+}
+
+/// Produces the framing bytes of a ZIP archive; the caller streams each entry's data
+/// in between `start_entry` and `finish_entry`.
+#[derive(Default)]
+pub struct ZipStream {
+    offset: u64,
+    entries: Vec<Entry>,
+    current: Option<(String, u64, crc32fast::Hasher, u64)>,
+}
+
+impl ZipStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the local file header for a new entry.
+    pub fn start_entry(&mut self, name: &str) -> Vec<u8> {
+        let mut out = Vec::with_capacity(30 + name.len());
+        put_u32(&mut out, LOCAL_HEADER_SIG);
+        put_u16(&mut out, VERSION_STORED);
+        put_u16(&mut out, FLAGS);
+        put_u16(&mut out, 0); // stored
+        put_u16(&mut out, 0); // mod time
+        put_u16(&mut out, 0x21); // mod date: 1980-01-01
+        put_u32(&mut out, 0); // crc, in descriptor
+        put_u32(&mut out, 0); // compressed size, in descriptor
+        put_u32(&mut out, 0); // size, in descriptor
+        put_u16(&mut out, name.len() as u16);
+        put_u16(&mut out, 0); // extra length
+        out.extend_from_slice(name.as_bytes());
+
+        self.current = Some((name.to_string(), self.offset, crc32fast::Hasher::new(), 0));
+        self.offset += out.len() as u64;
+        out
+    }
+
+    /// Feeds entry data through the CRC; the data itself is sent by the caller unchanged.
+    pub fn update(&mut self, chunk: &[u8]) {
+        if let Some((_, _, hasher, size)) = self.current.as_mut() {
+            hasher.update(chunk);
+            *size += chunk.len() as u64;
+        }
+        self.offset += chunk.len() as u64;
+    }
+
+    /// Returns the data descriptor closing the current entry.
+    pub fn finish_entry(&mut self) -> Vec<u8> {
+        let Some((name, offset, hasher, size)) = self.current.take() else {
+            return Vec::new();
+        };
+        let crc = hasher.finalize();
+
+        let mut out = Vec::with_capacity(24);
+        put_u32(&mut out, DATA_DESCRIPTOR_SIG);
+        put_u32(&mut out, crc);
+        if size >= u32::MAX as u64 {
+            put_u64(&mut out, size);
+            put_u64(&mut out, size);
+        } else {
+            put_u32(&mut out, size as u32);
+            put_u32(&mut out, size as u32);
+        }
+        self.offset += out.len() as u64;
+        self.entries.push(Entry { name, crc, size, offset });
+        out
+    }
+
+    /// Returns the central directory and end records.
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let cd_offset = self.offset;
+
+        for entry in &self.entries {
+            let needs_zip64 = entry.size >= u32::MAX as u64 || entry.offset >= u32::MAX as u64;
+            let mut extra = Vec::new();
+            if needs_zip64 {
+                put_u16(&mut extra, 0x0001);
+                put_u16(&mut extra, 24);
+                put_u64(&mut extra, entry.size);
+                put_u64(&mut extra, entry.size);
+                put_u64(&mut extra, entry.offset);
+            }
+            let clamp = |v: u64| if needs_zip64 { u32::MAX } else { v as u32 };
+
+            put_u32(&mut out, CENTRAL_HEADER_SIG);
+            put_u16(&mut out, if needs_zip64 { VERSION_ZIP64 } else { VERSION_STORED }); // made by
+            put_u16(&mut out, if needs_zip64 { VERSION_ZIP64 } else { VERSION_STORED }); // needed
+            put_u16(&mut out, FLAGS);
+            put_u16(&mut out, 0);
+            put_u16(&mut out, 0);
+            put_u16(&mut out, 0x21);
+            put_u32(&mut out, entry.crc);
+            put_u32(&mut out, clamp(entry.size));
+            put_u32(&mut out, clamp(entry.size));
+            put_u16(&mut out, entry.name.len() as u16);
+            put_u16(&mut out, extra.len() as u16);
+            put_u16(&mut out, 0); // comment length
+            put_u16(&mut out, 0); // disk number
+            put_u16(&mut out, 0); // internal attributes
+            put_u32(&mut out, 0); // external attributes
+            put_u32(&mut out, clamp(entry.offset));
+            out.extend_from_slice(entry.name.as_bytes());
+            out.extend_from_slice(&extra);
+        }
+
+        let cd_size = out.len() as u64;
+        let count = self.entries.len() as u64;
+        let needs_zip64 = cd_offset >= u32::MAX as u64 || count >= u16::MAX as u64;
+
+        if needs_zip64 {
+            let zip64_end_offset = cd_offset + cd_size;
+            put_u32(&mut out, ZIP64_END_SIG);
+            put_u64(&mut out, 44);
+            put_u16(&mut out, VERSION_ZIP64);
+            put_u16(&mut out, VERSION_ZIP64);
+            put_u32(&mut out, 0);
+            put_u32(&mut out, 0);
+            put_u64(&mut out, count);
+            put_u64(&mut out, count);
+            put_u64(&mut out, cd_size);
+            put_u64(&mut out, cd_offset);
+
+            put_u32(&mut out, ZIP64_LOCATOR_SIG);
+            put_u32(&mut out, 0);
+            put_u64(&mut out, zip64_end_offset);
+            put_u32(&mut out, 1);
+        }
+
+        put_u32(&mut out, END_SIG);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, if needs_zip64 { u16::MAX } else { count as u16 });
+        put_u16(&mut out, if needs_zip64 { u16::MAX } else { count as u16 });
+        put_u32(&mut out, if needs_zip64 { u32::MAX } else { cd_size as u32 });
+        put_u32(&mut out, if needs_zip64 { u32::MAX } else { cd_offset as u32 });
+        put_u16(&mut out, 0);
+        out
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, v: u64) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
@@ -1,14 +1,63 @@
 use axum::{
-    extract::{FromRequestParts, FromRef},
+    extract::{FromRequestParts, FromRef, MatchedPath, Path, Request, State},
     http::{request::Parts, StatusCode},
-    response::{IntoResponse, Response},
+    middleware::Next,
+    response::Response,
 };
 use base64::{engine::general_purpose, Engine as _};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::{debug, error};
 
-use crate::{models::InternalUser, AppState};
+use crate::{
+    handlers::{auth_document_response, error_response, wants_opds_v2},
+    models::InternalUser,
+    share::ShareGrant,
+    AppState,
+};
+
+/// The `Link` header of 401 responses, pointing readers at the authentication document.
+const AUTH_DOCUMENT_LINK: &str =
+    "</opds/auth>; rel=\"http://opds-spec.org/auth/document\"; type=\"application/opds-authentication+json\"";
+
+/// The catalog routes a shelf share link may use: those that filter their books by the
+/// shelf, and those of single items, which are checked against it.
+const SHELF_ROUTES: [&str; 10] = [
+    "/",
+    "/libraries/{library_id}",
+    "/libraries/{library_id}/search-definition",
+    "/libraries/{library_id}/updated",
+    "/libraries/{library_id}/recent",
+    "/covers/{item_id}",
+    "/items/{item_id}/files/{file_id}",
+    "/items/{item_id}/audiobook.zip",
+    "/items/{item_id}/tracks/{track}",
+    "/proxy/{*any}",
+];
+
+pub mod backend;
+pub mod throttle;
+
+/// Who may use a route. Applied with [`require`] as a route layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthPolicy {
+    /// No credentials needed, e.g. health checks.
+    Public,
+    /// Any user that `authenticate` accepts.
+    User,
+    /// Users listed in `OPDS_ADMINS`.
+    Admin,
+    /// No credentials; requests read the `OPDS_PUBLIC_LIBRARY` as its user, and
+    /// anything outside that library is not found.
+    PublicCatalog,
+    /// Requests of a share link, with the grant of its token in their extensions, read
+    /// as the share user; anything outside the grant is not found.
+    Share,
+}
 
+/// The authenticated user. `require` puts it into the request extensions; routes
+/// without the layer authenticate on extraction instead.
+#[derive(Clone)]
 pub struct AuthUser(pub InternalUser);
 
 impl<S> FromRequestParts<S> for AuthUser
@@ -22,114 +71,225 @@ async fn from_request_parts(
         parts: &mut Parts,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<AuthUser>() {
+            return Ok(user.clone());
+        }
         let state = Arc::<AppState>::from_ref(state);
-        // 1. Check OPDS_NO_AUTH
-        if state.config.opds_no_auth {
-            if !state.config.abs_noauth_username.is_empty() && !state.config.abs_noauth_password.is_empty() {
-                // Check local cached anonymous user
-                {
-                    let cache = state.anonymous_user.read().await;
-                    if let Some((user, expires)) = &*cache {
-                        if tokio::time::Instant::now() < *expires {
-                            return Ok(AuthUser(user.clone()));
-                        }
-                    }
-                }
-                // Acquire write lock and refresh
-                let mut cache = state.anonymous_user.write().await;
-                // Double check in case another thread populated it while we waited for write lock
+        authenticate(&state, parts).await.map(AuthUser)
+    }
+}
+
+/// Middleware enforcing `policy` and attaching the user to the request.
+pub async fn require(
+    State((state, policy)): State<(Arc<AppState>, AuthPolicy)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if policy == AuthPolicy::Public {
+        return next.run(req).await;
+    }
+
+    let (mut parts, body) = req.into_parts();
+    if matches!(policy, AuthPolicy::PublicCatalog | AuthPolicy::Share) {
+        let scope = if policy == AuthPolicy::PublicCatalog {
+            state.config.public_user().map(|user| (user, Some(state.config.opds_public_library.clone()), None))
+        } else {
+            let grant = parts.extensions.get::<ShareGrant>().cloned();
+            state.config.share_user().zip(grant).map(|(user, grant)| (user, grant.library, grant.shelf))
+        };
+        let Some((user, library, shelf)) = scope else {
+            return error_response(&state, &parts.headers, StatusCode::NOT_FOUND, "Not found");
+        };
+        if !in_scope(&state, &user, library.as_deref(), shelf.as_deref(), &mut parts).await {
+            return error_response(&state, &parts.headers, StatusCode::NOT_FOUND, "Not found");
+        }
+        parts.extensions.insert(AuthUser(user));
+        return next.run(Request::from_parts(parts, body)).await;
+    }
+
+    let user = match authenticate(&state, &parts).await {
+        Ok(user) => user,
+        Err(res) => return res,
+    };
+    if policy == AuthPolicy::Admin && !state.config.is_admin(&user.name) {
+        debug!("User {} is not an admin", user.name);
+        return error_response(&state, &parts.headers, StatusCode::FORBIDDEN, "Admin access required");
+    }
+    parts.extensions.insert(AuthUser(user));
+    next.run(Request::from_parts(parts, body)).await
+}
+
+/// Whether the library or item a public catalog or share request is for lies in
+/// `library`, if given, and on `shelf`, if given. Shelves belong to a library and only
+/// list books, so their requests are limited to `SHELF_ROUTES` in it and can't browse
+/// categories. Proxied requests must be for an item, which is checked like the others.
+async fn in_scope(state: &AppState, user: &InternalUser, library: Option<&str>, shelf: Option<&str>, parts: &mut Parts) -> bool {
+    let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+        .await
+        .map(|Path(params)| params)
+        .unwrap_or_default();
+    if library.is_some_and(|library| params.get("library_id").is_some_and(|id| id != library)) {
+        return false;
+    }
+    let shelf = match shelf {
+        Some(name) => match state.config.shelf(name) {
+            Some(shelf) => Some(&shelf.query),
+            None => return false,
+        },
+        None => None,
+    };
+    if shelf.is_some() {
+        // Share requests are served by their own router, so routes match without a prefix
+        let route = parts.extensions.get::<MatchedPath>().map(MatchedPath::as_str);
+        if library.is_none()
+            || !route.is_some_and(|route| SHELF_ROUTES.contains(&route))
+            || parts.uri.query().is_some_and(|query| query.contains("categories="))
+        {
+            return false;
+        }
+    }
+    let proxied = match params.get("any") {
+        Some(path) => match crate::handlers::proxied_item_id(&format!("/{}", path.trim_start_matches('/'))) {
+            Some(item_id) => Some(item_id.to_string()),
+            None => return false,
+        },
+        None => None,
+    };
+    let Some(item_id) = proxied.as_ref().or(params.get("item_id")).filter(|_| library.is_some() || shelf.is_some()) else {
+        return true;
+    };
+    match state.api_client.get_item(user, item_id).await {
+        Ok(item) => {
+            library.is_none_or(|library| item.library_id.as_deref() == Some(library))
+                && shelf.is_none_or(|shelf| state.service.matches_shelf(&item, shelf))
+        }
+        Err(e) => {
+            debug!("Item {} not found: {}", item_id, e);
+            false
+        }
+    }
+}
+
+/// Resolves the user of a request: the anonymous user when `OPDS_NO_AUTH` is on,
+/// otherwise Basic credentials checked by the `AUTH_BACKENDS` or a `?token=` query
+/// parameter.
+pub async fn authenticate(state: &AppState, parts: &Parts) -> Result<InternalUser, Response> {
+    // 1. Check OPDS_NO_AUTH
+    if state.config.opds_no_auth {
+        if !state.config.abs_noauth_username.is_empty() && !state.config.abs_noauth_password.is_empty() {
+            // Check local cached anonymous user
+            {
+                let cache = state.anonymous_user.read().await;
                 if let Some((user, expires)) = &*cache {
                     if tokio::time::Instant::now() < *expires {
-                        return Ok(AuthUser(user.clone()));
+                        return Ok(user.clone());
                     }
                 }
-                match state
-                    .api_client
-                    .login(&state.config.abs_noauth_username, &state.config.abs_noauth_password)
-                    .await
-                {
-                    Ok(user) => {
-                        let expires = tokio::time::Instant::now() + std::time::Duration::from_secs(500); // 500s (<10min TTL)
-                        *cache = Some((user.clone(), expires));
-                        return Ok(AuthUser(user));
-                    }
-                    Err(e) => {
-                        error!("Auto-login failed for default user: {}", e);
-                        return Err((StatusCode::UNAUTHORIZED, format!("Authentication failed: {}", e))
-                            .into_response());
-                    }
+            }
+            // Acquire write lock and refresh
+            let mut cache = state.anonymous_user.write().await;
+            // Double check in case another thread populated it while we waited for write lock
+            if let Some((user, expires)) = &*cache {
+                if tokio::time::Instant::now() < *expires {
+                    return Ok(user.clone());
+                }
+            }
+            match state
+                .api_client
+                .login(&state.config.abs_noauth_username, &state.config.abs_noauth_password)
+                .await
+            {
+                Ok(user) => {
+                    let expires = tokio::time::Instant::now() + std::time::Duration::from_secs(500); // 500s (<10min TTL)
+                    *cache = Some((user.clone(), expires));
+                    return Ok(user);
+                }
+                Err(e) => {
+                    error!("Auto-login failed for default user: {}", e);
+                    return Err(error_response(state, &parts.headers, e.status(), &format!("Authentication failed: {}", e)));
                 }
-            } else {
-                error!("OPDS_NO_AUTH enabled but credentials missing");
-                return Err((StatusCode::INTERNAL_SERVER_ERROR, "Server configuration error").into_response());
             }
+        } else {
+            error!("OPDS_NO_AUTH enabled but credentials missing");
+            return Err(error_response(state, &parts.headers, StatusCode::INTERNAL_SERVER_ERROR, "Server configuration error"));
         }
+    }
 
-        // 2. Check Basic Auth
-        let auth_header = parts
-            .headers
-            .get("Authorization")
-            .and_then(|h| h.to_str().ok());
-
-        match auth_header {
-            Some(header) if header.starts_with("Basic ") => {
-                let code = &header[6..];
-                if let Ok(decoded) = general_purpose::STANDARD.decode(code) {
-                    if let Ok(creds) = String::from_utf8(decoded) {
-                         if let Some((username, password)) = creds.split_once(':') {
-                             // Check internal users first
-                             if let Some(internal_user) = state.config.internal_users.iter().find(|u| {
-                                 u.name.eq_ignore_ascii_case(username) && u.password.as_deref() == Some(password)
-                             }) {
-                                 debug!("Internal user authenticated: {}", username);
-                                 return Ok(AuthUser(internal_user.clone()));
-                             }
+    // 2. Check Basic Auth
+    let auth_header = parts
+        .headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok());
 
-                             // Check ABS login
-                             debug!("Attempting ABS login for: {}", username);
-                             match state.api_client.login(username, password).await {
-                                 Ok(user) => {
-                                     debug!("ABS user authenticated: {}", username);
-                                     return Ok(AuthUser(user));
-                                 }
-                                 Err(e) => {
-                                     debug!("Authentication failed for user {}: {}", username, e);
+    match auth_header {
+        Some(header) if header.starts_with("Basic ") => {
+            let code = &header[6..];
+            if let Ok(decoded) = general_purpose::STANDARD.decode(code) {
+                if let Ok(creds) = String::from_utf8(decoded) {
+                     if let Some((username, password)) = creds.split_once(':') {
+                         let client = state.login_throttle.client_ip(parts);
+                         if let Some(wait) = state.login_throttle.locked(client, username) {
+                             debug!("Refusing login of {} while locked out", username);
+                             let mut res = error_response(state, &parts.headers, StatusCode::TOO_MANY_REQUESTS, "Too many failed logins, try again later");
+                             res.headers_mut().insert(axum::http::header::RETRY_AFTER, (wait.as_secs() + 1).into());
+                             return Err(res);
+                         }
+                         let mut unchecked = false;
+                         for backend in &state.logins().backends {
+                             match backend.verify(username, password).await {
+                                 Ok(Some(user)) => {
+                                     state.login_throttle.succeeded(client, username);
+                                     return Ok(user);
                                  }
+                                 Ok(None) => {}
+                                 Err(_) => unchecked = true,
                              }
                          }
-                    }
+                         // Credentials nobody could check aren't a failed login
+                         if unchecked {
+                             let res = error_response(state, &parts.headers, StatusCode::SERVICE_UNAVAILABLE, "Logins can't be checked right now, try again later");
+                             return Err(crate::error::with_retry_after(res));
+                         }
+                         state.login_throttle.failed(client, username);
+                     }
                 }
             }
-            _ => {
-                // If Authorization header is not present, check query parameter ?token=...
-                if let Some(query) = parts.uri.query() {
-                    if let Some(token) = get_token_from_query(query) {
-                        if let Some(internal_user) = state.config.internal_users.iter().find(|u| {
-                            u.api_key == token
-                        }) {
-                            debug!("Token-authenticated internal user: {}", internal_user.name);
-                            return Ok(AuthUser(internal_user.clone()));
-                        }
-
-                        debug!("Using query token as ABS bearer key");
-                        return Ok(AuthUser(InternalUser {
-                            name: "abs_user".to_string(),
-                            api_key: token.to_string(),
-                            password: None,
-                        }));
+        }
+        _ => {
+            // If Authorization header is not present, check query parameter ?token=...
+            if let Some(query) = parts.uri.query() {
+                if let Some(token) = get_token_from_query(query) {
+                    if let Some(internal_user) = state.logins().users.iter().find(|u| {
+                        u.api_key == token
+                    }) {
+                        debug!("Token-authenticated internal user: {}", internal_user.name);
+                        return Ok(internal_user.clone());
                     }
+
+                    debug!("Using query token as ABS bearer key");
+                    return Ok(InternalUser {
+                        name: "abs_user".to_string(),
+                        api_key: token.to_string(),
+                        password: None,
+                    });
                 }
             }
         }
-
-        // Failed
-        let mut res = (StatusCode::UNAUTHORIZED, "Authentication required").into_response();
-        res.headers_mut().insert(
-            "WWW-Authenticate",
-            axum::http::HeaderValue::from_static("Basic realm=\"OPDS\""),
-        );
-        Err(res)
     }
+
+    // Failed. OPDS 2 clients get the authentication document itself, as its spec asks;
+    // every client is pointed at it
+    let mut res = if wants_opds_v2(&parts.headers) {
+        auth_document_response(state, &parts.headers, StatusCode::UNAUTHORIZED)
+    } else {
+        error_response(state, &parts.headers, StatusCode::UNAUTHORIZED, "Authentication required")
+    };
+    res.headers_mut().insert(
+        "WWW-Authenticate",
+        axum::http::HeaderValue::from_static("Basic realm=\"OPDS\""),
+    );
+    res.headers_mut().insert(axum::http::header::LINK, axum::http::HeaderValue::from_static(AUTH_DOCUMENT_LINK));
+    Err(res)
 }
 
 pub(crate) fn get_token_from_query(query: &str) -> Option<&str> {
@@ -0,0 +1,413 @@
+//! Where Basic credentials are checked. `AUTH_BACKENDS` lists the backends in the
+//! order they are tried; the first one that accepts the credentials decides.
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, warn};
+
+use crate::api::AbsClient;
+use crate::cache::token_key;
+use crate::error::Result;
+use crate::models::{AppConfig, AuthBackendKind, InternalUser, UserMapping};
+
+/// How long a checked htpasswd password or ABS token is remembered. Readers send their
+/// credentials with every request, and bcrypt and argon2 are slow on purpose.
+const VERIFIED_TTL: Duration = Duration::from_secs(600);
+
+/// Checks a username and password. Backends log their own failures: a backend that
+/// cannot decide lets the next one try.
+#[async_trait]
+pub trait AuthBackend: Send + Sync {
+    /// The user for these credentials, `None` if this backend doesn't accept them, or an
+    /// error if it couldn't check them, e.g. because ABS or the directory is down.
+    async fn verify(&self, username: &str, password: &str) -> Result<Option<InternalUser>>;
+}
+
+/// The backends configured by `AUTH_BACKENDS`, in order. A htpasswd file that
+/// cannot be read is logged and left out.
+pub fn from_config(config: &AppConfig, client: Arc<dyn AbsClient + Send + Sync>) -> Vec<Arc<dyn AuthBackend>> {
+    config
+        .auth_backends()
+        .into_iter()
+        .filter_map(|kind| -> Option<Arc<dyn AuthBackend>> {
+            match kind {
+                AuthBackendKind::Env => Some(Arc::new(EnvUsers::new(
+                    config.internal_users.clone(),
+                    config.user_map.clone(),
+                    client.clone(),
+                ))),
+                AuthBackendKind::Htpasswd => Htpasswd::open(&config.htpasswd_file, &config.htpasswd_api_key)
+                    .inspect_err(|e| error!("Skipping the htpasswd auth backend: {}", e))
+                    .ok()
+                    .map(|backend| Arc::new(backend) as Arc<dyn AuthBackend>),
+                AuthBackendKind::Abs => Some(Arc::new(AbsPassthrough::new(client.clone()))),
+                AuthBackendKind::AbsToken => Some(Arc::new(AbsToken::new(client.clone()))),
+                #[cfg(feature = "ldap")]
+                AuthBackendKind::Ldap => Some(Arc::new(Ldap::new(config))),
+                #[cfg(not(feature = "ldap"))]
+                AuthBackendKind::Ldap => {
+                    error!("Skipping the ldap auth backend: this build doesn't have the `ldap` feature");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The users from `OPDS_USERS`, and those from `OPDS_USER_MAP`, who read ABS through a
+/// login we make for them. ABS sessions are cached by the client, like any login.
+pub struct EnvUsers {
+    users: Vec<InternalUser>,
+    mapped: HashMap<String, UserMapping>,
+    client: Arc<dyn AbsClient + Send + Sync>,
+    /// Hashed passwords checked recently, by their hash.
+    verified: Verified,
+}
+
+impl EnvUsers {
+    pub fn new(users: Vec<InternalUser>, mapped: HashMap<String, UserMapping>, client: Arc<dyn AbsClient + Send + Sync>) -> Self {
+        Self { users, mapped, client, verified: Verified::default() }
+    }
+
+    /// Whether `password` is `stored`, as it is or as its hash.
+    async fn matches(&self, stored: &str, password: &str) -> bool {
+        if !is_hash(stored) {
+            return stored == password;
+        }
+        if self.verified.remembers(stored, password) {
+            return true;
+        }
+        let (hash, attempt) = (stored.to_string(), password.to_string());
+        let matches = tokio::task::spawn_blocking(move || check_hash(&hash, &attempt)).await.unwrap_or(false);
+        if matches {
+            self.verified.remember(stored, password);
+        }
+        matches
+    }
+}
+
+#[async_trait]
+impl AuthBackend for EnvUsers {
+    async fn verify(&self, username: &str, password: &str) -> Result<Option<InternalUser>> {
+        for user in self.users.iter().filter(|u| u.name.eq_ignore_ascii_case(username)) {
+            let Some(stored) = &user.password else { continue };
+            if self.matches(stored, password).await {
+                debug!("Internal user authenticated: {}", username);
+                return Ok(Some(user.clone()));
+            }
+        }
+
+        let mut mapped = None;
+        for (name, mapping) in self.mapped.iter().filter(|(name, _)| name.eq_ignore_ascii_case(username)) {
+            if self.matches(&mapping.password, password).await {
+                mapped = Some((name, mapping));
+                break;
+            }
+        }
+        let Some((name, mapping)) = mapped else {
+            return Ok(None);
+        };
+        match self.client.login(&mapping.abs_username, &mapping.abs_password).await {
+            Ok(abs_user) => {
+                debug!("Mapped user {} authenticated as ABS user {}", name, mapping.abs_username);
+                Ok(Some(InternalUser { name: name.clone(), api_key: abs_user.api_key, password: None }))
+            }
+            Err(e) => {
+                warn!("ABS login of {} for mapped user {} failed: {}", mapping.abs_username, name, e);
+                if e.is_rejection() { Ok(None) } else { Err(e) }
+            }
+        }
+    }
+}
+
+/// Users of an Apache htpasswd file, all reading ABS with one API key. bcrypt
+/// (`htpasswd -B`), SHA-1 (`htpasswd -s`) and argon2 (`abs_opds add-user`) entries are
+/// supported.
+pub struct Htpasswd {
+    hashes: HashMap<String, String>,
+    api_key: String,
+    /// Users whose password was checked recently.
+    verified: Verified,
+}
+
+impl Htpasswd {
+    /// Reads the file once; edits take effect when the logins are reloaded.
+    pub fn open(path: &str, api_key: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read HTPASSWD_FILE {}: {}", path, e))?;
+        Ok(Self::parse(&content, api_key))
+    }
+
+    pub fn parse(content: &str, api_key: &str) -> Self {
+        let mut hashes = HashMap::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((user, hash)) if hash.starts_with("$2") || hash.starts_with("{SHA}") || is_hash(hash) => {
+                    hashes.insert(user.to_string(), hash.to_string());
+                }
+                Some((user, _)) => {
+                    warn!("Skipping htpasswd user {}: only bcrypt, argon2 and SHA-1 hashes are supported", user)
+                }
+                None => warn!("Skipping malformed htpasswd line"),
+            }
+        }
+        Self { hashes, api_key: api_key.to_string(), verified: Verified::default() }
+    }
+
+    fn user(&self, username: &str) -> InternalUser {
+        InternalUser { name: username.to_string(), api_key: self.api_key.clone(), password: None }
+    }
+}
+
+/// An argon2 hash as `hash_password` writes it, e.g. `$argon2id$v=19$m=19456,t=2,p=1$...`,
+/// or a bcrypt hash as `htpasswd -nbB` prints it. `OPDS_USERS` and `OPDS_USER_MAP`
+/// passwords that aren't one are compared as they are.
+fn is_hash(password: &str) -> bool {
+    is_argon2(password) || ["$2a$", "$2b$", "$2y$"].iter().any(|prefix| password.starts_with(prefix))
+}
+
+fn is_argon2(password: &str) -> bool {
+    password.starts_with("$argon2")
+}
+
+/// Passwords that matched recently, keyed by what they were checked for, so readers
+/// sending credentials with every request don't pay for the check each time.
+#[derive(Default)]
+struct Verified(Mutex<HashMap<String, (String, Instant)>>);
+
+impl Verified {
+    fn remembers(&self, key: &str, password: &str) -> bool {
+        let verified = self.0.lock().unwrap();
+        verified.get(key).is_some_and(|(password_key, expires)| *password_key == token_key(password) && Instant::now() < *expires)
+    }
+
+    fn remember(&self, key: &str, password: &str) {
+        let mut verified = self.0.lock().unwrap();
+        let now = Instant::now();
+        verified.retain(|_, (_, expires)| now < *expires);
+        verified.insert(key.to_string(), (token_key(password), now + VERIFIED_TTL));
+    }
+}
+
+/// An argon2id hash of `password` with a random salt, for `OPDS_USERS` and htpasswd files.
+pub fn hash_password(password: &str) -> String {
+    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+
+    let salt = SaltString::generate(&mut OsRng);
+    argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("the default argon2 parameters are valid")
+        .to_string()
+}
+
+fn check_hash(hash: &str, password: &str) -> bool {
+    use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+    if let Some(sha) = hash.strip_prefix("{SHA}") {
+        let mut hasher = sha1_smol::Sha1::new();
+        hasher.update(password.as_bytes());
+        general_purpose::STANDARD.encode(hasher.digest().bytes()) == sha
+    } else if is_argon2(hash) {
+        PasswordHash::new(hash)
+            .is_ok_and(|hash| argon2::Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+#[async_trait]
+impl AuthBackend for Htpasswd {
+    async fn verify(&self, username: &str, password: &str) -> Result<Option<InternalUser>> {
+        let Some(hash) = self.hashes.get(username).cloned() else {
+            return Ok(None);
+        };
+        if self.verified.remembers(username, password) {
+            return Ok(Some(self.user(username)));
+        }
+
+        let attempt = password.to_string();
+        let matches = tokio::task::spawn_blocking(move || check_hash(&hash, &attempt))
+            .await
+            .unwrap_or(false);
+        if !matches {
+            return Ok(None);
+        }
+        debug!("htpasswd user authenticated: {}", username);
+        self.verified.remember(username, password);
+        Ok(Some(self.user(username)))
+    }
+}
+
+/// Logs in to ABS with the reader's credentials.
+pub struct AbsPassthrough {
+    client: Arc<dyn AbsClient + Send + Sync>,
+}
+
+impl AbsPassthrough {
+    pub fn new(client: Arc<dyn AbsClient + Send + Sync>) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for AbsPassthrough {
+    async fn verify(&self, username: &str, password: &str) -> Result<Option<InternalUser>> {
+        debug!("Attempting ABS login for: {}", username);
+        match self.client.login(username, password).await {
+            Ok(user) => {
+                debug!("ABS user authenticated: {}", username);
+                Ok(Some(user))
+            }
+            Err(e) if e.is_rejection() => {
+                debug!("Authentication failed for user {}: {}", username, e);
+                Ok(None)
+            }
+            Err(e) => {
+                warn!("ABS login of {} couldn't be checked: {}", username, e);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Takes the password as an ABS API token, which must belong to the given user.
+pub struct AbsToken {
+    client: Arc<dyn AbsClient + Send + Sync>,
+    /// Tokens checked recently, by hash, with the user they belong to.
+    verified: Mutex<HashMap<String, (InternalUser, Instant)>>,
+}
+
+impl AbsToken {
+    pub fn new(client: Arc<dyn AbsClient + Send + Sync>) -> Self {
+        Self { client, verified: Mutex::new(HashMap::new()) }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for AbsToken {
+    async fn verify(&self, username: &str, password: &str) -> Result<Option<InternalUser>> {
+        let token_hash = token_key(password);
+        let cached = {
+            let verified = self.verified.lock().unwrap();
+            verified.get(&token_hash).filter(|(_, expires)| Instant::now() < *expires).map(|(user, _)| user.clone())
+        };
+        let user = match cached {
+            Some(user) => user,
+            None => match self.client.verify_token(password).await {
+                Ok(user) => {
+                    let mut verified = self.verified.lock().unwrap();
+                    let now = Instant::now();
+                    verified.retain(|_, (_, expires)| now < *expires);
+                    verified.insert(token_hash, (user.clone(), now + VERIFIED_TTL));
+                    user
+                }
+                Err(e) if e.is_rejection() => {
+                    debug!("Token authentication failed for user {}: {}", username, e);
+                    return Ok(None);
+                }
+                Err(e) => {
+                    warn!("ABS token of {} couldn't be checked: {}", username, e);
+                    return Err(e);
+                }
+            },
+        };
+        if !user.name.eq_ignore_ascii_case(username) {
+            debug!("Token of {} presented as {}", user.name, username);
+            return Ok(None);
+        }
+        debug!("ABS token authenticated: {}", username);
+        Ok(Some(user))
+    }
+}
+
+/// Binds to an LDAP directory as the user, optionally checking their group with
+/// `LDAP_GROUP_FILTER`. Every directory user reads ABS with `LDAP_API_KEY`.
+#[cfg(feature = "ldap")]
+pub struct Ldap {
+    url: String,
+    bind_dn: String,
+    group_filter: String,
+    search_base: String,
+    api_key: String,
+    /// Users whose password was checked recently.
+    verified: Verified,
+}
+
+#[cfg(feature = "ldap")]
+impl Ldap {
+    /// How long connecting to the directory may take.
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            url: config.ldap_url.clone(),
+            bind_dn: config.ldap_bind_dn.clone(),
+            group_filter: config.ldap_group_filter.clone(),
+            search_base: config.ldap_search_base.clone(),
+            api_key: config.ldap_api_key.clone(),
+            verified: Verified::default(),
+        }
+    }
+
+    /// Binds as `username` and checks the group filter.
+    async fn check(&self, username: &str, password: &str) -> Result<bool, ldap3::LdapError> {
+        let settings = ldap3::LdapConnSettings::new().set_conn_timeout(Self::CONNECT_TIMEOUT);
+        let (conn, mut ldap) = ldap3::LdapConnAsync::with_settings(settings, &self.url).await?;
+        ldap3::drive!(conn);
+
+        let dn = self.bind_dn.replace("{username}", &ldap3::dn_escape(username));
+        if ldap.simple_bind(&dn, password).await?.success().is_err() {
+            return Ok(false);
+        }
+        let member = if self.group_filter.is_empty() {
+            true
+        } else {
+            let filter = self.group_filter.replace("{username}", &ldap3::ldap_escape(username));
+            let (base, scope) = if self.search_base.is_empty() {
+                (dn.as_str(), ldap3::Scope::Base)
+            } else {
+                (self.search_base.as_str(), ldap3::Scope::Subtree)
+            };
+            let (entries, _) = ldap.search(base, scope, &filter, vec!["1.1"]).await?.success()?;
+            !entries.is_empty()
+        };
+        let _ = ldap.unbind().await;
+        Ok(member)
+    }
+}
+
+#[cfg(feature = "ldap")]
+#[async_trait]
+impl AuthBackend for Ldap {
+    async fn verify(&self, username: &str, password: &str) -> Result<Option<InternalUser>> {
+        // An empty password would be an anonymous bind, which directories accept
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+        if self.verified.remembers(username, password) {
+            return Ok(Some(InternalUser { name: username.to_string(), api_key: self.api_key.clone(), password: None }));
+        }
+
+        match self.check(username, password).await {
+            Ok(true) => {
+                debug!("LDAP user authenticated: {}", username);
+                self.verified.remember(username, password);
+                Ok(Some(InternalUser { name: username.to_string(), api_key: self.api_key.clone(), password: None }))
+            }
+            Ok(false) => {
+                debug!("LDAP rejected user {}", username);
+                Ok(None)
+            }
+            Err(e) => {
+                error!("LDAP check of user {} failed: {}", username, e);
+                Err(anyhow::anyhow!("LDAP check failed: {}", e).into())
+            }
+        }
+    }
+}
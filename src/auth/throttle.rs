@@ -0,0 +1,149 @@
+//! Login throttling (`LOGIN_MAX_FAILURES`, `LOGIN_LOCKOUT_SECS`). Failed Basic logins are
+//! counted per client address and per username; past the allowed failures each further
+//! one locks them out for twice as long as the last, up to the lockout, and requests
+//! meanwhile are refused before any backend, or ABS, sees their credentials. A client
+//! that logged in as a user before isn't locked out of that user by someone else's
+//! guessing, only by failures from its own address.
+
+use axum::extract::ConnectInfo;
+use axum::http::request::Parts;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::models::IpNet;
+
+/// How long failures are remembered after the last one, or after the lockout ends.
+const FAILURE_WINDOW: Duration = Duration::from_secs(900);
+/// How long a client that logged in as a user is known to be theirs.
+const KNOWN_CLIENT_WINDOW: Duration = Duration::from_secs(7 * 24 * 3600);
+
+struct Failures {
+    count: u32,
+    locked_until: Instant,
+}
+
+/// Failed logins by client address and username.
+pub struct Throttle {
+    max_failures: u32,
+    lockout: Duration,
+    trusted_proxies: Vec<IpNet>,
+    failures: Mutex<HashMap<String, Failures>>,
+    /// When each client last logged in as each user, by address and lowercase username.
+    known: Mutex<HashMap<(IpAddr, String), Instant>>,
+}
+
+impl Throttle {
+    /// Allows `max_failures` failed logins before locking out, for at most `lockout`;
+    /// 0 failures turns throttling off. `X-Forwarded-For` is taken from connections from
+    /// `trusted_proxies`.
+    pub fn new(max_failures: u32, lockout: Duration, trusted_proxies: Vec<IpNet>) -> Self {
+        Self {
+            max_failures,
+            lockout,
+            trusted_proxies,
+            failures: Mutex::new(HashMap::new()),
+            known: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The keys a login of `username` from `client` is counted under.
+    fn keys(client: Option<IpAddr>, username: &str) -> Vec<String> {
+        let mut keys = vec![format!("user:{}", username.to_lowercase())];
+        keys.extend(client.map(|ip| format!("ip:{}", ip)));
+        keys
+    }
+
+    fn is_known(&self, client: Option<IpAddr>, username: &str) -> bool {
+        let Some(ip) = client else { return false };
+        let known = self.known.lock().unwrap();
+        known.get(&(ip, username.to_lowercase())).is_some_and(|at| at.elapsed() < KNOWN_CLIENT_WINDOW)
+    }
+
+    /// How much longer a login of `username` from `client` is locked out, if it is.
+    pub fn locked(&self, client: Option<IpAddr>, username: &str) -> Option<Duration> {
+        if self.max_failures == 0 {
+            return None;
+        }
+        let mut keys = Self::keys(client, username);
+        if self.is_known(client, username) {
+            keys.remove(0);
+        }
+        let failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+        keys.iter()
+            .filter_map(|key| failures.get(key))
+            .filter(|f| now < f.locked_until)
+            .map(|f| f.locked_until - now)
+            .max()
+    }
+
+    /// Counts a failed login of `username` from `client`, locking out those past the
+    /// allowed failures.
+    pub fn failed(&self, client: Option<IpAddr>, username: &str) {
+        if self.max_failures == 0 {
+            return;
+        }
+        let mut failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+        failures.retain(|_, f| now < f.locked_until + FAILURE_WINDOW);
+        for key in Self::keys(client, username) {
+            let entry = failures.entry(key.clone()).or_insert(Failures { count: 0, locked_until: now });
+            entry.count += 1;
+            if entry.count > self.max_failures {
+                let doublings = (entry.count - self.max_failures - 1).min(31);
+                let backoff = Duration::from_secs(1u64 << doublings).min(self.lockout);
+                entry.locked_until = now + backoff;
+                tracing::warn!("Locking out {} for {} s after {} failed logins", key, backoff.as_secs(), entry.count);
+            } else {
+                // Forgotten a window after the last failure
+                entry.locked_until = now;
+            }
+        }
+    }
+
+    /// Forgets the failures of `username` and `client` after a successful login, and
+    /// remembers the client as theirs.
+    pub fn succeeded(&self, client: Option<IpAddr>, username: &str) {
+        if self.max_failures == 0 {
+            return;
+        }
+        let mut failures = self.failures.lock().unwrap();
+        for key in Self::keys(client, username) {
+            failures.remove(&key);
+        }
+        if let Some(ip) = client {
+            let mut known = self.known.lock().unwrap();
+            known.retain(|_, at| at.elapsed() < KNOWN_CLIENT_WINDOW);
+            known.insert((ip, username.to_lowercase()), Instant::now());
+        }
+    }
+
+    /// The address of the client sending a request. Requests from a trusted proxy, or
+    /// over a Unix socket, are taken from the nearest address in `X-Forwarded-For` that
+    /// isn't a trusted proxy itself, or from `X-Real-IP`; anyone else could set those to
+    /// dodge the count.
+    pub fn client_ip(&self, parts: &Parts) -> Option<IpAddr> {
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+        if peer.is_some_and(|ip| !self.is_trusted(ip)) {
+            return peer;
+        }
+        let header = |name: &str| parts.headers.get(name).and_then(|h| h.to_str().ok());
+        let forwarded: Vec<IpAddr> = header("x-forwarded-for")
+            .map(|value| value.split(',').filter_map(|ip| ip.trim().parse().ok()).collect())
+            .unwrap_or_default();
+        forwarded
+            .iter()
+            .rev()
+            .find(|ip| !self.is_trusted(**ip))
+            .or(forwarded.first())
+            .copied()
+            .or_else(|| header("x-real-ip").and_then(|ip| ip.trim().parse().ok()))
+            .or(peer)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+}
@@ -0,0 +1,314 @@
+//! Load test against a generated library: starts a fake Audiobookshelf serving N items
+//! and the catalog in front of it, replays reader traffic (feed pages, covers,
+//! downloads) from concurrent readers and prints a latency report.
+//!
+//! Usage: cargo run --release --bin loadgen -- [--items N] [--readers N] [--requests N]
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use abs_opds::models::AppConfig;
+
+const LIBRARY_ID: &str = "loadgen";
+const USERNAME: &str = "reader";
+const PASSWORD: &str = "reader";
+const TOKEN: &str = "loadgen-token";
+/// Size of every downloaded book.
+const DOWNLOAD_SIZE: usize = 512 * 1024;
+
+struct Options {
+    items: usize,
+    readers: usize,
+    /// Requests per reader.
+    requests: usize,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options { items: 5000, readers: 8, requests: 200 };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let target = match arg.as_str() {
+                "--items" => &mut options.items,
+                "--readers" => &mut options.readers,
+                "--requests" => &mut options.requests,
+                other => return Err(format!("Unknown argument: {}", other)),
+            };
+            *target = args
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .ok_or_else(|| format!("{} needs a positive number", arg))?;
+        }
+        Ok(options)
+    }
+}
+
+/// The generated library, as ABS would serve it.
+struct Backend {
+    items: Vec<Value>,
+    cover: Vec<u8>,
+    book: Vec<u8>,
+}
+
+impl Backend {
+    fn generate(count: usize) -> Self {
+        const GENRES: [&str; 8] = ["Fantasy", "Science Fiction", "Mystery", "Romance", "History", "Biography", "Poetry", "Travel"];
+        const LANGUAGES: [&str; 4] = ["en", "en", "de", "fr"];
+
+        let authors = (count / 8).max(1);
+        let items = (0..count)
+            .map(|i| {
+                let series = (i % 3 == 0).then(|| format!("Saga {} #{}", i % 200, i % 7 + 1));
+                json!({
+                    "id": format!("item-{}", i),
+                    "media": {
+                        "ebookFormat": "epub",
+                        "metadata": {
+                            "title": format!("Book {}", i),
+                            "subtitle": (i % 5 == 0).then(|| format!("Part {}", i % 4 + 1)),
+                            "description": format!("The {}th book of the generated library.", i),
+                            "genres": [GENRES[i % GENRES.len()]],
+                            "tags": [],
+                            "publisher": "Loadgen Press",
+                            "language": LANGUAGES[i % LANGUAGES.len()],
+                            "publishedYear": (1950 + i % 75).to_string(),
+                            "authorName": format!("Author {}", i % authors),
+                            "seriesName": series,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut cover = Vec::new();
+        image::RgbImage::from_fn(400, 600, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, 128]))
+            .write_to(&mut std::io::Cursor::new(&mut cover), image::ImageFormat::Jpeg)
+            .expect("encoding the cover works");
+
+        Self { items, cover, book: vec![0x42; DOWNLOAD_SIZE] }
+    }
+
+    fn item(&self, id: &str) -> Option<&Value> {
+        self.items.iter().find(|item| item["id"] == id)
+    }
+
+    fn router(self: Arc<Self>) -> Router {
+        fn library() -> Value {
+            json!({ "id": LIBRARY_ID, "name": "Generated Library", "icon": "database" })
+        }
+
+        Router::new()
+            .route("/login", post(|| async { Json(json!({ "user": { "username": USERNAME, "accessToken": TOKEN } })) }))
+            .route("/api/libraries", get(|| async { Json(json!({ "libraries": [library()] })) }))
+            .route("/api/libraries/{id}", get(|| async { Json(library()) }))
+            .route(
+                "/api/libraries/{id}/items",
+                get(|State(backend): State<Arc<Backend>>| async move { Json(json!({ "results": backend.items })) }),
+            )
+            .route(
+                "/api/items/{id}",
+                get(|State(backend): State<Arc<Backend>>, Path(id): Path<String>| async move {
+                    match backend.item(&id) {
+                        Some(item) => Json(item.clone()).into_response(),
+                        None => StatusCode::NOT_FOUND.into_response(),
+                    }
+                }),
+            )
+            .route(
+                "/api/items/{id}/cover",
+                get(|State(backend): State<Arc<Backend>>| async move {
+                    ([("content-type", "image/jpeg")], backend.cover.clone()).into_response()
+                }),
+            )
+            .route("/api/items/{id}/download", get(download))
+            .route("/api/items/{id}/ebook", get(download))
+            .route("/api/session/local", post(|| async { StatusCode::OK }))
+            .with_state(self)
+    }
+}
+
+async fn download(State(backend): State<Arc<Backend>>) -> Response {
+    ([("content-type", "application/epub+zip")], backend.book.clone()).into_response()
+}
+
+async fn serve(router: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("binding a local port works");
+    let addr = listener.local_addr().expect("a bound listener has an address");
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    addr
+}
+
+/// A small xorshift generator, so runs are repeatable without another dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    durations: Vec<Duration>,
+    errors: usize,
+}
+
+/// One reader browsing the catalog the way OPDS readers do: open the root and the
+/// library, page through books loading their covers, sometimes search or browse authors,
+/// and sometimes download a book.
+async fn reader(base: String, seed: u64, options: Arc<Options>) -> BTreeMap<&'static str, Samples> {
+    let client = reqwest::Client::new();
+    let mut rng = Rng(seed);
+    let mut samples: BTreeMap<&'static str, Samples> = BTreeMap::new();
+    let pages = options.items.div_ceil(20);
+    let mut sent = 0;
+
+    let mut request = async |kind: &'static str, path: String| {
+        let start = Instant::now();
+        let result = client.get(format!("{}{}", base, path)).basic_auth(USERNAME, Some(PASSWORD)).send().await;
+        let ok = match result {
+            Ok(response) => response.status().is_success() && response.bytes().await.is_ok(),
+            Err(_) => false,
+        };
+        let entry = samples.entry(kind).or_default();
+        entry.durations.push(start.elapsed());
+        if !ok {
+            entry.errors += 1;
+        }
+    };
+
+    while sent < options.requests {
+        let mut visit: Vec<(&'static str, String)> = vec![
+            ("root", "/opds".to_string()),
+            ("categories", format!("/opds/libraries/{}?categories=true", LIBRARY_ID)),
+        ];
+        for _ in 0..1 + rng.below(3) {
+            let page = rng.below(pages);
+            visit.push(("page", format!("/opds/libraries/{}?page={}", LIBRARY_ID, page)));
+            for _ in 0..4 {
+                let item = page * 20 + rng.below(20);
+                visit.push(("cover", format!("/opds/covers/item-{}", item.min(options.items - 1))));
+            }
+        }
+        if rng.chance(30) {
+            visit.push(("search", format!("/opds/libraries/{}?q=book%20{}", LIBRARY_ID, rng.below(options.items))));
+        }
+        if rng.chance(20) {
+            visit.push(("authors", format!("/opds/libraries/{}/authors", LIBRARY_ID)));
+        }
+        if rng.chance(25) {
+            let item = rng.below(options.items);
+            visit.push(("download", format!("/opds/proxy/api/items/item-{}/download?token={}", item, TOKEN)));
+        }
+
+        for (kind, path) in visit.into_iter().take(options.requests - sent) {
+            request(kind, path).await;
+            sent += 1;
+        }
+    }
+    samples
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+fn print_report(results: BTreeMap<&'static str, Samples>, elapsed: Duration) {
+    let ms = |d: Duration| format!("{:.1}", d.as_secs_f64() * 1000.0);
+    println!(
+        "{:<12} {:>7} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "request", "count", "errors", "mean ms", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    let mut total = 0;
+    let mut errors = 0;
+    for (kind, mut samples) in results {
+        samples.durations.sort();
+        let count = samples.durations.len();
+        let mean = samples.durations.iter().sum::<Duration>() / count as u32;
+        println!(
+            "{:<12} {:>7} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            kind,
+            count,
+            samples.errors,
+            ms(mean),
+            ms(percentile(&samples.durations, 0.5)),
+            ms(percentile(&samples.durations, 0.9)),
+            ms(percentile(&samples.durations, 0.99)),
+            ms(samples.durations[count - 1]),
+        );
+        total += count;
+        errors += samples.errors;
+    }
+    println!();
+    println!(
+        "{} requests, {} errors in {:.2} s ({:.0} requests/s)",
+        total,
+        errors,
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64()
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => Arc::new(options),
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("Usage: loadgen [--items N] [--readers N] [--requests N]");
+            std::process::exit(2);
+        }
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "warn".into()),
+        )
+        .init();
+
+    let backend = serve(Arc::new(Backend::generate(options.items)).router()).await;
+    let config: AppConfig = AppConfig::builder()
+        .abs_url(format!("http://{}", backend))
+        .use_proxy(true)
+        .user(USERNAME, TOKEN, PASSWORD)
+        .build()
+        .expect("the load test configuration is valid");
+    let server = serve(abs_opds::build_router(abs_opds::build_app_state(config).await)).await;
+
+    println!(
+        "{} readers sending {} requests each against {} items",
+        options.readers, options.requests, options.items
+    );
+    let start = Instant::now();
+    let readers: Vec<_> = (0..options.readers)
+        .map(|i| tokio::spawn(reader(format!("http://{}", server), 0x9E37_79B9 + i as u64 * 7919, options.clone())))
+        .collect();
+
+    let mut results: BTreeMap<&'static str, Samples> = BTreeMap::new();
+    for reader in readers {
+        for (kind, samples) in reader.await.expect("readers don't panic") {
+            let entry = results.entry(kind).or_default();
+            entry.durations.extend(samples.durations);
+            entry.errors += samples.errors;
+        }
+    }
+    print_report(results, start.elapsed());
+}
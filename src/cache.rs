@@ -0,0 +1,726 @@
+//! Storage behind the login token, library items and cover caches. Entries live in
+//! memory by default; `CACHE_URL` selects SQLite or Redis, so several instances
+//! behind a load balancer can share them, and `COVER_CACHE_DIR` keeps covers as files.
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+/// A key-value store with per-entry expiry. Stores log their own failures and
+/// treat them as misses: a broken cache must not break the catalog.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    /// The value of `key`, unless it is missing or expired.
+    async fn get(&self, key: &str) -> Option<Vec<u8>>;
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration);
+    async fn remove(&self, key: &str);
+    /// The number of live entries whose key starts with `prefix`, for stores that can
+    /// tell without scanning everything.
+    async fn count(&self, _prefix: &str) -> Option<usize> {
+        None
+    }
+    /// The live entries whose key starts with `prefix`, for stores that can list them
+    /// without scanning everything.
+    async fn entries(&self, _prefix: &str) -> Option<Vec<EntryInfo>> {
+        None
+    }
+    /// Writes out anything held back, before the server stops.
+    async fn flush(&self) {}
+}
+
+/// An entry as shown by the admin cache endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryInfo {
+    pub key: String,
+    pub size: usize,
+    /// Unknown for entries written before the store recorded it.
+    pub age: Option<Duration>,
+    pub expires_in: Duration,
+}
+
+/// A cache of the catalog: a key prefix in a store.
+#[derive(Clone)]
+pub struct RegisteredCache {
+    pub name: &'static str,
+    pub prefix: &'static str,
+    pub store: Arc<dyn CacheStore>,
+}
+
+struct Registration {
+    name: &'static str,
+    prefix: &'static str,
+    store: Weak<dyn CacheStore>,
+}
+
+static REGISTERED: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+
+/// Makes the entries of `store` starting with `prefix` visible as `name` in the
+/// metrics and the admin cache endpoint, for as long as the store is in use.
+pub fn register(name: &'static str, prefix: &'static str, store: &Arc<dyn CacheStore>) {
+    let mut registered = REGISTERED.lock().unwrap();
+    registered.retain(|r| r.store.strong_count() > 0);
+    registered.push(Registration { name, prefix, store: Arc::downgrade(store) });
+}
+
+/// The registered caches whose store is still in use.
+pub fn registered() -> Vec<RegisteredCache> {
+    REGISTERED
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|r| Some(RegisteredCache { name: r.name, prefix: r.prefix, store: r.store.upgrade()? }))
+        .collect()
+}
+
+enum Backend<'a> {
+    Memory,
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    Sqlite(&'a str),
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    Redis(&'a str),
+}
+
+fn backend(url: &str) -> anyhow::Result<Backend<'_>> {
+    let url = url.trim();
+    if url.is_empty() || url == "memory" {
+        Ok(Backend::Memory)
+    } else if let Some(path) = url.strip_prefix("sqlite://") {
+        if cfg!(feature = "sqlite") {
+            Ok(Backend::Sqlite(path))
+        } else {
+            Err(anyhow::anyhow!("CACHE_URL {} needs a build with the `sqlite` feature", url))
+        }
+    } else if url.starts_with("redis://") || url.starts_with("rediss://") {
+        if cfg!(feature = "redis") {
+            Ok(Backend::Redis(url))
+        } else {
+            Err(anyhow::anyhow!("CACHE_URL {} needs a build with the `redis` feature", url))
+        }
+    } else {
+        Err(anyhow::anyhow!(
+            "Invalid CACHE_URL '{}'. Expected memory, sqlite://<path> or redis://<host>",
+            url
+        ))
+    }
+}
+
+/// Checks `CACHE_URL` without opening anything.
+pub fn check_url(url: &str) -> anyhow::Result<()> {
+    backend(url).map(|_| ())
+}
+
+/// Opens the shared store configured by `CACHE_URL` (`sqlite://<path>` or
+/// `redis://<host>`). `None` means each cache keeps its own in-memory store, either
+/// because none is configured or because it could not be opened.
+pub fn open_shared(url: &str) -> Option<Arc<dyn CacheStore>> {
+    let store: anyhow::Result<Arc<dyn CacheStore>> = match backend(url) {
+        Ok(Backend::Memory) => return None,
+        #[cfg(feature = "sqlite")]
+        Ok(Backend::Sqlite(path)) => SqliteStore::open(path).map(|s| Arc::new(s) as Arc<dyn CacheStore>),
+        #[cfg(feature = "redis")]
+        Ok(Backend::Redis(url)) => RedisStore::open(url).map(|s| Arc::new(s) as Arc<dyn CacheStore>),
+        #[allow(unreachable_patterns)]
+        Ok(_) => unreachable!("backend() rejects stores that are not compiled in"),
+        Err(e) => Err(e),
+    };
+    store
+        .inspect_err(|e| tracing::error!("Using in-memory caches, the cache store failed to open: {}", e))
+        .ok()
+}
+
+/// Flushes the stores of the registered caches, each once.
+pub async fn flush_all() {
+    let mut flushed: Vec<*const ()> = Vec::new();
+    for cache in registered() {
+        let store = Arc::as_ptr(&cache.store) as *const ();
+        if !flushed.contains(&store) {
+            flushed.push(store);
+            cache.store.flush().await;
+        }
+    }
+}
+
+/// A cache key that doesn't reveal the ABS token it is derived from.
+pub fn token_key(token: &str) -> String {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(token.as_bytes());
+    hasher.digest().to_string()
+}
+
+struct MemoryEntry {
+    value: Vec<u8>,
+    inserted: Instant,
+    expires: Instant,
+    /// When it was last read or written, as a count of the store's accesses.
+    used: AtomicU64,
+}
+
+/// The default store. Expired entries are purged on insert; when full, the entry
+/// closest to expiry makes room, or with [`MemoryStore::lru`] the least recently used.
+pub struct MemoryStore {
+    entries: RwLock<HashMap<String, MemoryEntry>>,
+    max_entries: usize,
+    lru: bool,
+    accesses: AtomicU64,
+}
+
+impl MemoryStore {
+    pub fn new(max_entries: usize) -> Self {
+        Self { entries: RwLock::new(HashMap::new()), max_entries, lru: false, accesses: AtomicU64::new(0) }
+    }
+
+    /// A store that evicts the least recently used entry when full, for entries that
+    /// all live as long, where the one closest to expiry is just the oldest.
+    pub fn lru(max_entries: usize) -> Self {
+        Self { lru: true, ..Self::new(max_entries) }
+    }
+
+    fn access(&self) -> u64 {
+        self.accesses.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryStore {
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let entries = self.entries.read().unwrap();
+        let entry = entries.get(key).filter(|entry| Instant::now() < entry.expires)?;
+        entry.used.store(self.access(), Ordering::Relaxed);
+        Some(entry.value.clone())
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        let mut entries = self.entries.write().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, entry| now < entry.expires);
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            let victim = if self.lru {
+                entries.iter().min_by_key(|(_, e)| e.used.load(Ordering::Relaxed)).map(|(k, _)| k.clone())
+            } else {
+                entries.iter().min_by_key(|(_, e)| e.expires).map(|(k, _)| k.clone())
+            };
+            if let Some(victim) = victim {
+                entries.remove(&victim);
+            }
+        }
+        let used = AtomicU64::new(self.access());
+        entries.insert(key.to_string(), MemoryEntry { value, inserted: now, expires: now + ttl, used });
+    }
+
+    async fn remove(&self, key: &str) {
+        self.entries.write().unwrap().remove(key);
+    }
+
+    async fn count(&self, prefix: &str) -> Option<usize> {
+        let entries = self.entries.read().unwrap();
+        let now = Instant::now();
+        Some(entries.iter().filter(|(key, entry)| key.starts_with(prefix) && now < entry.expires).count())
+    }
+
+    async fn entries(&self, prefix: &str) -> Option<Vec<EntryInfo>> {
+        let entries = self.entries.read().unwrap();
+        let now = Instant::now();
+        Some(
+            entries
+                .iter()
+                .filter(|(key, entry)| key.starts_with(prefix) && now < entry.expires)
+                .map(|(key, entry)| EntryInfo {
+                    key: key.clone(),
+                    size: entry.value.len(),
+                    age: Some(now - entry.inserted),
+                    expires_in: entry.expires - now,
+                })
+                .collect(),
+        )
+    }
+}
+
+struct ValueEntry<T> {
+    value: Arc<T>,
+    size: usize,
+    inserted: Instant,
+    expires: Instant,
+}
+
+/// Values kept in memory as they are, for callers that would otherwise parse them on
+/// every hit. Expiry and eviction work like [`MemoryStore`]. As a [`CacheStore`] it
+/// takes and gives JSON, so it can be registered and listed like the others.
+pub struct ValueStore<T> {
+    entries: RwLock<HashMap<String, ValueEntry<T>>>,
+    max_entries: usize,
+}
+
+impl<T> ValueStore<T> {
+    pub fn new(max_entries: usize) -> Self {
+        Self { entries: RwLock::new(HashMap::new()), max_entries }
+    }
+
+    /// The value of `key`, unless it is missing or expired.
+    pub fn get_value(&self, key: &str) -> Option<Arc<T>> {
+        let entries = self.entries.read().unwrap();
+        entries.get(key).filter(|entry| Instant::now() < entry.expires).map(|entry| entry.value.clone())
+    }
+
+    /// Keeps `value`, which took `size` bytes to send, for `ttl`.
+    pub fn set_value(&self, key: &str, value: Arc<T>, size: usize, ttl: Duration) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.write().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, entry| now < entry.expires);
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            if let Some(oldest) = entries.iter().min_by_key(|(_, e)| e.expires).map(|(k, _)| k.clone()) {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key.to_string(), ValueEntry { value, size, inserted: now, expires: now + ttl });
+    }
+
+    pub fn remove_value(&self, key: &str) {
+        self.entries.write().unwrap().remove(key);
+    }
+}
+
+#[async_trait]
+impl<T> CacheStore for ValueStore<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + Send + Sync,
+{
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        serde_json::to_vec(&*self.get_value(key)?).ok()
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        if let Ok(parsed) = serde_json::from_slice(&value) {
+            self.set_value(key, Arc::new(parsed), value.len(), ttl);
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        self.remove_value(key);
+    }
+
+    async fn count(&self, prefix: &str) -> Option<usize> {
+        let entries = self.entries.read().unwrap();
+        let now = Instant::now();
+        Some(entries.iter().filter(|(key, entry)| key.starts_with(prefix) && now < entry.expires).count())
+    }
+
+    async fn entries(&self, prefix: &str) -> Option<Vec<EntryInfo>> {
+        let entries = self.entries.read().unwrap();
+        let now = Instant::now();
+        Some(
+            entries
+                .iter()
+                .filter(|(key, entry)| key.starts_with(prefix) && now < entry.expires)
+                .map(|(key, entry)| EntryInfo {
+                    key: key.clone(),
+                    size: entry.size,
+                    age: Some(now - entry.inserted),
+                    expires_in: entry.expires - now,
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Entries as files in a directory, one per key, for large values such as covers that
+/// should outlive restarts without filling memory. When the files take more than
+/// `max_bytes`, expired entries go first, then the least recently read.
+pub struct DiskStore {
+    files: Arc<DiskFiles>,
+}
+
+struct DiskFiles {
+    dir: std::path::PathBuf,
+    max_bytes: u64,
+    /// Bytes taken by the files, as of the last write.
+    size: Mutex<u64>,
+}
+
+/// A file of a [`DiskStore`]: its header, and how big and how recently read it is.
+struct DiskFile {
+    path: std::path::PathBuf,
+    header: Option<DiskHeader>,
+    size: u64,
+    read: std::time::SystemTime,
+}
+
+/// The first line of a file: when it was written and expires, in milliseconds since the
+/// epoch, and its key. The value follows.
+struct DiskHeader {
+    created: i64,
+    expires: i64,
+    key: String,
+}
+
+impl DiskHeader {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.trim_end_matches('\n').splitn(3, ' ');
+        Some(Self {
+            created: parts.next()?.parse().ok()?,
+            expires: parts.next()?.parse().ok()?,
+            key: parts.next()?.to_string(),
+        })
+    }
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+impl DiskStore {
+    /// Opens `dir`, creating it if needed, keeping up to `max_bytes` in it.
+    pub fn open(dir: impl Into<std::path::PathBuf>, max_bytes: u64) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let files = DiskFiles { dir, max_bytes, size: Mutex::new(0) };
+        let size = files.list().iter().map(|f| f.size).sum();
+        *files.size.lock().unwrap() = size;
+        Ok(Self { files: Arc::new(files) })
+    }
+
+    /// Runs `f` on the files off the async workers.
+    async fn with_files<T: Send + 'static>(&self, f: impl FnOnce(&DiskFiles) -> std::io::Result<T> + Send + 'static) -> Option<T> {
+        let files = self.files.clone();
+        match tokio::task::spawn_blocking(move || f(&files)).await {
+            Ok(Ok(value)) => Some(value),
+            Ok(Err(e)) => {
+                tracing::warn!("Disk cache error in {}: {}", self.files.dir.display(), e);
+                None
+            }
+            Err(e) => {
+                tracing::warn!("Disk cache task failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for DiskStore {
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let key = key.to_string();
+        self.with_files(move |files| files.read(&key)).await.flatten()
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        let key = key.to_string();
+        self.with_files(move |files| files.write(&key, &value, ttl)).await;
+    }
+
+    async fn remove(&self, key: &str) {
+        let key = key.to_string();
+        self.with_files(move |files| match std::fs::remove_file(files.path(&key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        })
+        .await;
+    }
+
+    async fn count(&self, prefix: &str) -> Option<usize> {
+        Some(self.entries(prefix).await?.len())
+    }
+
+    async fn entries(&self, prefix: &str) -> Option<Vec<EntryInfo>> {
+        let prefix = prefix.to_string();
+        self.with_files(move |files| {
+            let now = now_millis();
+            let millis = |ms: i64| Duration::from_millis(ms.max(0) as u64);
+            Ok(files
+                .list()
+                .into_iter()
+                .filter_map(|file| Some((file.header?, file.size)))
+                .filter(|(header, _)| header.key.starts_with(&prefix) && header.expires > now)
+                .map(|(header, size)| EntryInfo {
+                    size: size as usize,
+                    age: Some(millis(now - header.created)),
+                    expires_in: millis(header.expires - now),
+                    key: header.key,
+                })
+                .collect())
+        })
+        .await
+    }
+}
+
+impl DiskFiles {
+    fn path(&self, key: &str) -> std::path::PathBuf {
+        self.dir.join(token_key(key))
+    }
+
+    /// The files of the store, with their headers.
+    fn list(&self) -> Vec<DiskFile> {
+        use std::io::BufRead;
+
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_str().is_some_and(|name| name.len() == 40 && name.bytes().all(|b| b.is_ascii_hexdigit())))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                let mut line = String::new();
+                let header = std::fs::File::open(entry.path())
+                    .ok()
+                    .and_then(|file| std::io::BufReader::new(file).read_line(&mut line).ok())
+                    .and_then(|_| DiskHeader::parse(&line));
+                Some(DiskFile {
+                    path: entry.path(),
+                    header,
+                    size: metadata.len(),
+                    read: metadata.modified().ok()?,
+                })
+            })
+            .collect()
+    }
+
+    fn read(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let path = self.path(key);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let Some(split) = bytes.iter().position(|&b| b == b'\n') else {
+            return Ok(None);
+        };
+        let header = std::str::from_utf8(&bytes[..split]).ok().and_then(DiskHeader::parse);
+        if !header.is_some_and(|h| h.key == key && h.expires > now_millis()) {
+            return Ok(None);
+        }
+        // The modification time records the last read, for eviction
+        std::fs::File::options().append(true).open(&path)?.set_modified(std::time::SystemTime::now())?;
+        Ok(Some(bytes[split + 1..].to_vec()))
+    }
+
+    fn write(&self, key: &str, value: &[u8], ttl: Duration) -> std::io::Result<()> {
+        let now = now_millis();
+        let mut bytes = format!("{} {} {}\n", now, now + ttl.as_millis() as i64, key).into_bytes();
+        bytes.extend_from_slice(value);
+
+        // Written aside and renamed, so readers never see half a file
+        let path = self.path(key);
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        std::fs::write(&partial, &bytes)?;
+        let replaced = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        std::fs::rename(&partial, &path)?;
+
+        let mut size = self.size.lock().unwrap();
+        *size = (*size + bytes.len() as u64).saturating_sub(replaced);
+        if *size > self.max_bytes {
+            *size = self.evict();
+        }
+        Ok(())
+    }
+
+    /// Removes expired files, then the least recently read until the rest fit in
+    /// `max_bytes`, returning the bytes left.
+    fn evict(&self) -> u64 {
+        let now = now_millis();
+        let (expired, mut live): (Vec<DiskFile>, Vec<DiskFile>) =
+            self.list().into_iter().partition(|f| f.header.as_ref().is_none_or(|h| h.expires <= now));
+        for file in expired {
+            std::fs::remove_file(&file.path).ok();
+        }
+        live.sort_by_key(|f| std::cmp::Reverse(f.read));
+        let mut size = 0;
+        for file in live {
+            if size + file.size <= self.max_bytes {
+                size += file.size;
+            } else {
+                std::fs::remove_file(&file.path).ok();
+            }
+        }
+        size
+    }
+}
+
+/// Entries in a SQLite table, for instances sharing a volume.
+#[cfg(feature = "sqlite")]
+pub struct SqliteStore {
+    connection: Arc<std::sync::Mutex<rusqlite::Connection>>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let connection = rusqlite::Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS cache (key TEXT PRIMARY KEY, value BLOB NOT NULL, expires INTEGER NOT NULL);",
+        )?;
+        // Caches created before entries were listed lack the insertion time
+        let has_created = connection.prepare("SELECT created FROM cache LIMIT 0").is_ok();
+        if !has_created {
+            connection.execute_batch("ALTER TABLE cache ADD COLUMN created INTEGER;")?;
+        }
+        Ok(Self { connection: Arc::new(std::sync::Mutex::new(connection)) })
+    }
+
+    /// Runs `f` on the connection off the async workers.
+    async fn with_connection<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+    ) -> Option<T> {
+        let connection = self.connection.clone();
+        let result = tokio::task::spawn_blocking(move || f(&connection.lock().unwrap())).await;
+        match result {
+            Ok(Ok(value)) => Some(value),
+            Ok(Err(e)) => {
+                tracing::warn!("SQLite cache error: {}", e);
+                None
+            }
+            Err(e) => {
+                tracing::warn!("SQLite cache task failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn unix_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl CacheStore for SqliteStore {
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        use rusqlite::OptionalExtension;
+
+        let key = key.to_string();
+        self.with_connection(move |c| {
+            c.query_row(
+                "SELECT value FROM cache WHERE key = ?1 AND expires > ?2",
+                rusqlite::params![key, unix_millis()],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+        .flatten()
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        let key = key.to_string();
+        let expires = unix_millis() + ttl.as_millis() as i64;
+        self.with_connection(move |c| {
+            c.execute("DELETE FROM cache WHERE expires <= ?1", [unix_millis()])?;
+            c.execute(
+                "INSERT OR REPLACE INTO cache (key, value, expires, created) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![key, value, expires, unix_millis()],
+            )
+        })
+        .await;
+    }
+
+    async fn remove(&self, key: &str) {
+        let key = key.to_string();
+        self.with_connection(move |c| c.execute("DELETE FROM cache WHERE key = ?1", [key])).await;
+    }
+
+    async fn count(&self, prefix: &str) -> Option<usize> {
+        let prefix = prefix.to_string();
+        self.with_connection(move |c| {
+            c.query_row(
+                "SELECT COUNT(*) FROM cache WHERE substr(key, 1, length(?1)) = ?1 AND expires > ?2",
+                rusqlite::params![prefix, unix_millis()],
+                |row| row.get::<_, i64>(0),
+            )
+        })
+        .await
+        .map(|count| count as usize)
+    }
+
+    async fn entries(&self, prefix: &str) -> Option<Vec<EntryInfo>> {
+        let prefix = prefix.to_string();
+        self.with_connection(move |c| {
+            let now = unix_millis();
+            let mut statement = c.prepare(
+                "SELECT key, length(value), created, expires FROM cache WHERE substr(key, 1, length(?1)) = ?1 AND expires > ?2",
+            )?;
+            let rows = statement.query_map(rusqlite::params![prefix, now], |row| {
+                let millis = |ms: i64| Duration::from_millis(ms.max(0) as u64);
+                Ok(EntryInfo {
+                    key: row.get(0)?,
+                    size: row.get::<_, i64>(1)? as usize,
+                    age: row.get::<_, Option<i64>>(2)?.map(|created| millis(now - created)),
+                    expires_in: millis(row.get::<_, i64>(3)? - now),
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn flush(&self) {
+        // Moves the write-ahead log into the database, so the file is complete on its own
+        self.with_connection(|c| c.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")).await;
+    }
+}
+
+/// Entries in Redis, which expires them itself. Entries are not counted, as that
+/// would mean scanning the whole keyspace.
+#[cfg(feature = "redis")]
+pub struct RedisStore {
+    client: redis::Client,
+    connection: tokio::sync::OnceCell<redis::aio::ConnectionManager>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisStore {
+    /// Checks the URL; the connection is made on first use.
+    pub fn open(url: &str) -> anyhow::Result<Self> {
+        Ok(Self { client: redis::Client::open(url)?, connection: tokio::sync::OnceCell::new() })
+    }
+
+    async fn connection(&self) -> Option<redis::aio::ConnectionManager> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| redis::aio::ConnectionManager::new(self.client.clone()))
+            .await;
+        match connection {
+            Ok(connection) => Some(connection.clone()),
+            Err(e) => {
+                tracing::warn!("Redis cache unavailable: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl CacheStore for RedisStore {
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let mut connection = self.connection().await?;
+        redis::AsyncCommands::get::<_, Option<Vec<u8>>>(&mut connection, key)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Redis cache error: {}", e);
+                None
+            })
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        let Some(mut connection) = self.connection().await else { return };
+        let ttl = ttl.as_millis().max(1) as u64;
+        let result: redis::RedisResult<()> = redis::cmd("SET").arg(key).arg(value).arg("PX").arg(ttl).query_async(&mut connection).await;
+        if let Err(e) = result {
+            tracing::warn!("Redis cache error: {}", e);
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        let Some(mut connection) = self.connection().await else { return };
+        if let Err(e) = redis::AsyncCommands::del::<_, ()>(&mut connection, key).await {
+            tracing::warn!("Redis cache error: {}", e);
+        }
+    }
+}
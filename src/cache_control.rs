@@ -0,0 +1,63 @@
+//! `Cache-Control` for successful responses, by [`CacheClass`]: feeds, search
+//! descriptions, covers and downloads each get the policy of their `CACHE_CONTROL_*`
+//! variable, so operators can let readers and CDNs keep what doesn't change.
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+use crate::models::CacheClass;
+use crate::AppState;
+
+/// The class of a response to `path` with `content_type`, or `None` for responses
+/// such as the landing page and health checks, which keep their defaults. OPDS 2 feeds
+/// share one content type, so `publications` tells whether they list books.
+pub fn classify(path: &str, content_type: &str, publications: bool) -> Option<CacheClass> {
+    if content_type.contains("opensearchdescription+xml") {
+        Some(CacheClass::Search)
+    } else if content_type.starts_with("image/") {
+        Some(CacheClass::Covers)
+    } else if content_type.contains("kind=acquisition") || (content_type.contains("opds+json") && publications) {
+        Some(CacheClass::Acquisition)
+    } else if content_type.contains("kind=navigation") || content_type.contains("opds+json") {
+        Some(CacheClass::Navigation)
+    } else if path.contains("/items/") || path.contains("/proxy/") {
+        Some(CacheClass::Downloads)
+    } else {
+        None
+    }
+}
+
+/// Adds the configured `Cache-Control` to successful responses, replacing any from ABS.
+pub async fn apply(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let path = req.uri().path().to_string();
+    let response = next.run(req).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    let content_type = response.headers().get(header::CONTENT_TYPE).and_then(|c| c.to_str().ok()).unwrap_or("").to_string();
+
+    let (class, mut response) = if content_type.contains("opds+json") {
+        let (parts, body) = response.into_parts();
+        let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::error!("Failed to read feed response: {}", e);
+                return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        let publications = bytes.windows(b"\"publications\"".len()).any(|window| window == b"\"publications\"");
+        (classify(&path, &content_type, publications), Response::from_parts(parts, Body::from(bytes)))
+    } else {
+        (classify(&path, &content_type, false), response)
+    };
+
+    let value = class.map(|class| state.config.cache_control(class)).filter(|value| !value.is_empty());
+    if let Some(value) = value.and_then(|value| HeaderValue::from_str(value).ok()) {
+        response.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+    response
+}
@@ -0,0 +1,252 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use crate::auth::backend::hash_password;
+use crate::i18n::I18n;
+
+/// Runs a command-line subcommand if one was given. Returns the process exit code, or
+/// `None` when the server should start normally.
+pub fn run(args: &[String]) -> Option<i32> {
+    match args.first().map(String::as_str) {
+        None => None,
+        Some("i18n-report") => Some(i18n_report(&args[1..])),
+        Some("hash-password") => Some(print_hash(&args[1..])),
+        Some("add-user") => Some(add_user(&args[1..])),
+        Some("export") => Some(export(&args[1..])),
+        Some("help" | "--help" | "-h") => {
+            print_usage();
+            Some(0)
+        }
+        Some(other) => {
+            eprintln!("Unknown command: {}", other);
+            print_usage();
+            Some(2)
+        }
+    }
+}
+
+fn print_usage() {
+    println!("Usage: abs_opds [COMMAND]");
+    println!();
+    println!("Without a command the OPDS server is started.");
+    println!();
+    println!("Commands:");
+    println!("  i18n-report [DIR]  List missing and unused translation keys per language.");
+    println!("                     DIR defaults to LANGUAGES_DIR or ./languages.");
+    println!("  hash-password [PASSWORD]");
+    println!("                     Print an argon2 hash of PASSWORD, read from stdin if not given.");
+    println!("  add-user NAME [API_KEY] [--file PATH]");
+    println!("                     Print an OPDS_USERS entry for NAME reading ABS with API_KEY, or");
+    println!("                     without API_KEY a htpasswd line, written to PATH if given.");
+    println!("                     The password is read from stdin.");
+    println!("  export --out DIR [--covers] [--token KEY] [--base-url URL]");
+    println!("                     Write the catalog as static files to DIR, read with the ABS API");
+    println!("                     key KEY (default: of the first OPDS_USERS user). Downloads link to");
+    println!("                     URL, the live catalog, if given.");
+}
+
+/// Writes the catalog to static files; exits with 1 if it couldn't.
+fn export(args: &[String]) -> i32 {
+    use crate::export::ExportOptions;
+
+    let mut out = None;
+    let mut covers = false;
+    let mut token = None;
+    let mut base_url = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let target = match arg.as_str() {
+            "--covers" => {
+                covers = true;
+                continue;
+            }
+            "--out" => &mut out,
+            "--token" => &mut token,
+            "--base-url" => &mut base_url,
+            other => {
+                eprintln!("Unknown argument: {}", other);
+                return 2;
+            }
+        };
+        match args.next() {
+            Some(value) => *target = Some(value.clone()),
+            None => {
+                eprintln!("{} needs a value", arg);
+                return 2;
+            }
+        }
+    }
+    let Some(out) = out else {
+        eprintln!("Usage: abs_opds export --out DIR [--covers] [--token KEY] [--base-url URL]");
+        return 2;
+    };
+
+    dotenvy::dotenv().ok();
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "warn".into()))
+        .init();
+    let config = match crate::load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let Some(token) = token.or_else(|| config.internal_users.first().map(|user| user.api_key.clone())) else {
+        eprintln!("export needs --token when OPDS_USERS is empty");
+        return 2;
+    };
+    let options = ExportOptions { out: out.into(), covers, token, base_url };
+
+    // The server's runtime is already running; the export borrows it
+    let result = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(async {
+            let app = crate::build_router(crate::build_app_state(config).await);
+            crate::export::export(app, &options).await
+        })
+    });
+    match result {
+        Ok(summary) => {
+            println!("Exported {} feeds and {} covers to {}", summary.feeds, summary.covers, options.out.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("Export failed: {}", e);
+            1
+        }
+    }
+}
+
+/// Reads a password from the first line of stdin, prompting on a terminal.
+fn read_password() -> Result<String, String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+        std::io::stderr().flush().ok();
+    }
+    let mut line = String::new();
+    stdin.lock().read_line(&mut line).map_err(|e| format!("Cannot read the password: {}", e))?;
+    let password = line.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err("The password is empty".to_string());
+    }
+    Ok(password.to_string())
+}
+
+fn print_hash(args: &[String]) -> i32 {
+    let password = match args.first() {
+        Some(password) => password.clone(),
+        None => match read_password() {
+            Ok(password) => password,
+            Err(message) => {
+                eprintln!("{}", message);
+                return 1;
+            }
+        },
+    };
+    println!("{}", hash_password(&password));
+    0
+}
+
+/// Prints a user entry with a hashed password, or writes it into a htpasswd file.
+fn add_user(args: &[String]) -> i32 {
+    let mut positional = Vec::new();
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--file" => match args.next() {
+                Some(path) => file = Some(path.clone()),
+                None => {
+                    eprintln!("--file needs a path");
+                    return 2;
+                }
+            },
+            _ => positional.push(arg.as_str()),
+        }
+    }
+    let (name, api_key) = match positional.as_slice() {
+        [name] => (*name, None),
+        [name, api_key] => (*name, Some(*api_key)),
+        _ => {
+            eprintln!("Usage: abs_opds add-user NAME [API_KEY] [--file PATH]");
+            return 2;
+        }
+    };
+    // ':' separates the fields of an entry, ',' the entries of OPDS_USERS
+    if [Some(name), api_key].into_iter().flatten().any(|field| field.is_empty() || field.contains([':', ','])) {
+        eprintln!("Names and API keys can't be empty or contain ':' or ','");
+        return 2;
+    }
+    if api_key.is_some() && file.is_some() {
+        eprintln!("--file writes htpasswd lines, which take no API key");
+        return 2;
+    }
+
+    let hash = match read_password() {
+        Ok(password) => hash_password(&password),
+        Err(message) => {
+            eprintln!("{}", message);
+            return 1;
+        }
+    };
+    if let Some(api_key) = api_key {
+        println!("{}:{}:{}", name, api_key, hash);
+        eprintln!("Add the entry to OPDS_USERS, separated from the others by a comma.");
+        return 0;
+    }
+    let line = format!("{}:{}", name, hash);
+    let Some(path) = file else {
+        println!("{}", line);
+        return 0;
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return 1;
+        }
+    };
+    if let Err(e) = std::fs::write(&path, with_htpasswd_line(&content, name, &line)) {
+        eprintln!("Cannot write {}: {}", path, e);
+        return 1;
+    }
+    eprintln!("Saved {} in {}", name, path);
+    0
+}
+
+/// `content` of a htpasswd file with `line` for `name`, replacing the user's old line.
+pub(crate) fn with_htpasswd_line(content: &str, name: &str, line: &str) -> String {
+    let prefix = format!("{}:", name);
+    let mut lines: Vec<&str> = content.lines().filter(|l| !l.trim_start().starts_with(&prefix)).collect();
+    lines.push(line);
+    lines.join("\n") + "\n"
+}
+
+/// Prints missing/unused translation keys; exits with 1 if any language misses keys.
+fn i18n_report(args: &[String]) -> i32 {
+    dotenvy::dotenv().ok();
+    let dir = args
+        .first()
+        .cloned()
+        .or_else(|| std::env::var("LANGUAGES_DIR").ok())
+        .unwrap_or_else(|| "languages".to_string());
+
+    let reports = I18n::with_overrides(std::path::Path::new(&dir)).report();
+    let mut incomplete = false;
+    for report in &reports {
+        if report.missing.is_empty() && report.unused.is_empty() {
+            println!("{}: complete", report.language);
+            continue;
+        }
+        println!("{}: {} missing, {} unused", report.language, report.missing.len(), report.unused.len());
+        for key in &report.missing {
+            println!("  missing: {}", key);
+        }
+        for key in &report.unused {
+            println!("  unused:  {}", key);
+        }
+        incomplete |= !report.missing.is_empty();
+    }
+    if incomplete { 1 } else { 0 }
+}
@@ -0,0 +1,146 @@
+//! The optional configuration file (`CONFIG_FILE`), in TOML or YAML by its extension.
+//! Its keys are the environment variables, in either case, and environment variables
+//! override them. Lists of values are joined with commas and tables are passed on as
+//! JSON, so `OPDS_USER_MAP` or `OPDS_WEBHOOKS` can be written out instead of quoted;
+//! `users` lists the logins of `OPDS_USERS` one by one.
+//!
+//! Either source can name a file for any variable with `<NAME>_FILE`, as Docker secrets
+//! are mounted, and its content is the value.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+
+use crate::models::InternalUser;
+
+/// A login of the `users` list, as an entry of `OPDS_USERS` would give it.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileUser {
+    name: String,
+    api_key: String,
+    password: String,
+}
+
+/// What a configuration file sets.
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    /// Variables, keyed by lowercase name as `envy` reads them.
+    pub vars: Vec<(String, String)>,
+    pub users: Vec<InternalUser>,
+}
+
+/// Reads the file at `path`.
+pub fn read(path: &Path) -> anyhow::Result<ConfigFile> {
+    let text = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read CONFIG_FILE {}: {}", path.display(), e))?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let document: Value = match extension.as_str() {
+        "toml" => toml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid CONFIG_FILE {}: {}", path.display(), e))?,
+        "yaml" | "yml" => serde_yaml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid CONFIG_FILE {}: {}", path.display(), e))?,
+        _ => return Err(anyhow::anyhow!("CONFIG_FILE {} must end in .toml, .yaml or .yml", path.display())),
+    };
+    let Value::Object(entries) = document else {
+        return Err(anyhow::anyhow!("CONFIG_FILE {} must hold a table of settings", path.display()));
+    };
+
+    let mut file = ConfigFile::default();
+    for (key, value) in entries {
+        let key = key.to_lowercase();
+        if key == "users" {
+            let users: Vec<FileUser> = serde_json::from_value(value).map_err(|e| anyhow::anyhow!("Invalid users in CONFIG_FILE: {}", e))?;
+            file.users = users
+                .into_iter()
+                .map(|user| InternalUser { name: user.name, api_key: user.api_key, password: Some(user.password) })
+                .collect();
+            continue;
+        }
+        if let Some(value) = to_var(value) {
+            file.vars.push((key, value));
+        }
+    }
+    Ok(file)
+}
+
+/// `vars`, keyed by lowercase name, with each `<name>_file` of a setting replaced by
+/// `<name>` set to the trimmed content of the file it names. Other variables ending in
+/// `_FILE`, such as `SSL_CERT_FILE` or `HTPASSWD_FILE` itself, are left alone.
+pub fn read_secret_files(vars: Vec<(String, String)>) -> anyhow::Result<Vec<(String, String)>> {
+    let settings = setting_names();
+    let names: Vec<String> = vars.iter().map(|(key, _)| key.clone()).collect();
+    vars.into_iter()
+        .map(|(key, value)| {
+            let Some(name) = key.strip_suffix("_file").filter(|name| settings.contains(name) && !settings.contains(&key.as_str())) else {
+                return Ok((key, value));
+            };
+            if names.iter().any(|other| other == name) {
+                return Err(anyhow::anyhow!("Set either {} or {}, not both", name.to_uppercase(), key.to_uppercase()));
+            }
+            let content = std::fs::read_to_string(value.trim())
+                .map_err(|e| anyhow::anyhow!("Failed to read {} {}: {}", key.to_uppercase(), value.trim(), e))?;
+            Ok((name.to_string(), content.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The names of the settings, as `AppConfig` asks a deserializer for them.
+fn setting_names() -> &'static [&'static str] {
+    use serde::de::{self, Visitor};
+
+    #[derive(Debug)]
+    enum Probe {
+        Fields(&'static [&'static str]),
+        Other,
+    }
+
+    impl std::fmt::Display for Probe {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("not a struct")
+        }
+    }
+
+    impl std::error::Error for Probe {}
+
+    impl de::Error for Probe {
+        fn custom<T: std::fmt::Display>(_msg: T) -> Self {
+            Probe::Other
+        }
+    }
+
+    struct Fields;
+
+    impl<'de> de::Deserializer<'de> for Fields {
+        type Error = Probe;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Probe> {
+            Err(Probe::Other)
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], _visitor: V) -> Result<V::Value, Probe> {
+            Err(Probe::Fields(fields))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+            unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    static NAMES: std::sync::OnceLock<&'static [&'static str]> = std::sync::OnceLock::new();
+    NAMES.get_or_init(|| match crate::models::AppConfig::deserialize(Fields) {
+        Err(Probe::Fields(fields)) => fields,
+        _ => &[],
+    })
+}
+
+/// `value` as an environment variable would hold it, or `None` for null.
+fn to_var(value: Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s),
+        Value::Bool(_) | Value::Number(_) => Some(value.to_string()),
+        Value::Array(items) if items.iter().all(|item| !item.is_array() && !item.is_object()) => {
+            Some(items.into_iter().filter_map(to_var).collect::<Vec<_>>().join(","))
+        }
+        Value::Array(_) | Value::Object(_) => Some(value.to_string()),
+    }
+}
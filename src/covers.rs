@@ -0,0 +1,311 @@
+use axum::body::Bytes;
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use serde::Deserialize;
+use crate::cache::{self, CacheStore, MemoryStore};
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod glyphs;
+
+/// Upper bound for `?width=`, so a single request can't make us allocate huge buffers.
+pub const MAX_COVER_WIDTH: u32 = 2000;
+
+/// Book covers in a library's cover mosaic, laid out 2×2.
+pub const MOSAIC_COVERS: usize = 4;
+const MOSAIC_WIDTH: u32 = 400;
+const MOSAIC_HEIGHT: u32 = 600;
+
+const COVER_CACHE_TTL: Duration = Duration::from_secs(3600);
+const COVER_CACHE_MAX_ENTRIES: usize = 500;
+/// Covers rarely change, and on disk there is room to keep them for longer.
+const DISK_COVER_CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverFormat {
+    Jpeg,
+    Png,
+    Webp,
+    /// Grayscale JPEG, the smallest option for e-ink screens.
+    Grayscale,
+}
+
+impl CoverFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CoverFormat::Jpeg => "jpeg",
+            CoverFormat::Png => "png",
+            CoverFormat::Webp => "webp",
+            CoverFormat::Grayscale => "grayscale",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CoverQuery {
+    pub width: Option<u32>,
+    pub format: Option<CoverFormat>,
+}
+
+#[derive(Clone)]
+pub struct Cover {
+    pub bytes: Bytes,
+    pub content_type: &'static str,
+}
+
+/// Cache of transformed covers, keyed by user, item, width and format.
+#[derive(Clone)]
+pub struct CoverCache {
+    store: Arc<dyn CacheStore>,
+    ttl: Duration,
+}
+
+impl Default for CoverCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CoverCache {
+    pub fn new() -> Self {
+        Self::with_store(Arc::new(MemoryStore::new(COVER_CACHE_MAX_ENTRIES)))
+    }
+
+    pub fn with_store(store: Arc<dyn CacheStore>) -> Self {
+        cache::register("covers", "cover:", &store);
+        Self { store, ttl: COVER_CACHE_TTL }
+    }
+
+    /// Covers kept as files in `COVER_CACHE_DIR`, up to `max_bytes`, for a week.
+    pub fn on_disk(store: cache::DiskStore) -> Self {
+        Self { ttl: DISK_COVER_CACHE_TTL, ..Self::with_store(Arc::new(store)) }
+    }
+
+    /// The cache configured by `COVER_CACHE_DIR`, else in the `CACHE_URL` store, else
+    /// in memory.
+    pub fn from_config(config: &crate::models::AppConfig) -> Self {
+        let dir = config.cover_cache_dir.trim();
+        if !dir.is_empty() {
+            match cache::DiskStore::open(dir, config.cover_cache_max_mb * 1024 * 1024) {
+                Ok(store) => return Self::on_disk(store),
+                Err(e) => tracing::error!("Caching covers in memory, COVER_CACHE_DIR {} failed to open: {}", dir, e),
+            }
+        }
+        cache::open_shared(&config.cache_url).map(Self::with_store).unwrap_or_default()
+    }
+
+    pub fn key(api_key: &str, item_id: &str, query: &CoverQuery) -> String {
+        format!(
+            "cover:{}:{}:{}:{}",
+            cache::token_key(api_key),
+            item_id,
+            query.width.map(|w| w.to_string()).unwrap_or_default(),
+            query.format.map(|f| f.as_str()).unwrap_or_default()
+        )
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Cover> {
+        // Stored as the content type, a newline, then the image
+        let value = self.store.get(key).await;
+        crate::metrics::cache_lookup("covers", value.is_some());
+        let value = value?;
+        let split = value.iter().position(|&b| b == b'\n')?;
+        let content_type = match std::str::from_utf8(&value[..split]).ok()? {
+            "image/svg+xml" => "image/svg+xml",
+            mime => ImageFormat::from_mime_type(mime)?.to_mime_type(),
+        };
+        Some(Cover { bytes: Bytes::copy_from_slice(&value[split + 1..]), content_type })
+    }
+
+    pub async fn insert(&self, key: String, cover: Cover) {
+        let mut value = Vec::with_capacity(cover.content_type.len() + 1 + cover.bytes.len());
+        value.extend_from_slice(cover.content_type.as_bytes());
+        value.push(b'\n');
+        value.extend_from_slice(&cover.bytes);
+        self.store.set(&key, value, self.ttl).await;
+    }
+}
+
+const PLACEHOLDER_TEMPLATE: &str = include_str!("../assets/placeholder_cover.svg");
+const PLACEHOLDER_BACKGROUNDS: [&str; 6] = ["#3b4a6b", "#6b3b4a", "#3b6b5a", "#5a3b6b", "#6b5a3b", "#2f4f4f"];
+const PLACEHOLDER_LINE_CHARS: usize = 18;
+const PLACEHOLDER_MAX_LINES: usize = 5;
+
+fn placeholder_background(title: &str) -> &'static str {
+    PLACEHOLDER_BACKGROUNDS[title.bytes().map(usize::from).sum::<usize>() % PLACEHOLDER_BACKGROUNDS.len()]
+}
+
+/// Renders an SVG cover with the title and author for items that have no cover in ABS.
+/// The background colour is derived from the title so placeholders are stable but varied.
+pub fn placeholder(title: &str, author: Option<&str>) -> Cover {
+    let background = placeholder_background(title);
+
+    let lines = wrap_words(title, PLACEHOLDER_LINE_CHARS, PLACEHOLDER_MAX_LINES);
+    // Vertically centre the title block in the upper part of the cover
+    let title_y = 260 - (lines.len().saturating_sub(1) * 42 / 2);
+    let title_svg = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let dy = if i == 0 { "0" } else { "42" };
+            format!("<tspan x=\"200\" dy=\"{}\">{}</tspan>", dy, quick_xml::escape::escape(line.as_str()))
+        })
+        .collect::<String>();
+
+    let svg = PLACEHOLDER_TEMPLATE
+        .replace("{{background}}", background)
+        .replace("{{title_y}}", &title_y.to_string())
+        .replace("{{title}}", &title_svg)
+        .replace("{{author}}", &quick_xml::escape::escape(author.unwrap_or("")));
+
+    Cover {
+        bytes: Bytes::from(svg),
+        content_type: "image/svg+xml",
+    }
+}
+
+/// The placeholder for a cover request: the SVG one, or when a `?format=` is asked for,
+/// the same layout drawn as a raster image with the built-in bitmap font and encoded
+/// like any other cover.
+pub fn placeholder_for(title: &str, author: Option<&str>, query: &CoverQuery) -> anyhow::Result<Cover> {
+    if query.format.is_none() {
+        return Ok(placeholder(title, author));
+    }
+
+    let hex = u32::from_str_radix(placeholder_background(title).trim_start_matches('#'), 16)?;
+    let [_, r, g, b] = hex.to_be_bytes();
+    let background = image::Rgb([r, g, b]);
+    let white = image::Rgb([0xff, 0xff, 0xff]);
+    let blend = |opacity: u16| image::Rgb([r, g, b].map(|c| ((c as u16 * (100 - opacity) + 0xff * opacity) / 100) as u8));
+
+    let mut canvas = image::RgbImage::from_pixel(MOSAIC_WIDTH, MOSAIC_HEIGHT, background);
+    let frame = blend(60);
+    for (x, y, pixel) in canvas.enumerate_pixels_mut() {
+        let inside = (20..380).contains(&x) && (20..580).contains(&y);
+        let border = !(24..376).contains(&x) || !(24..576).contains(&y);
+        if inside && border {
+            *pixel = frame;
+        }
+    }
+
+    // Same block layout as the SVG, at sizes the 5×7 font fits within the frame
+    let lines = wrap_words(title, PLACEHOLDER_LINE_CHARS, PLACEHOLDER_MAX_LINES);
+    let title_top = 240 - (lines.len().saturating_sub(1) as u32 * 42 / 2);
+    for (i, line) in lines.iter().enumerate() {
+        glyphs::draw_centered(&mut canvas, line, 200, title_top + i as u32 * 42, 3, white);
+    }
+    if let Some(author) = author {
+        let author: String = author.chars().take(28).collect();
+        glyphs::draw_centered(&mut canvas, &author, 200, 486, 2, blend(85));
+    }
+
+    encode(DynamicImage::ImageRgb8(canvas), None, query)
+}
+
+fn wrap_words(text: &str, max_chars: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > max_chars {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        if let Some(last) = lines.last_mut() {
+            last.push('…');
+        }
+    }
+    lines
+}
+
+/// A library cover as JPEG: a single book cover filling it, or up to `MOSAIC_COVERS` in
+/// a 2×2 grid. Covers that can't be decoded are skipped; fails if none can.
+pub fn mosaic(covers: &[Vec<u8>]) -> anyhow::Result<Cover> {
+    let images: Vec<DynamicImage> = covers
+        .iter()
+        .filter_map(|bytes| image::load_from_memory(bytes).ok())
+        .take(MOSAIC_COVERS)
+        .collect();
+    if images.is_empty() {
+        return Err(anyhow::anyhow!("No decodable cover for a mosaic"));
+    }
+
+    let mut canvas = image::RgbImage::from_pixel(MOSAIC_WIDTH, MOSAIC_HEIGHT, image::Rgb([0x2f, 0x4f, 0x4f]));
+    if let [image] = images.as_slice() {
+        let tile = image.resize_to_fill(MOSAIC_WIDTH, MOSAIC_HEIGHT, FilterType::Triangle).to_rgb8();
+        image::imageops::overlay(&mut canvas, &tile, 0, 0);
+    } else {
+        let (width, height) = (MOSAIC_WIDTH / 2, MOSAIC_HEIGHT / 2);
+        for (i, image) in images.iter().enumerate() {
+            let tile = image.resize_to_fill(width, height, FilterType::Triangle).to_rgb8();
+            let (x, y) = ((i as u32 % 2) * width, (i as u32 / 2) * height);
+            image::imageops::overlay(&mut canvas, &tile, i64::from(x), i64::from(y));
+        }
+    }
+
+    let mut out = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(canvas).write_to(&mut out, ImageFormat::Jpeg)?;
+    Ok(Cover {
+        bytes: Bytes::from(out.into_inner()),
+        content_type: "image/jpeg",
+    })
+}
+
+/// Resizes and/or transcodes a cover image. Without a width or format the original
+/// bytes are returned untouched.
+pub fn transform(bytes: Vec<u8>, query: &CoverQuery) -> anyhow::Result<Cover> {
+    let source_format = image::guess_format(&bytes).ok();
+
+    if query.width.is_none() && query.format.is_none() {
+        let content_type = source_format.map(|f| f.to_mime_type()).unwrap_or("application/octet-stream");
+        return Ok(Cover { bytes: Bytes::from(bytes), content_type });
+    }
+
+    encode(image::load_from_memory(&bytes)?, source_format, query)
+}
+
+fn encode(mut img: DynamicImage, source_format: Option<ImageFormat>, query: &CoverQuery) -> anyhow::Result<Cover> {
+    if let Some(width) = query.width {
+        let width = width.clamp(1, MAX_COVER_WIDTH);
+        if width < img.width() {
+            let height = (img.height() as u64 * width as u64 / img.width() as u64).max(1) as u32;
+            img = img.resize_exact(width, height, FilterType::Triangle);
+        }
+    }
+
+    let target = match query.format {
+        Some(CoverFormat::Jpeg) => ImageFormat::Jpeg,
+        Some(CoverFormat::Png) => ImageFormat::Png,
+        Some(CoverFormat::Webp) => ImageFormat::WebP,
+        Some(CoverFormat::Grayscale) => {
+            img = DynamicImage::ImageLuma8(img.to_luma8());
+            ImageFormat::Jpeg
+        }
+        None => match source_format {
+            Some(f @ (ImageFormat::Png | ImageFormat::WebP)) => f,
+            _ => ImageFormat::Jpeg,
+        },
+    };
+
+    // JPEG has no alpha channel
+    if target == ImageFormat::Jpeg && img.color().has_alpha() {
+        img = DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, target)?;
+
+    Ok(Cover {
+        bytes: Bytes::from(out.into_inner()),
+        content_type: target.to_mime_type(),
+    })
+}
@@ -0,0 +1,32 @@
+//! The time budget of a request (`REQUEST_TIMEOUT_MS`). Fetching from ABS, filtering and
+//! building the feed all come out of the same budget; when it runs out the handler is
+//! dropped, which cancels the ABS calls still in flight, and the reader gets a 504
+//! instead of a connection held open for as long as ABS takes.
+
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::AppState;
+
+/// Gives every request `REQUEST_TIMEOUT_MS` to produce its response. Streamed bodies,
+/// such as downloads, are not limited once their headers are sent.
+pub async fn enforce(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let budget = state.config.request_timeout_ms;
+    if budget == 0 {
+        return next.run(req).await;
+    }
+    let headers = req.headers().clone();
+    let path = req.uri().path().to_string();
+    match tokio::time::timeout(Duration::from_millis(budget), next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("{} took longer than {} ms, cancelled", path, budget);
+            let message = format!("The request took longer than {} ms and was cancelled", budget);
+            crate::handlers::error_response(&state, &headers, StatusCode::GATEWAY_TIMEOUT, &message)
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! A built-in sample library served instead of Audiobookshelf when `DEMO_MODE` is on,
+//! for trying readers against the catalog or working on feeds offline.
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::api::{AbsClient, AuthClient, CatalogClient};
+use crate::error::{AppError, Result};
+use crate::models::{AbsItemResult, AbsItemsResponse, AbsLibrary, InternalUser};
+
+const DEMO_LIBRARY: &str = include_str!("../assets/demo_library.json");
+
+/// The token handed to demo users. Nothing checks it.
+pub const DEMO_TOKEN: &str = "demo";
+
+#[derive(Deserialize)]
+struct DemoLibrary {
+    libraries: Vec<AbsLibrary>,
+    /// Keyed by library ID.
+    items: HashMap<String, Vec<AbsItemResult>>,
+}
+
+/// Serves the sample library from `assets/demo_library.json`. Any credentials log in;
+/// there are no covers, so readers see the generated placeholders, and no files.
+pub struct DemoClient {
+    library: DemoLibrary,
+}
+
+impl DemoClient {
+    pub fn new() -> Self {
+        let library = serde_json::from_str(DEMO_LIBRARY).expect("the bundled demo library is valid");
+        Self { library }
+    }
+}
+
+impl Default for DemoClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AbsClient for DemoClient {}
+
+#[async_trait]
+impl AuthClient for DemoClient {
+    async fn login(&self, username: &str, _password: &str) -> Result<InternalUser> {
+        Ok(InternalUser { name: username.to_string(), api_key: DEMO_TOKEN.to_string(), password: None })
+    }
+
+    async fn verify_token(&self, _token: &str) -> Result<InternalUser> {
+        Err(anyhow::anyhow!("The demo library has no ABS tokens").into())
+    }
+}
+
+#[async_trait]
+impl CatalogClient for DemoClient {
+    async fn get_libraries(&self, _user: &InternalUser) -> Result<Vec<AbsLibrary>> {
+        Ok(self.library.libraries.clone())
+    }
+
+    async fn get_library(&self, _user: &InternalUser, library_id: &str) -> Result<AbsLibrary> {
+        self.library
+            .libraries
+            .iter()
+            .find(|library| library.id == library_id)
+            .cloned()
+            .ok_or_else(|| AppError::upstream("Failed to fetch library details", reqwest::StatusCode::NOT_FOUND))
+    }
+
+    async fn get_items(&self, _user: &InternalUser, library_id: &str) -> Result<AbsItemsResponse> {
+        let results = self
+            .library
+            .items
+            .get(library_id)
+            .cloned()
+            .ok_or_else(|| AppError::upstream("Failed to fetch library items", reqwest::StatusCode::NOT_FOUND))?;
+        Ok(AbsItemsResponse { results })
+    }
+
+    async fn get_item(&self, _user: &InternalUser, item_id: &str) -> Result<AbsItemResult> {
+        self.library
+            .items
+            .values()
+            .flatten()
+            .find(|item| item.id == item_id)
+            .cloned()
+            .ok_or_else(|| AppError::upstream("Failed to fetch library item", reqwest::StatusCode::NOT_FOUND))
+    }
+
+    async fn get_cover(&self, _user: &InternalUser, _item_id: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,92 @@
+use crate::models::AbsItemResult;
+use axum::http::{header, HeaderMap, HeaderValue};
+use unicode_normalization::UnicodeNormalization;
+
+const MAX_FILENAME_CHARS: usize = 150;
+
+/// Builds "Author - Title" from item metadata, falling back to the item ID when
+/// the title is missing.
+pub fn download_basename(item: &AbsItemResult) -> String {
+    let metadata = &item.media.metadata;
+    let title = metadata.title.as_deref().map(str::trim).filter(|t| !t.is_empty());
+    let author = metadata.author_name.as_deref().map(str::trim).filter(|a| !a.is_empty());
+    match (author, title) {
+        (Some(author), Some(title)) => format!("{} - {}", author, title),
+        (None, Some(title)) => title.to_string(),
+        _ => item.id.clone(),
+    }
+}
+
+/// Removes characters that are invalid in file names on common file systems and
+/// collapses whitespace.
+pub fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => ' ',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    let collapsed = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    let trimmed = collapsed.trim_matches(|c: char| c == '.' || c == ' ');
+    trimmed.chars().take(MAX_FILENAME_CHARS).collect::<String>().trim_end().to_string()
+}
+
+/// Picks a file extension for a download, preferring whatever ABS itself announced.
+pub fn extension_for(upstream: &HeaderMap, fallback: Option<&str>) -> Option<String> {
+    let from_disposition = upstream
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit_once('.'))
+        .map(|(_, ext)| ext.trim_end_matches(['"', ';', ' ']).to_ascii_lowercase())
+        .filter(|ext| !ext.is_empty() && ext.len() <= 5 && ext.chars().all(|c| c.is_ascii_alphanumeric()));
+    if from_disposition.is_some() {
+        return from_disposition;
+    }
+
+    let from_content_type = upstream
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(crate::mime::extension);
+    from_content_type.or(fallback).map(str::to_string)
+}
+
+/// Builds an `attachment` Content-Disposition with an ASCII fallback name and an
+/// RFC 5987 encoded UTF-8 name for clients that support it.
+pub fn content_disposition(basename: &str, extension: Option<&str>) -> Option<HeaderValue> {
+    let name = sanitize_filename(basename);
+    if name.is_empty() {
+        return None;
+    }
+    let filename = match extension {
+        Some(ext) => format!("{}.{}", name, ext),
+        None => name,
+    };
+
+    let ascii_fallback: String = filename
+        .nfd()
+        .filter(|c| !crate::xml::is_combining_mark(*c))
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+
+    HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii_fallback,
+        percent_encode_attr(&filename)
+    ))
+    .ok()
+}
+
+fn percent_encode_attr(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() * 3);
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
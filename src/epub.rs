@@ -0,0 +1,140 @@
+use crate::models::AbsMetadata;
+use crate::series::SeriesParser;
+use std::io::{Cursor, Read, Write};
+use zip::{ZipArchive, ZipWriter};
+
+/// EPUBs larger than this are served untouched instead of being buffered for rewriting.
+pub const MAX_EPUB_REWRITE_BYTES: usize = 100 * 1024 * 1024;
+
+/// The ABS metadata we inject into the package document of downloaded EPUBs.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EpubMetadata {
+    pub series: Option<String>,
+    pub sequence: Option<String>,
+    pub isbn: Option<String>,
+    pub description: Option<String>,
+}
+
+impl EpubMetadata {
+    pub fn from_abs(metadata: &AbsMetadata, series: &SeriesParser) -> Self {
+        // seriesName looks like "Mistborn #1, Cosmere #3"; the first series wins
+        let (series, sequence) = metadata
+            .series_name
+            .as_deref()
+            .and_then(|s| series.entries(s).next())
+            .map_or((None, None), |(name, seq)| (Some(name.into_owned()), seq.map(str::to_string)));
+
+        Self {
+            series,
+            sequence,
+            isbn: metadata.isbn.clone().filter(|s| !s.trim().is_empty()),
+            description: metadata.description.clone().filter(|s| !s.trim().is_empty()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_none() && self.isbn.is_none() && self.description.is_none()
+    }
+}
+
+/// Rewrites the OPF package document of an EPUB, adding series, ISBN and description
+/// metadata that is not already present. All other entries are copied verbatim.
+pub fn embed_metadata(epub: &[u8], metadata: &EpubMetadata) -> anyhow::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(epub))?;
+
+    let opf_path = {
+        let mut container = String::new();
+        archive.by_name("META-INF/container.xml")?.read_to_string(&mut container)?;
+        find_rootfile(&container).ok_or_else(|| anyhow::anyhow!("No rootfile in container.xml"))?
+    };
+
+    let opf = {
+        let mut opf = String::new();
+        archive.by_name(&opf_path)?.read_to_string(&mut opf)?;
+        opf
+    };
+    let patched_opf = patch_opf(&opf, metadata)?;
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::with_capacity(epub.len() + 1024)));
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if entry.name() == opf_path {
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated);
+            drop(entry);
+            writer.start_file(opf_path.as_str(), options)?;
+            writer.write_all(patched_opf.as_bytes())?;
+        } else {
+            // Raw copy keeps the stored `mimetype` entry first and uncompressed, as EPUB requires
+            writer.raw_copy_file(entry)?;
+        }
+    }
+
+    Ok(writer.finish()?.into_inner())
+}
+
+fn find_rootfile(container: &str) -> Option<String> {
+    static ROOTFILE_REGEX: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let regex = ROOTFILE_REGEX.get_or_init(|| {
+        regex::Regex::new(r#"<(?:\w+:)?rootfile[^>]*\sfull-path\s*=\s*"([^"]+)""#).expect("Failed to compile regex")
+    });
+    regex.captures(container).map(|c| c[1].to_string())
+}
+
+pub(crate) fn patch_opf(opf: &str, metadata: &EpubMetadata) -> anyhow::Result<String> {
+    static METADATA_END_REGEX: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let regex = METADATA_END_REGEX.get_or_init(|| {
+        regex::Regex::new(r"</(?:\w+:)?metadata\s*>").expect("Failed to compile regex")
+    });
+    let end = regex
+        .find(opf)
+        .ok_or_else(|| anyhow::anyhow!("No metadata element in OPF"))?
+        .start();
+
+    let escape = |s: &str| quick_xml::escape::escape(s).to_string();
+    let mut additions = String::new();
+
+    if let Some(isbn) = &metadata.isbn {
+        let digits: String = isbn.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        let existing: String = opf.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        if !existing.contains(&digits) {
+            additions.push_str(&format!(
+                "<dc:identifier id=\"abs-isbn\">urn:isbn:{}</dc:identifier>\n",
+                escape(isbn.trim_start_matches("urn:isbn:"))
+            ));
+        }
+    }
+
+    if let Some(description) = &metadata.description {
+        if !opf.contains("<dc:description") {
+            additions.push_str(&format!("<dc:description>{}</dc:description>\n", escape(description)));
+        }
+    }
+
+    if let Some(series) = &metadata.series {
+        if !opf.contains("calibre:series") {
+            additions.push_str(&format!("<meta name=\"calibre:series\" content=\"{}\"/>\n", escape(series)));
+            if let Some(sequence) = &metadata.sequence {
+                additions.push_str(&format!("<meta name=\"calibre:series_index\" content=\"{}\"/>\n", escape(sequence)));
+            }
+        }
+        if !opf.contains("belongs-to-collection") {
+            additions.push_str(&format!(
+                "<meta property=\"belongs-to-collection\" id=\"abs-series\">{}</meta>\n<meta refines=\"#abs-series\" property=\"collection-type\">series</meta>\n",
+                escape(series)
+            ));
+            if let Some(sequence) = &metadata.sequence {
+                additions.push_str(&format!(
+                    "<meta refines=\"#abs-series\" property=\"group-position\">{}</meta>\n",
+                    escape(sequence)
+                ));
+            }
+        }
+    }
+
+    let mut patched = String::with_capacity(opf.len() + additions.len());
+    patched.push_str(&opf[..end]);
+    patched.push_str(&additions);
+    patched.push_str(&opf[end..]);
+    Ok(patched)
+}
@@ -0,0 +1,75 @@
+//! The crate-wide error type and how it maps to HTTP statuses.
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+pub type Result<T, E = AppError> = std::result::Result<T, E>;
+
+/// How many seconds readers are asked to wait before trying again while ABS is down.
+pub const RETRY_AFTER_SECS: u64 = 30;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    /// ABS answered, but not with a success status.
+    #[error("{context}: status {status}{}", hint(*.status))]
+    Upstream { context: &'static str, status: StatusCode },
+    /// ABS could not be reached, or answered with something we could not read.
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl AppError {
+    pub fn upstream(context: &'static str, status: reqwest::StatusCode) -> Self {
+        let status = StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        AppError::Upstream { context, status }
+    }
+
+    /// Whether ABS turned down the credentials or token, as opposed to not answering.
+    pub fn is_rejection(&self) -> bool {
+        matches!(self, AppError::Upstream { status: StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN, .. })
+    }
+
+    /// The status to answer the client with. Rejected credentials, denied permissions and
+    /// missing libraries or items are passed on as they are, and an ABS that can't be reached or says it's
+    /// unavailable is a 503; any other upstream failure is a bad gateway.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Upstream { status, .. } => match *status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND | StatusCode::SERVICE_UNAVAILABLE => *status,
+                _ => StatusCode::BAD_GATEWAY,
+            },
+            AppError::Http(e) if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+            AppError::Http(e) if e.is_connect() => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Http(_) => StatusCode::BAD_GATEWAY,
+            AppError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// What the reader can do about an upstream status, so a wrong library ID or a revoked
+/// permission doesn't read like a bug.
+fn hint(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::FORBIDDEN => " (ABS doesn't allow this user to access it, check the user's permissions in ABS)",
+        StatusCode::NOT_FOUND => " (ABS has no library or item with this ID)",
+        _ => "",
+    }
+}
+
+/// Plain-text fallback for endpoints that don't serve feeds. Feed handlers go through
+/// `handlers::error_response`, which answers in the client's language and OPDS version.
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        with_retry_after((self.status(), self.to_string()).into_response())
+    }
+}
+
+/// Asks readers to back off for [`RETRY_AFTER_SECS`] if `response` is a 503.
+pub fn with_retry_after(mut response: Response) -> Response {
+    if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+        response.headers_mut().insert(axum::http::header::RETRY_AFTER, RETRY_AFTER_SECS.into());
+    }
+    response
+}
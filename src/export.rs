@@ -0,0 +1,156 @@
+//! `abs_opds export`: crawls the Atom catalog from its root and writes every feed, and
+//! optionally every cover, to static files that link each other by relative paths.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::PathBuf;
+use tower::ServiceExt;
+
+pub struct ExportOptions {
+    /// Where the files are written.
+    pub out: PathBuf,
+    /// Also writes the covers, as `covers/{item}.jpg`.
+    pub covers: bool,
+    /// The ABS API key the catalog is read with, sent as `?token=`.
+    pub token: String,
+    /// Where links that aren't exported, such as downloads, point, e.g. the live catalog's
+    /// `https://books.example.com`. Without it they stay as they are.
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ExportSummary {
+    pub feeds: usize,
+    pub covers: usize,
+}
+
+/// The catalog paths that are exported: feeds and search descriptions, not downloads.
+fn is_feed(href: &str) -> bool {
+    let path = href.split('?').next().unwrap_or("");
+    (path == "/opds" || path.starts_with("/opds/libraries/"))
+        && !path.ends_with("/cover")
+        && !href.contains("{searchTerms}")
+}
+
+/// The item of an ABS cover link, e.g. `/opds/proxy/api/items/{id}/cover`.
+fn cover_item(href: &str) -> Option<&str> {
+    let path = href.split('?').next().unwrap_or("");
+    path.strip_suffix("/cover")?.rsplit_once("/api/items/").map(|(_, id)| id).filter(|id| !id.contains('/'))
+}
+
+fn sanitize(segment: &str) -> String {
+    segment.chars().map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect()
+}
+
+/// The file of a feed, relative to the export: `/opds/libraries/lib1?categories=true`
+/// becomes `opds/libraries/lib1-{hash of the query}.xml`, as queries hold arbitrary names.
+fn feed_file(href: &str) -> String {
+    let (path, query) = href.split_once('?').unwrap_or((href, ""));
+    let path = path.trim_matches('/').split('/').map(sanitize).collect::<Vec<_>>().join("/");
+    if query.is_empty() {
+        format!("{}.xml", path)
+    } else {
+        format!("{}-{}.xml", path, &crate::cache::token_key(query)[..12])
+    }
+}
+
+/// `href` without its `token` parameter, so exports don't give away the API key.
+fn without_token(href: &str) -> String {
+    let Some((path, query)) = href.split_once('?') else { return href.to_string() };
+    let query: Vec<&str> = query.split('&').filter(|param| !param.starts_with("token=")).collect();
+    if query.is_empty() { path.to_string() } else { format!("{}?{}", path, query.join("&")) }
+}
+
+/// `target` relative to the directory of `from`, both relative to the export.
+fn relative(from: &str, target: &str) -> String {
+    format!("{}{}", "../".repeat(from.matches('/').count()), target)
+}
+
+/// Writes the catalog `app` serves to `options.out`.
+pub async fn export(app: Router, options: &ExportOptions) -> anyhow::Result<ExportSummary> {
+    let href_attribute = Regex::new(r#"href="([^"]*)""#).expect("the href pattern is valid");
+    let mut summary = ExportSummary::default();
+    let mut queued: BTreeSet<String> = BTreeSet::from(["/opds".to_string()]);
+    let mut queue: VecDeque<String> = VecDeque::from(["/opds".to_string()]);
+    let mut covers: HashMap<String, String> = HashMap::new();
+
+    while let Some(href) = queue.pop_front() {
+        let Some(xml) = fetch(&app, &href, &options.token).await? else { continue };
+        let xml = String::from_utf8_lossy(&xml).into_owned();
+        let file = feed_file(&href);
+
+        let rewritten = href_attribute.replace_all(&xml, |captures: &regex::Captures| {
+            let link = quick_xml::escape::unescape(&captures[1]).map(|l| l.into_owned()).unwrap_or_else(|_| captures[1].to_string());
+            let target = if is_feed(&link) {
+                if queued.insert(link.clone()) {
+                    queue.push_back(link.clone());
+                }
+                relative(&file, &feed_file(&link))
+            } else if let Some(item) = cover_item(&link).filter(|_| options.covers) {
+                let cover = covers.entry(item.to_string()).or_insert_with(|| format!("covers/{}.jpg", sanitize(item)));
+                relative(&file, cover)
+            } else {
+                let link = without_token(&link);
+                match &options.base_url {
+                    Some(base) if link.starts_with('/') => format!("{}{}", base.trim_end_matches('/'), link),
+                    _ => link,
+                }
+            };
+            format!("href=\"{}\"", quick_xml::escape::escape(&target))
+        });
+        write(options, &file, rewritten.as_bytes())?;
+        summary.feeds += 1;
+    }
+
+    for (item, file) in &covers {
+        let href = format!("/opds/covers/{}?format=jpeg", item);
+        if let Some(bytes) = fetch(&app, &href, &options.token).await? {
+            write(options, file, &bytes)?;
+            summary.covers += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// `href` with the characters feeds leave in names, like spaces, percent-encoded.
+fn encode_uri(href: &str) -> String {
+    href.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=%".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+/// A catalog response, or `None` for pages that are gone, which are logged and skipped.
+async fn fetch(app: &Router, href: &str, token: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    let separator = if href.contains('?') { '&' } else { '?' };
+    let request = Request::builder()
+        .uri(format!("{}{}token={}", encode_uri(href), separator, token))
+        .header("Accept", "application/atom+xml")
+        .body(Body::empty())?;
+    let response = app.clone().oneshot(request).await?;
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED {
+        anyhow::bail!("The catalog rejected the export's API key");
+    }
+    if !status.is_success() {
+        tracing::warn!("Skipping {}: {}", href, status);
+        return Ok(None);
+    }
+    Ok(Some(axum::body::to_bytes(response.into_body(), usize::MAX).await?.to_vec()))
+}
+
+fn write(options: &ExportOptions, file: &str, bytes: &[u8]) -> anyhow::Result<()> {
+    let path = options.out.join(file);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| anyhow::anyhow!("Cannot create {}: {}", dir.display(), e))?;
+    }
+    std::fs::write(&path, bytes).map_err(|e| anyhow::anyhow!("Cannot write {}: {}", path.display(), e))
+}
@@ -1,5 +1,13 @@
+use crate::archive;
 use crate::auth::AuthUser;
-use crate::models::ItemType;
+use crate::covers::{self, CoverCache, CoverQuery};
+use crate::downloads;
+use crate::epub::{self, EpubMetadata};
+use crate::error::{with_retry_after, AppError};
+use crate::metrics;
+use crate::models::{AbsItemResult, InternalUser, ItemType};
+use crate::opds::model::{Feed, PROXY_PATH};
+use crate::series::SeriesParser;
 use crate::xml::OpdsBuilder;
 use crate::opds2::Opds2Builder;
 use crate::AppState;
@@ -9,24 +17,76 @@
     response::{IntoResponse, Response},
     body::Body,
 };
+use futures_util::StreamExt;
 use std::sync::Arc;
 use sha1_smol::Sha1;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Default)]
 pub struct LibraryQuery {
     pub categories: Option<String>,
     #[serde(default)]
     pub page: usize,
     pub q: Option<String>,
     pub author: Option<String>,
+    pub narrator: Option<String>,
     pub title: Option<String>,
     pub name: Option<String>,
     #[serde(rename = "type")]
     pub type_: Option<ItemType>,
     pub start: Option<String>,
+    /// Items per page, overriding `OPDS_PAGE_SIZE` up to `MAX_PAGE_SIZE`.
+    pub limit: Option<usize>,
+    /// Name of a virtual shelf from `OPDS_SHELVES` whose filter applies.
+    pub shelf: Option<String>,
+    /// Only items added or changed after this time, as RFC 3339 or milliseconds since
+    /// the epoch, for clients syncing what's new.
+    pub updated_since: Option<String>,
+    /// An order such as `title` or `added:desc`, replacing the one of the feed.
+    pub sort: Option<String>,
+    /// Only items of an ebook format such as `epub`, or `audiobook`.
+    pub format: Option<String>,
+    /// Lists the most recently updated items first; set by the `updated` route.
+    #[serde(skip)]
+    pub recently_updated: bool,
+    /// Lists the newest items, up to `OPDS_RECENT_LIMIT`; set by the `recent` route.
+    #[serde(skip)]
+    pub recently_added: bool,
+    /// Lists the items the user is reading, most recently read first; set by the
+    /// `continue` route.
+    #[serde(skip)]
+    pub in_progress: bool,
+    /// Id of the ABS collection whose books are listed, in its order; set by the
+    /// `collections/{id}` route.
+    #[serde(skip)]
+    pub collection: Option<String>,
+}
+
+/// The path of a library's publication feed, without query parameters.
+fn items_feed_path(library_id: &str, query: &LibraryQuery) -> String {
+    if query.recently_updated {
+        format!("/opds/libraries/{}/updated", library_id)
+    } else if query.recently_added {
+        format!("/opds/libraries/{}/recent", library_id)
+    } else if query.in_progress {
+        format!("/opds/libraries/{}/continue", library_id)
+    } else if let Some(collection) = &query.collection {
+        format!("/opds/libraries/{}/collections/{}", library_id, collection)
+    } else {
+        format!("/opds/libraries/{}", library_id)
+    }
+}
+
+/// Whether the reader asked for fresh data with `Cache-Control: no-cache`, as browsers
+/// do on a hard reload.
+fn wants_refresh(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(axum::http::header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .any(|value| value.split(',').any(|directive| directive.trim().eq_ignore_ascii_case("no-cache")))
 }
 
-fn wants_opds_v2(headers: &HeaderMap) -> bool {
+pub(crate) fn wants_opds_v2(headers: &HeaderMap) -> bool {
     if let Some(accept) = headers.get(axum::http::header::ACCEPT).and_then(|h| h.to_str().ok()) {
         accept.contains("application/opds+json")
     } else {
@@ -34,21 +94,131 @@ fn wants_opds_v2(headers: &HeaderMap) -> bool {
     }
 }
 
+/// `feed` as OPDS 1, streamed as it is written, or 304 Not Modified if the reader's copy
+/// has the same ETag. The ETag and length are measured in a first pass, so HEAD requests
+/// and conditional GETs are answered without holding the document.
+fn feed_response(headers: &HeaderMap, feed: Feed) -> Response {
+    let (etag, length) = match OpdsBuilder::measure_feed(&feed) {
+        Ok(measured) => measured,
+        Err(e) => {
+            tracing::error!("Failed to write feed: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if headers.get(axum::http::header::IF_NONE_MATCH).and_then(|h| h.to_str().ok()) == Some(etag.as_str()) {
+        return StatusCode::NOT_MODIFIED.into_response();
+    }
+    let etag_value = axum::http::HeaderValue::try_from(etag).unwrap();
+    (
+        [
+            (axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static(feed.kind.content_type())),
+            (axum::http::header::ETAG, etag_value),
+            (axum::http::header::CONTENT_LENGTH, axum::http::HeaderValue::from(length)),
+        ],
+        OpdsBuilder::stream_feed(feed),
+    )
+        .into_response()
+}
+
+/// An error readers can display: an OPDS 1 feed with a single error entry, or an
+/// RFC 7807 problem document for OPDS 2 clients.
+pub(crate) fn error_response(state: &AppState, headers: &HeaderMap, status: StatusCode, message: &str) -> Response {
+    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
+    if wants_opds_v2(headers) {
+        let title = state.i18n.localize_with("feed.error", lang, &[("message", status.canonical_reason().unwrap_or("Error"))]);
+        let json = Opds2Builder::build_problem(status.as_u16(), &title, message);
+        return with_retry_after((status, [(axum::http::header::CONTENT_TYPE, "application/problem+json")], json).into_response());
+    }
+    let xml = OpdsBuilder::build_error_feed(message, &state.i18n, lang).unwrap_or_default();
+    with_retry_after((status, [(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")], xml).into_response())
+}
+
+/// The landing page, linking the catalog below the path it is served at.
+pub async fn landing_page(
+    State(state): State<Arc<AppState>>,
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    headers: HeaderMap,
+) -> Response {
+    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
+    let base = uri.path().trim_end_matches('/');
+    let public = !state.config.opds_public_library.is_empty();
+    let html = crate::landing::render(base, &crate::landing::request_origin(&headers), public, &state.i18n, lang);
+    ([(axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response()
+}
+
+pub async fn health() -> &'static str {
+    "OK"
+}
+
+pub async fn metrics() -> impl IntoResponse {
+    ([(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics::render().await)
+}
+
+/// Keys, sizes and ages of the cached entries, to check whether stale data is served.
+pub async fn admin_cache() -> impl IntoResponse {
+    let mut caches = Vec::new();
+    for cache in crate::cache::registered() {
+        let mut entries = cache.store.entries(cache.prefix).await;
+        if let Some(entries) = &mut entries {
+            entries.sort_by(|a, b| a.key.cmp(&b.key));
+        }
+        caches.push(serde_json::json!({
+            "name": cache.name,
+            // Stores that can't list their entries cheaply, like Redis, aren't listed
+            "listed": entries.is_some(),
+            "entries": entries.unwrap_or_default().iter().map(|entry| serde_json::json!({
+                "key": entry.key,
+                "size": entry.size,
+                "age_secs": entry.age.map(|age| age.as_secs()),
+                "expires_in_secs": entry.expires_in.as_secs(),
+            })).collect::<Vec<_>>(),
+        }));
+    }
+    ([(axum::http::header::CONTENT_TYPE, "application/json")], serde_json::json!({ "caches": caches }).to_string())
+}
+
+/// Reloads the logins, as SIGHUP does, so added users can log in without a restart.
+pub async fn admin_reload(State(state): State<Arc<AppState>>) -> Response {
+    match state.reload() {
+        Ok(users) => {
+            tracing::info!("Reloaded the configuration, {} users", users);
+            ([(axum::http::header::CONTENT_TYPE, "application/json")], serde_json::json!({ "users": users }).to_string()).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Kept the logins, reloading the configuration failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Drops the ABS login cached for a user, e.g. after their password or permissions
+/// changed in ABS, so their next request logs in again. Readers of `OPDS_USER_MAP`
+/// drop the login of the ABS user they are mapped to.
+pub async fn forget_login(State(state): State<Arc<AppState>>, Path(username): Path<String>) -> Response {
+    let logins = state.logins();
+    let abs_username = logins.abs_username(&username);
+    let forgotten = state.api_client.forget_login(abs_username).await;
+    tracing::info!("Forgot the cached ABS login of {} ({}): {}", username, abs_username, forgotten);
+    ([(axum::http::header::CONTENT_TYPE, "application/json")], serde_json::json!({ "user": username, "forgotten": forgotten }).to_string())
+        .into_response()
+}
+
 pub async fn get_opds_root(
     State(state): State<Arc<AppState>>,
     AuthUser(user): AuthUser,
     headers: HeaderMap,
 ) -> Response {
+    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
     match state.service.get_libraries(&user).await {
         Ok(libraries) => {
             let updated_time = chrono::Utc::now().to_rfc3339();
             if wants_opds_v2(&headers) {
-                let json = if libraries.len() == 1 {
-                    let library_id = &libraries[0].id;
-                    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
-                    Opds2Builder::build_categories_root(library_id, &state.i18n, lang, &updated_time)
+                let json = if let Some(library) = state.service.root_library(&user, &libraries) {
+                    let library_id = &library.id;
+                    let empty = state.service.empty_categories(&user, library_id).await;
+                    Opds2Builder::build_categories_root(library_id, library.media_type, &empty, &state.config.shelves, &state.i18n, lang, &updated_time)
                 } else {
-                    Opds2Builder::build_root(&libraries, &updated_time)
+                    Opds2Builder::build_root(&libraries, &state.i18n, lang, &updated_time)
                 };
 
                 let etag = {
@@ -71,82 +241,76 @@ pub async fn get_opds_root(
                 ).into_response();
             }
 
-            if libraries.len() == 1 {
-                 let library_id = &libraries[0].id;
-                 let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
-                 let xml = OpdsBuilder::build_opds_skeleton(
-                     &format!("urn:uuid:{}", library_id),
-                     "Categories",
-                     OpdsBuilder::build_category_entries(library_id, &state.i18n, lang, &updated_time),
-                     None,
-                     None,
-                     None,
-                     &format!("/opds/libraries/{}", library_id),
-                     false,
-                 ).unwrap_or_else(|_| String::new());
-
-                 let etag = {
-                     let mut hasher = Sha1::new();
-                     hasher.update(xml.as_bytes());
-                     format!("W/\"{}\"", hasher.digest())
-                 };
-                 if let Some(if_none_match) = headers.get(axum::http::header::IF_NONE_MATCH).and_then(|h| h.to_str().ok()) {
-                     if if_none_match == etag {
-                         return StatusCode::NOT_MODIFIED.into_response();
-                     }
-                 }
-                 let etag_value = axum::http::HeaderValue::try_from(etag).unwrap();
-                 return (
-                     [
-                         (axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/atom+xml;profile=opds-catalog;kind=navigation")),
-                         (axum::http::header::ETAG, etag_value),
-                     ],
-                     xml,
-                 ).into_response();
+            if let Some(library) = state.service.root_library(&user, &libraries) {
+                 let library_id = &library.id;
+                 let feed = state.service.categories_root_feed(&user, library_id, library.media_type, lang).await;
+                 return feed_response(&headers, feed);
             }
 
-            let mut hasher = Sha1::new();
-            hasher.update(user.name.as_bytes());
-            let user_hash = hasher.digest().to_string();
-
-            let xml = OpdsBuilder::build_opds_skeleton(
-                &user_hash,
-                &format!("{}'s Libraries", user.name),
-                OpdsBuilder::build_library_entry_list(&libraries, &updated_time),
-                None,
-                Some(&user),
-                None,
-                "/opds",
-                false,
-            ).unwrap_or_else(|_| String::new());
- 
-            let etag = {
-                let mut hasher = Sha1::new();
-                hasher.update(xml.as_bytes());
-                format!("W/\"{}\"", hasher.digest())
-            };
-            if let Some(if_none_match) = headers.get(axum::http::header::IF_NONE_MATCH).and_then(|h| h.to_str().ok()) {
-                if if_none_match == etag {
-                    return StatusCode::NOT_MODIFIED.into_response();
-                }
-            }
-            let etag_value = axum::http::HeaderValue::try_from(etag).unwrap();
-            return (
-                [
-                    (axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/atom+xml;profile=opds-catalog;kind=navigation")),
-                    (axum::http::header::ETAG, etag_value),
-                ],
-                xml,
-            ).into_response();
+            let feed = state.service.libraries_feed(&user, &libraries, lang).await;
+            feed_response(&headers, feed)
         }
         Err(e) => {
             tracing::error!("Failed to fetch libraries: {}", e);
-            let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to fetch libraries: {}", e)).unwrap_or_default();
-            ([(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")], error_xml).into_response()
+            error_response(&state, &headers, e.status(), &format!("Failed to fetch libraries: {}", e))
         }
     }
 }
 
+/// The books of a library, most recently updated in ABS first, so metadata fixes and
+/// newly added files show up in catalogs.
+pub async fn get_recently_updated(
+    state: State<Arc<AppState>>,
+    user: AuthUser,
+    library_id: Path<String>,
+    Query(mut query): Query<LibraryQuery>,
+    headers: HeaderMap,
+) -> Response {
+    query.recently_updated = true;
+    query.categories = None;
+    get_library(state, user, library_id, Query(query), headers).await
+}
+
+/// The newest books of a library, by when they were added to ABS, for a "New books" shelf.
+pub async fn get_recently_added(
+    state: State<Arc<AppState>>,
+    user: AuthUser,
+    library_id: Path<String>,
+    Query(mut query): Query<LibraryQuery>,
+    headers: HeaderMap,
+) -> Response {
+    query.recently_added = true;
+    query.categories = None;
+    get_library(state, user, library_id, Query(query), headers).await
+}
+
+/// The books of a library the user started and hasn't finished, for a "Continue
+/// reading" shelf.
+pub async fn get_in_progress(
+    state: State<Arc<AppState>>,
+    user: AuthUser,
+    library_id: Path<String>,
+    Query(mut query): Query<LibraryQuery>,
+    headers: HeaderMap,
+) -> Response {
+    query.in_progress = true;
+    query.categories = None;
+    get_library(state, user, library_id, Query(query), headers).await
+}
+
+/// The books of one ABS collection, in the order it was arranged in.
+pub async fn get_collection(
+    state: State<Arc<AppState>>,
+    user: AuthUser,
+    Path((library_id, collection_id)): Path<(String, String)>,
+    Query(mut query): Query<LibraryQuery>,
+    headers: HeaderMap,
+) -> Response {
+    query.collection = Some(collection_id);
+    query.categories = None;
+    get_library(state, user, Path(library_id), Query(query), headers).await
+}
+
 pub async fn get_library(
     State(state): State<Arc<AppState>>,
     AuthUser(user): AuthUser,
@@ -156,10 +320,24 @@ pub async fn get_library(
 ) -> Response {
     let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
     let updated_time = chrono::Utc::now().to_rfc3339();
+    if let Some(shelf) = query.shelf.as_deref().filter(|name| state.config.shelf(name).is_none()) {
+        return error_response(&state, &headers, StatusCode::NOT_FOUND, &format!("Unknown shelf: {}", shelf));
+    }
+    if let Some(since) = query.updated_since.as_deref().filter(|since| crate::query::parse_timestamp(since).is_none()) {
+        return error_response(&state, &headers, StatusCode::BAD_REQUEST, &format!("Invalid updated_since: {}", since));
+    }
+    if let Some(Err(e)) = query.sort.as_deref().map(crate::query::SortOrder::parse) {
+        return error_response(&state, &headers, StatusCode::BAD_REQUEST, &format!("Invalid sort: {}", e));
+    }
+    if wants_refresh(&headers) {
+        state.api_client.forget_items(&user, &library_id).await;
+    }
 
     if wants_opds_v2(&headers) {
         if query.categories.is_some() {
-            let json = Opds2Builder::build_categories_root(&library_id, &state.i18n, lang, &updated_time);
+            let empty = state.service.empty_categories(&user, &library_id).await;
+            let media_type = state.service.media_type(&user, &library_id).await;
+            let json = Opds2Builder::build_categories_root(&library_id, media_type, &empty, &state.config.shelves, &state.i18n, lang, &updated_time);
             let etag = {
                 let mut hasher = Sha1::new();
                 hasher.update(json.as_bytes());
@@ -184,34 +362,47 @@ pub async fn get_library(
             Ok(library) => {
                 match state.service.get_filtered_items(&user, &library_id, &query).await {
                     Ok((paginated_items, total_items)) => {
-                        let page_size = state.config.opds_page_size;
-                        let total_pages = (total_items + page_size - 1) / page_size;
+                        let page_size = state.config.page_size_for(query.limit);
+                        let total_pages = total_items.div_ceil(page_size);
 
-                        let link_url = if state.config.use_proxy { "/opds/proxy" } else { &state.config.abs_url };
+                        let link_url = if state.config.use_proxy { PROXY_PATH } else { &state.config.abs_url };
 
-                        let mut url_base = format!("/opds/libraries/{}", library_id);
+                        let mut url_base = items_feed_path(&library_id, &query);
                         let mut params = Vec::new();
                         if let Some(q) = &query.q { params.push(format!("q={}", q)); }
                         if let Some(t) = &query.type_ { params.push(format!("type={}", t)); }
                         if let Some(n) = &query.name { params.push(format!("name={}", n)); }
                         if let Some(a) = &query.author { params.push(format!("author={}", a)); }
+                        if let Some(n) = &query.narrator { params.push(format!("narrator={}", n)); }
                         if let Some(t) = &query.title { params.push(format!("title={}", t)); }
+                        if let Some(s) = &query.start { params.push(format!("start={}", s)); }
+                        if let Some(l) = query.limit { params.push(format!("limit={}", l)); }
+                        if let Some(s) = &query.shelf { params.push(format!("shelf={}", s)); }
+                        if let Some(u) = &query.updated_since { params.push(format!("updated_since={}", u)); }
+                        if let Some(s) = &query.sort { params.push(format!("sort={}", s)); }
+                        if let Some(f) = &query.format { params.push(format!("format={}", f)); }
 
                         if !params.is_empty() {
                             url_base.push('?');
                             url_base.push_str(&params.join("&"));
                         }
 
+                        let build_started = std::time::Instant::now();
                         let json = Opds2Builder::build_publications(
                             &library_id,
                             &library.name,
                             &paginated_items,
                             &user,
                             link_url,
+                            &|id| state.config.web_item_url(id),
+                            &state.config.acquisition_links(),
                             &updated_time,
                             Some((query.page, page_size, total_items, total_pages)),
                             &url_base,
+                            &state.i18n,
+                            lang,
                         );
+                        metrics::record_phase("get_filtered_items", "build", build_started.elapsed());
 
                         let etag = {
                             let mut hasher = Sha1::new();
@@ -234,127 +425,71 @@ pub async fn get_library(
                     }
                     Err(e) => {
                         tracing::error!("Failed to filter items: {}", e);
-                        let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to filter items: {}", e)).unwrap_or_default();
-                        return (
-                            [(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")],
-                            error_xml,
-                        ).into_response();
+                        return error_response(&state, &headers, e.status(), &format!("Failed to filter items: {}", e));
                     }
                 }
             }
             Err(e) => {
                 tracing::error!("Failed to fetch library: {}", e);
-                let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to fetch library: {}", e)).unwrap_or_default();
-                return (
-                    [(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")],
-                    error_xml,
-                ).into_response();
+                return error_response(&state, &headers, e.status(), &format!("Failed to fetch library: {}", e));
             }
         }
     }
 
     if query.categories.is_some() {
-          let xml = OpdsBuilder::build_opds_skeleton(
-              &format!("urn:uuid:{}", library_id),
-              "Categories",
-              OpdsBuilder::build_category_entries(&library_id, &state.i18n, lang, &updated_time),
-              None,
-              None,
-              None,
-              &format!("/opds/libraries/{}", library_id),
-              false,
-          ).unwrap_or_else(|_| String::new());
-
-          let etag = {
-              let mut hasher = Sha1::new();
-              hasher.update(xml.as_bytes());
-              format!("W/\"{}\"", hasher.digest())
-          };
-          if let Some(if_none_match) = headers.get(axum::http::header::IF_NONE_MATCH).and_then(|h| h.to_str().ok()) {
-              if if_none_match == etag {
-                  return StatusCode::NOT_MODIFIED.into_response();
-              }
-          }
-          let etag_value = axum::http::HeaderValue::try_from(etag).unwrap();
-          return (
-              [
-                  (axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/atom+xml;profile=opds-catalog;kind=navigation")),
-                  (axum::http::header::ETAG, etag_value),
-              ],
-              xml,
-          ).into_response();
+          let media_type = state.service.media_type(&user, &library_id).await;
+          let feed = state.service.categories_root_feed(&user, &library_id, media_type, lang).await;
+          return feed_response(&headers, feed);
     }
 
     match state.service.get_library(&user, &library_id).await {
         Ok(library) => {
             match state.service.get_filtered_items(&user, &library_id, &query).await {
                 Ok((paginated_items, total_items)) => {
-                    let page_size = state.config.opds_page_size;
-                    let total_pages = (total_items + page_size - 1) / page_size;
-
-                    let link_url = if state.config.use_proxy { "/opds/proxy" } else { &state.config.abs_url };
+                    let page_size = state.config.page_size_for(query.limit);
+                    let total_pages = total_items.div_ceil(page_size);
 
-                    let mut url_base = format!("/opds/libraries/{}", library_id);
+                    let path = items_feed_path(&library_id, &query);
                     let mut params = Vec::new();
                     if let Some(q) = &query.q { params.push(format!("q={}", q)); }
                     if let Some(t) = &query.type_ { params.push(format!("type={}", t)); }
                     if let Some(n) = &query.name { params.push(format!("name={}", n)); }
                     if let Some(a) = &query.author { params.push(format!("author={}", a)); }
+                    if let Some(n) = &query.narrator { params.push(format!("narrator={}", n)); }
                     if let Some(t) = &query.title { params.push(format!("title={}", t)); }
-
-                    if !params.is_empty() {
-                        url_base.push('?');
-                        url_base.push_str(&params.join("&"));
-                    }
-
-                    let mut url_buf = String::with_capacity(256);
-                    let xml = OpdsBuilder::build_opds_skeleton(
-                        &format!("urn:uuid:{}", library_id),
-                        &library.name,
-                        |writer| {
-                            for item in paginated_items {
-                                OpdsBuilder::build_item_entry(writer, &item, &user, link_url, &updated_time, &mut url_buf)?;
-                            }
-                            Ok(())
-                        },
-                        Some(&library),
-                        Some(&user),
-                        Some((query.page, page_size, total_items, total_pages)),
-                        &url_base,
-                        true,
-                    ).unwrap_or_else(|_| String::new());
-
-                    let etag = {
-                        let mut hasher = Sha1::new();
-                        hasher.update(xml.as_bytes());
-                        format!("W/\"{}\"", hasher.digest())
+                    if let Some(s) = &query.start { params.push(format!("start={}", s)); }
+                    if let Some(l) = query.limit { params.push(format!("limit={}", l)); }
+                    if let Some(s) = &query.shelf { params.push(format!("shelf={}", s)); }
+                    if let Some(u) = &query.updated_since { params.push(format!("updated_since={}", u)); }
+                    let format_param = query.format.as_ref().map(|f| format!("format={}", f));
+                    let sort_param = query.sort.as_ref().map(|s| format!("sort={}", s));
+                    let href = |facet_params: &[&Option<String>]| {
+                        let all: Vec<&str> = params.iter().chain(facet_params.iter().copied().flatten()).map(String::as_str).collect();
+                        if all.is_empty() { path.clone() } else { format!("{}?{}", path, all.join("&")) }
                     };
-                    if let Some(if_none_match) = headers.get(axum::http::header::IF_NONE_MATCH).and_then(|h| h.to_str().ok()) {
-                        if if_none_match == etag {
-                            return StatusCode::NOT_MODIFIED.into_response();
-                        }
-                    }
+                    let url_base = href(&[&format_param, &sort_param]);
 
-                    let etag_value = axum::http::HeaderValue::try_from(etag).unwrap();
-                    (
-                        [
-                            (axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/atom+xml;profile=opds-catalog;kind=acquisition")),
-                            (axum::http::header::ETAG, etag_value),
-                        ],
-                        xml,
-                    ).into_response()
+                    let page_info = Some((query.page, page_size, total_items, total_pages));
+                    let build_started = std::time::Instant::now();
+                    // Facets switch to other formats or orders of the same items, from their first page
+                    let sort = query.sort.as_deref().and_then(|s| crate::query::SortOrder::parse(s).ok());
+                    let mut facets = state.service.format_facets(&href(&[&sort_param]), query.format.as_deref(), lang);
+                    facets.extend(state.service.sort_facets(&href(&[&format_param]), sort, lang));
+                    let feed = state.service.items_feed(&user, &library, &paginated_items, page_info, &url_base, lang)
+                        .with_facets(facets);
+                    let response = feed_response(&headers, feed);
+                    metrics::record_phase("get_filtered_items", "build", build_started.elapsed());
+                    response
                 },
                 Err(e) => {
                     tracing::error!("Failed to filter items: {}", e);
-                    let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to filter items: {}", e)).unwrap_or_default();
-                    ([(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")], error_xml).into_response()
+                    error_response(&state, &headers, e.status(), &format!("Failed to filter items: {}", e))
                 }
             }
         },
         Err(e) => {
             tracing::error!("Failed to fetch library: {}", e);
-            let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to fetch library: {}", e)).unwrap_or_default();
-            ([(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")], error_xml).into_response()
+            error_response(&state, &headers, e.status(), &format!("Failed to fetch library: {}", e))
         }
     }
 }
@@ -366,9 +501,10 @@ pub async fn get_category(
     Query(query): Query<LibraryQuery>,
     headers: HeaderMap,
 ) -> Response {
+    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
     let item_type_str = type_.as_str();
-    if !["authors", "narrators", "genres", "series"].contains(&item_type_str) {
-        return (StatusCode::BAD_REQUEST, "Invalid type").into_response();
+    if !["authors", "narrators", "genres", "series", "titles"].contains(&item_type_str) {
+        return error_response(&state, &headers, StatusCode::BAD_REQUEST, "Invalid type");
     }
 
     if wants_opds_v2(&headers) {
@@ -378,13 +514,17 @@ pub async fn get_category(
                     Ok(categories_res) => {
                         let json = match categories_res {
                             crate::service::CategoriesResult::Letters(letters) => {
-                                Opds2Builder::build_category_letters(&library_id, &library.name, &type_, &letters)
+                                Opds2Builder::build_category_letters(&library_id, &library.name, &type_, &letters, &state.i18n, lang)
                             }
                             crate::service::CategoriesResult::Items { items, page_info } => {
-                                let mut url_base = format!("/opds/libraries/{}/{}", library_id, type_);
-                                if let Some(start) = &query.start {
-                                    url_base.push_str(&format!("?start={}", start));
-                                }
+                                let items: Vec<(String, String)> = items
+                                    .into_iter()
+                                    .map(|name| {
+                                        let title = state.service.category_title(&type_, &name);
+                                        (name, title)
+                                    })
+                                    .collect();
+                                let url_base = crate::xml::category_href(&library_id, &type_, query.start.as_deref(), query.limit);
                                 Opds2Builder::build_category_items(
                                     &library_id,
                                     &library.name,
@@ -417,70 +557,228 @@ pub async fn get_category(
                     }
                     Err(e) => {
                         tracing::error!("Failed to fetch category data: {}", e);
-                        let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to fetch category data: {}", e)).unwrap_or_default();
-                        return (
-                            [(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")],
-                            error_xml,
-                        ).into_response();
+                        return error_response(&state, &headers, e.status(), &format!("Failed to fetch category data: {}", e));
                     }
                 }
             }
             Err(e) => {
                 tracing::error!("Failed to fetch library: {}", e);
-                let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to fetch library: {}", e)).unwrap_or_default();
-                return (
-                    [(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")],
-                    error_xml,
-                ).into_response();
+                return error_response(&state, &headers, e.status(), &format!("Failed to fetch library: {}", e));
             }
         }
     }
 
-    match state.service.get_categories(&user, &library_id, &type_, &query).await {
-        Ok(xml) => {
-            let etag = {
-                let mut hasher = Sha1::new();
-                hasher.update(xml.as_bytes());
-                format!("W/\"{}\"", hasher.digest())
-            };
-            if let Some(if_none_match) = headers.get(axum::http::header::IF_NONE_MATCH).and_then(|h| h.to_str().ok()) {
-                if if_none_match == etag {
-                    return StatusCode::NOT_MODIFIED.into_response();
-                }
-            }
-            let etag_value = axum::http::HeaderValue::try_from(etag).unwrap();
-            (
-                [
-                    (axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/atom+xml;profile=opds-catalog;kind=navigation")),
-                    (axum::http::header::ETAG, etag_value),
-                ],
-                xml,
-            ).into_response()
+    match state.service.get_categories(&user, &library_id, &type_, &query, lang).await {
+        Ok(feed) => {
+            let build_started = std::time::Instant::now();
+            let response = feed_response(&headers, feed);
+            metrics::record_phase("get_categories", "build", build_started.elapsed());
+            response
         }
         Err(e) => {
             tracing::error!("Failed to fetch category items: {}", e);
-            let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to fetch category items: {}", e)).unwrap_or_default();
-            ([(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")], error_xml).into_response()
+            error_response(&state, &headers, e.status(), &format!("Failed to fetch category items: {}", e))
+        }
+    }
+}
+
+/// The collections of a library, each leading to a feed of its books.
+pub async fn get_collections(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(library_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
+    match state.service.collections_feed(&user, &library_id, lang).await {
+        Ok(feed) => {
+            feed_response(&headers, feed)
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch collections: {}", e);
+            error_response(&state, &headers, e.status(), &format!("Failed to fetch collections: {}", e))
         }
     }
 }
 
+/// A podcast library's episodes, newest first, as an Atom acquisition feed.
+pub async fn get_episodes(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(library_id): Path<String>,
+    Query(query): Query<LibraryQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
+    match state.service.episodes_feed(&user, &library_id, query.page, lang).await {
+        Ok(feed) => {
+            feed_response(&headers, feed)
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch episodes: {}", e);
+            error_response(&state, &headers, e.status(), &format!("Failed to fetch episodes: {}", e))
+        }
+    }
+}
+
+pub async fn auth_document(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    auth_document_response(&state, &headers, StatusCode::OK)
+}
+
+/// The OPDS authentication document, describing how to sign in with Basic auth.
+pub(crate) fn auth_document_response(state: &AppState, headers: &HeaderMap, status: StatusCode) -> Response {
+    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
+    (
+        status,
+        [(axum::http::header::CONTENT_TYPE, crate::opds::model::AUTH_DOCUMENT_TYPE)],
+        Opds2Builder::build_auth_document(&state.i18n, lang),
+    )
+        .into_response()
+}
+
 pub async fn search_definition(
+    State(state): State<Arc<AppState>>,
     Path(library_id): Path<String>,
+    headers: HeaderMap,
 ) -> Response {
-    match OpdsBuilder::build_search_definition(&library_id) {
+    let lang = headers.get("accept-language").and_then(|h| h.to_str().ok());
+    match OpdsBuilder::build_search_definition(&library_id, &state.i18n, lang) {
         Ok(xml) => ([(axum::http::header::CONTENT_TYPE, "application/opensearchdescription+xml")], xml).into_response(),
         Err(e) => {
             tracing::error!("Failed to build search definition: {}", e);
-            let error_xml = OpdsBuilder::build_error_feed(&format!("Failed to build search definition: {}", e)).unwrap_or_default();
-            ([(axum::http::header::CONTENT_TYPE, "application/atom+xml;profile=opds-catalog;kind=navigation")], error_xml).into_response()
+            error_response(&state, &headers, StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to build search definition: {}", e))
         }
     }
 }
 
+pub async fn get_cover(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(item_id): Path<String>,
+    Query(query): Query<CoverQuery>,
+) -> Response {
+    let cache_key = CoverCache::key(&user.api_key, &item_id, &query);
+    let cover = match state.cover_cache.get(&cache_key).await {
+        Some(cover) => cover,
+        None => {
+            let bytes = match state.api_client.get_cover(&user, &item_id).await {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) => {
+                    let (title, author, found) = match state.api_client.get_item(&user, &item_id).await {
+                        Ok(item) => (item.media.metadata.title, item.media.metadata.author_name, true),
+                        Err(e) => {
+                            tracing::debug!("No metadata for placeholder cover of {}: {}", item_id, e);
+                            (None, None, false)
+                        }
+                    };
+                    let render = move || covers::placeholder_for(title.as_deref().unwrap_or("Untitled"), author.as_deref(), &query);
+                    let cover = match tokio::task::spawn_blocking(render).await {
+                        Ok(Ok(cover)) => cover,
+                        Ok(Err(e)) => {
+                            tracing::error!("Failed to render placeholder cover for {}: {}", item_id, e);
+                            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                        }
+                        Err(e) => {
+                            tracing::error!("Placeholder cover task failed: {}", e);
+                            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                        }
+                    };
+                    // An "Untitled" placeholder for a failed lookup must not outlive the failure
+                    if found {
+                        state.cover_cache.insert(cache_key, cover.clone()).await;
+                    }
+                    return (
+                        [(axum::http::header::CONTENT_TYPE, cover.content_type)],
+                        cover.bytes,
+                    ).into_response();
+                }
+                Err(e) => {
+                    tracing::error!("Failed to fetch cover: {}", e);
+                    return e.into_response();
+                }
+            };
+
+            // Decoding and re-encoding is CPU bound, keep it off the async workers
+            let cover = match tokio::task::spawn_blocking(move || covers::transform(bytes, &query)).await {
+                Ok(Ok(cover)) => cover,
+                Ok(Err(e)) => {
+                    tracing::error!("Failed to transform cover for {}: {}", item_id, e);
+                    return (StatusCode::UNPROCESSABLE_ENTITY, "Unsupported cover image").into_response();
+                }
+                Err(e) => {
+                    tracing::error!("Cover transform task failed: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            state.cover_cache.insert(cache_key, cover.clone()).await;
+            cover
+        }
+    };
+
+    (
+        [(axum::http::header::CONTENT_TYPE, cover.content_type)],
+        cover.bytes,
+    ).into_response()
+}
+
+/// A library's cover: a mosaic of its most recently updated books' covers, or a
+/// placeholder with its name when they have none.
+pub async fn get_library_cover(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(library_id): Path<String>,
+) -> Response {
+    let cache_key = CoverCache::key(&user.api_key, &format!("library:{}", library_id), &CoverQuery::default());
+    let cover = match state.cover_cache.get(&cache_key).await {
+        Some(cover) => cover,
+        None => {
+            let library = match state.service.get_library(&user, &library_id).await {
+                Ok(library) => library,
+                Err(e) => {
+                    tracing::error!("Failed to fetch library: {}", e);
+                    return e.into_response();
+                }
+            };
+            let query = LibraryQuery { recently_updated: true, limit: Some(covers::MOSAIC_COVERS), ..LibraryQuery::default() };
+            let items = match state.service.get_filtered_items(&user, &library_id, &query).await {
+                Ok((items, _)) => items,
+                Err(e) => {
+                    tracing::error!("Failed to filter items: {}", e);
+                    return e.into_response();
+                }
+            };
+            let book_covers: Vec<Vec<u8>> =
+                futures_util::future::join_all(items.iter().map(|item| state.api_client.get_cover(&user, &item.id)))
+                    .await
+                    .into_iter()
+                    .filter_map(|cover| cover.ok().flatten())
+                    .collect();
+
+            let cover = match tokio::task::spawn_blocking(move || covers::mosaic(&book_covers)).await {
+                Ok(Ok(cover)) => cover,
+                Ok(Err(e)) => {
+                    tracing::debug!("Placeholder cover for library {}: {}", library_id, e);
+                    covers::placeholder(&library.name, None)
+                }
+                Err(e) => {
+                    tracing::error!("Cover mosaic task failed: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            state.cover_cache.insert(cache_key, cover.clone()).await;
+            cover
+        }
+    };
+
+    (
+        [(axum::http::header::CONTENT_TYPE, cover.content_type)],
+        cover.bytes,
+    ).into_response()
+}
+
 pub async fn proxy_handler(
     State(state): State<Arc<AppState>>,
-    AuthUser(_user): AuthUser,
+    AuthUser(user): AuthUser,
     req: axum::extract::Request,
 ) -> Response {
     if !state.config.use_proxy {
@@ -492,22 +790,552 @@ pub async fn proxy_handler(
     }
 
     let path = req.uri().path();
-    let target_path = path.strip_prefix("/opds/proxy").unwrap_or(path);
+    // Public and shared catalogs serve the proxy at `/proxy` below their own path
+    let target_path = path.strip_prefix(PROXY_PATH).or_else(|| path.strip_prefix("/proxy")).unwrap_or(path);
 
     if target_path.contains("..") {
         return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
     }
+    if !state.config.proxy_allows(target_path) {
+        tracing::warn!("Refused to proxy {}, which PROXY_ALLOWED_PATHS doesn't allow", target_path);
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
 
     let target_url = format!("{}{}", state.config.abs_url, target_path);
 
-    let full_target_url = if let Some(query) = req.uri().query() {
-        format!("{}?{}", target_url, query)
+    // The reader's token, if it sent one, is for us; ABS gets the user's from the header
+    let query: Vec<&str> = req.uri().query().unwrap_or("").split('&').filter(|p| !p.is_empty() && !p.starts_with("token=")).collect();
+    let full_target_url = if query.is_empty() { target_url } else { format!("{}?{}", target_url, query.join("&")) };
+
+    let mut response = forward_upstream(&state, &full_target_url, Some(&user.api_key), req.headers()).await;
+    match download_item_id(target_path) {
+        Some(item_id) => {
+            ensure_resumable(&state, &full_target_url, Some(&user.api_key), &mut response).await;
+            finish_download(&state, &user, item_id, response).await
+        }
+        None => response,
+    }
+}
+
+pub async fn get_library_file(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path((item_id, file_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let is_safe_segment = |s: &str| !s.is_empty() && !s.contains('/') && !s.contains("..");
+    if !is_safe_segment(&item_id) || !is_safe_segment(&file_id) {
+        return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
+    }
+
+    let target_url = format!("{}/api/items/{}/file/{}", state.config.abs_url, item_id, file_id);
+    let mut response = forward_upstream(&state, &target_url, Some(&user.api_key), &headers).await;
+    ensure_resumable(&state, &target_url, Some(&user.api_key), &mut response).await;
+    finish_download(&state, &user, &item_id, response).await
+}
+
+/// Streams one audio track of an item, numbered from 1 in ABS's track order. Ranges are
+/// passed on, so players can seek.
+pub async fn get_audio_track(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path((item_id, track)): Path<(String, usize)>,
+    headers: HeaderMap,
+) -> Response {
+    if item_id.is_empty() || item_id.contains('/') || item_id.contains("..") {
+        return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
+    }
+
+    let item = match state.api_client.get_item(&user, &item_id).await {
+        Ok(item) => item,
+        Err(e) => {
+            tracing::error!("Failed to fetch item {} for streaming: {}", item_id, e);
+            return e.into_response();
+        }
+    };
+
+    let mut audio_files = item.media.audio_files;
+    audio_files.sort_by_key(|f| f.index.unwrap_or(usize::MAX));
+    let Some(file) = track.checked_sub(1).and_then(|i| audio_files.get(i)) else {
+        return (StatusCode::NOT_FOUND, "No such track").into_response();
+    };
+
+    let target_url = format!("{}/api/items/{}/file/{}", state.config.abs_url, item_id, file.ino);
+    let mut response = forward_upstream(&state, &target_url, Some(&user.api_key), &headers).await;
+    ensure_resumable(&state, &target_url, Some(&user.api_key), &mut response).await;
+    response
+}
+
+/// Streams all audio files of an item as a single ZIP, assembled on the fly from the
+/// individual ABS file endpoints.
+pub async fn get_audiobook_zip(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(item_id): Path<String>,
+) -> Response {
+    if item_id.is_empty() || item_id.contains('/') || item_id.contains("..") {
+        return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
+    }
+
+    let item = match state.api_client.get_item(&user, &item_id).await {
+        Ok(item) => item,
+        Err(e) => {
+            tracing::error!("Failed to fetch item {} for zip download: {}", item_id, e);
+            return e.into_response();
+        }
+    };
+
+    let mut audio_files = item.media.audio_files.clone();
+    if audio_files.is_empty() {
+        return (StatusCode::NOT_FOUND, "No audio files").into_response();
+    }
+    audio_files.sort_by_key(|f| f.index.unwrap_or(usize::MAX));
+    report_download(&state, &user, &item);
+
+    let basename = downloads::download_basename(&item);
+    let folder = Some(downloads::sanitize_filename(&basename)).filter(|f| !f.is_empty()).unwrap_or_else(|| item.id.clone());
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<axum::body::Bytes, std::io::Error>>(8);
+
+    let mut api_key = Some(user.api_key.clone());
+    tokio::spawn(async move {
+        let mut zip = archive::ZipStream::new();
+        for file in audio_files {
+            let name = format!("{}/{}", folder, downloads::sanitize_filename(&file.metadata.filename));
+            let url = format!("{}/api/items/{}/file/{}", state.config.abs_url, item_id, file.ino);
+            let build = |bearer: Option<&str>| state.api_client_raw.get(&url).bearer_auth(bearer.unwrap_or_default());
+            let upstream = match send_upstream(&state, &mut api_key, build).await.and_then(|r| r.error_for_status()) {
+                Ok(resp) => resp,
+                Err(e) => {
+                    // The headers are already sent, so all we can do is abort the body
+                    tracing::error!("Zip download of {} failed at {}: {}", item_id, file.ino, e);
+                    let _ = tx.send(Err(std::io::Error::other(e))).await;
+                    return;
+                }
+            };
+
+            if tx.send(Ok(zip.start_entry(&name).into())).await.is_err() {
+                return;
+            }
+            let mut stream = upstream.bytes_stream();
+            while let Some(chunk) = stream.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        tracing::error!("Zip download of {} failed at {}: {}", item_id, file.ino, e);
+                        let _ = tx.send(Err(std::io::Error::other(e))).await;
+                        return;
+                    }
+                };
+                zip.update(&chunk);
+                if tx.send(Ok(chunk)).await.is_err() {
+                    return;
+                }
+            }
+            if tx.send(Ok(zip.finish_entry().into())).await.is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Ok(zip.finish().into())).await;
+    });
+
+    let body = Body::from_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/zip"));
+    if let Some(value) = downloads::content_disposition(&basename, Some("zip")) {
+        headers.insert(axum::http::header::CONTENT_DISPOSITION, value);
+    }
+    (StatusCode::OK, headers, body).into_response()
+}
+
+/// The item an ABS path below `/api/items/` is about, for checking proxied requests.
+pub(crate) fn proxied_item_id(abs_path: &str) -> Option<&str> {
+    let rest = abs_path.strip_prefix("/api/items/")?;
+    rest.split('/').next().filter(|item_id| !item_id.is_empty())
+}
+
+/// Returns the item ID if the ABS path is one of the item download endpoints.
+fn download_item_id(abs_path: &str) -> Option<&str> {
+    let rest = abs_path.strip_prefix("/api/items/")?;
+    let (item_id, action) = rest.split_once('/')?;
+    let action = action.split('/').next().unwrap_or("");
+    (action == "download" || action == "ebook").then_some(item_id)
+}
+
+/// Lets readers resume interrupted downloads: advertises byte ranges where ABS serves
+/// them, answering a Range request with 206 or saying so on a HEAD request, and fills in
+/// a missing Content-Length from that HEAD request.
+async fn ensure_resumable(state: &AppState, target_url: &str, bearer: Option<&str>, response: &mut Response) {
+    use axum::http::header;
+
+    let status = response.status();
+    if status != StatusCode::OK && status != StatusCode::PARTIAL_CONTENT {
+        return;
+    }
+    // Lengths and ranges would refer to the encoded body, which the reader can't resume from
+    if response.headers().contains_key(header::CONTENT_ENCODING) {
+        return;
+    }
+
+    let bytes = axum::http::HeaderValue::from_static("bytes");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response.headers_mut().entry(header::ACCEPT_RANGES).or_insert(bytes);
+        return;
+    }
+    if response.headers().contains_key(header::CONTENT_LENGTH) {
+        return;
+    }
+
+    let build = |bearer: Option<&str>| {
+        let request_builder = state.api_client_raw.head(target_url);
+        match bearer {
+            Some(token) => request_builder.bearer_auth(token),
+            None => request_builder,
+        }
+    };
+    let head = match send_upstream(state, &mut bearer.map(str::to_string), build).await {
+        Ok(resp) if resp.status().is_success() => resp,
+        Ok(resp) => {
+            tracing::debug!("HEAD {} returned {}", target_url, resp.status());
+            return;
+        }
+        Err(e) => {
+            tracing::debug!("HEAD {} failed: {}", target_url, e);
+            return;
+        }
+    };
+    if let Some(value) = head
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| axum::http::HeaderValue::from_bytes(v.as_bytes()).ok())
+    {
+        response.headers_mut().insert(header::CONTENT_LENGTH, value);
+    }
+    if head.headers().get(reqwest::header::ACCEPT_RANGES).is_some_and(|v| v.as_bytes() == b"bytes") {
+        response.headers_mut().entry(header::ACCEPT_RANGES).or_insert(bytes);
+    }
+}
+
+/// Post-processes a successful download: embeds ABS metadata into EPUBs when enabled
+/// and replaces the upstream Content-Disposition with a readable name.
+async fn finish_download(state: &AppState, user: &InternalUser, item_id: &str, response: Response) -> Response {
+    if !response.status().is_success() {
+        return response;
+    }
+    let item = match state.api_client.get_item(user, item_id).await {
+        Ok(item) => item,
+        Err(e) => {
+            tracing::debug!("Keeping upstream download for {} untouched: {}", item_id, e);
+            return response;
+        }
+    };
+
+    // Range requests resuming a download would otherwise be reported again
+    if response.status() == StatusCode::OK {
+        report_download(state, user, &item);
+    }
+
+    let extension = downloads::extension_for(response.headers(), item.media.ebook_format.as_deref());
+    let mut response = if state.config.embed_epub_metadata && extension.as_deref() == Some("epub") {
+        embed_epub_metadata(response, &item, &state.service.series).await
+    } else {
+        response
+    };
+
+    if let Some(value) = downloads::content_disposition(&downloads::download_basename(&item), extension.as_deref()) {
+        response.headers_mut().insert(axum::http::header::CONTENT_DISPOSITION, value);
+    }
+    response
+}
+
+/// Reports a download to ABS in the background when `REPORT_DOWNLOADS` is enabled.
+fn report_download(state: &AppState, user: &InternalUser, item: &AbsItemResult) {
+    if !state.config.report_downloads {
+        return;
+    }
+    let client = state.api_client.clone();
+    let user = user.clone();
+    let item = item.clone();
+    tokio::spawn(async move {
+        let Some(progress) = client.progress() else {
+            tracing::debug!("Backend cannot record downloads");
+            return;
+        };
+        if let Err(e) = progress.report_download(&user, &item).await {
+            tracing::warn!("Failed to report download of {} to ABS: {}", item.id, e);
+        }
+    });
+}
+
+/// Buffers a full EPUB response and rewrites its OPF with the item's ABS metadata.
+/// Partial, compressed or oversized responses are passed through unchanged.
+async fn embed_epub_metadata(response: Response, item: &AbsItemResult, series: &SeriesParser) -> Response {
+    use axum::http::header;
+
+    let metadata = EpubMetadata::from_abs(&item.media.metadata, series);
+    let content_length = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if metadata.is_empty()
+        || response.status() != StatusCode::OK
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+        || content_length.is_none_or(|len| len > epub::MAX_EPUB_REWRITE_BYTES)
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let original = match axum::body::to_bytes(body, epub::MAX_EPUB_REWRITE_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to read EPUB for {}: {}", item.id, e);
+            return (StatusCode::BAD_GATEWAY, "Bad Gateway").into_response();
+        }
+    };
+
+    let source = original.clone();
+    let rewritten = tokio::task::spawn_blocking(move || epub::embed_metadata(&source, &metadata)).await;
+    let bytes = match rewritten {
+        Ok(Ok(bytes)) => {
+            // The body changed, so upstream validators and ranges no longer apply
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.remove(header::ETAG);
+            parts.headers.remove(header::ACCEPT_RANGES);
+            axum::body::Bytes::from(bytes)
+        }
+        Ok(Err(e)) => {
+            tracing::warn!("Serving {} without embedded metadata: {}", item.id, e);
+            original
+        }
+        Err(e) => {
+            tracing::error!("EPUB rewrite task failed for {}: {}", item.id, e);
+            original
+        }
+    };
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// Middleware for a catalog mounted below `prefix`: rebases the catalog's own links
+/// (`/opds...`) in feeds, search descriptions and redirects onto the prefix.
+pub async fn rebase_links(State(prefix): State<String>, req: axum::extract::Request, next: axum::middleware::Next) -> Response {
+    use axum::http::header;
+
+    let (mut parts, body) = next.run(req).await.into_parts();
+    let location = parts.headers.get(header::LOCATION).and_then(|l| l.to_str().ok());
+    if let Some(location) = location.filter(|l| l.starts_with("/opds")) {
+        if let Ok(value) = axum::http::HeaderValue::try_from(format!("{}{}", prefix, location)) {
+            parts.headers.insert(header::LOCATION, value);
+        }
+    }
+    // Links are always quoted attribute or JSON string values
+    map_catalog(parts, body, |catalog| catalog.replace("\"/opds", &format!("\"{}/opds", prefix))).await
+}
+
+/// The root of the public catalog: the feed of `OPDS_PUBLIC_LIBRARY`.
+pub async fn get_public_catalog(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    query: Query<LibraryQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let library_id = state.config.opds_public_library.clone();
+    get_library(State(state), user, Path(library_id), query, headers).await
+}
+
+/// Points the links of public catalog feeds at the public routes.
+pub async fn public_catalog_links(req: axum::extract::Request, next: axum::middleware::Next) -> Response {
+    let (parts, body) = next.run(req).await.into_parts();
+    map_catalog(parts, body, |catalog| scope_links(catalog, "/opds/public")).await
+}
+
+/// `catalog` with its links to the root, libraries, covers, items and the proxy below
+/// `base`.
+fn scope_links(catalog: &str, base: &str) -> String {
+    ["libraries/", "covers/", "items/", "proxy/"]
+        .iter()
+        .fold(catalog.replace("\"/opds\"", &format!("\"{}\"", base)), |catalog, route| {
+            catalog.replace(&format!("\"/opds/{}", route), &format!("\"{}/{}", base, route))
+        })
+}
+
+/// A share link: checks its token, then serves the rest of its path from the shared
+/// catalog routes with the token's grant, pointing the links of feeds below the link.
+/// A shelf-scoped link passes its shelf on to every request.
+pub async fn share_catalog(State(state): State<Arc<AppState>>, shared: axum::Router, req: axum::extract::Request) -> Response {
+    use tower::ServiceExt;
+
+    let path = req.uri().path();
+    let rest = path.split_once("/opds/share/").map_or("", |(_, rest)| rest);
+    let (token, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let grant = match crate::share::verify(&state.config.opds_share_secret, token, chrono::Utc::now().timestamp()) {
+        Ok(grant) => grant,
+        Err(e) => {
+            let status = if e == crate::share::ShareError::Expired { StatusCode::GONE } else { StatusCode::NOT_FOUND };
+            return error_response(&state, req.headers(), status, &e.to_string());
+        }
+    };
+
+    let mut query: Vec<String> = req
+        .uri()
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("shelf="))
+        .map(str::to_string)
+        .collect();
+    if let Some(shelf) = &grant.shelf {
+        query.push(format!("shelf={}", crate::share::encode_query_value(shelf)));
+    }
+    let uri = if query.is_empty() { format!("/{}", rest) } else { format!("/{}?{}", rest, query.join("&")) };
+    let base = format!("/opds/share/{}", token);
+
+    // A fresh request, so the path parameters of this route don't reach the shared routes
+    let mut shared_req = axum::extract::Request::new(Body::empty());
+    *shared_req.method_mut() = req.method().clone();
+    *shared_req.headers_mut() = req.headers().clone();
+    *shared_req.uri_mut() = match uri.parse() {
+        Ok(uri) => uri,
+        Err(_) => return error_response(&state, req.headers(), StatusCode::BAD_REQUEST, "Invalid path"),
+    };
+    shared_req.extensions_mut().insert(grant);
+
+    let response = match shared.oneshot(shared_req).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    };
+    let (parts, body) = response.into_parts();
+    map_catalog(parts, body, |catalog| scope_links(catalog, &base)).await
+}
+
+/// The root of a share link: its library, or the libraries of the share user.
+pub async fn get_shared_catalog(
+    state: State<Arc<AppState>>,
+    user: AuthUser,
+    axum::Extension(grant): axum::Extension<crate::share::ShareGrant>,
+    query: Query<LibraryQuery>,
+    headers: HeaderMap,
+) -> Response {
+    match grant.library {
+        Some(library_id) => get_library(state, user, Path(library_id), query, headers).await,
+        None => get_opds_root(state, user, headers).await,
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ShareRequest {
+    pub library: Option<String>,
+    /// A shelf of `library` from `OPDS_SHELVES`.
+    pub shelf: Option<String>,
+    /// Seconds until the link stops working; without, it doesn't.
+    pub expires_in: Option<u64>,
+}
+
+/// Mints a share link from `{"library": …, "shelf": …, "expires_in": …}`, each optional.
+pub async fn mint_share(
+    State(state): State<Arc<AppState>>,
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<ShareRequest>,
+) -> Response {
+    if let Some(shelf) = &request.shelf {
+        if request.library.is_none() {
+            return (StatusCode::BAD_REQUEST, "A shelf share needs its library").into_response();
+        }
+        if state.config.shelf(shelf).is_none() {
+            return (StatusCode::BAD_REQUEST, format!("Unknown shelf: {}", shelf)).into_response();
+        }
+    }
+    let expires = request
+        .expires_in
+        .and_then(|secs| chrono::Utc::now().checked_add_signed(chrono::Duration::seconds(i64::try_from(secs).ok()?)));
+    let grant = crate::share::ShareGrant {
+        library: request.library,
+        shelf: request.shelf,
+        expires: expires.map(|expires| expires.timestamp()),
+    };
+    let token = crate::share::mint(&state.config.opds_share_secret, &grant);
+    let prefix = uri.path().strip_suffix("/admin/shares").unwrap_or("");
+    let url = format!("{}{}/opds/share/{}", crate::landing::request_origin(&headers), prefix, token);
+    let body = serde_json::json!({ "url": url, "token": token, "expires": expires.map(|expires| expires.to_rfc3339()) });
+    ([(axum::http::header::CONTENT_TYPE, "application/json")], body.to_string()).into_response()
+}
+
+/// Rewrites the body of feeds and search descriptions with `map`; other responses pass.
+async fn map_catalog(
+    mut parts: axum::http::response::Parts,
+    body: Body,
+    map: impl FnOnce(&str) -> String,
+) -> Response {
+    use axum::http::header;
+
+    let content_type = parts.headers.get(header::CONTENT_TYPE).and_then(|c| c.to_str().ok()).unwrap_or("");
+    let is_catalog = ["atom+xml", "opds+json", "opensearchdescription+xml"].iter().any(|t| content_type.contains(t));
+    if !is_catalog {
+        return Response::from_parts(parts, body);
+    }
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to read catalog response: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let mapped = map(&String::from_utf8_lossy(&bytes));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(mapped))
+}
+
+/// Maps a Location pointing at ABS to the address the client used to reach it: the
+/// proxy when `USE_PROXY` is on, otherwise the public `ABS_URL`. Other hosts are untouched.
+pub(crate) fn rewrite_location(location: &str, abs_url: &str, use_proxy: bool) -> String {
+    let abs_url = abs_url.trim_end_matches('/');
+    let abs_path = if location.starts_with('/') && !location.starts_with("//") {
+        Some(location)
     } else {
-        target_url
+        location
+            .strip_prefix(abs_url)
+            .filter(|rest| rest.is_empty() || rest.starts_with('/') || rest.starts_with('?'))
     };
 
-    let mut request_builder = state.api_client_raw.get(&full_target_url);
+    match abs_path {
+        Some(path) if use_proxy => format!("{}{}", PROXY_PATH, path),
+        Some(path) => format!("{}{}", abs_url, path),
+        None => location.to_string(),
+    }
+}
+
+/// Sends the request `build` makes to ABS with `bearer`. If ABS rejects the token as
+/// expired and the client can renew it, `bearer` becomes the new one and the request is
+/// sent again with it.
+async fn send_upstream(
+    state: &AppState,
+    bearer: &mut Option<String>,
+    build: impl Fn(Option<&str>) -> reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    let response = build(bearer.as_deref()).send().await?;
+    let Some(token) = bearer.as_deref().filter(|_| response.status() == reqwest::StatusCode::UNAUTHORIZED) else {
+        return Ok(response);
+    };
+    match state.api_client.refresh(token).await {
+        Some(renewed) => build(Some(bearer.insert(renewed))).send().await,
+        None => Ok(response),
+    }
+}
 
+/// Streams an upstream ABS GET response back to the client, forwarding only safe
+/// request headers and stripping hop-by-hop response headers. When `bearer` is set
+/// the request is authenticated server-side with that ABS token.
+async fn forward_upstream(
+    state: &AppState,
+    target_url: &str,
+    bearer: Option<&str>,
+    req_headers: &HeaderMap,
+) -> Response {
     // Forward safe request headers
     let allowed_req_headers = [
         axum::http::header::RANGE,
@@ -516,14 +1344,20 @@ pub async fn proxy_handler(
         axum::http::header::ACCEPT,
         axum::http::header::ACCEPT_ENCODING,
     ];
-
-    for header_name in &allowed_req_headers {
-        if let Some(value) = req.headers().get(header_name) {
-            request_builder = request_builder.header(header_name.clone(), value.clone());
+    let build = |bearer: Option<&str>| {
+        let mut request_builder = state.api_client_raw.get(target_url);
+        if let Some(token) = bearer {
+            request_builder = request_builder.bearer_auth(token);
         }
-    }
+        for header_name in &allowed_req_headers {
+            if let Some(value) = req_headers.get(header_name) {
+                request_builder = request_builder.header(header_name.clone(), value.clone());
+            }
+        }
+        request_builder
+    };
 
-    match request_builder.send().await {
+    match send_upstream(state, &mut bearer.map(str::to_string), build).await {
         Ok(resp) => {
             let mut headers = HeaderMap::new();
             // Convert reqwest status to axum status
@@ -546,6 +1380,16 @@ pub async fn proxy_handler(
                 if hop_by_hop_headers.contains(&k_str.as_str()) {
                     continue;
                 }
+                // reqwest already follows redirects; whatever is left must not leak the ABS address
+                if k_str == "location" || k_str == "content-location" {
+                    if let Some(rewritten) = v.to_str().ok()
+                        .map(|loc| rewrite_location(loc, &state.config.abs_url, state.config.use_proxy))
+                        .and_then(|loc| axum::http::HeaderValue::from_str(&loc).ok())
+                    {
+                        headers.insert(k.clone(), rewritten);
+                    }
+                    continue;
+                }
                 if let Ok(h_name) = axum::http::header::HeaderName::from_bytes(k.as_str().as_bytes()) {
                      if let Ok(h_val) = axum::http::header::HeaderValue::from_bytes(v.as_bytes()) {
                           headers.insert(h_name, h_val);
@@ -560,7 +1404,7 @@ pub async fn proxy_handler(
         }
         Err(e) => {
             tracing::error!("Proxy error: {}", e);
-            (StatusCode::BAD_GATEWAY, "Bad Gateway").into_response()
+            AppError::from(e).into_response()
         }
     }
 }
@@ -1,65 +1,283 @@
 use serde_json::Value;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
 
+/// Translations compiled into the binary, so the server works from any working directory.
+const EMBEDDED_LANGUAGES: [(&str, &str); 3] = [
+    ("en", include_str!("../languages/en.json")),
+    ("de", include_str!("../languages/de.json")),
+    ("cs", include_str!("../languages/cs.json")),
+];
+
+/// Every translation key the code looks up. Keep in sync when adding strings; a test
+/// checks this list against the `localize` calls in the source.
+pub const USED_KEYS: &[&str] = &[
+    "auth.login",
+    "auth.password",
+    "category.all",
+    "category.authors",
+    "category.collections",
+    "category.continue",
+    "category.count",
+    "category.episodes",
+    "category.genres",
+    "category.letter",
+    "category.narrators",
+    "category.recent",
+    "category.series",
+    "category.shows",
+    "category.titles",
+    "category.tracks",
+    "category.updated",
+    "facet.format",
+    "facet.sort",
+    "feed.categories",
+    "feed.error",
+    "feed.libraries",
+    "feed.library_list",
+    "format.all",
+    "format.audiobooks",
+    "landing.app.aldiko",
+    "landing.app.koreader",
+    "landing.app.moon",
+    "landing.app.other",
+    "landing.app.thorium",
+    "landing.apps",
+    "landing.credentials",
+    "landing.intro",
+    "landing.public",
+    "landing.title",
+    "library.count",
+    "library.count_one",
+    "link.search",
+    "link.search_scope",
+    "link.web_interface",
+    "number.thousands",
+    "search.description",
+    "sort.added",
+    "sort.author",
+    "sort.published",
+    "sort.series",
+    "sort.title",
+    "webhook.added",
+];
+
+/// Missing and unused keys of one language, compared to `USED_KEYS`.
+#[derive(Debug, PartialEq)]
+pub struct TranslationReport {
+    pub language: String,
+    pub missing: Vec<String>,
+    pub unused: Vec<String>,
+}
+
 #[derive(Clone)]
 pub struct I18n {
     localizations: Arc<HashMap<String, Value>>,
     fallback_language: String,
+    fallbacks: Arc<HashMap<String, Vec<String>>>,
+    pinned: Arc<HashMap<String, String>>,
+}
+
+impl Default for I18n {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl I18n {
     pub fn new() -> Self {
-        let mut localizations = HashMap::new();
-
-        if let Ok(json) = serde_json::from_str(include_str!("../languages/en.json")) {
-            localizations.insert("en".to_string(), json);
-        }
-        if let Ok(json) = serde_json::from_str(include_str!("../languages/de.json")) {
-            localizations.insert("de".to_string(), json);
+        I18n {
+            localizations: Arc::new(Self::embedded()),
+            fallback_language: "en".to_string(),
+            fallbacks: Arc::default(),
+            pinned: Arc::default(),
         }
-        if let Ok(json) = serde_json::from_str(include_str!("../languages/cs.json")) {
-            localizations.insert("cs".to_string(), json);
+    }
+
+    /// Loads the embedded translations, then applies any `<lang>.json` files found in
+    /// `dir` on top. Keys in those files replace the embedded ones; unknown languages
+    /// are added. A missing directory is not an error.
+    pub fn with_overrides(dir: &Path) -> Self {
+        let mut localizations = Self::embedded();
+
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::debug!("No translation overrides loaded from {}: {}", dir.display(), e);
+                return I18n {
+                    localizations: Arc::new(localizations),
+                    fallback_language: "en".to_string(),
+                    fallbacks: Arc::default(),
+                    pinned: Arc::default(),
+                };
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(lang) = path.file_stem().and_then(|s| s.to_str()).map(|s| s.to_lowercase()) else {
+                continue;
+            };
+            let overrides = match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| serde_json::from_str::<Value>(&s).map_err(anyhow::Error::from))
+            {
+                Ok(Value::Object(map)) => map,
+                Ok(_) => {
+                    tracing::warn!("Ignoring {}: expected a JSON object", path.display());
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!("Ignoring {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            let target = localizations
+                .entry(lang)
+                .or_insert_with(|| Value::Object(Default::default()));
+            if let Value::Object(target) = target {
+                target.extend(overrides);
+            }
         }
 
         I18n {
             localizations: Arc::new(localizations),
             fallback_language: "en".to_string(),
+            fallbacks: Arc::default(),
+            pinned: Arc::default(),
         }
     }
 
-    pub fn localize(&self, key: &str, lang: Option<&str>) -> String {
-        let localizations = &self.localizations;
-        let language_code = lang
-            .and_then(|l| l.split('-').next())
-            .map(|l| l.to_lowercase())
-            .unwrap_or_else(|| self.fallback_language.clone());
-
-        let language = if localizations.contains_key(&language_code) {
-            &language_code
-        } else {
-            &self.fallback_language
-        };
+    fn embedded() -> HashMap<String, Value> {
+        EMBEDDED_LANGUAGES
+            .iter()
+            .filter_map(|(lang, json)| serde_json::from_str(json).ok().map(|v| (lang.to_string(), v)))
+            .collect()
+    }
 
-        if let Some(lang_map) = localizations.get(language) {
-            if let Some(val) = lang_map.get(key) {
-                if let Some(s) = val.as_str() {
-                    return s.to_string();
-                }
+    /// Replaces the default language and adds explicit fallbacks, given as comma-separated
+    /// chains like `pt:pt-br,gsw:de-ch:de` (`pt` falls back to `pt-br`, `gsw` to `de-ch`
+    /// and then `de`). Regional tags always fall back to their base language as well.
+    pub fn with_fallbacks(mut self, default_language: &str, fallbacks: &str) -> Self {
+        let mut chains: HashMap<String, Vec<String>> = HashMap::new();
+        for chain in fallbacks.split(',') {
+            let mut parts = chain.split(':').map(normalize_tag).filter(|p| !p.is_empty());
+            if let Some(from) = parts.next() {
+                chains.entry(from).or_default().extend(parts);
             }
         }
+        self.fallback_language = normalize_tag(default_language);
+        self.fallbacks = Arc::new(chains);
+        self
+    }
 
-        // Fallback
-        if language != &self.fallback_language {
-            if let Some(lang_map) = localizations.get(&self.fallback_language) {
-                 if let Some(val) = lang_map.get(key) {
-                    if let Some(s) = val.as_str() {
-                        return s.to_string();
-                    }
-                }
+    /// The languages to try for an `Accept-Language` value, most preferred first,
+    /// ending with the default language.
+    pub fn candidates(&self, lang: Option<&str>) -> Vec<String> {
+        let mut tags: Vec<(String, f32)> = lang
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|part| {
+                let mut pieces = part.split(';');
+                let tag = normalize_tag(pieces.next()?);
+                let q = pieces
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && tag != "*" && q > 0.0).then_some((tag, q))
+            })
+            .collect();
+        tags.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut candidates = Vec::new();
+        for (tag, _) in tags {
+            self.push_chain(&tag, &mut candidates);
+        }
+        self.push_chain(&self.fallback_language.clone(), &mut candidates);
+        candidates
+    }
+
+    fn push_chain(&self, tag: &str, candidates: &mut Vec<String>) {
+        if tag.is_empty() || candidates.iter().any(|c| c == tag) {
+            return;
+        }
+        candidates.push(tag.to_string());
+        if let Some(fallbacks) = self.fallbacks.get(tag) {
+            for fallback in fallbacks {
+                self.push_chain(fallback, candidates);
             }
         }
+        if let Some((base, _)) = tag.rsplit_once('-') {
+            self.push_chain(base, candidates);
+        }
+    }
 
-        key.to_string()
+    /// Fixes `key` to `value` in every language, e.g. for operator-configured labels.
+    /// `None` keeps the translations.
+    pub fn with_pinned(mut self, key: &str, value: Option<&str>) -> Self {
+        if let Some(value) = value.filter(|v| !v.trim().is_empty()) {
+            Arc::make_mut(&mut self.pinned).insert(key.to_string(), value.to_string());
+        }
+        self
     }
+
+    pub fn localize(&self, key: &str, lang: Option<&str>) -> String {
+        if let Some(value) = self.pinned.get(key) {
+            return value.clone();
+        }
+        self.candidates(lang)
+            .iter()
+            .filter_map(|language| self.localizations.get(language))
+            .find_map(|lang_map| lang_map.get(key).and_then(|v| v.as_str()))
+            .map(str::to_string)
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    /// Compares every loaded language against `USED_KEYS`, sorted by language.
+    pub fn report(&self) -> Vec<TranslationReport> {
+        let mut reports: Vec<TranslationReport> = self
+            .localizations
+            .iter()
+            .map(|(language, map)| {
+                let keys: Vec<&str> = map.as_object().map(|o| o.keys().map(String::as_str).collect()).unwrap_or_default();
+                let mut missing: Vec<String> = USED_KEYS.iter().filter(|k| !keys.contains(k)).map(|k| k.to_string()).collect();
+                let mut unused: Vec<String> = keys.iter().filter(|k| !USED_KEYS.contains(k)).map(|k| k.to_string()).collect();
+                missing.sort();
+                unused.sort();
+                TranslationReport { language: language.clone(), missing, unused }
+            })
+            .collect();
+        reports.sort_by(|a, b| a.language.cmp(&b.language));
+        reports
+    }
+
+    /// `count` with its digits grouped by the language's thousands separator, e.g. `3,214`.
+    pub fn format_count(&self, count: usize, lang: Option<&str>) -> String {
+        let separator = self.localize("number.thousands", lang);
+        let digits = count.to_string();
+        let mut out = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                out.push_str(&separator);
+            }
+            out.push(digit);
+        }
+        out
+    }
+
+    /// Like `localize`, replacing `{name}` placeholders with the given values.
+    pub fn localize_with(&self, key: &str, lang: Option<&str>, args: &[(&str, &str)]) -> String {
+        args.iter().fold(self.localize(key, lang), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+    }
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().replace('_', "-").to_lowercase()
 }
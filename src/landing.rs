@@ -0,0 +1,68 @@
+//! The HTML page at `/`: the catalog address, the discovery links readers follow when
+//! given only the server address, and how to add the catalog in common apps.
+
+use axum::http::HeaderMap;
+use quick_xml::escape::escape;
+
+use crate::i18n::I18n;
+
+/// Apps with their setup steps, in the order they are listed.
+const APPS: [(&str, &str); 4] = [
+    ("KOReader", "landing.app.koreader"),
+    ("Moon+ Reader", "landing.app.moon"),
+    ("Aldiko", "landing.app.aldiko"),
+    ("Thorium Reader", "landing.app.thorium"),
+];
+
+/// Where the client reached us, e.g. `https://books.example.com`, from the `Host` header
+/// or a reverse proxy's `X-Forwarded-*` headers. Empty without a host.
+pub fn request_origin(headers: &HeaderMap) -> String {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    match header("x-forwarded-host").or_else(|| header("host")) {
+        Some(host) => format!("{}://{}", header("x-forwarded-proto").unwrap_or("http"), host),
+        None => String::new(),
+    }
+}
+
+/// The page for a catalog at `{origin}{base}/opds`; `public` lists the public catalog too.
+pub fn render(base: &str, origin: &str, public: bool, i18n: &I18n, lang: Option<&str>) -> String {
+    let catalog = escape(format!("{}/opds", base)).into_owned();
+    let address = escape(format!("{}{}/opds", origin, base)).into_owned();
+    let text = |key: &str| escape(i18n.localize(key, lang)).into_owned();
+
+    let mut links = format!(
+        "<link rel=\"alternate\" type=\"application/atom+xml;profile=opds-catalog;kind=navigation\" href=\"{catalog}\">\n\
+         <link rel=\"alternate\" type=\"application/opds+json\" href=\"{catalog}\">\n"
+    );
+    let mut public_section = String::new();
+    if public {
+        links.push_str(&format!(
+            "<link rel=\"alternate\" type=\"application/atom+xml;profile=opds-catalog;kind=acquisition\" href=\"{catalog}/public\">\n"
+        ));
+        public_section = format!("<p>{}</p>\n<p><code>{}/public</code></p>\n", text("landing.public"), address);
+    }
+    let apps: String = APPS
+        .iter()
+        .map(|(app, key)| format!("<li><strong>{}</strong>: {}</li>\n", app, text(key)))
+        .collect();
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n{links}</head>\n<body>\n<h1>{title}</h1>\n\
+         <p>{intro}</p>\n<p><code>{address}</code></p>\n<p>{credentials}</p>\n{public_section}\
+         <h2>{apps_title}</h2>\n<ul>\n{apps}<li>{other}</li>\n</ul>\n</body>\n</html>\n",
+        title = text("landing.title"),
+        intro = text("landing.intro"),
+        credentials = text("landing.credentials"),
+        apps_title = text("landing.apps"),
+        other = text("landing.app.other"),
+    )
+}
@@ -1,5 +1,5 @@
 use axum::{
-    routing::{get, any},
+    routing::{get, any, delete, post},
     Router,
 };
 use std::sync::Arc;
@@ -7,21 +7,45 @@
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 pub mod api;
+pub mod archive;
 pub mod auth;
+pub mod cache;
+pub mod cache_control;
+pub mod cli;
+pub mod config_file;
+pub mod covers;
+pub mod deadline;
+pub mod demo;
+pub mod downloads;
+pub mod epub;
+pub mod error;
+pub mod export;
 pub mod handlers;
 pub mod i18n;
+pub mod landing;
+pub mod metrics;
+pub mod mime;
 pub mod models;
+pub mod opds;
+pub mod query;
+pub mod recording;
+pub mod series;
+pub mod share;
 pub mod service;
+pub mod webhooks;
 pub mod xml;
 pub mod opds2;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 pub mod tests;
 #[cfg(test)]
-#[path = "performance_tests.rs"]
-pub mod performance_tests;
+mod golden_tests;
 
 use api::AbsClient;
 use api::ApiClient;
+use auth::AuthPolicy;
 use i18n::I18n;
 use models::AppConfig;
 use service::LibraryService;
@@ -33,65 +57,256 @@ pub struct AppState {
     pub api_client_raw: reqwest::Client,
     pub service: LibraryService<dyn AbsClient + Send + Sync>,
     pub anonymous_user: tokio::sync::RwLock<Option<(crate::models::InternalUser, tokio::time::Instant)>>,
+    pub cover_cache: covers::CoverCache,
+    pub login_throttle: auth::throttle::Throttle,
+    logins: std::sync::RwLock<Arc<Logins>>,
 }
 
-pub async fn build_app_state(config: AppConfig) -> Arc<AppState> {
-    let i18n = I18n::new();
+/// Who can log in: the users of `OPDS_USERS` and the auth backends. Reloading the
+/// configuration replaces them while requests already checked carry on.
+pub struct Logins {
+    pub users: Vec<models::InternalUser>,
+    /// The readers of `OPDS_USER_MAP`, by the name they log in with.
+    pub user_map: std::collections::HashMap<String, models::UserMapping>,
+    pub backends: Vec<Arc<dyn auth::backend::AuthBackend>>,
+}
 
-    // Create shared reqwest Client with 10 seconds timeout
-    let api_client_raw = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .build()
-        .unwrap_or_else(|_| reqwest::Client::new());
+impl Logins {
+    fn from_config(config: &AppConfig, api_client: Arc<dyn AbsClient + Send + Sync>) -> Self {
+        Self {
+            users: config.internal_users.clone(),
+            user_map: config.user_map.clone(),
+            backends: auth::backend::from_config(config, api_client),
+        }
+    }
 
-    let api_client = Arc::new(ApiClient::new(config.abs_url.clone(), api_client_raw.clone()));
-    let client_dyn: Arc<dyn AbsClient + Send + Sync> = api_client;
+    /// The ABS user `username` logs in to ABS as: the one it is mapped to, or itself.
+    pub fn abs_username<'a>(&'a self, username: &'a str) -> &'a str {
+        self.user_map
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(username))
+            .map_or(username, |(_, mapping)| mapping.abs_username.as_str())
+    }
+}
 
-    let service = LibraryService::new(client_dyn.clone(), config.clone(), i18n.clone());
+impl AppState {
+    /// Assembles the state from its parts, for embedding the catalog in another axum
+    /// application. `http_client` is used for proxied downloads and covers.
+    pub fn new(config: AppConfig, api_client: Arc<dyn AbsClient + Send + Sync>, http_client: reqwest::Client) -> Arc<Self> {
+        let i18n = I18n::with_overrides(std::path::Path::new(&config.languages_dir))
+            .with_fallbacks(&config.default_language, &config.language_fallbacks)
+            .with_pinned("auth.login", config.opds_login_label.as_deref())
+            .with_pinned("auth.password", config.opds_password_label.as_deref());
+        let service = LibraryService::new(api_client.clone(), config.clone(), i18n.clone());
+        let cover_cache = covers::CoverCache::from_config(&config);
+        let logins = Logins::from_config(&config, api_client.clone());
+        let login_throttle = auth::throttle::Throttle::new(
+            config.login_max_failures,
+            std::time::Duration::from_secs(config.login_lockout_secs),
+            config.trusted_proxies().unwrap_or_default(),
+        );
 
-    Arc::new(AppState {
-        config,
-        api_client: client_dyn,
-        i18n,
-        api_client_raw,
-        service,
-        anonymous_user: tokio::sync::RwLock::new(None),
-    })
+        Arc::new(AppState {
+            config,
+            api_client,
+            i18n,
+            api_client_raw: http_client,
+            service,
+            anonymous_user: tokio::sync::RwLock::new(None),
+            cover_cache,
+            login_throttle,
+            logins: std::sync::RwLock::new(Arc::new(logins)),
+        })
+    }
+
+    /// The logins as of now.
+    pub fn logins(&self) -> Arc<Logins> {
+        self.logins.read().unwrap().clone()
+    }
+
+    /// Reads the configuration again, from the environment and the `CONFIG_FILE` the
+    /// catalog started with, and swaps in its logins, returning how many users there
+    /// are. Only logins change; other settings still need a restart. Without a file
+    /// the users it was built with are kept, as an app embedding the catalog may not
+    /// have set any variables, and only the htpasswd file is read again.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let reloaded;
+        let config = if self.config.config_file.trim().is_empty() {
+            &self.config
+        } else {
+            let vars = std::env::vars()
+                .filter(|(key, _)| !key.eq_ignore_ascii_case("CONFIG_FILE"))
+                .chain([("CONFIG_FILE".to_string(), self.config.config_file.clone())]);
+            reloaded = load_config_from(vars)?;
+            &reloaded
+        };
+        let logins = Logins::from_config(config, self.api_client.clone());
+        let users = logins.users.len() + logins.user_map.len();
+        *self.logins.write().unwrap() = Arc::new(logins);
+        Ok(users)
+    }
 }
 
-pub async fn build_app_state_with_mock(
-    config: AppConfig,
-    mock_client: Arc<dyn AbsClient + Send + Sync>
-) -> Arc<AppState> {
-    let i18n = I18n::new();
-    let api_client_raw = reqwest::Client::builder()
+fn http_client() -> reqwest::Client {
+    // Shared reqwest Client with 10 seconds timeout
+    reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(10))
         .build()
-        .unwrap_or_else(|_| reqwest::Client::new());
-
-    let service = LibraryService::new(mock_client.clone(), config.clone(), i18n.clone());
+        .unwrap_or_else(|_| reqwest::Client::new())
+}
 
-    Arc::new(AppState {
-        config,
-        api_client: mock_client,
-        i18n,
-        api_client_raw,
-        service,
-        anonymous_user: tokio::sync::RwLock::new(None),
-    })
+pub async fn build_app_state(config: AppConfig) -> Arc<AppState> {
+    let http_client = http_client();
+    if config.demo_mode {
+        return AppState::new(config, Arc::new(demo::DemoClient::new()), http_client);
+    }
+    if !config.abs_replay_dir.is_empty() {
+        let replay = recording::ReplayClient::new(&config.abs_replay_dir);
+        return AppState::new(config, Arc::new(replay), http_client);
+    }
+    let api_client = match cache::open_shared(&config.cache_url) {
+        Some(store) => ApiClient::with_cache(config.abs_url.clone(), http_client.clone(), store),
+        None => ApiClient::new(config.abs_url.clone(), http_client.clone()),
+    };
+    let api_client = api_client
+        .with_token_cache(std::time::Duration::from_secs(config.token_cache_ttl_secs), config.token_cache_max_users)
+        .with_items_cache(std::time::Duration::from_secs(config.items_cache_ttl_secs), config.items_cache_max_entries);
+    let api_client: Arc<dyn AbsClient + Send + Sync> = if config.abs_record_dir.is_empty() {
+        Arc::new(api_client)
+    } else {
+        Arc::new(recording::RecordingClient::new(Arc::new(api_client), &config.abs_record_dir))
+    };
+    AppState::new(config, api_client, http_client)
 }
 
 pub fn build_router(state: Arc<AppState>) -> Router {
-    Router::new()
+    let mut public = Router::new()
+        .route("/health", get(handlers::health))
+        .route("/opds/auth", get(handlers::auth_document))
+        .route("/opds/libraries/{library_id}/search-definition", get(handlers::search_definition));
+    if state.config.metrics_enabled {
+        public = public.route("/metrics", get(handlers::metrics));
+    }
+    // A root alias serves the catalog itself there instead
+    if state.config.opds_landing_page && !state.config.root_aliases().iter().any(|alias| alias == "/") {
+        public = public.route("/", get(handlers::landing_page));
+    }
+
+    let user = Router::new()
         .route("/opds", get(handlers::get_opds_root))
         .route("/opds/libraries/{library_id}", get(handlers::get_library))
-        .route("/opds/libraries/{library_id}/search-definition", get(handlers::search_definition))
+        .route("/opds/libraries/{library_id}/updated", get(handlers::get_recently_updated))
+        .route("/opds/libraries/{library_id}/recent", get(handlers::get_recently_added))
+        .route("/opds/libraries/{library_id}/continue", get(handlers::get_in_progress))
+        .route("/opds/libraries/{library_id}/episodes", get(handlers::get_episodes))
+        .route("/opds/libraries/{library_id}/collections", get(handlers::get_collections))
+        .route("/opds/libraries/{library_id}/collections/{collection_id}", get(handlers::get_collection))
+        .route("/opds/libraries/{library_id}/cover", get(handlers::get_library_cover))
         .route("/opds/libraries/{library_id}/{type}", get(handlers::get_category))
-        .route("/opds/proxy/{*any}", any(handlers::proxy_handler))
+        .route("/opds/covers/{item_id}", get(handlers::get_cover))
+        .route("/opds/items/{item_id}/files/{file_id}", get(handlers::get_library_file))
+        .route("/opds/items/{item_id}/audiobook.zip", get(handlers::get_audiobook_zip))
+        .route("/opds/items/{item_id}/tracks/{track}", get(handlers::get_audio_track))
+        .route("/opds/proxy/{*any}", any(handlers::proxy_handler));
+    let user = state
+        .config
+        .root_aliases()
+        .iter()
+        .fold(user, |user, alias| user.route(alias, get(handlers::get_opds_root)));
+
+    let mut admin = Router::new()
+        .route("/admin/cache", get(handlers::admin_cache))
+        .route("/admin/reload", post(handlers::admin_reload))
+        .route("/admin/tokens/{username}", delete(handlers::forget_login));
+
+    if state.config.share_user().is_some() {
+        let shared = with_policy(catalog_routes(get(handlers::get_shared_catalog)), &state, AuthPolicy::Share).with_state(state.clone());
+        let share = move |state, req| handlers::share_catalog(state, shared.clone(), req);
+        public = public
+            .route("/opds/share/{token}", get(share.clone()))
+            .route("/opds/share/{token}/{*rest}", get(share));
+        admin = admin.route("/admin/shares", post(handlers::mint_share));
+    }
+
+    Router::new()
+        .merge(with_policy(public, &state, AuthPolicy::Public))
+        .merge(with_policy(user, &state, AuthPolicy::User))
+        .merge(with_policy(admin, &state, AuthPolicy::Admin))
+        .nest(
+            "/opds/public",
+            with_policy(catalog_routes(get(handlers::get_public_catalog)), &state, AuthPolicy::PublicCatalog)
+                .layer(axum::middleware::from_fn(handlers::public_catalog_links)),
+        )
+        .layer(axum::middleware::from_fn_with_state(state.clone(), deadline::enforce))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), cache_control::apply))
         .layer(TraceLayer::new_for_http())
         .with_state(state)
 }
 
+/// The read-only catalog routes of the public catalog and share links, with `root` at `/`.
+fn catalog_routes(root: axum::routing::MethodRouter<Arc<AppState>>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", root)
+        .route("/libraries/{library_id}", get(handlers::get_library))
+        .route("/libraries/{library_id}/search-definition", get(handlers::search_definition))
+        .route("/libraries/{library_id}/updated", get(handlers::get_recently_updated))
+        .route("/libraries/{library_id}/recent", get(handlers::get_recently_added))
+        .route("/libraries/{library_id}/continue", get(handlers::get_in_progress))
+        .route("/libraries/{library_id}/episodes", get(handlers::get_episodes))
+        .route("/libraries/{library_id}/collections", get(handlers::get_collections))
+        .route("/libraries/{library_id}/collections/{collection_id}", get(handlers::get_collection))
+        .route("/libraries/{library_id}/cover", get(handlers::get_library_cover))
+        .route("/libraries/{library_id}/{type}", get(handlers::get_category))
+        .route("/covers/{item_id}", get(handlers::get_cover))
+        .route("/items/{item_id}/files/{file_id}", get(handlers::get_library_file))
+        .route("/items/{item_id}/audiobook.zip", get(handlers::get_audiobook_zip))
+        .route("/items/{item_id}/tracks/{track}", get(handlers::get_audio_track))
+        .route("/proxy/{*any}", any(handlers::proxy_handler))
+}
+
+/// The catalog below `prefix`, e.g. `/books` serves `/books/opds`. Links in feeds and
+/// search descriptions are rebased onto the prefix, so readers stay inside the mount.
+pub fn router_with_prefix(state: Arc<AppState>, prefix: &str) -> Router {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() {
+        return build_router(state);
+    }
+    Router::new()
+        .nest(prefix, build_router(state))
+        .layer(axum::middleware::from_fn_with_state(prefix.to_string(), handlers::rebase_links))
+}
+
+/// Guards every route of `router` with `policy`.
+pub fn with_policy(router: Router<Arc<AppState>>, state: &Arc<AppState>, policy: AuthPolicy) -> Router<Arc<AppState>> {
+    router.route_layer(axum::middleware::from_fn_with_state((state.clone(), policy), auth::require))
+}
+
+/// The configuration of the environment, parsed and validated.
+pub fn load_config() -> anyhow::Result<AppConfig> {
+    load_config_from(std::env::vars())
+}
+
+/// The configuration of `vars`, read over the `CONFIG_FILE` they name, if any.
+pub fn load_config_from(vars: impl IntoIterator<Item = (String, String)>) -> anyhow::Result<AppConfig> {
+    let vars = config_file::read_secret_files(vars.into_iter().map(|(key, value)| (key.to_lowercase(), value)).collect())?;
+    let file = match vars.iter().find(|(key, value)| key == "config_file" && !value.trim().is_empty()) {
+        Some((_, path)) => config_file::read(std::path::Path::new(path.trim()))?,
+        None => config_file::ConfigFile::default(),
+    };
+    // The environment overrides the file, including its users
+    let users_from_env = vars.iter().any(|(key, _)| key == "opds_users");
+    let mut merged: std::collections::HashMap<String, String> = config_file::read_secret_files(file.vars)?.into_iter().collect();
+    merged.extend(vars);
+
+    let mut config = envy::from_iter::<_, AppConfig>(merged).map_err(|e| anyhow::anyhow!("Failed to load configuration: {}", e))?;
+    config.parse_users().map_err(|e| anyhow::anyhow!("Configuration error: {}", e))?;
+    if !users_from_env {
+        config.internal_users.extend(file.users);
+    }
+    config.validate().map_err(|e| anyhow::anyhow!("Configuration validation failed: {}", e))?;
+    Ok(config)
+}
+
 pub async fn run() {
     dotenvy::dotenv().ok();
 
@@ -103,35 +318,156 @@ pub async fn run() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let mut config = envy::from_env::<AppConfig>().expect("Failed to load configuration");
-    if let Err(e) = config.parse_users() {
-        tracing::error!("Configuration error: {}", e);
-        std::process::exit(1);
-    }
-    if let Err(e) = config.validate() {
-        tracing::error!("Configuration validation failed: {}", e);
-        std::process::exit(1);
-    }
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let port = config.port;
+    let addresses = config.bind_addresses().expect("validated by load_config");
+    let drain = std::time::Duration::from_secs(config.shutdown_timeout_secs);
     let abs_url = config.abs_url.clone();
+    let demo_mode = config.demo_mode;
 
     let state = build_app_state(config).await;
+    webhooks::spawn(state.clone());
+    reload_on_hangup(state.clone());
     let app = build_router(state);
 
-    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
-    tracing::info!("OPDS server running at http://{}", addr);
-    tracing::info!("Server URL: {}", abs_url);
+    if demo_mode {
+        tracing::warn!("Demo mode: serving the built-in sample library, any credentials log in");
+    } else {
+        tracing::info!("Server URL: {}", abs_url);
+    }
 
-    let listener = match tokio::net::TcpListener::bind(addr).await {
-        Ok(l) => l,
-        Err(e) => {
-            tracing::error!("Failed to bind to address {}: {}", addr, e);
-            std::process::exit(1);
+    if let Err(e) = serve(app, &addresses, shutdown_signal(), drain).await {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+    cache::flush_all().await;
+    tracing::info!("Stopped");
+}
+
+/// Reloads the logins whenever the process gets SIGHUP.
+fn reload_on_hangup(state: Arc<AppState>) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => signal,
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            match state.reload() {
+                Ok(users) => tracing::info!("Reloaded the configuration, {} users", users),
+                Err(e) => tracing::error!("Kept the logins, reloading the configuration failed: {}", e),
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+/// Resolves on SIGINT, or SIGTERM as sent by `docker stop`.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
         }
     };
-    if let Err(e) = axum::serve(listener, app).await {
-        tracing::error!("Server error: {}", e);
-        std::process::exit(1);
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serves `app` on every address until one of them fails, or until `shutdown`
+/// resolves: then no new connections are accepted and open ones get `drain` to
+/// finish. All addresses are bound before any is served, so a taken port is reported
+/// at startup.
+pub async fn serve(
+    app: Router,
+    addresses: &[models::BindAddress],
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+    drain: std::time::Duration,
+) -> anyhow::Result<()> {
+    let (stop, stopped) = tokio::sync::watch::channel(false);
+    let stopping = async move |mut stopped: tokio::sync::watch::Receiver<bool>| {
+        stopped.wait_for(|stop| *stop).await.ok();
+    };
+    let mut servers = tokio::task::JoinSet::new();
+    for address in addresses {
+        match address {
+            models::BindAddress::Tcp(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to bind to address {}: {}", addr, e))?;
+                // Login throttling counts failures by the client's address
+                let (app, stopping) = (app.clone().into_make_service_with_connect_info::<std::net::SocketAddr>(), stopping(stopped.clone()));
+                servers.spawn(async move { axum::serve(listener, app).with_graceful_shutdown(stopping).await });
+            }
+            #[cfg(unix)]
+            models::BindAddress::Unix(path) => {
+                // A socket left behind by an earlier run would make binding fail
+                if std::fs::symlink_metadata(path).is_ok_and(|m| std::os::unix::fs::FileTypeExt::is_socket(&m.file_type())) {
+                    std::fs::remove_file(path).ok();
+                }
+                let listener = tokio::net::UnixListener::bind(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to bind to socket {}: {}", path.display(), e))?;
+                let (app, stopping) = (app.clone(), stopping(stopped.clone()));
+                servers.spawn(async move { axum::serve(listener, app).with_graceful_shutdown(stopping).await });
+            }
+            #[cfg(not(unix))]
+            models::BindAddress::Unix(path) => {
+                return Err(anyhow::anyhow!("Unix sockets are not supported here: {}", path.display()));
+            }
+        }
+        tracing::info!("OPDS server running at {}", address);
+    }
+
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            result = servers.join_next() => match result {
+                Some(result) => result?.map_err(|e| anyhow::anyhow!("Server error: {}", e))?,
+                None => return Ok(()),
+            },
+            _ = &mut shutdown => break,
+        }
+    }
+
+    tracing::info!("Shutting down, waiting up to {} s for open connections", drain.as_secs());
+    stop.send_replace(true);
+    let drained = tokio::time::timeout(drain, async {
+        while let Some(result) = servers.join_next().await {
+            if let Ok(Err(e)) = result {
+                tracing::warn!("Server error while shutting down: {}", e);
+            }
+        }
+    })
+    .await;
+    if drained.is_err() {
+        tracing::warn!("Closing connections still open after {} s", drain.as_secs());
+        servers.shutdown().await;
     }
+    Ok(())
 }
@@ -2,5 +2,9 @@
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = abs_opds::cli::run(&args) {
+        std::process::exit(code);
+    }
     run().await;
 }
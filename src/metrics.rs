@@ -0,0 +1,114 @@
+//! Process-wide metrics, served in the Prometheus text format at `/metrics` when
+//! `METRICS_ENABLED` is on: feed build phases, and lookups and sizes of the caches.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the duration buckets, in seconds.
+const BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative; `render` adds them up.
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+struct Registry {
+    /// Keyed by operation and phase.
+    phases: BTreeMap<(&'static str, &'static str), Histogram>,
+    /// Keyed by cache and `hit` or `miss`.
+    cache_lookups: BTreeMap<(&'static str, &'static str), u64>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    phases: BTreeMap::new(),
+    cache_lookups: BTreeMap::new(),
+});
+
+/// Records how long one phase of an operation took, e.g. the upstream fetch of
+/// `get_filtered_items`.
+pub fn record_phase(operation: &'static str, phase: &'static str, elapsed: Duration) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.phases.entry((operation, phase)).or_default().observe(elapsed.as_secs_f64());
+}
+
+/// Times consecutive phases of one operation.
+pub struct PhaseTimer {
+    operation: &'static str,
+    last: Instant,
+}
+
+impl PhaseTimer {
+    pub fn start(operation: &'static str) -> Self {
+        Self { operation, last: Instant::now() }
+    }
+
+    /// Records and returns the time since the previous phase ended, or since the start.
+    pub fn phase(&mut self, phase: &'static str) -> Duration {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        record_phase(self.operation, phase, elapsed);
+        self.last = now;
+        elapsed
+    }
+}
+
+/// Counts a lookup in one of the caches (`token`, `items` or `covers`).
+pub fn cache_lookup(cache: &'static str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    tracing::debug!("{} cache {}", cache, result);
+    *REGISTRY.lock().unwrap().cache_lookups.entry((cache, result)).or_default() += 1;
+}
+
+/// All metrics in the Prometheus text exposition format.
+pub async fn render() -> String {
+    let mut entries: BTreeMap<&'static str, usize> = BTreeMap::new();
+    for cache in crate::cache::registered() {
+        if let Some(count) = cache.store.count(cache.prefix).await {
+            *entries.entry(cache.name).or_default() += count;
+        }
+    }
+
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+    out.push_str("# HELP abs_opds_phase_duration_seconds Time spent per phase of building a feed.\n");
+    out.push_str("# TYPE abs_opds_phase_duration_seconds histogram\n");
+    for ((operation, phase), histogram) in &registry.phases {
+        let labels = format!("operation=\"{}\",phase=\"{}\"", operation, phase);
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "abs_opds_phase_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, cumulative);
+        }
+        let _ = writeln!(out, "abs_opds_phase_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, histogram.count);
+        let _ = writeln!(out, "abs_opds_phase_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+        let _ = writeln!(out, "abs_opds_phase_duration_seconds_count{{{}}} {}", labels, histogram.count);
+    }
+
+    out.push_str("# HELP abs_opds_cache_lookups_total Cache lookups by cache and result.\n");
+    out.push_str("# TYPE abs_opds_cache_lookups_total counter\n");
+    for ((cache, result), count) in &registry.cache_lookups {
+        let _ = writeln!(out, "abs_opds_cache_lookups_total{{cache=\"{}\",result=\"{}\"}} {}", cache, result, count);
+    }
+
+    out.push_str("# HELP abs_opds_cache_entries Live entries per cache.\n");
+    out.push_str("# TYPE abs_opds_cache_entries gauge\n");
+    for (cache, count) in entries {
+        let _ = writeln!(out, "abs_opds_cache_entries{{cache=\"{}\"}} {}", cache, count);
+    }
+    out
+}
@@ -0,0 +1,52 @@
+//! MIME types of the files we link to, by file extension or ABS `ebookFormat`. Supporting
+//! another format is one more row in [`TYPES`].
+
+/// Files we can't tell the type of.
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Extensions and their MIME types. An extension may appear twice for an alias, and the
+/// first extension of a type is the one its downloads are named with.
+const TYPES: &[(&str, &str)] = &[
+    ("epub", "application/epub+zip"),
+    ("kepub", "application/kepub+zip"),
+    ("pdf", "application/pdf"),
+    ("mobi", "application/x-mobipocket-ebook"),
+    ("azw3", "application/vnd.amazon.mobi8-ebook"),
+    ("azw", "application/vnd.amazon.ebook"),
+    ("fb2", "application/x-fictionbook+xml"),
+    ("cbz", "application/vnd.comicbook+zip"),
+    ("cbr", "application/vnd.comicbook-rar"),
+    ("cb7", "application/x-cb7"),
+    ("txt", "text/plain"),
+    ("rtf", "application/rtf"),
+    ("zip", "application/zip"),
+    ("mp3", "audio/mpeg"),
+    ("m4b", "audio/mp4"),
+    ("m4b", "audio/x-m4b"),
+    ("m4a", "audio/mp4"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("flac", "audio/flac"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("webp", "image/webp"),
+];
+
+/// The MIME type of `extension`, in any case.
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    let extension = extension.trim().trim_start_matches('.').to_ascii_lowercase();
+    TYPES.iter().find(|(ext, _)| *ext == extension).map(|(_, mime)| *mime)
+}
+
+/// The extension files of `mime` get, ignoring parameters such as `; charset=`.
+pub fn extension(mime: &str) -> Option<&'static str> {
+    let mime = mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    TYPES.iter().find(|(_, m)| *m == mime).map(|(ext, _)| *ext)
+}
+
+/// The MIME type of an ABS ebook format such as `"azw3"`; unknown formats, and
+/// audiobooks without an ebook, are [`OCTET_STREAM`].
+pub fn from_format(format: Option<&str>) -> &'static str {
+    format.and_then(from_extension).unwrap_or(OCTET_STREAM)
+}
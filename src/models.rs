@@ -1,4 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The name of the user that reads the public catalog.
+pub const PUBLIC_USER: &str = "public";
+/// The user that share links read as.
+pub const SHARE_USER: &str = "share";
+/// The user libraries are checked for new items as, with `WEBHOOK_API_KEY`.
+pub const WEBHOOK_USER: &str = "webhooks";
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct InternalUser {
@@ -22,6 +30,42 @@ pub struct Library {
     pub id: String,
     pub name: String,
     pub icon: Option<String>,
+    #[serde(default)]
+    pub media_type: MediaType,
+}
+
+/// What a library holds, as ABS marks it; each kind gets its own category cards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Podcast,
+    Music,
+    /// Also what other and unknown types read as.
+    #[default]
+    #[serde(other)]
+    Book,
+}
+
+impl MediaType {
+    /// The translation key of the card listing every item.
+    pub fn all_items_key(self) -> &'static str {
+        match self {
+            MediaType::Book => "category.all",
+            MediaType::Podcast => "category.shows",
+            MediaType::Music => "category.tracks",
+        }
+    }
+
+    /// The category cards after the one listing every item. Podcasts and music have no
+    /// narrators or series, and podcasts list their newest episodes instead of authors.
+    /// Books start with the ones the user is reading.
+    pub fn categories(self) -> &'static [&'static str] {
+        match self {
+            MediaType::Book => &["continue", "authors", "narrators", "genres", "series", "collections", "titles", "recent", "updated"],
+            MediaType::Podcast => &["episodes", "genres", "titles", "recent", "updated"],
+            MediaType::Music => &["genres", "titles", "recent", "updated"],
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,21 +89,51 @@ pub struct LibraryItem {
     pub narrators: Vec<Author>,
     #[serde(default)]
     pub series: Vec<String>,
+    /// Position within `series[0]`, e.g. `"1"` or `"2.5"`.
+    #[serde(default)]
+    pub series_sequence: Option<String>,
+    pub format: Option<String>,
+    #[serde(default)]
+    pub audio_file_count: usize,
+    /// Other copies of the same book merged into this entry by `MERGE_EDITIONS`.
+    #[serde(default)]
+    pub editions: Vec<Edition>,
+    /// When ABS last changed the item, as RFC 3339.
+    #[serde(default)]
+    pub updated: Option<String>,
+}
+
+/// The parts of an item needed for its acquisition links.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Edition {
+    pub id: String,
     pub format: Option<String>,
+    #[serde(default)]
+    pub audio_file_count: usize,
 }
 
 impl LibraryItem {
+    /// This item followed by the editions merged into it.
+    pub fn all_editions(&self) -> impl Iterator<Item = Edition> + '_ {
+        let own = Edition {
+            id: self.id.clone(),
+            format: self.format.clone(),
+            audio_file_count: self.audio_file_count,
+        };
+        std::iter::once(own).chain(self.editions.iter().cloned())
+    }
+
     pub fn matches_search(&self, term: &str) -> bool {
         if term.is_empty() {
             return true;
         }
-        self.title.as_deref().map_or(false, |s| s.to_lowercase().contains(term)) ||
-        self.subtitle.as_deref().map_or(false, |s| s.to_lowercase().contains(term)) ||
-        self.description.as_deref().map_or(false, |s| s.to_lowercase().contains(term)) ||
-        self.publisher.as_deref().map_or(false, |s| s.to_lowercase().contains(term)) ||
-        self.isbn.as_deref().map_or(false, |s| s.to_lowercase().contains(term)) ||
-        self.language.as_deref().map_or(false, |s| s.to_lowercase().contains(term)) ||
-        self.published_year.as_deref().map_or(false, |s| s.to_lowercase().contains(term)) ||
+        self.title.as_deref().is_some_and(|s| s.to_lowercase().contains(term)) ||
+        self.subtitle.as_deref().is_some_and(|s| s.to_lowercase().contains(term)) ||
+        self.description.as_deref().is_some_and(|s| s.to_lowercase().contains(term)) ||
+        self.publisher.as_deref().is_some_and(|s| s.to_lowercase().contains(term)) ||
+        self.isbn.as_deref().is_some_and(|s| s.to_lowercase().contains(term)) ||
+        self.language.as_deref().is_some_and(|s| s.to_lowercase().contains(term)) ||
+        self.published_year.as_deref().is_some_and(|s| s.to_lowercase().contains(term)) ||
         self.authors.iter().any(|a| a.name.to_lowercase().contains(term)) ||
         self.genres.iter().any(|g| g.to_lowercase().contains(term)) ||
         self.tags.iter().any(|t| t.to_lowercase().contains(term))
@@ -91,6 +165,201 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     }
 }
 
+/// The acquisition links written for each publication, in the configured order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcquisitionLink {
+    /// ABS `/download`, the original files (a zip for multi-file items).
+    Download,
+    /// ABS `/ebook`, typed with the item's ebook format.
+    Ebook,
+    /// Our on-the-fly zip of multi-file audiobooks.
+    Zip,
+    /// One open-access link per audio track, streamed from ABS, for audio apps that play
+    /// instead of downloading.
+    Stream,
+}
+
+impl AcquisitionLink {
+    pub const DEFAULT_ORDER: [AcquisitionLink; 4] =
+        [AcquisitionLink::Download, AcquisitionLink::Ebook, AcquisitionLink::Zip, AcquisitionLink::Stream];
+}
+
+impl std::str::FromStr for AcquisitionLink {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "download" => Ok(AcquisitionLink::Download),
+            "ebook" => Ok(AcquisitionLink::Ebook),
+            "zip" => Ok(AcquisitionLink::Zip),
+            "stream" => Ok(AcquisitionLink::Stream),
+            other => Err(anyhow::anyhow!(
+                "Invalid acquisition link '{}'. Expected one of: download, ebook, zip, stream",
+                other
+            )),
+        }
+    }
+}
+
+/// How OPDS 1 feeds tell readers to log in, configured by `OPDS_AUTH_BLOCK`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthBlock {
+    /// The non-standard inline `<authentication>` element with the Basic labels.
+    Inline,
+    /// Nothing; readers rely on the 401 challenge.
+    None,
+    /// A link to the authentication document at `/opds/auth`.
+    Link,
+}
+
+impl std::str::FromStr for AuthBlock {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "inline" => Ok(AuthBlock::Inline),
+            "none" => Ok(AuthBlock::None),
+            "link" => Ok(AuthBlock::Link),
+            other => Err(anyhow::anyhow!(
+                "Invalid OPDS_AUTH_BLOCK '{}'. Expected one of: inline, none, link",
+                other
+            )),
+        }
+    }
+}
+
+/// Where the server listens, one of the entries of `BIND_ADDRESS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindAddress {
+    /// An IPv4 or IPv6 address and port.
+    Tcp(std::net::SocketAddr),
+    /// A Unix domain socket, for a reverse proxy on the same host.
+    Unix(std::path::PathBuf),
+}
+
+impl BindAddress {
+    /// Parses `entry`: an IP address, which listens on `port`, an address with its own
+    /// port such as `[::1]:8080`, or `unix:<path>`.
+    pub fn parse(entry: &str, port: u16) -> anyhow::Result<Self> {
+        let entry = entry.trim();
+        if let Some(path) = entry.strip_prefix("unix:") {
+            if path.is_empty() || cfg!(not(unix)) {
+                return Err(anyhow::anyhow!("Invalid BIND_ADDRESS '{}'. Unix sockets need a path, and a Unix system", entry));
+            }
+            return Ok(BindAddress::Unix(path.into()));
+        }
+        if let Ok(addr) = entry.parse::<std::net::SocketAddr>() {
+            return Ok(BindAddress::Tcp(addr));
+        }
+        let ip = entry.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')).unwrap_or(entry);
+        ip.parse::<std::net::IpAddr>()
+            .map(|ip| BindAddress::Tcp(std::net::SocketAddr::new(ip, port)))
+            .map_err(|_| anyhow::anyhow!(
+                "Invalid BIND_ADDRESS '{}'. Expected an IP address, optionally with a port, or unix:<path>",
+                entry
+            ))
+    }
+}
+
+impl std::fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindAddress::Tcp(addr) => write!(f, "http://{}", addr),
+            BindAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A range of addresses in CIDR notation, such as `172.16.0.0/12`, one of the entries
+/// of `TRUSTED_PROXIES`. A bare address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    pub addr: std::net::IpAddr,
+    pub prefix: u8,
+}
+
+impl IpNet {
+    pub fn parse(entry: &str) -> anyhow::Result<Self> {
+        let entry = entry.trim();
+        let invalid = || anyhow::anyhow!("Invalid TRUSTED_PROXIES entry '{}'. Expected an address or a CIDR range such as 10.0.0.0/8", entry);
+        let (addr, prefix) = entry.split_once('/').map_or((entry, None), |(addr, prefix)| (addr, Some(prefix)));
+        let addr: std::net::IpAddr = addr.parse().map_err(|_| invalid())?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|prefix| *prefix <= bits).ok_or_else(invalid)?,
+            None => bits,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    /// Whether `ip` lies in the range. IPv4 addresses mapped to IPv6 count as IPv4.
+    pub fn contains(&self, ip: std::net::IpAddr) -> bool {
+        use std::net::IpAddr;
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        let masked = |bits: u128, width: u32| if self.prefix == 0 { 0 } else { bits >> (width - self.prefix as u32) };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => masked(u32::from(net) as u128, 32) == masked(u32::from(ip) as u128, 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => masked(u128::from(net), 128) == masked(u128::from(ip), 128),
+            _ => false,
+        }
+    }
+}
+
+/// The kinds of responses that get their own `Cache-Control`, each configured by a
+/// `CACHE_CONTROL_*` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheClass {
+    /// Feeds of other feeds: the root, categories and letters.
+    Navigation,
+    /// Feeds of books.
+    Acquisition,
+    /// OpenSearch descriptions.
+    Search,
+    /// Book and library covers.
+    Covers,
+    /// Files, tracks and ZIPs of books, and anything else from the proxy.
+    Downloads,
+}
+
+/// Where Basic credentials are checked, tried in the order configured by
+/// `AUTH_BACKENDS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthBackendKind {
+    /// The users from `OPDS_USERS`.
+    Env,
+    /// An Apache htpasswd file, with every user reading as `HTPASSWD_API_KEY`.
+    Htpasswd,
+    /// A login to ABS with the same credentials.
+    Abs,
+    /// The password is an ABS API token of the user, e.g. for accounts that log in
+    /// with OpenID Connect.
+    AbsToken,
+    /// A bind to an LDAP directory, with every user reading as `LDAP_API_KEY`. Needs a
+    /// build with the `ldap` feature.
+    Ldap,
+}
+
+impl std::str::FromStr for AuthBackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "env" => Ok(AuthBackendKind::Env),
+            "htpasswd" => Ok(AuthBackendKind::Htpasswd),
+            "abs" => Ok(AuthBackendKind::Abs),
+            "abs-token" => Ok(AuthBackendKind::AbsToken),
+            "ldap" => Ok(AuthBackendKind::Ldap),
+            other => Err(anyhow::anyhow!(
+                "Invalid auth backend '{}'. Expected one of: env, htpasswd, abs, abs-token, ldap",
+                other
+            )),
+        }
+    }
+}
+
 // Structures for deserializing ABS API responses
 
 #[derive(Debug, Deserialize, Clone)]
@@ -98,32 +367,125 @@ pub struct AbsLibrariesResponse {
     pub libraries: Vec<AbsLibrary>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AbsLibrary {
     pub id: String,
     pub name: String,
     pub icon: Option<String>,
+    #[serde(rename = "mediaType", default)]
+    pub media_type: MediaType,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// A page of a podcast library's newest episodes.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AbsEpisodesResponse {
+    pub episodes: Vec<AbsEpisode>,
+    /// Missing from older ABS versions.
+    pub total: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AbsEpisode {
+    pub id: String,
+    #[serde(rename = "libraryItemId")]
+    pub library_item_id: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// In milliseconds since the epoch.
+    #[serde(rename = "publishedAt")]
+    pub published_at: Option<i64>,
+    #[serde(rename = "audioFile")]
+    pub audio_file: Option<AbsEpisodeFile>,
+    /// The show the episode belongs to.
+    pub podcast: Option<AbsPodcast>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AbsEpisodeFile {
+    pub ino: String,
+    #[serde(rename = "mimeType")]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AbsPodcast {
+    pub metadata: AbsPodcastMetadata,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AbsPodcastMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+}
+
+/// A library's collections, as `/api/libraries/{id}/collections` lists them.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AbsCollectionsResponse {
+    pub results: Vec<AbsCollection>,
+}
+
+/// A collection a user put together in ABS, with its books in the order they arranged.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AbsCollection {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub books: Vec<AbsCollectionBook>,
+}
+
+/// A book of a collection; ABS sends the whole item, of which we need the id.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AbsCollectionBook {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AbsItemsResponse {
     pub results: Vec<AbsItemResult>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AbsItemResult {
     pub id: String,
     pub media: AbsMedia,
+    /// When the item or its files last changed, in milliseconds since the epoch.
+    #[serde(rename = "updatedAt", default)]
+    pub updated_at: Option<i64>,
+    /// When the item was added to the library, in milliseconds since the epoch.
+    #[serde(rename = "addedAt", default)]
+    pub added_at: Option<i64>,
+    #[serde(rename = "libraryId", default)]
+    pub library_id: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AbsMedia {
     pub metadata: AbsMetadata,
     #[serde(rename = "ebookFormat")]
     pub ebook_format: Option<String>,
+    /// Only present on minified items, e.g. in library listings.
+    #[serde(rename = "numAudioFiles")]
+    pub num_audio_files: Option<usize>,
+    /// Only present on expanded items.
+    #[serde(rename = "audioFiles", default)]
+    pub audio_files: Vec<AbsAudioFile>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AbsAudioFile {
+    pub ino: String,
+    pub index: Option<usize>,
+    pub metadata: AbsFileMetadata,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AbsFileMetadata {
+    pub filename: String,
+    pub size: Option<u64>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AbsMetadata {
     pub title: Option<String>,
     pub subtitle: Option<String>,
@@ -132,6 +494,7 @@ pub struct AbsMetadata {
     pub tags: Option<Vec<String>>,
     pub publisher: Option<String>,
     pub isbn: Option<String>,
+    pub asin: Option<String>,
     pub language: Option<String>,
     #[serde(rename = "publishedYear")]
     pub published_year: Option<String>,
@@ -153,40 +516,330 @@ pub struct AbsUserResponse {
     pub username: String,
     #[serde(rename = "accessToken")]
     pub access_token: String,
+    /// Sent by ABS 2.26 and later, whose access tokens expire, when asked with
+    /// `x-return-tokens`.
+    #[serde(rename = "refreshToken", default)]
+    pub refresh_token: Option<String>,
 }
 
+/// What the catalog links to through the proxy: items, their covers, ebooks and downloads.
+pub const DEFAULT_PROXY_ALLOWED_PATHS: &str = "/api/items/*,/api/items/*/cover,/api/items/*/ebook,/api/items/*/ebook/*,/api/items/*/download";
+
+/// Routes without parameters, which `OPDS_ROOT_ALIASES` can't take over.
+const RESERVED_PATHS: [&str; 8] = ["/opds", "/opds/auth", "/opds/public", "/health", "/metrics", "/admin/cache", "/admin/shares", "/admin/reload"];
+
 // App Configuration
 #[derive(Clone, Deserialize)]
 pub struct AppConfig {
+    /// The TOML or YAML file the settings were read over, if any; see `config_file`.
+    #[serde(default)]
+    pub config_file: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    /// Where to listen, comma separated; see [`BindAddress`].
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
     #[serde(default = "default_use_proxy")]
     pub use_proxy: bool,
+    /// The ABS paths the proxy forwards, comma-separated, where `*` stands for one path
+    /// segment; anything else is refused, so the proxy can't reach the rest of the ABS API.
+    #[serde(default = "default_proxy_allowed_paths")]
+    pub proxy_allowed_paths: String,
     #[serde(default = "default_abs_url")]
     pub abs_url: String,
+    /// Where readers reach the ABS web UI, e.g. behind a reverse proxy; books link to it.
+    #[serde(default)]
+    pub abs_public_url: String,
+    /// More paths serving the catalog root, comma-separated, e.g. `/,/opds/v1.2/catalog`
+    /// for readers that look there first.
+    #[serde(default)]
+    pub opds_root_aliases: String,
     #[serde(default)]
     pub opds_users: String, // Raw string from env
     #[serde(skip)]
     pub internal_users: Vec<InternalUser>,
+    /// OPDS logins that read ABS as an ABS user, as a JSON object keyed by OPDS username,
+    /// e.g. `{"kid": {"password": "…", "abs_username": "family", "abs_password": "…"}}`.
+    #[serde(default)]
+    pub opds_user_map: String,
+    #[serde(skip)]
+    pub user_map: HashMap<String, UserMapping>,
     #[serde(default = "default_false")]
     pub show_audiobooks: bool,
     #[serde(default = "default_false")]
     pub show_char_cards: bool,
+    /// Book and category counts on the library and category cards.
+    #[serde(default = "default_true")]
+    pub show_counts: bool,
+    /// Lists authors as "Sanderson, Brandon", sorted and grouped by surname.
+    #[serde(default = "default_false")]
+    pub authors_by_surname: bool,
+    /// Entry title of books in a series, with `{title}`, `{series}` and `{position}`,
+    /// e.g. `{title} ({series} #{position})`. Empty shows the plain title.
+    #[serde(default)]
+    pub title_template: String,
+    /// Regex for the position within a series name, removed from the name; its `sequence`
+    /// group is the position. Empty keeps series names as they are.
+    #[serde(default = "default_series_pattern")]
+    pub series_pattern: String,
+    /// Keeps the position in series names, e.g. "Mistborn #1", while still ordering by it.
+    #[serde(default = "default_false")]
+    pub series_keep_sequence: bool,
     #[serde(default = "default_false")]
     pub opds_no_auth: bool, // Renamed from no_auth_mode to match env
     #[serde(default)]
     pub abs_noauth_username: String,
     #[serde(default)]
     pub abs_noauth_password: String,
+    /// A library served read-only without credentials below `/opds/public`. Empty turns
+    /// the public catalog off.
+    #[serde(default)]
+    pub opds_public_library: String,
+    /// The ABS API key the public catalog reads with. It ends up in download links, so it
+    /// should belong to an ABS user that can only see the public library.
+    #[serde(default)]
+    pub opds_public_api_key: String,
+    /// Signs share links, which an admin mints at `/admin/shares`. Empty turns them off;
+    /// changing it revokes every link.
+    #[serde(default)]
+    pub opds_share_secret: String,
+    /// The ABS API key share links read with. Like the public catalog's, it ends up in
+    /// download links.
+    #[serde(default)]
+    pub opds_share_api_key: String,
     #[serde(default = "default_page_size")]
     pub opds_page_size: usize,
+    /// The largest page a reader can ask for with `?limit=`.
+    #[serde(default = "default_max_page_size")]
+    pub max_page_size: usize,
+    /// How many of the newest items the recently added feed lists; 0 lists them all.
+    #[serde(default = "default_recent_limit")]
+    pub opds_recent_limit: usize,
+    #[serde(default = "default_false")]
+    pub embed_epub_metadata: bool,
+    #[serde(default = "default_false")]
+    pub report_downloads: bool,
+    #[serde(default = "default_acquisition_links")]
+    pub opds_acquisition_links: String,
+    /// `inline`, `none` or `link`; see [`AuthBlock`].
+    #[serde(default = "default_auth_block")]
+    pub opds_auth_block: String,
+    #[serde(default = "default_languages_dir")]
+    pub languages_dir: String,
+    #[serde(default = "default_language")]
+    pub default_language: String,
+    #[serde(default)]
+    pub language_fallbacks: String,
+    pub opds_login_label: Option<String>,
+    pub opds_password_label: Option<String>,
+    #[serde(default)]
+    pub hidden_tags: String,
+    #[serde(default)]
+    pub hidden_genres: String,
+    #[serde(default = "default_false")]
+    pub merge_editions: bool,
+    #[serde(default)]
+    pub opds_user_options: String, // Raw JSON from env
+    /// Virtual shelves as a JSON object of names and saved filters, e.g.
+    /// `{"Cozy Mysteries": "genre:Mystery AND tag:cozy"}`.
+    #[serde(default)]
+    pub opds_shelves: String,
+    #[serde(skip)]
+    pub shelves: Vec<Shelf>,
+    /// Order of item feeds that don't have one of their own, e.g. `added:desc`. Empty
+    /// keeps the order ABS lists items in.
+    #[serde(default)]
+    pub opds_sort: String,
+    /// `OPDS_SORT` per library, as a JSON object of library ids and orders.
+    #[serde(default)]
+    pub opds_library_sort: String,
+    #[serde(skip)]
+    pub default_sort: Option<crate::query::SortOrder>,
+    #[serde(skip)]
+    pub library_sorts: HashMap<String, crate::query::SortOrder>,
+    #[serde(skip)]
+    pub user_options: HashMap<String, UserOptions>,
+    #[serde(default)]
+    pub opds_admins: String,
+    #[serde(default)]
+    pub cache_url: String,
+    /// A directory covers are kept in across restarts; empty keeps them with the other caches.
+    #[serde(default)]
+    pub cover_cache_dir: String,
+    /// How many megabytes of covers `cover_cache_dir` may hold before the least recently
+    /// read are removed.
+    #[serde(default = "default_cover_cache_max_mb")]
+    pub cover_cache_max_mb: u64,
+    #[serde(default = "default_auth_backends")]
+    pub auth_backends: String,
+    #[serde(default)]
+    pub htpasswd_file: String,
+    /// Failed logins allowed per client address and username before they are locked
+    /// out; 0 never locks out.
+    #[serde(default = "default_login_max_failures")]
+    pub login_max_failures: u32,
+    /// The longest lockout, in seconds; each failure past the allowed ones doubles it,
+    /// starting at one second.
+    #[serde(default = "default_login_lockout_secs")]
+    pub login_lockout_secs: u64,
+    /// Reverse proxies whose `X-Forwarded-For` names the client, as comma-separated
+    /// ranges; see [`IpNet`]. Only loopback by default: anyone else on a private network
+    /// could otherwise pick the address their failures are counted against.
+    #[serde(default = "default_trusted_proxies")]
+    pub trusted_proxies: String,
+    #[serde(default)]
+    pub htpasswd_api_key: String,
+    /// The directory of the ldap backend, e.g. `ldaps://ldap.example.com`.
+    #[serde(default)]
+    pub ldap_url: String,
+    /// The DN users bind as, with `{username}`, e.g. `uid={username},ou=people,dc=example,dc=com`
+    /// or `{username}@example.com` for Active Directory.
+    #[serde(default)]
+    pub ldap_bind_dn: String,
+    /// A filter, with `{username}`, that users must match after binding, e.g.
+    /// `(memberOf=cn=opds,ou=groups,dc=example,dc=com)`. Empty lets every user in.
+    #[serde(default)]
+    pub ldap_group_filter: String,
+    /// Where `LDAP_GROUP_FILTER` is searched; empty searches the entry users bound as.
+    #[serde(default)]
+    pub ldap_search_base: String,
+    #[serde(default)]
+    pub ldap_api_key: String,
+    #[serde(default = "default_false")]
+    pub metrics_enabled: bool,
+    /// Serves a page at `/` telling people and readers where the catalog is.
+    #[serde(default = "default_true")]
+    pub opds_landing_page: bool,
+    #[serde(default = "default_slow_upstream_ms")]
+    pub slow_upstream_ms: u64,
+    /// How long an ABS login is reused for a user's requests before logging in again; 0
+    /// logs in for every request.
+    #[serde(default = "default_token_cache_ttl_secs")]
+    pub token_cache_ttl_secs: u64,
+    /// How many users' ABS logins are kept in memory, the least recently used making room.
+    #[serde(default = "default_token_cache_max_users")]
+    pub token_cache_max_users: usize,
+    /// How long a library's items are reused before they're read from ABS again; 0 reads
+    /// them for every request.
+    #[serde(default = "default_items_cache_ttl_secs")]
+    pub items_cache_ttl_secs: u64,
+    /// How many item lists, one per user and library, are kept in memory.
+    #[serde(default = "default_items_cache_max_entries")]
+    pub items_cache_max_entries: usize,
+    /// How long a request may take before it's cancelled with a 504; 0 waits forever.
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    /// How long open connections, such as downloads, may finish after SIGTERM or
+    /// SIGINT before the server stops anyway.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// `Cache-Control` of each [`CacheClass`]; empty sends none.
+    #[serde(default = "default_cache_control_navigation")]
+    pub cache_control_navigation: String,
+    #[serde(default = "default_cache_control_acquisition")]
+    pub cache_control_acquisition: String,
+    #[serde(default = "default_cache_control_search")]
+    pub cache_control_search: String,
+    #[serde(default = "default_cache_control_covers")]
+    pub cache_control_covers: String,
+    #[serde(default = "default_cache_control_downloads")]
+    pub cache_control_downloads: String,
+    #[serde(default = "default_false")]
+    pub demo_mode: bool,
+    #[serde(default)]
+    pub abs_record_dir: String,
+    #[serde(default)]
+    pub abs_replay_dir: String,
+    /// Webhooks told about new items, as a JSON array, e.g.
+    /// `[{"url": "https://ntfy.sh/books", "format": "ntfy", "library": "Audiobooks"}]`.
+    #[serde(default)]
+    pub opds_webhooks: String,
+    #[serde(skip)]
+    pub webhooks: Vec<Webhook>,
+    /// How often libraries are checked for new items, in seconds.
+    #[serde(default = "default_webhook_interval_secs")]
+    pub webhook_interval_secs: u64,
+    /// The ABS API key libraries are checked with; empty uses the first of `OPDS_USERS`.
+    #[serde(default)]
+    pub webhook_api_key: String,
+}
+
+/// A webhook from `OPDS_WEBHOOKS`, called for every item added to its libraries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Id or name of the only library it hears about; `None` for all of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library: Option<String>,
+}
+
+/// What a [`Webhook`] is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The library and item as JSON, for scripts and home automation.
+    #[default]
+    Json,
+    /// A plain-text message for an ntfy topic URL.
+    Ntfy,
+    /// A message for a Discord webhook URL.
+    Discord,
+}
+
+/// A virtual shelf from `OPDS_SHELVES`: a named filter shown as a card in the categories feed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shelf {
+    pub name: String,
+    pub query: crate::query::ShelfQuery,
+}
+
+/// An OPDS login from `OPDS_USER_MAP`, for which we log in to ABS as another user.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserMapping {
+    /// The password readers log in with.
+    pub password: String,
+    pub abs_username: String,
+    pub abs_password: String,
+}
+
+/// Per-user settings from `OPDS_USER_OPTIONS`, a JSON object keyed by user name, e.g.
+/// `{"kids": {"hidden_tags": ["adult"]}}`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserOptions {
+    /// Added to `HIDDEN_TAGS` for this user.
+    pub hidden_tags: Vec<String>,
+    /// Added to `HIDDEN_GENRES` for this user.
+    pub hidden_genres: Vec<String>,
+    /// Id or name of the library whose categories the root feed opens with, even when
+    /// the user can see several.
+    pub default_library: Option<String>,
+    /// Suffixes titles with the user's reading progress, e.g. "Dune — 43%".
+    pub show_progress: bool,
 }
 
 impl AppConfig {
     // Method to parse internal users after deserialization
     pub fn parse_users(&mut self) -> anyhow::Result<()> {
+        // Argon2 hashes hold commas, as in `m=19456,t=2,p=1`: a piece without a colon
+        // continues the entry before it
+        let mut entries: Vec<String> = Vec::new();
+        for piece in self.opds_users.split(',') {
+            match entries.last_mut() {
+                Some(entry) if !piece.contains(':') && !piece.trim().is_empty() => {
+                    entry.push(',');
+                    entry.push_str(piece);
+                }
+                _ => entries.push(piece.to_string()),
+            }
+        }
+
         let mut users = Vec::new();
-        for user_str in self.opds_users.split(',') {
+        for user_str in &entries {
             if user_str.trim().is_empty() {
                 continue;
             }
@@ -204,31 +857,786 @@ pub fn parse_users(&mut self) -> anyhow::Result<()> {
             });
         }
         self.internal_users = users;
+
+        if !self.opds_user_map.trim().is_empty() {
+            self.user_map = serde_json::from_str(&self.opds_user_map)
+                .map_err(|e| anyhow::anyhow!("Invalid OPDS_USER_MAP: {}", e))?;
+        }
+
+        if !self.opds_user_options.trim().is_empty() {
+            self.user_options = serde_json::from_str(&self.opds_user_options)
+                .map_err(|e| anyhow::anyhow!("Invalid OPDS_USER_OPTIONS: {}", e))?;
+        }
+
+        if !self.opds_shelves.trim().is_empty() {
+            // Shelves are listed by name
+            let shelves: std::collections::BTreeMap<String, String> = serde_json::from_str(&self.opds_shelves)
+                .map_err(|e| anyhow::anyhow!("Invalid OPDS_SHELVES: {}", e))?;
+            self.shelves = shelves
+                .into_iter()
+                .map(|(name, query)| {
+                    let query = crate::query::ShelfQuery::parse(&query)
+                        .map_err(|e| anyhow::anyhow!("Invalid OPDS_SHELVES shelf '{}': {}", name, e))?;
+                    Ok(Shelf { name, query })
+                })
+                .collect::<anyhow::Result<_>>()?;
+        }
+
+        if !self.opds_sort.trim().is_empty() {
+            self.default_sort = Some(
+                crate::query::SortOrder::parse(&self.opds_sort).map_err(|e| anyhow::anyhow!("Invalid OPDS_SORT: {}", e))?,
+            );
+        }
+        if !self.opds_library_sort.trim().is_empty() {
+            let sorts: HashMap<String, String> = serde_json::from_str(&self.opds_library_sort)
+                .map_err(|e| anyhow::anyhow!("Invalid OPDS_LIBRARY_SORT: {}", e))?;
+            self.library_sorts = sorts
+                .into_iter()
+                .map(|(library, order)| {
+                    let order = crate::query::SortOrder::parse(&order)
+                        .map_err(|e| anyhow::anyhow!("Invalid OPDS_LIBRARY_SORT for '{}': {}", library, e))?;
+                    Ok((library, order))
+                })
+                .collect::<anyhow::Result<_>>()?;
+        }
+
+        if !self.opds_webhooks.trim().is_empty() {
+            self.webhooks = serde_json::from_str(&self.opds_webhooks)
+                .map_err(|e| anyhow::anyhow!("Invalid OPDS_WEBHOOKS: {}", e))?;
+        }
         Ok(())
     }
 
+    /// The default order of the item feeds of `library_id`, if one is configured.
+    pub fn sort_for(&self, library_id: &str) -> Option<crate::query::SortOrder> {
+        self.library_sorts.get(library_id).copied().or(self.default_sort)
+    }
+
+    /// The shelf called `name`.
+    pub fn shelf(&self, name: &str) -> Option<&Shelf> {
+        self.shelves.iter().find(|shelf| shelf.name == name)
+    }
+
+    /// Options configured for `user`, or the defaults.
+    pub fn user_options(&self, user: &str) -> UserOptions {
+        self.user_options.get(user).cloned().unwrap_or_default()
+    }
+
+    /// Whether `user` is listed in `OPDS_ADMINS` and may use admin routes.
+    pub fn is_admin(&self, user: &str) -> bool {
+        self.opds_admins.split(',').any(|admin| !admin.trim().is_empty() && admin.trim() == user)
+    }
+
+    /// The user anonymous readers of the public catalog read as, if `OPDS_PUBLIC_LIBRARY` is set.
+    pub fn public_user(&self) -> Option<InternalUser> {
+        (!self.opds_public_library.is_empty()).then(|| InternalUser {
+            name: PUBLIC_USER.to_string(),
+            api_key: self.opds_public_api_key.clone(),
+            password: None,
+        })
+    }
+
+    /// The user share links read as, if they are on.
+    pub fn share_user(&self) -> Option<InternalUser> {
+        (!self.opds_share_secret.is_empty()).then(|| InternalUser {
+            name: SHARE_USER.to_string(),
+            api_key: self.opds_share_api_key.clone(),
+            password: None,
+        })
+    }
+
+    /// The user libraries are checked for new items as: `WEBHOOK_API_KEY`, or else the
+    /// first of `OPDS_USERS`.
+    pub fn webhook_user(&self) -> Option<InternalUser> {
+        if self.webhook_api_key.is_empty() {
+            return self.internal_users.first().cloned();
+        }
+        Some(InternalUser { name: WEBHOOK_USER.to_string(), api_key: self.webhook_api_key.clone(), password: None })
+    }
+
+    /// The page of an item in the ABS web UI at `ABS_PUBLIC_URL`. Without it there is
+    /// none: `ABS_URL` is usually an address only this server can reach.
+    pub fn web_item_url(&self, item_id: &str) -> Option<String> {
+        if self.abs_public_url.is_empty() {
+            return None;
+        }
+        Some(format!("{}/item/{}", self.abs_public_url.trim_end_matches('/'), item_id))
+    }
+
+    /// Acquisition links in the order configured by `OPDS_ACQUISITION_LINKS`. Unknown
+    /// names are rejected by `validate`, so they are skipped here.
+    pub fn acquisition_links(&self) -> Vec<AcquisitionLink> {
+        self.opds_acquisition_links
+            .split(',')
+            .filter_map(|l| l.parse().ok())
+            .collect()
+    }
+
+    /// Whether the proxy forwards requests for the ABS `path`, by `PROXY_ALLOWED_PATHS`.
+    pub fn proxy_allows(&self, path: &str) -> bool {
+        let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+        self.proxy_allowed_paths.split(',').map(str::trim).filter(|p| !p.is_empty()).any(|pattern| {
+            let pattern: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+            pattern.len() == segments.len()
+                && pattern.iter().zip(&segments).all(|(p, s)| if *p == "*" { !s.is_empty() } else { p == s })
+        })
+    }
+
+    /// The paths of `OPDS_ROOT_ALIASES`, without trailing slashes or repeats.
+    pub fn root_aliases(&self) -> Vec<String> {
+        let mut aliases: Vec<String> = Vec::new();
+        for alias in self.opds_root_aliases.split(',').map(str::trim).filter(|alias| !alias.is_empty()) {
+            let alias = match alias.trim_end_matches('/') {
+                "" => "/".to_string(),
+                alias => alias.to_string(),
+            };
+            if !aliases.contains(&alias) {
+                aliases.push(alias);
+            }
+        }
+        aliases
+    }
+
+    /// The `Cache-Control` of responses of `class`, empty for none.
+    pub fn cache_control(&self, class: CacheClass) -> &str {
+        match class {
+            CacheClass::Navigation => &self.cache_control_navigation,
+            CacheClass::Acquisition => &self.cache_control_acquisition,
+            CacheClass::Search => &self.cache_control_search,
+            CacheClass::Covers => &self.cache_control_covers,
+            CacheClass::Downloads => &self.cache_control_downloads,
+        }
+    }
+
+    /// How feeds advertise authentication. An invalid `OPDS_AUTH_BLOCK` is rejected by
+    /// `validate`, so it falls back to the inline block here.
+    pub fn auth_block(&self) -> AuthBlock {
+        self.opds_auth_block.parse().unwrap_or(AuthBlock::Inline)
+    }
+
+    /// Auth backends in the order configured by `AUTH_BACKENDS`. Unknown names are
+    /// rejected by `validate`, so they are skipped here.
+    pub fn auth_backends(&self) -> Vec<AuthBackendKind> {
+        self.auth_backends
+            .split(',')
+            .filter_map(|b| b.parse().ok())
+            .collect()
+    }
+
+    /// The ranges of `TRUSTED_PROXIES`.
+    pub fn trusted_proxies(&self) -> anyhow::Result<Vec<IpNet>> {
+        self.trusted_proxies.split(',').filter(|entry| !entry.trim().is_empty()).map(IpNet::parse).collect()
+    }
+
+    /// The addresses of `BIND_ADDRESS`, in order.
+    pub fn bind_addresses(&self) -> anyhow::Result<Vec<BindAddress>> {
+        let addresses = self
+            .bind_address
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| BindAddress::parse(entry, self.port))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if addresses.is_empty() {
+            return Err(anyhow::anyhow!("BIND_ADDRESS needs at least one address"));
+        }
+        Ok(addresses)
+    }
+
+    /// The page size of a request: its `?limit=` capped at `MAX_PAGE_SIZE`, or
+    /// `OPDS_PAGE_SIZE` without one.
+    pub fn page_size_for(&self, limit: Option<usize>) -> usize {
+        match limit {
+            Some(limit) => limit.clamp(1, self.max_page_size.max(1)),
+            None => self.opds_page_size,
+        }
+    }
+
     pub fn validate(&self) -> anyhow::Result<()> {
         if self.abs_url.trim().is_empty() {
             return Err(anyhow::anyhow!("ABS_URL cannot be empty"));
         }
-        if !self.opds_no_auth && self.internal_users.is_empty() {
+        if self.max_page_size == 0 {
+            return Err(anyhow::anyhow!("MAX_PAGE_SIZE must be at least 1"));
+        }
+        self.bind_addresses()?;
+        self.trusted_proxies()?;
+        if !self.title_template.is_empty() && !self.title_template.contains("{title}") {
+            return Err(anyhow::anyhow!("TITLE_TEMPLATE must contain {{title}}"));
+        }
+        if let Err(e) = crate::series::SeriesParser::new(&self.series_pattern, self.series_keep_sequence) {
+            return Err(anyhow::anyhow!("Invalid SERIES_PATTERN: {}", e));
+        }
+        for (name, value) in [
+            ("CACHE_CONTROL_NAVIGATION", &self.cache_control_navigation),
+            ("CACHE_CONTROL_ACQUISITION", &self.cache_control_acquisition),
+            ("CACHE_CONTROL_SEARCH", &self.cache_control_search),
+            ("CACHE_CONTROL_COVERS", &self.cache_control_covers),
+            ("CACHE_CONTROL_DOWNLOADS", &self.cache_control_downloads),
+        ] {
+            if axum::http::HeaderValue::from_str(value).is_err() {
+                return Err(anyhow::anyhow!("{} is not a valid header value: {}", name, value));
+            }
+        }
+        for backend in self.auth_backends.split(',').filter(|b| !b.trim().is_empty()) {
+            backend.parse::<AuthBackendKind>()?;
+        }
+        let backends = self.auth_backends();
+        // The env backend needs users of its own, unless a htpasswd file or directory provides them.
+        if !self.opds_no_auth
+            && !self.demo_mode
+            && self.abs_replay_dir.is_empty()
+            && self.internal_users.is_empty()
+            && self.user_map.is_empty()
+            && backends.contains(&AuthBackendKind::Env)
+            && !backends.contains(&AuthBackendKind::Htpasswd)
+            && !backends.contains(&AuthBackendKind::Ldap)
+        {
+            return Err(anyhow::anyhow!(
+                "No users configured and OPDS_NO_AUTH is false. Please set OPDS_USERS or OPDS_USER_MAP, or enable OPDS_NO_AUTH."
+            ));
+        }
+        if backends.contains(&AuthBackendKind::Htpasswd)
+            && (self.htpasswd_file.trim().is_empty() || self.htpasswd_api_key.trim().is_empty())
+        {
             return Err(anyhow::anyhow!(
-                "No users configured and OPDS_NO_AUTH is false. Please set OPDS_USERS or enable OPDS_NO_AUTH."
+                "AUTH_BACKENDS includes htpasswd, but HTPASSWD_FILE or HTPASSWD_API_KEY is not set."
             ));
         }
-        if self.opds_no_auth {
-            if self.abs_noauth_username.trim().is_empty() || self.abs_noauth_password.trim().is_empty() {
+        if backends.contains(&AuthBackendKind::Ldap) {
+            if !cfg!(feature = "ldap") {
+                return Err(anyhow::anyhow!("AUTH_BACKENDS includes ldap, which needs a build with the `ldap` feature"));
+            }
+            if self.ldap_url.trim().is_empty() || self.ldap_api_key.trim().is_empty() {
+                return Err(anyhow::anyhow!("AUTH_BACKENDS includes ldap, but LDAP_URL or LDAP_API_KEY is not set."));
+            }
+            if !self.ldap_bind_dn.contains("{username}") {
+                return Err(anyhow::anyhow!("LDAP_BIND_DN must contain {{username}}"));
+            }
+        }
+        if self.default_language.trim().is_empty() {
+            return Err(anyhow::anyhow!("DEFAULT_LANGUAGE cannot be empty"));
+        }
+        for chain in self.language_fallbacks.split(',').filter(|c| !c.trim().is_empty()) {
+            if chain.split(':').filter(|p| !p.trim().is_empty()).count() < 2 {
                 return Err(anyhow::anyhow!(
-                    "OPDS_NO_AUTH is enabled, but ABS_NOAUTH_USERNAME or ABS_NOAUTH_PASSWORD is not set."
+                    "Invalid language fallback '{}'. Expected format: language:fallback[:fallback...]",
+                    chain
                 ));
             }
         }
+        crate::cache::check_url(&self.cache_url)?;
+        if self.token_cache_max_users == 0 {
+            return Err(anyhow::anyhow!("TOKEN_CACHE_MAX_USERS must be at least 1"));
+        }
+        if !self.cover_cache_dir.trim().is_empty() && self.cover_cache_max_mb == 0 {
+            return Err(anyhow::anyhow!("COVER_CACHE_MAX_MB must be at least 1"));
+        }
+        if !self.abs_record_dir.trim().is_empty() && !self.abs_replay_dir.trim().is_empty() {
+            return Err(anyhow::anyhow!("ABS_RECORD_DIR and ABS_REPLAY_DIR cannot be used together"));
+        }
+        for link in self.opds_acquisition_links.split(',').filter(|l| !l.trim().is_empty()) {
+            link.parse::<AcquisitionLink>()?;
+        }
+        self.opds_auth_block.parse::<AuthBlock>()?;
+        for pattern in self.proxy_allowed_paths.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if !pattern.starts_with('/') {
+                return Err(anyhow::anyhow!("Invalid PROXY_ALLOWED_PATHS path '{}': expected a path like /api/items/*/cover", pattern));
+            }
+        }
+        for alias in self.root_aliases() {
+            if !alias.starts_with('/') || alias.contains(['{', '}', '*', '?']) {
+                return Err(anyhow::anyhow!("Invalid OPDS_ROOT_ALIASES path '{}': expected a path like /opds/v1.2/catalog", alias));
+            }
+            if RESERVED_PATHS.contains(&alias.as_str()) || alias.starts_with("/opds/public/") || alias.starts_with("/opds/share/") || alias.starts_with("/admin/tokens/") {
+                return Err(anyhow::anyhow!("OPDS_ROOT_ALIASES path '{}' is already a route", alias));
+            }
+        }
+        if self.opds_no_auth
+            && (self.abs_noauth_username.trim().is_empty() || self.abs_noauth_password.trim().is_empty())
+        {
+            return Err(anyhow::anyhow!(
+                "OPDS_NO_AUTH is enabled, but ABS_NOAUTH_USERNAME or ABS_NOAUTH_PASSWORD is not set."
+            ));
+        }
+        if self.opds_public_library.trim().is_empty() != self.opds_public_api_key.trim().is_empty() {
+            return Err(anyhow::anyhow!("OPDS_PUBLIC_LIBRARY and OPDS_PUBLIC_API_KEY must be set together"));
+        }
+        if self.opds_share_secret.trim().is_empty() != self.opds_share_api_key.trim().is_empty() {
+            return Err(anyhow::anyhow!("OPDS_SHARE_SECRET and OPDS_SHARE_API_KEY must be set together"));
+        }
+        if !self.webhooks.is_empty() {
+            if self.webhook_user().is_none() {
+                return Err(anyhow::anyhow!("OPDS_WEBHOOKS needs WEBHOOK_API_KEY when OPDS_USERS is empty"));
+            }
+            if self.webhook_interval_secs == 0 {
+                return Err(anyhow::anyhow!("WEBHOOK_INTERVAL_SECS must be at least 1"));
+            }
+        }
         Ok(())
     }
 }
 
+/// The defaults of an empty environment.
+impl Default for AppConfig {
+    fn default() -> Self {
+        envy::from_iter(std::iter::empty::<(String, String)>()).expect("Every AppConfig field has a default")
+    }
+}
+
+impl AppConfig {
+    /// Starts a configuration in code from the same defaults as an empty environment.
+    pub fn builder() -> AppConfigBuilder {
+        AppConfigBuilder { config: AppConfig::default() }
+    }
+}
+
+/// Typed setters for `AppConfig`, for tests and applications embedding the catalog.
+/// Each setter corresponds to the environment variable of the same name.
+#[derive(Clone)]
+pub struct AppConfigBuilder {
+    config: AppConfig,
+}
+
+impl AppConfigBuilder {
+    /// Reads logins from this file when the configuration is reloaded.
+    pub fn config_file(mut self, path: impl Into<String>) -> Self {
+        self.config.config_file = path.into();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Listens on these addresses; see [`BindAddress`].
+    pub fn bind_addresses(mut self, addresses: &[&str]) -> Self {
+        self.config.bind_address = addresses.join(",");
+        self
+    }
+
+    pub fn use_proxy(mut self, use_proxy: bool) -> Self {
+        self.config.use_proxy = use_proxy;
+        self
+    }
+
+    /// Lets the proxy forward only these ABS paths, where `*` stands for one segment.
+    pub fn proxy_allowed_paths(mut self, paths: &[&str]) -> Self {
+        self.config.proxy_allowed_paths = paths.join(",");
+        self
+    }
+
+    pub fn abs_url(mut self, abs_url: impl Into<String>) -> Self {
+        self.config.abs_url = abs_url.into();
+        self
+    }
+
+    pub fn abs_public_url(mut self, abs_public_url: impl Into<String>) -> Self {
+        self.config.abs_public_url = abs_public_url.into();
+        self
+    }
+
+    /// Adds an entry to `OPDS_USERS`.
+    pub fn user(mut self, name: &str, api_key: &str, password: &str) -> Self {
+        if !self.config.opds_users.is_empty() {
+            self.config.opds_users.push(',');
+        }
+        self.config.opds_users.push_str(&format!("{}:{}:{}", name, api_key, password));
+        self
+    }
+
+    pub fn show_audiobooks(mut self, show: bool) -> Self {
+        self.config.show_audiobooks = show;
+        self
+    }
+
+    pub fn show_char_cards(mut self, show: bool) -> Self {
+        self.config.show_char_cards = show;
+        self
+    }
+
+    pub fn show_counts(mut self, show: bool) -> Self {
+        self.config.show_counts = show;
+        self
+    }
+
+    pub fn authors_by_surname(mut self, by_surname: bool) -> Self {
+        self.config.authors_by_surname = by_surname;
+        self
+    }
+
+    pub fn title_template(mut self, template: impl Into<String>) -> Self {
+        self.config.title_template = template.into();
+        self
+    }
+
+    pub fn series_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.config.series_pattern = pattern.into();
+        self
+    }
+
+    pub fn series_keep_sequence(mut self, keep: bool) -> Self {
+        self.config.series_keep_sequence = keep;
+        self
+    }
+
+    /// Turns on `OPDS_NO_AUTH`, logging everyone in as this ABS user.
+    pub fn no_auth(mut self, username: &str, password: &str) -> Self {
+        self.config.opds_no_auth = true;
+        self.config.abs_noauth_username = username.to_string();
+        self.config.abs_noauth_password = password.to_string();
+        self
+    }
+
+    /// Serves `library_id` without credentials below `/opds/public`, read with `api_key`.
+    pub fn public_library(mut self, library_id: impl Into<String>, api_key: impl Into<String>) -> Self {
+        self.config.opds_public_library = library_id.into();
+        self.config.opds_public_api_key = api_key.into();
+        self
+    }
+
+    /// Turns share links on, signed with `secret` and reading ABS with `api_key`.
+    pub fn share_links(mut self, secret: impl Into<String>, api_key: impl Into<String>) -> Self {
+        self.config.opds_share_secret = secret.into();
+        self.config.opds_share_api_key = api_key.into();
+        self
+    }
+
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.config.opds_page_size = page_size;
+        self
+    }
+
+    pub fn max_page_size(mut self, max: usize) -> Self {
+        self.config.max_page_size = max;
+        self
+    }
+
+    /// Lists the `limit` newest items in the recently added feed; 0 lists them all.
+    pub fn recent_limit(mut self, limit: usize) -> Self {
+        self.config.opds_recent_limit = limit;
+        self
+    }
+
+    pub fn embed_epub_metadata(mut self, embed: bool) -> Self {
+        self.config.embed_epub_metadata = embed;
+        self
+    }
+
+    pub fn report_downloads(mut self, report: bool) -> Self {
+        self.config.report_downloads = report;
+        self
+    }
+
+    pub fn acquisition_links(mut self, links: &[AcquisitionLink]) -> Self {
+        let names: Vec<&str> = links
+            .iter()
+            .map(|link| match link {
+                AcquisitionLink::Download => "download",
+                AcquisitionLink::Ebook => "ebook",
+                AcquisitionLink::Zip => "zip",
+                AcquisitionLink::Stream => "stream",
+            })
+            .collect();
+        self.config.opds_acquisition_links = names.join(",");
+        self
+    }
+
+    pub fn auth_block(mut self, block: AuthBlock) -> Self {
+        self.config.opds_auth_block = match block {
+            AuthBlock::Inline => "inline",
+            AuthBlock::None => "none",
+            AuthBlock::Link => "link",
+        }
+        .to_string();
+        self
+    }
+
+    pub fn languages_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.languages_dir = dir.into();
+        self
+    }
+
+    pub fn default_language(mut self, language: impl Into<String>) -> Self {
+        self.config.default_language = language.into();
+        self
+    }
+
+    /// Adds a `LANGUAGE_FALLBACKS` chain, e.g. `&["gsw", "de-ch", "de"]`.
+    pub fn language_fallback(mut self, chain: &[&str]) -> Self {
+        if !self.config.language_fallbacks.is_empty() {
+            self.config.language_fallbacks.push(',');
+        }
+        self.config.language_fallbacks.push_str(&chain.join(":"));
+        self
+    }
+
+    pub fn login_label(mut self, label: impl Into<String>) -> Self {
+        self.config.opds_login_label = Some(label.into());
+        self
+    }
+
+    pub fn password_label(mut self, label: impl Into<String>) -> Self {
+        self.config.opds_password_label = Some(label.into());
+        self
+    }
+
+    /// Serves the catalog root at these paths too.
+    pub fn root_aliases(mut self, aliases: &[&str]) -> Self {
+        self.config.opds_root_aliases = aliases.join(",");
+        self
+    }
+
+    pub fn hidden_tags(mut self, tags: &[&str]) -> Self {
+        self.config.hidden_tags = tags.join(",");
+        self
+    }
+
+    pub fn hidden_genres(mut self, genres: &[&str]) -> Self {
+        self.config.hidden_genres = genres.join(",");
+        self
+    }
+
+    pub fn merge_editions(mut self, merge: bool) -> Self {
+        self.config.merge_editions = merge;
+        self
+    }
+
+    pub fn user_options(mut self, user: &str, options: UserOptions) -> Self {
+        self.config.user_options.insert(user.to_string(), options);
+        self
+    }
+
+    /// Adds an `OPDS_USER_MAP` login that reads ABS as `abs_username`.
+    pub fn mapped_user(mut self, name: &str, password: &str, abs_username: &str, abs_password: &str) -> Self {
+        let mapping = UserMapping {
+            password: password.to_string(),
+            abs_username: abs_username.to_string(),
+            abs_password: abs_password.to_string(),
+        };
+        self.config.user_map.insert(name.to_string(), mapping);
+        self
+    }
+
+    /// Adds a user to `OPDS_ADMINS`.
+    pub fn admin(mut self, user: &str) -> Self {
+        if !self.config.opds_admins.is_empty() {
+            self.config.opds_admins.push(',');
+        }
+        self.config.opds_admins.push_str(user);
+        self
+    }
+
+    /// `memory`, `sqlite://<path>` or `redis://<host>`.
+    pub fn cache_url(mut self, url: impl Into<String>) -> Self {
+        self.config.cache_url = url.into();
+        self
+    }
+
+    /// Keeps covers as files in `dir`, up to `max_mb` megabytes.
+    pub fn cover_cache(mut self, dir: impl Into<String>, max_mb: u64) -> Self {
+        self.config.cover_cache_dir = dir.into();
+        self.config.cover_cache_max_mb = max_mb;
+        self
+    }
+
+    /// Locks out a client address or username after `max_failures` failed logins, for
+    /// up to `lockout_secs`; 0 failures turns it off.
+    pub fn login_throttle(mut self, max_failures: u32, lockout_secs: u64) -> Self {
+        self.config.login_max_failures = max_failures;
+        self.config.login_lockout_secs = lockout_secs;
+        self
+    }
+
+    /// Takes the client address from `X-Forwarded-For` for connections from these ranges.
+    pub fn trusted_proxies(mut self, ranges: &[&str]) -> Self {
+        self.config.trusted_proxies = ranges.join(",");
+        self
+    }
+
+    pub fn auth_backends(mut self, backends: &[AuthBackendKind]) -> Self {
+        let names: Vec<&str> = backends
+            .iter()
+            .map(|backend| match backend {
+                AuthBackendKind::Env => "env",
+                AuthBackendKind::Htpasswd => "htpasswd",
+                AuthBackendKind::Abs => "abs",
+                AuthBackendKind::AbsToken => "abs-token",
+                AuthBackendKind::Ldap => "ldap",
+            })
+            .collect();
+        self.config.auth_backends = names.join(",");
+        self
+    }
+
+    /// Adds the htpasswd backend's file, whose users all read as `api_key`.
+    pub fn htpasswd(mut self, file: impl Into<String>, api_key: impl Into<String>) -> Self {
+        self.config.htpasswd_file = file.into();
+        self.config.htpasswd_api_key = api_key.into();
+        self
+    }
+
+    /// Sets the ldap backend's directory, bind DN template and the API key its users read as.
+    pub fn ldap(mut self, url: impl Into<String>, bind_dn: impl Into<String>, api_key: impl Into<String>) -> Self {
+        self.config.ldap_url = url.into();
+        self.config.ldap_bind_dn = bind_dn.into();
+        self.config.ldap_api_key = api_key.into();
+        self
+    }
+
+    /// Lets only the users matching `filter` in, searched below `search_base` or, if it is
+    /// empty, on the users' own entries.
+    pub fn ldap_group_filter(mut self, filter: impl Into<String>, search_base: impl Into<String>) -> Self {
+        self.config.ldap_group_filter = filter.into();
+        self.config.ldap_search_base = search_base.into();
+        self
+    }
+
+    pub fn metrics_enabled(mut self, enabled: bool) -> Self {
+        self.config.metrics_enabled = enabled;
+        self
+    }
+
+    pub fn landing_page(mut self, enabled: bool) -> Self {
+        self.config.opds_landing_page = enabled;
+        self
+    }
+
+    /// Warns when fetching or filtering a library takes at least this long; 0 turns
+    /// the warning off.
+    pub fn slow_upstream_ms(mut self, ms: u64) -> Self {
+        self.config.slow_upstream_ms = ms;
+        self
+    }
+
+    /// Reuses the ABS logins of up to `max_users` for `ttl_secs` seconds.
+    pub fn token_cache(mut self, ttl_secs: u64, max_users: usize) -> Self {
+        self.config.token_cache_ttl_secs = ttl_secs;
+        self.config.token_cache_max_users = max_users;
+        self
+    }
+
+    /// Reuses up to `max_entries` item lists for `ttl_secs` seconds.
+    pub fn items_cache(mut self, ttl_secs: u64, max_entries: usize) -> Self {
+        self.config.items_cache_ttl_secs = ttl_secs;
+        self.config.items_cache_max_entries = max_entries;
+        self
+    }
+
+    /// Sends `value` as the `Cache-Control` of responses of `class`; empty sends none.
+    pub fn cache_control(mut self, class: CacheClass, value: impl Into<String>) -> Self {
+        let field = match class {
+            CacheClass::Navigation => &mut self.config.cache_control_navigation,
+            CacheClass::Acquisition => &mut self.config.cache_control_acquisition,
+            CacheClass::Search => &mut self.config.cache_control_search,
+            CacheClass::Covers => &mut self.config.cache_control_covers,
+            CacheClass::Downloads => &mut self.config.cache_control_downloads,
+        };
+        *field = value.into();
+        self
+    }
+
+    /// Cancels requests still unanswered after this long with a 504; 0 turns the limit off.
+    pub fn request_timeout_ms(mut self, ms: u64) -> Self {
+        self.config.request_timeout_ms = ms;
+        self
+    }
+
+    /// Gives open connections this long to finish when the server is stopped.
+    pub fn shutdown_timeout_secs(mut self, secs: u64) -> Self {
+        self.config.shutdown_timeout_secs = secs;
+        self
+    }
+
+    /// Serves the built-in sample library instead of reading ABS.
+    pub fn demo_mode(mut self, demo: bool) -> Self {
+        self.config.demo_mode = demo;
+        self
+    }
+
+    /// Writes the ABS responses to `dir`, see `recording::RecordingClient`.
+    pub fn record_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.abs_record_dir = dir.into();
+        self
+    }
+
+    /// Serves the ABS responses recorded in `dir` instead of reading ABS.
+    pub fn replay_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.abs_replay_dir = dir.into();
+        self
+    }
+
+    /// Virtual shelves as name and saved filter, parsed like `OPDS_SHELVES` by `build`.
+    pub fn shelves(mut self, shelves: &[(&str, &str)]) -> Self {
+        let shelves: std::collections::BTreeMap<&str, &str> = shelves.iter().copied().collect();
+        self.config.opds_shelves = serde_json::to_string(&shelves).expect("shelves serialize");
+        self
+    }
+
+    /// The `OPDS_SORT` order, e.g. `added:desc`.
+    pub fn sort(mut self, order: impl Into<String>) -> Self {
+        self.config.opds_sort = order.into();
+        self
+    }
+
+    /// Orders per library id, parsed like `OPDS_LIBRARY_SORT` by `build`.
+    pub fn library_sorts(mut self, sorts: &[(&str, &str)]) -> Self {
+        let sorts: std::collections::BTreeMap<&str, &str> = sorts.iter().copied().collect();
+        self.config.opds_library_sort = serde_json::to_string(&sorts).expect("sorts serialize");
+        self
+    }
+
+    /// Webhooks told about new items, parsed like `OPDS_WEBHOOKS` by `build`.
+    pub fn webhooks(mut self, webhooks: &[Webhook]) -> Self {
+        self.config.opds_webhooks = serde_json::to_string(webhooks).expect("webhooks serialize");
+        self
+    }
+
+    /// Checks libraries for new items with `api_key` instead of the first user's key.
+    pub fn webhook_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.config.webhook_api_key = api_key.into();
+        self
+    }
+
+    /// Parses the users and checks the configuration like at startup.
+    pub fn build(self) -> anyhow::Result<AppConfig> {
+        let mut config = self.config;
+        config.parse_users()?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
 fn default_port() -> u16 { 3010 }
+fn default_login_max_failures() -> u32 { 5 }
+fn default_login_lockout_secs() -> u64 { 900 }
+fn default_trusted_proxies() -> String { "127.0.0.0/8,::1".to_string() }
+fn default_bind_address() -> String { "0.0.0.0".to_string() }
 fn default_use_proxy() -> bool { false }
+fn default_proxy_allowed_paths() -> String { DEFAULT_PROXY_ALLOWED_PATHS.to_string() }
 fn default_abs_url() -> String { "http://localhost:3000".to_string() }
 fn default_false() -> bool { false }
+fn default_true() -> bool { true }
 fn default_page_size() -> usize { 20 }
+fn default_max_page_size() -> usize { 200 }
+fn default_recent_limit() -> usize { 100 }
+fn default_acquisition_links() -> String { "download,ebook,zip,stream".to_string() }
+fn default_auth_block() -> String { "inline".to_string() }
+fn default_languages_dir() -> String { "languages".to_string() }
+fn default_language() -> String { "en".to_string() }
+fn default_auth_backends() -> String { "env,abs".to_string() }
+fn default_slow_upstream_ms() -> u64 { 2000 }
+fn default_request_timeout_ms() -> u64 { 25_000 }
+fn default_shutdown_timeout_secs() -> u64 { 30 }
+fn default_cover_cache_max_mb() -> u64 { 256 }
+fn default_token_cache_ttl_secs() -> u64 { crate::api::DEFAULT_TOKEN_CACHE_TTL.as_secs() }
+fn default_token_cache_max_users() -> usize { crate::api::DEFAULT_TOKEN_CACHE_MAX_USERS }
+fn default_items_cache_ttl_secs() -> u64 { crate::api::DEFAULT_ITEMS_CACHE_TTL.as_secs() }
+fn default_items_cache_max_entries() -> usize { crate::api::DEFAULT_ITEMS_CACHE_MAX_ENTRIES }
+fn default_webhook_interval_secs() -> u64 { 300 }
+fn default_cache_control_navigation() -> String { "private, max-age=300".to_string() }
+fn default_cache_control_acquisition() -> String { "private, no-cache".to_string() }
+fn default_cache_control_search() -> String { "public, max-age=86400".to_string() }
+fn default_cache_control_covers() -> String { "private, max-age=604800".to_string() }
+fn default_cache_control_downloads() -> String { "private, no-cache".to_string() }
+fn default_series_pattern() -> String { crate::series::DEFAULT_SERIES_PATTERN.to_string() }
@@ -0,0 +1,3 @@
+//! Format-independent OPDS documents.
+
+pub mod model;
@@ -0,0 +1,458 @@
+//! OPDS 1.2 catalog documents as plain data. `LibraryService` builds these and
+//! `OpdsBuilder::build_feed` serializes them, so entries can be inspected in tests
+//! without parsing XML.
+
+use crate::i18n::I18n;
+use crate::models::{AbsEpisode, AcquisitionLink, AuthBlock, Edition, InternalUser, Library, LibraryItem, MediaType};
+
+pub const ACQUISITION_REL: &str = "http://opds-spec.org/acquisition";
+pub const IMAGE_REL: &str = "http://opds-spec.org/image";
+pub const THUMBNAIL_REL: &str = "http://opds-spec.org/image/thumbnail";
+pub const STREAM_REL: &str = "http://opds-spec.org/acquisition/open-access";
+pub const FACET_REL: &str = "http://opds-spec.org/facet";
+/// Width of the publication thumbnails ABS scales covers down to.
+pub const THUMBNAIL_WIDTH: u32 = 400;
+pub const AUTH_DOCUMENT_REL: &str = "http://opds-spec.org/auth/document";
+pub const AUTH_DOCUMENT_TYPE: &str = "application/opds-authentication+json";
+pub const AUTH_DOCUMENT_HREF: &str = "/opds/auth";
+/// Where `USE_PROXY` serves ABS from.
+pub const PROXY_PATH: &str = "/opds/proxy";
+
+/// A link to the ABS API `path`, with `query` if not empty, under `link_url`: through the
+/// proxy, which signs the request in for the reader, or straight to ABS with the user's
+/// token in the query.
+pub fn abs_href(link_url: &str, path: &str, query: &str, user: &InternalUser) -> String {
+    let token = (link_url != PROXY_PATH).then(|| format!("token={}", user.api_key));
+    let params: Vec<&str> = token.as_deref().into_iter().chain(Some(query).filter(|q| !q.is_empty())).collect();
+    if params.is_empty() {
+        format!("{}{}", link_url, path)
+    } else {
+        format!("{}{}?{}", link_url, path, params.join("&"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedKind {
+    Navigation,
+    Acquisition,
+}
+
+impl FeedKind {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            FeedKind::Navigation => "application/atom+xml;profile=opds-catalog;kind=navigation",
+            FeedKind::Acquisition => "application/atom+xml;profile=opds-catalog;kind=acquisition",
+        }
+    }
+}
+
+/// An Atom link. Empty `rel`, `type_` and `title` are left out when serialized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub rel: String,
+    pub type_: String,
+    pub title: String,
+    pub href: String,
+}
+
+impl Link {
+    pub fn new(rel: &str, type_: &str, href: impl Into<String>) -> Self {
+        Self { rel: rel.to_string(), type_: type_.to_string(), title: String::new(), href: href.into() }
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+}
+
+/// The page of a publication in the ABS web UI.
+pub fn web_link(href: impl Into<String>) -> Link {
+    Link::new("alternate", "text/html", href)
+}
+
+/// An OPDS facet link, e.g. one sort order out of a "Sort by" group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Facet {
+    pub group: String,
+    pub title: String,
+    pub href: String,
+    pub active: bool,
+    pub count: Option<usize>,
+}
+
+/// The labels readers show in their login prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authentication {
+    pub login: String,
+    pub password: String,
+}
+
+/// OpenSearch totals and the start/first/previous/next/last links of a paged feed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pagination {
+    pub total_results: usize,
+    pub start_index: usize,
+    pub items_per_page: usize,
+    pub links: Vec<Link>,
+}
+
+impl Pagination {
+    /// `page_info` is `(page, page_size, total_items, total_pages)`, pages counted from 0.
+    pub fn new(page_info: (usize, usize, usize, usize), url_base: &str, kind: FeedKind) -> Self {
+        let (page, page_size, total_items, total_pages) = page_info;
+
+        static PAGE_REGEX: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+        let regex = PAGE_REGEX.get_or_init(|| {
+            regex::Regex::new(r"[?&]page=\d+").expect("Failed to compile regex")
+        });
+        let clean_url = if url_base.contains("?page=") || url_base.contains("&page=") {
+            regex.replace(url_base, "").to_string()
+        } else {
+            url_base.to_string()
+        };
+        let separator = if clean_url.contains('?') { "&" } else { "?" };
+        let page_href = |page: usize| format!("{}{}page={}", clean_url, separator, page);
+
+        let type_ = kind.content_type();
+        let mut links = vec![
+            Link::new("start", type_, clean_url.clone()),
+            Link::new("first", type_, clean_url.clone()),
+        ];
+        if page > 0 {
+            let prev_page = page - 1;
+            let href = if prev_page > 0 { page_href(prev_page) } else { clean_url.clone() };
+            links.push(Link::new("previous", type_, href));
+        }
+        if page + 1 < total_pages {
+            links.push(Link::new("next", type_, page_href(page + 1)));
+        }
+        if total_pages > 1 {
+            links.push(Link::new("last", type_, page_href(total_pages - 1)));
+        }
+
+        Self {
+            total_results: total_items,
+            start_index: page * page_size + 1,
+            items_per_page: page_size,
+            links,
+        }
+    }
+}
+
+/// An Atom entry: a navigation card or a publication with its metadata.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    pub id: String,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub updated: String,
+    /// Plain-text content, e.g. the description.
+    pub content: Option<String>,
+    pub publisher: Option<String>,
+    /// Written as `dcterms:identifier`, e.g. `urn:isbn:…`.
+    pub identifier: Option<String>,
+    pub issued: Option<String>,
+    pub language: Option<String>,
+    /// Written as Atom `contributor`s, e.g. the narrators.
+    pub contributors: Vec<Person>,
+    pub series: Option<String>,
+    pub series_index: Option<String>,
+    pub links: Vec<Link>,
+    pub authors: Vec<Person>,
+    pub categories: Vec<String>,
+}
+
+/// The MARC relator code of narrators.
+pub const NARRATOR_ROLE: &str = "nrt";
+
+/// An Atom person construct: a name and, for authors and narrators, their feed in the
+/// catalog.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Person {
+    pub name: String,
+    pub uri: Option<String>,
+    /// MARC relator code, written as `opf:role`, e.g. [`NARRATOR_ROLE`].
+    pub role: Option<String>,
+}
+
+/// The feed of the books listed under `name` in a category, e.g. one author's books.
+pub fn category_item_href(library_id: &str, type_: &str, name: &str) -> String {
+    if type_ == "narrators" {
+        format!("/opds/libraries/{}?narrator={}", library_id, name)
+    } else {
+        format!("/opds/libraries/{}?name={}&type={}", library_id, name, type_)
+    }
+}
+
+impl Entry {
+    /// A card leading to another catalog feed, of `kind`.
+    pub fn navigation(id: impl Into<String>, title: impl Into<String>, href: impl Into<String>, kind: FeedKind, updated: &str) -> Self {
+        Self {
+            id: id.into(),
+            title: Some(title.into()),
+            updated: updated.to_string(),
+            links: vec![Link::new("subsection", kind.content_type(), href)],
+            ..Default::default()
+        }
+    }
+
+    /// A navigation card whose id is derived from its title, like `j.r.r.-tolkien`.
+    pub fn card(title: &str, href: impl Into<String>, kind: FeedKind, updated: &str) -> Self {
+        let id: String = title
+            .chars()
+            .flat_map(|c| if c == ' ' { '-'.to_lowercase() } else { c.to_lowercase() })
+            .collect();
+        Self::navigation(id, title, href, kind, updated)
+    }
+
+    /// A card for one author, narrator, genre or series in a category feed.
+    pub fn category_card(name: &str, type_: &str, library_id: &str, updated: &str) -> Self {
+        Self::card(name, category_item_href(library_id, type_, name), FeedKind::Acquisition, updated)
+    }
+
+    /// A library card, with a mosaic of its covers for clients that show a grid.
+    pub fn library(library: &Library, updated: &str) -> Self {
+        let mut entry = Self::navigation(
+            library.id.clone(),
+            library.name.clone(),
+            format!("/opds/libraries/{}?categories=true", library.id),
+            FeedKind::Navigation,
+            updated,
+        );
+        let cover = format!("/opds/libraries/{}/cover", library.id);
+        entry.links.push(Link::new(IMAGE_REL, "image/jpeg", cover.clone()));
+        entry.links.push(Link::new(THUMBNAIL_REL, "image/jpeg", cover));
+        entry
+    }
+
+    /// A publication with acquisition links for it and every merged edition.
+    pub fn publication(
+        item: &LibraryItem,
+        user: &InternalUser,
+        library_id: &str,
+        link_url: &str,
+        acquisition_links: &[AcquisitionLink],
+        updated: &str,
+    ) -> Self {
+        let mut links = Vec::new();
+        for edition in item.all_editions() {
+            let mime_type = crate::mime::from_format(edition.format.as_deref());
+            for link in acquisition_links {
+                if *link == AcquisitionLink::Stream {
+                    for (href, title) in track_links(&edition) {
+                        let stream = Link::new(STREAM_REL, "audio/mpeg", href);
+                        links.push(match title {
+                            Some(title) => stream.with_title(title),
+                            None => stream,
+                        });
+                    }
+                    continue;
+                }
+                let (href, type_) = match link {
+                    AcquisitionLink::Download => (
+                        abs_href(link_url, &format!("/api/items/{}/download", edition.id), "", user),
+                        crate::mime::OCTET_STREAM,
+                    ),
+                    AcquisitionLink::Ebook => (abs_href(link_url, &format!("/api/items/{}/ebook", edition.id), "", user), mime_type),
+                    AcquisitionLink::Zip if edition.audio_file_count > 1 => {
+                        (format!("/opds/items/{}/audiobook.zip", edition.id), "application/zip")
+                    }
+                    AcquisitionLink::Zip | AcquisitionLink::Stream => continue,
+                };
+                links.push(Link::new(ACQUISITION_REL, type_, href));
+            }
+        }
+        // ABS picks the cover format from the Accept header unless it is asked for one
+        links.extend(cover_links(link_url, &item.id, user));
+
+        Self {
+            id: format!("urn:uuid:{}", item.id),
+            title: item.title.clone(),
+            subtitle: item.subtitle.clone(),
+            updated: item.updated.clone().unwrap_or_else(|| updated.to_string()),
+            content: item.description.clone(),
+            publisher: item.publisher.clone(),
+            identifier: item.isbn.as_ref().map(|isbn| {
+                if isbn.starts_with("urn:isbn:") { isbn.clone() } else { format!("urn:isbn:{}", isbn) }
+            }),
+            issued: item.published_year.clone(),
+            language: item.language.clone(),
+            contributors: item
+                .narrators
+                .iter()
+                .map(|n| Person {
+                    name: n.name.clone(),
+                    uri: Some(category_item_href(library_id, "narrators", &n.name)),
+                    role: Some(NARRATOR_ROLE.to_string()),
+                })
+                .collect(),
+            series: item.series.first().cloned(),
+            series_index: item.series.first().and(item.series_sequence.clone()),
+            links,
+            authors: item
+                .authors
+                .iter()
+                .map(|a| Person {
+                    name: a.name.clone(),
+                    uri: Some(category_item_href(library_id, "authors", &a.name)),
+                    role: None,
+                })
+                .collect(),
+            categories: item.genres.iter().chain(item.tags.iter()).cloned().collect(),
+        }
+    }
+
+    /// A podcast episode: its audio file to download and the cover of its show.
+    pub fn episode(episode: &AbsEpisode, user: &InternalUser, link_url: &str, updated: &str) -> Self {
+        let show = episode.podcast.as_ref().map(|podcast| &podcast.metadata);
+        let mut links = Vec::new();
+        if let Some(file) = &episode.audio_file {
+            let type_ = file.mime_type.as_deref().unwrap_or("audio/mpeg");
+            links.push(Link::new(ACQUISITION_REL, type_, format!("/opds/items/{}/files/{}", episode.library_item_id, file.ino)));
+        }
+        links.extend(cover_links(link_url, &episode.library_item_id, user));
+
+        Self {
+            id: format!("urn:uuid:{}", episode.id),
+            title: episode.title.clone(),
+            subtitle: show.and_then(|show| show.title.clone()),
+            updated: episode
+                .published_at
+                .and_then(chrono::DateTime::from_timestamp_millis)
+                .map_or_else(|| updated.to_string(), |published| published.to_rfc3339()),
+            content: episode.description.clone(),
+            links,
+            authors: show
+                .and_then(|show| show.author.clone())
+                .map(|name| Person { name, uri: None, role: None })
+                .into_iter()
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    /// The cards of a library's categories feed: all items, then the categories of its
+    /// media type.
+    pub fn categories(library_id: &str, media_type: MediaType, i18n: &I18n, lang: Option<&str>, updated: &str) -> Vec<Self> {
+        let mut entries = vec![Self::navigation(
+            library_id,
+            i18n.localize(media_type.all_items_key(), lang),
+            format!("/opds/libraries/{}", library_id),
+            FeedKind::Acquisition,
+            updated,
+        )];
+        for &type_ in media_type.categories() {
+            let kind = if ["continue", "recent", "updated", "episodes"].contains(&type_) { FeedKind::Acquisition } else { FeedKind::Navigation };
+            entries.push(Self::navigation(
+                type_,
+                i18n.localize(&format!("category.{}", type_), lang),
+                format!("/opds/libraries/{}/{}", library_id, type_),
+                kind,
+                updated,
+            ));
+        }
+        entries
+    }
+}
+
+/// The cover and thumbnail links of an item, as JPEG.
+fn cover_links(link_url: &str, item_id: &str, user: &InternalUser) -> [Link; 2] {
+    let path = format!("/api/items/{}/cover", item_id);
+    [
+        Link::new(IMAGE_REL, "image/jpeg", abs_href(link_url, &path, "format=jpeg", user)),
+        Link::new(THUMBNAIL_REL, "image/jpeg", abs_href(link_url, &path, &format!("format=jpeg&width={}", THUMBNAIL_WIDTH), user)),
+    ]
+}
+
+/// The streaming links of an edition's audio tracks, with a `track/count` title when
+/// there is more than one.
+pub fn track_links(edition: &Edition) -> impl Iterator<Item = (String, Option<String>)> + '_ {
+    let count = edition.audio_file_count;
+    (1..=count).map(move |track| {
+        let href = format!("/opds/items/{}/tracks/{}", edition.id, track);
+        (href, (count > 1).then(|| format!("{}/{}", track, count)))
+    })
+}
+
+/// An OPDS catalog feed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feed {
+    pub id: String,
+    pub title: String,
+    pub updated: String,
+    pub kind: FeedKind,
+    pub authentication: Option<Authentication>,
+    /// `self`, search and other feed-level links, in output order.
+    pub links: Vec<Link>,
+    pub pagination: Option<Pagination>,
+    pub facets: Vec<Facet>,
+    pub entries: Vec<Entry>,
+}
+
+impl Feed {
+    pub fn new(id: impl Into<String>, title: impl Into<String>, kind: FeedKind, self_href: &str, i18n: &I18n, lang: Option<&str>) -> Self {
+        Self {
+            id: id.into(),
+            title: title.into(),
+            updated: chrono::Utc::now().to_rfc3339(),
+            kind,
+            authentication: Some(Authentication {
+                login: i18n.localize("auth.login", lang),
+                password: i18n.localize("auth.password", lang),
+            }),
+            links: vec![Link::new("self", kind.content_type(), self_href)],
+            pagination: None,
+            facets: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// The `self` link's href.
+    pub fn self_href(&self) -> &str {
+        self.links.iter().find(|l| l.rel == "self").map_or("", |l| l.href.as_str())
+    }
+
+    /// Adds the web interface and search links of `library`, plus a search limited to
+    /// this feed's filter if it has one.
+    pub fn with_library_links(mut self, library: &Library, i18n: &I18n, lang: Option<&str>) -> Self {
+        let search_title = i18n.localize("link.search", lang);
+        let acquisition = FeedKind::Acquisition.content_type();
+        let scoped = crate::xml::scoped_search_href(self.self_href(), "{searchTerms}");
+        self.links.push(Link::new("alternate", "text/html", format!("/library/{}", library.id)).with_title(i18n.localize("link.web_interface", lang)));
+        self.links.push(Link::new("search", "application/opensearchdescription+xml", format!("/opds/libraries/{}/search-definition", library.id)).with_title(search_title.clone()));
+        self.links.push(Link::new("search", acquisition, format!("/opds/libraries/{}?q={{searchTerms}}", library.id)).with_title(search_title));
+        if let Some(href) = scoped {
+            self.links.push(Link::new("search", acquisition, href).with_title(i18n.localize("link.search_scope", lang)));
+        }
+        self
+    }
+
+    /// Replaces the inline authentication block as `block` asks.
+    pub fn with_auth_block(mut self, block: AuthBlock) -> Self {
+        match block {
+            AuthBlock::Inline => {}
+            AuthBlock::None => self.authentication = None,
+            AuthBlock::Link => {
+                self.authentication = None;
+                self.links.push(Link::new(AUTH_DOCUMENT_REL, AUTH_DOCUMENT_TYPE, AUTH_DOCUMENT_HREF));
+            }
+        }
+        self
+    }
+
+    pub fn with_pagination(mut self, page_info: Option<(usize, usize, usize, usize)>) -> Self {
+        self.pagination = page_info.map(|info| Pagination::new(info, self.self_href(), self.kind));
+        self
+    }
+
+    pub fn with_entries(mut self, entries: Vec<Entry>) -> Self {
+        self.entries = entries;
+        self
+    }
+
+    pub fn with_facets(mut self, facets: Vec<Facet>) -> Self {
+        self.facets = facets;
+        self
+    }
+}
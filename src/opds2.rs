@@ -1,6 +1,7 @@
 use serde::Serialize;
-use crate::models::{Library, LibraryItem, InternalUser};
+use crate::models::{AcquisitionLink, Library, LibraryItem, InternalUser};
 use crate::i18n::I18n;
+use crate::opds::model::{abs_href, track_links, STREAM_REL};
 
 #[derive(Serialize)]
 pub struct Feed {
@@ -36,6 +37,17 @@ pub struct Link {
     pub templated: Option<bool>,
 }
 
+/// Where readers learn how to sign in, should a request be refused.
+fn auth_document_link() -> Link {
+    Link {
+        href: crate::opds::model::AUTH_DOCUMENT_HREF.to_string(),
+        rel: Some(crate::opds::model::AUTH_DOCUMENT_REL.to_string()),
+        type_: Some(crate::opds::model::AUTH_DOCUMENT_TYPE.to_string()),
+        title: None,
+        templated: None,
+    }
+}
+
 #[derive(Serialize)]
 pub struct Publication {
     pub metadata: PublicationMetadata,
@@ -47,6 +59,8 @@ pub struct Publication {
 #[derive(Serialize)]
 pub struct Contributor {
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub links: Option<Vec<Link>>,
 }
 
 #[derive(Serialize)]
@@ -87,19 +101,78 @@ pub struct BelongsTo {
 #[derive(Serialize)]
 pub struct SeriesMetadata {
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<f64>,
 }
 
 pub struct Opds2Builder;
 
+/// RFC 7807 problem details.
+#[derive(Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+}
+
+/// An OPDS Authentication Document.
+#[derive(Serialize)]
+pub struct AuthDocument {
+    pub id: String,
+    pub title: String,
+    pub authentication: Vec<AuthFlow>,
+}
+
+#[derive(Serialize)]
+pub struct AuthFlow {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub labels: AuthLabels,
+}
+
+#[derive(Serialize)]
+pub struct AuthLabels {
+    pub login: String,
+    pub password: String,
+}
+
 impl Opds2Builder {
-    pub fn build_root(libraries: &[Library], _updated_time: &str) -> String {
+    /// The authentication document describing our Basic auth.
+    pub fn build_auth_document(i18n: &I18n, lang: Option<&str>) -> String {
+        let document = AuthDocument {
+            id: crate::opds::model::AUTH_DOCUMENT_HREF.to_string(),
+            title: "ABS-OPDS".to_string(),
+            authentication: vec![AuthFlow {
+                type_: "http://opds-spec.org/auth/basic".to_string(),
+                labels: AuthLabels {
+                    login: i18n.localize("auth.login", lang),
+                    password: i18n.localize("auth.password", lang),
+                },
+            }],
+        };
+        serde_json::to_string(&document).unwrap_or_default()
+    }
+
+    pub fn build_problem(status: u16, title: &str, detail: &str) -> String {
+        let problem = Problem {
+            type_: "about:blank".to_string(),
+            title: title.to_string(),
+            status,
+            detail: detail.to_string(),
+        };
+        serde_json::to_string(&problem).unwrap_or_default()
+    }
+
+    pub fn build_root(libraries: &[Library], i18n: &I18n, lang: Option<&str>, _updated_time: &str) -> String {
         let links = vec![Link {
             href: "/opds".to_string(),
             rel: Some("self".to_string()),
             type_: Some("application/opds+json".to_string()),
             title: None,
             templated: None,
-        }];
+        }, auth_document_link()];
 
         let navigation = libraries
             .iter()
@@ -114,7 +187,7 @@ pub fn build_root(libraries: &[Library], _updated_time: &str) -> String {
 
         let feed = Feed {
             metadata: FeedMetadata {
-                title: "Libraries".to_string(),
+                title: i18n.localize("feed.library_list", lang),
                 number_of_items: None,
                 items_per_page: None,
                 current_page: None,
@@ -127,8 +200,12 @@ pub fn build_root(libraries: &[Library], _updated_time: &str) -> String {
         serde_json::to_string(&feed).unwrap_or_default()
     }
 
+    /// The cards of a library's categories, leaving out the `empty` ones, then its shelves.
     pub fn build_categories_root(
         library_id: &str,
+        media_type: crate::models::MediaType,
+        empty: &[&str],
+        shelves: &[crate::models::Shelf],
         i18n: &I18n,
         lang: Option<&str>,
         _updated_time: &str,
@@ -139,15 +216,16 @@ pub fn build_categories_root(
             type_: Some("application/opds+json".to_string()),
             title: None,
             templated: None,
-        }];
-
-        let categories = vec![
-            (library_id.to_string(), i18n.localize("category.all", lang)),
-            ("authors".to_string(), i18n.localize("category.authors", lang)),
-            ("narrators".to_string(), i18n.localize("category.narrators", lang)),
-            ("genres".to_string(), i18n.localize("category.genres", lang)),
-            ("series".to_string(), i18n.localize("category.series", lang)),
-        ];
+        }, auth_document_link()];
+
+        let mut categories = vec![(library_id.to_string(), i18n.localize(media_type.all_items_key(), lang))];
+        categories.extend(
+            media_type.categories().iter().map(|type_| (type_.to_string(), i18n.localize(&format!("category.{}", type_), lang))),
+        );
+        categories.retain(|(id, _)| id == library_id || !empty.contains(&id.as_str()));
+        let shelves = shelves
+            .iter()
+            .map(|shelf| (format!("/opds/libraries/{}?shelf={}", library_id, shelf.name), shelf.name.clone()));
 
         let navigation = categories
             .into_iter()
@@ -157,7 +235,10 @@ pub fn build_categories_root(
                 } else {
                     format!("/opds/libraries/{}/{}", library_id, id)
                 };
-
+                (href, title)
+            })
+            .chain(shelves)
+            .map(|(href, title)| {
                 Link {
                     href,
                     rel: None,
@@ -170,7 +251,7 @@ pub fn build_categories_root(
 
         let feed = Feed {
             metadata: FeedMetadata {
-                title: "Categories".to_string(),
+                title: i18n.localize("feed.categories", lang),
                 number_of_items: None,
                 items_per_page: None,
                 current_page: None,
@@ -188,6 +269,8 @@ pub fn build_category_letters(
         library_name: &str,
         type_: &str,
         letters: &[(String, usize)],
+        i18n: &I18n,
+        lang: Option<&str>,
     ) -> String {
         let links = vec![Link {
             href: format!("/opds/libraries/{}/{}", library_id, type_),
@@ -195,20 +278,15 @@ pub fn build_category_letters(
             type_: Some("application/opds+json".to_string()),
             title: None,
             templated: None,
-        }];
+        }, auth_document_link()];
 
         let navigation = letters
             .iter()
             .map(|(letter, count)| Link {
-                href: format!(
-                    "/opds/libraries/{}/{}?start={}",
-                    library_id,
-                    type_,
-                    letter.to_lowercase()
-                ),
+                href: crate::xml::letter_href(library_id, type_, letter),
                 rel: None,
                 type_: Some("application/opds+json".to_string()),
-                title: Some(format!("{} ({})", letter, count)),
+                title: Some(i18n.localize_with("category.letter", lang, &[("letter", letter), ("count", &count.to_string())])),
                 templated: None,
             })
             .collect();
@@ -228,11 +306,12 @@ pub fn build_category_letters(
         serde_json::to_string(&feed).unwrap_or_default()
     }
 
+    /// `items` are the names of the page with the titles to show for them.
     pub fn build_category_items(
         library_id: &str,
         library_name: &str,
         type_: &str,
-        items: &[String],
+        items: &[(String, String)],
         page_info: Option<(usize, usize, usize, usize)>,
         url_base: &str,
     ) -> String {
@@ -242,7 +321,7 @@ pub fn build_category_items(
             type_: Some("application/opds+json".to_string()),
             title: None,
             templated: None,
-        }];
+        }, auth_document_link()];
 
         let mut current_page = None;
         let mut items_per_page = None;
@@ -316,7 +395,7 @@ pub fn build_category_items(
 
         let navigation = items
             .iter()
-            .map(|item| {
+            .map(|(item, title)| {
                 let mut url_buf = String::new();
                 for c in item.chars() {
                     if c == ' ' {
@@ -329,13 +408,17 @@ pub fn build_category_items(
                 }
 
                 Link {
-                    href: format!(
-                        "/opds/libraries/{}?name={}&type={}",
-                        library_id, item, type_
-                    ),
+                    href: if type_ == "narrators" {
+                        format!("/opds/libraries/{}?narrator={}", library_id, item)
+                    } else {
+                        format!(
+                            "/opds/libraries/{}?name={}&type={}",
+                            library_id, item, type_
+                        )
+                    },
                     rel: None,
                     type_: Some("application/opds+json".to_string()),
-                    title: Some(item.clone()),
+                    title: Some(title.clone()),
                     templated: None,
                 }
             })
@@ -356,15 +439,20 @@ pub fn build_category_items(
         serde_json::to_string(&feed).unwrap_or_default()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn build_publications(
         library_id: &str,
         library_name: &str,
         items: &[LibraryItem],
         user: &InternalUser,
         link_url: &str,
+        web_item_url: &dyn Fn(&str) -> Option<String>,
+        acquisition_links: &[AcquisitionLink],
         updated_time: &str,
         page_info: Option<(usize, usize, usize, usize)>,
         url_base: &str,
+        i18n: &I18n,
+        lang: Option<&str>,
     ) -> String {
         let mut links = vec![Link {
             href: url_base.to_string(),
@@ -372,16 +460,25 @@ pub fn build_publications(
             type_: Some("application/opds+json".to_string()),
             title: None,
             templated: None,
-        }];
+        }, auth_document_link()];
 
         // Add template search link
         links.push(Link {
             href: format!("/opds/libraries/{}?q={{query}}", library_id),
             rel: Some("search".to_string()),
             type_: Some("application/opds+json".to_string()),
-            title: Some("Search this library".to_string()),
+            title: Some(i18n.localize("link.search", lang)),
             templated: Some(true),
         });
+        if let Some(href) = crate::xml::scoped_search_href(url_base, "{query}") {
+            links.push(Link {
+                href,
+                rel: Some("search".to_string()),
+                type_: Some("application/opds+json".to_string()),
+                title: Some(i18n.localize("link.search_scope", lang)),
+                templated: Some(true),
+            });
+        }
 
         let mut current_page = None;
         let mut items_per_page = None;
@@ -456,56 +553,77 @@ pub fn build_publications(
         let publications = items
             .iter()
             .map(|item| {
-                let format = item.format.as_deref().unwrap_or("");
-                let (mime_type, schema_type) = match format {
-                    "audiobook" => ("audio/mpeg", "http://schema.org/Audiobook"),
-                    "epub" => ("application/epub+zip", "http://schema.org/Book"),
-                    "pdf" => ("application/pdf", "http://schema.org/Book"),
-                    "mobi" => ("application/x-mobipocket-ebook", "http://schema.org/Book"),
-                    _ => ("application/octet-stream", "http://schema.org/Book"),
+                let schema_type = if item.format.is_none() && item.audio_file_count > 0 {
+                    "http://schema.org/Audiobook"
+                } else {
+                    "http://schema.org/Book"
                 };
 
-                let p_links = vec![
-                    Link {
-                        href: format!(
-                            "{}/api/items/{}/download?token={}",
-                            link_url, item.id, user.api_key
-                        ),
-                        rel: Some("download".to_string()),
-                        type_: Some("application/octet-stream".to_string()),
-                        title: None,
-                        templated: None,
-                    },
-                    Link {
-                        href: format!(
-                            "{}/api/items/{}/ebook?token={}",
-                            link_url, item.id, user.api_key
-                        ),
-                        rel: Some("download".to_string()),
-                        type_: Some(mime_type.to_string()),
+                let mut p_links = item
+                    .all_editions()
+                    .flat_map(|edition| {
+                        let mime_type = crate::mime::from_format(edition.format.as_deref());
+                        acquisition_links
+                            .iter()
+                            .flat_map(|link| {
+                                if *link == AcquisitionLink::Stream {
+                                    return track_links(&edition)
+                                        .map(|(href, title)| Link {
+                                            href,
+                                            rel: Some(STREAM_REL.to_string()),
+                                            type_: Some("audio/mpeg".to_string()),
+                                            title,
+                                            templated: None,
+                                        })
+                                        .collect();
+                                }
+                                let (href, type_) = match link {
+                                    AcquisitionLink::Download => (
+                                        abs_href(link_url, &format!("/api/items/{}/download", edition.id), "", user),
+                                        crate::mime::OCTET_STREAM,
+                                    ),
+                                    AcquisitionLink::Ebook => (
+                                        abs_href(link_url, &format!("/api/items/{}/ebook", edition.id), "", user),
+                                        mime_type,
+                                    ),
+                                    AcquisitionLink::Zip if edition.audio_file_count > 1 => {
+                                        (format!("/opds/items/{}/audiobook.zip", edition.id), "application/zip")
+                                    }
+                                    AcquisitionLink::Zip | AcquisitionLink::Stream => return Vec::new(),
+                                };
+                                vec![Link {
+                                    href,
+                                    rel: Some("download".to_string()),
+                                    type_: Some(type_.to_string()),
+                                    title: None,
+                                    templated: None,
+                                }]
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+                if let Some(href) = web_item_url(&item.id) {
+                    p_links.push(Link {
+                        href,
+                        rel: Some("alternate".to_string()),
+                        type_: Some("text/html".to_string()),
                         title: None,
                         templated: None,
-                    },
-                ];
+                    });
+                }
 
                 let images = vec![
                     Link {
-                        href: format!(
-                            "{}/api/items/{}/cover?token={}",
-                            link_url, item.id, user.api_key
-                        ),
+                        href: abs_href(link_url, &format!("/api/items/{}/cover", item.id), "format=jpeg", user),
                         rel: None,
-                        type_: Some("image/webp".to_string()),
+                        type_: Some("image/jpeg".to_string()),
                         title: None,
                         templated: None,
                     },
                     Link {
-                        href: format!(
-                            "{}/api/items/{}/cover?token={}",
-                            link_url, item.id, user.api_key
-                        ),
+                        href: abs_href(link_url, &format!("/api/items/{}/cover", item.id), "format=webp", user),
                         rel: None,
-                        type_: Some("image/png".to_string()),
+                        type_: Some("image/webp".to_string()),
                         title: None,
                         templated: None,
                     },
@@ -517,7 +635,16 @@ pub fn build_publications(
                     Some(
                         item.authors
                             .iter()
-                            .map(|a| Contributor { name: a.name.clone() })
+                            .map(|a| Contributor {
+                                name: a.name.clone(),
+                                links: Some(vec![Link {
+                                    href: crate::opds::model::category_item_href(library_id, "authors", &a.name),
+                                    rel: None,
+                                    type_: Some("application/opds+json".to_string()),
+                                    title: None,
+                                    templated: None,
+                                }]),
+                            })
                             .collect(),
                     )
                 };
@@ -528,7 +655,16 @@ pub fn build_publications(
                     Some(
                         item.narrators
                             .iter()
-                            .map(|a| Contributor { name: a.name.clone() })
+                            .map(|n| Contributor {
+                                name: n.name.clone(),
+                                links: Some(vec![Link {
+                                    href: crate::opds::model::category_item_href(library_id, "narrators", &n.name),
+                                    rel: None,
+                                    type_: Some("application/opds+json".to_string()),
+                                    title: None,
+                                    templated: None,
+                                }]),
+                            })
                             .collect(),
                     )
                 };
@@ -537,6 +673,7 @@ pub fn build_publications(
                     Some(BelongsTo {
                         series: Some(SeriesMetadata {
                             name: item.series[0].clone(),
+                            position: item.series_sequence.as_deref().and_then(|s| s.trim().parse().ok()),
                         }),
                     })
                 } else {
@@ -560,7 +697,7 @@ pub fn build_publications(
                         subtitle: item.subtitle.clone(),
                         identifier: Some(format!("urn:uuid:{}", item.id)),
                         language: item.language.clone(),
-                        modified: Some(updated_time.to_string()),
+                        modified: Some(item.updated.clone().unwrap_or_else(|| updated_time.to_string())),
                         description: item.description.clone(),
                         publisher: item.publisher.clone(),
                         author: authors,
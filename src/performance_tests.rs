@@ -1,48 +1,23 @@
 #[cfg(test)]
 mod tests {
-    use crate::api::AbsClient;
-    use crate::models::{AbsItemsResponse, AbsLibrary, AbsItemResult, AbsMedia, AbsMetadata, InternalUser, AppConfig};
+    use crate::models::{AbsItemsResponse, AbsLibrary, AbsItemResult, AppConfig};
     use crate::service::LibraryService;
     use crate::i18n::I18n;
     use crate::handlers::LibraryQuery;
-    use mockall::mock;
+    use crate::test_util::{create_item, mock_user, MockAbsClient};
     use std::sync::Arc;
     use std::time::Instant;
-    use async_trait::async_trait;
 
-    mock! {
-        pub AbsClient {}
-        #[async_trait]
-        impl AbsClient for AbsClient {
-            async fn login(&self, username: &str, password: &str) -> anyhow::Result<InternalUser>;
-            async fn get_libraries(&self, user: &InternalUser) -> anyhow::Result<Vec<AbsLibrary>>;
-            async fn get_library(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsLibrary>;
-            async fn get_items(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsItemsResponse>;
-        }
-    }
-
-    fn mock_user() -> InternalUser {
-        InternalUser {
-            name: "test_user".to_string(),
-            api_key: "test_token".to_string(),
-            password: None,
-        }
-    }
 
     fn mock_config() -> AppConfig {
-        AppConfig {
-            port: 3000,
-            use_proxy: false,
-            abs_url: "http://localhost:3000".to_string(),
-            opds_users: "user:token:pass".to_string(),
-            internal_users: vec![],
-            show_audiobooks: true,
-            show_char_cards: true,
-            opds_no_auth: false,
-            abs_noauth_username: "".to_string(),
-            abs_noauth_password: "".to_string(),
-            opds_page_size: 100,
-        }
+        AppConfig::builder()
+            .port(3000)
+            .user("user", "token", "pass")
+            .show_audiobooks(true)
+            .show_char_cards(true)
+            .page_size(100)
+            .build()
+            .unwrap()
     }
 
     fn mock_i18n() -> I18n {
@@ -53,29 +28,6 @@ fn mock_items_response(items: Vec<AbsItemResult>) -> AbsItemsResponse {
         AbsItemsResponse { results: items }
     }
 
-    fn create_item(id: &str, title: &str, author: Option<&str>, genre: Option<&str>) -> AbsItemResult {
-        AbsItemResult {
-            id: id.to_string(),
-            media: AbsMedia {
-                ebook_format: Some("epub".to_string()),
-                metadata: AbsMetadata {
-                    title: Some(title.to_string()),
-                    subtitle: None,
-                    description: None,
-                    genres: genre.map(|g| vec![g.to_string()]),
-                    tags: None,
-                    publisher: None,
-                    isbn: None,
-                    language: Some("en".to_string()),
-                    published_year: None,
-                    author_name: author.map(|a| a.to_string()),
-                    narrator_name: None,
-                    series_name: None,
-                },
-            },
-        }
-    }
-
     #[tokio::test]
     async fn test_performance_100000_items() {
         let mut mock_client = MockAbsClient::new();
@@ -97,7 +49,7 @@ async fn test_performance_100000_items() {
 
         mock_client
             .expect_get_library()
-            .returning(|_, _| Ok(AbsLibrary { id: "lib1".to_string(), name: "Test Library".to_string(), icon: None }));
+            .returning(|_, _| Ok(AbsLibrary { id: "lib1".to_string(), name: "Test Library".to_string(), icon: None, media_type: Default::default() }));
 
         let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
 
@@ -106,10 +58,20 @@ async fn test_performance_100000_items() {
             page: 0,
             categories: None,
             author: None,
+            narrator: None,
             title: None,
             name: None,
             type_: None,
             start: None,
+            limit: None,
+            shelf: None,
+            updated_since: None,
+            sort: None,
+            format: None,
+            recently_updated: false,
+            recently_added: false,
+            in_progress: false,
+            collection: None,
         };
 
         println!("Starting performance test with 100,000 items...");
@@ -125,16 +87,16 @@ async fn test_performance_100000_items() {
         // Measure get_categories (Authors)
         let start = Instant::now();
         let _categories = service.get_categories(&user, "lib1", "authors", &LibraryQuery {
-             q: None, page: 0, categories: None, author: None, title: None, name: None, type_: None, start: None
-        }).await.unwrap();
+             q: None, page: 0, categories: None, author: None, narrator: None, title: None, name: None, type_: None, start: None, limit: None, shelf: None, updated_since: None, sort: None, format: None, recently_updated: false, recently_added: false, in_progress: false, collection: None
+        }, None).await.unwrap();
         let duration = start.elapsed();
         println!("get_categories (authors) took: {:?}", duration);
 
         // Measure get_categories (Genres)
         let start = Instant::now();
         let _categories = service.get_categories(&user, "lib1", "genres", &LibraryQuery {
-             q: None, page: 0, categories: None, author: None, title: None, name: None, type_: None, start: None
-        }).await.unwrap();
+             q: None, page: 0, categories: None, author: None, narrator: None, title: None, name: None, type_: None, start: None, limit: None, shelf: None, updated_since: None, sort: None, format: None, recently_updated: false, recently_added: false, in_progress: false, collection: None
+        }, None).await.unwrap();
         let duration = start.elapsed();
         println!("get_categories (genres) took: {:?}", duration);
     }
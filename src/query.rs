@@ -0,0 +1,304 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// A parsed `?q=` search: whitespace-separated terms that must all match, where
+/// `"quoted phrases"` are kept together as a single term.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    terms: Vec<String>,
+    identifier: Option<String>,
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Self {
+        let mut terms = Vec::new();
+        let mut current = String::new();
+        let mut in_quotes = false;
+
+        for c in input.chars() {
+            match c {
+                '"' => {
+                    // A quote always ends the current term, so `foo"bar baz"` is two terms
+                    push_term(&mut terms, &mut current);
+                    in_quotes = !in_quotes;
+                }
+                c if c.is_whitespace() && !in_quotes => push_term(&mut terms, &mut current),
+                c => current.push(c),
+            }
+        }
+        // An unterminated quote runs to the end of the input
+        push_term(&mut terms, &mut current);
+
+        Self { terms, identifier: detect_identifier(input) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// The folded (lowercased, diacritic-free) terms; phrases keep their inner whitespace collapsed to single spaces.
+    pub fn terms(&self) -> &[String] {
+        &self.terms
+    }
+
+    /// The canonical ISBN-13 or ASIN if the whole query is one, e.g. from a scanned barcode.
+    /// Such queries should be matched exactly against the item identifiers instead of `terms`.
+    pub fn identifier(&self) -> Option<&str> {
+        self.identifier.as_deref()
+    }
+
+    /// True if every term is matched by `term_matches`, which is typically checked
+    /// against all searchable fields, so terms may match in different fields.
+    pub fn matches(&self, term_matches: impl Fn(&str) -> bool) -> bool {
+        self.terms.iter().all(|term| term_matches(term))
+    }
+}
+
+/// What a shelf condition checks, the `genre` of `genre:Mystery`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShelfField {
+    /// A bare term, searched in all fields like `?q=`.
+    Any,
+    Title,
+    Author,
+    Narrator,
+    Series,
+    Genre,
+    Tag,
+    Publisher,
+    Language,
+    /// The ebook format, or `audiobook`.
+    Format,
+}
+
+impl ShelfField {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "title" => Self::Title,
+            "author" => Self::Author,
+            "narrator" => Self::Narrator,
+            "series" => Self::Series,
+            "genre" => Self::Genre,
+            "tag" => Self::Tag,
+            "publisher" => Self::Publisher,
+            "language" => Self::Language,
+            "format" => Self::Format,
+            _ => return None,
+        })
+    }
+}
+
+/// A virtual shelf's saved filter, such as `genre:Mystery AND tag:cozy`: conditions that
+/// must all match, with values quoted when they contain spaces (`author:"Agatha Christie"`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShelfQuery {
+    conditions: Vec<(ShelfField, String)>,
+}
+
+impl ShelfQuery {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut tokens = Vec::new();
+        let mut current = String::new();
+        let mut in_quotes = false;
+        for c in input.chars() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                c if c.is_whitespace() && !in_quotes => tokens.push(std::mem::take(&mut current)),
+                c => current.push(c),
+            }
+        }
+        tokens.push(current);
+
+        let mut conditions = Vec::new();
+        for token in tokens.iter().filter(|t| !t.is_empty() && t.as_str() != "AND") {
+            let (field, value) = match token.split_once(':') {
+                Some((field, value)) => match ShelfField::parse(&field.to_ascii_lowercase()) {
+                    Some(field) => (field, value),
+                    None => return Err(format!("Unknown field '{}' in '{}'", field, input)),
+                },
+                None => (ShelfField::Any, token.as_str()),
+            };
+            let value = fold(&value.split_whitespace().collect::<Vec<_>>().join(" "));
+            if value.is_empty() {
+                return Err(format!("Empty condition '{}' in '{}'", token, input));
+            }
+            conditions.push((field, value));
+        }
+        if conditions.is_empty() {
+            return Err(format!("No conditions in '{}'", input));
+        }
+        Ok(Self { conditions })
+    }
+
+    /// The fields and folded values that must all match.
+    pub fn conditions(&self) -> &[(ShelfField, String)] {
+        &self.conditions
+    }
+}
+
+/// What item feeds can be ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Title,
+    /// The first author, by surname with `AUTHORS_BY_SURNAME`.
+    Author,
+    /// When the item was added to ABS.
+    Added,
+    /// When the item or its files last changed.
+    Updated,
+    /// The published year.
+    Published,
+    /// The first series, then the position in it.
+    Series,
+}
+
+/// An order of item feeds such as `added:desc`; ascending unless `:desc` is given.
+/// Items without a value sort last either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl SortOrder {
+    pub const RECENTLY_UPDATED: Self = Self { field: SortField::Updated, descending: true };
+    pub const RECENTLY_ADDED: Self = Self { field: SortField::Added, descending: true };
+
+    /// The orders offered as sort facets, with the i18n key of their titles.
+    pub const FACETS: [(Self, &'static str); 5] = [
+        (Self { field: SortField::Title, descending: false }, "sort.title"),
+        (Self { field: SortField::Author, descending: false }, "sort.author"),
+        (Self { field: SortField::Added, descending: true }, "sort.added"),
+        (Self { field: SortField::Published, descending: true }, "sort.published"),
+        (Self { field: SortField::Series, descending: false }, "sort.series"),
+    ];
+
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (field, direction) = input.trim().split_once(':').unwrap_or((input.trim(), "asc"));
+        let field = match field.to_ascii_lowercase().as_str() {
+            "title" => SortField::Title,
+            "author" => SortField::Author,
+            "added" => SortField::Added,
+            "updated" => SortField::Updated,
+            "published" => SortField::Published,
+            "series" => SortField::Series,
+            _ => return Err(format!("Unknown sort field '{}' in '{}'", field, input)),
+        };
+        let descending = match direction.to_ascii_lowercase().as_str() {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(format!("Unknown sort direction '{}' in '{}'", direction, input)),
+        };
+        Ok(Self { field, descending })
+    }
+}
+
+/// The form `parse` reads, e.g. `added:desc`.
+impl std::fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let field = match self.field {
+            SortField::Title => "title",
+            SortField::Author => "author",
+            SortField::Added => "added",
+            SortField::Updated => "updated",
+            SortField::Published => "published",
+            SortField::Series => "series",
+        };
+        write!(f, "{}:{}", field, if self.descending { "desc" } else { "asc" })
+    }
+}
+
+/// A point in time as milliseconds since the epoch, from either such a number or an
+/// RFC 3339 timestamp. A `+` offset that arrived unescaped in a query string reads as a
+/// space, so a space is taken as `+`.
+pub fn parse_timestamp(input: &str) -> Option<i64> {
+    let input = input.trim();
+    if let Ok(millis) = input.parse::<i64>() {
+        return Some(millis);
+    }
+    chrono::DateTime::parse_from_rfc3339(&input.replace(' ', "+")).ok().map(|at| at.timestamp_millis())
+}
+
+/// Lowercases and strips diacritics, so "Hašek" and "hasek" compare equal.
+pub fn fold(s: &str) -> String {
+    if s.is_ascii() {
+        return s.to_ascii_lowercase();
+    }
+    s.nfd()
+        .filter(|c| !crate::xml::is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn push_term(terms: &mut Vec<String>, current: &mut String) {
+    let term = fold(&current.split_whitespace().collect::<Vec<_>>().join(" "));
+    if !term.is_empty() {
+        terms.push(term);
+    }
+    current.clear();
+}
+
+/// Strips hyphens and spaces and uppercases an ISBN or ASIN, converting ISBN-10 to
+/// ISBN-13 so both forms of the same book compare equal.
+pub fn canonical_identifier(s: &str) -> String {
+    let compact: String = s
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' ' | '\u{2010}' | '\u{2013}'))
+        .collect::<String>()
+        .to_ascii_uppercase();
+    let compact = compact.strip_prefix("URN:ISBN:").or_else(|| compact.strip_prefix("ISBN:")).unwrap_or(&compact).to_string();
+
+    if is_isbn10(&compact) {
+        let body = format!("978{}", &compact[..9]);
+        format!("{}{}", body, isbn13_check_digit(&body))
+    } else {
+        compact
+    }
+}
+
+/// The canonical identifier if `input` is a valid ISBN-10/13 (checksum included) or an
+/// Amazon ASIN (`B0` followed by eight letters or digits).
+fn detect_identifier(input: &str) -> Option<String> {
+    let trimmed = input.trim();
+    let trimmed = trimmed
+        .get(..5)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("asin:"))
+        .map_or(trimmed, |_| &trimmed[5..]);
+    let id = canonical_identifier(trimmed);
+    let is_asin = id.len() == 10 && id.starts_with("B0") && id.chars().all(|c| c.is_ascii_alphanumeric());
+    (is_isbn13(&id) || is_asin).then_some(id)
+}
+
+fn is_isbn10(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    if bytes.len() != 10 || !bytes[..9].iter().all(u8::is_ascii_digit) {
+        return false;
+    }
+    let check = match bytes[9] {
+        b'X' => 10,
+        b if b.is_ascii_digit() => u32::from(b - b'0'),
+        _ => return false,
+    };
+    let sum: u32 = bytes[..9]
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (10 - i as u32) * u32::from(b - b'0'))
+        .sum::<u32>()
+        + check;
+    sum.is_multiple_of(11)
+}
+
+fn is_isbn13(s: &str) -> bool {
+    s.len() == 13
+        && s.bytes().all(|b| b.is_ascii_digit())
+        && (s.starts_with("978") || s.starts_with("979"))
+        && isbn13_check_digit(&s[..12]) == char::from(s.as_bytes()[12])
+}
+
+fn isbn13_check_digit(first12: &str) -> char {
+    let sum: u32 = first12
+        .bytes()
+        .enumerate()
+        .map(|(i, b)| u32::from(b - b'0') * if i % 2 == 0 { 1 } else { 3 })
+        .sum();
+    char::from(b'0' + ((10 - sum % 10) % 10) as u8)
+}
@@ -0,0 +1,205 @@
+//! Recording ABS responses to disk and serving them back, for reproducible tests and
+//! bug reports against real-world metadata. `ABS_RECORD_DIR` wraps the ABS client in a
+//! [`RecordingClient`]; `ABS_REPLAY_DIR` serves a recording with a [`ReplayClient`].
+//!
+//! A recording holds one JSON file per response, `libraries.json`,
+//! `library-<id>.json`, `items-<library id>.json` and `item-<id>.json`, and the covers
+//! as `cover-<id>.bin`. Tokens and passwords are never written.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::api::{AbsClient, AuthClient, CatalogClient, CollectionClient, PodcastClient, ProgressClient};
+use crate::error::{AppError, Result};
+use crate::models::{AbsItemResult, AbsItemsResponse, AbsLibrary, InternalUser};
+
+/// The token handed to users of a replay. Nothing checks it.
+pub const REPLAY_TOKEN: &str = "replay";
+
+/// The file of a response, with IDs reduced to characters safe in file names.
+fn file_name(kind: &str, id: Option<&str>, extension: &str) -> String {
+    match id {
+        Some(id) => {
+            let id: String = id
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                .collect();
+            format!("{}-{}.{}", kind, id, extension)
+        }
+        None => format!("{}.{}", kind, extension),
+    }
+}
+
+/// Passes everything on to the wrapped client and writes the responses to a directory.
+/// Responses are per user; the last one recorded wins.
+pub struct RecordingClient {
+    inner: Arc<dyn AbsClient + Send + Sync>,
+    dir: PathBuf,
+}
+
+impl RecordingClient {
+    pub fn new(inner: Arc<dyn AbsClient + Send + Sync>, dir: impl Into<PathBuf>) -> Self {
+        Self { inner, dir: dir.into() }
+    }
+
+    /// Writes `bytes`, logging failures: a recording must not break the catalog.
+    async fn write(&self, name: String, bytes: &[u8]) {
+        let result = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(self.dir.join(&name), bytes).await
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to record {}: {}", name, e);
+        }
+    }
+
+    async fn record<T: Serialize>(&self, name: String, value: &T) {
+        match serde_json::to_vec_pretty(value) {
+            Ok(json) => self.write(name, &json).await,
+            Err(e) => tracing::warn!("Failed to record {}: {}", name, e),
+        }
+    }
+}
+
+impl AbsClient for RecordingClient {
+    fn progress(&self) -> Option<&dyn ProgressClient> {
+        self.inner.progress()
+    }
+
+    fn podcasts(&self) -> Option<&dyn PodcastClient> {
+        self.inner.podcasts()
+    }
+
+    fn collections(&self) -> Option<&dyn CollectionClient> {
+        self.inner.collections()
+    }
+}
+
+#[async_trait]
+impl AuthClient for RecordingClient {
+    async fn login(&self, username: &str, password: &str) -> Result<InternalUser> {
+        self.inner.login(username, password).await
+    }
+
+    async fn verify_token(&self, token: &str) -> Result<InternalUser> {
+        self.inner.verify_token(token).await
+    }
+
+    async fn forget_login(&self, username: &str) -> bool {
+        self.inner.forget_login(username).await
+    }
+
+    async fn refresh(&self, token: &str) -> Option<String> {
+        self.inner.refresh(token).await
+    }
+}
+
+#[async_trait]
+impl CatalogClient for RecordingClient {
+    async fn get_libraries(&self, user: &InternalUser) -> Result<Vec<AbsLibrary>> {
+        let libraries = self.inner.get_libraries(user).await?;
+        self.record(file_name("libraries", None, "json"), &libraries).await;
+        Ok(libraries)
+    }
+
+    async fn get_library(&self, user: &InternalUser, library_id: &str) -> Result<AbsLibrary> {
+        let library = self.inner.get_library(user, library_id).await?;
+        self.record(file_name("library", Some(library_id), "json"), &library).await;
+        Ok(library)
+    }
+
+    async fn get_items(&self, user: &InternalUser, library_id: &str) -> Result<AbsItemsResponse> {
+        let items = self.inner.get_items(user, library_id).await?;
+        self.record(file_name("items", Some(library_id), "json"), &items).await;
+        Ok(items)
+    }
+
+    async fn get_item(&self, user: &InternalUser, item_id: &str) -> Result<AbsItemResult> {
+        let item = self.inner.get_item(user, item_id).await?;
+        self.record(file_name("item", Some(item_id), "json"), &item).await;
+        Ok(item)
+    }
+
+    async fn forget_items(&self, user: &InternalUser, library_id: &str) {
+        self.inner.forget_items(user, library_id).await
+    }
+
+    async fn get_cover(&self, user: &InternalUser, item_id: &str) -> Result<Option<Vec<u8>>> {
+        let cover = self.inner.get_cover(user, item_id).await?;
+        if let Some(bytes) = &cover {
+            self.write(file_name("cover", Some(item_id), "bin"), bytes).await;
+        }
+        Ok(cover)
+    }
+}
+
+/// Serves a recording. Any credentials log in, and responses that weren't recorded
+/// are not found.
+pub struct ReplayClient {
+    dir: PathBuf,
+}
+
+impl ReplayClient {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    async fn read(&self, name: &str, context: &'static str) -> Result<Vec<u8>> {
+        match tokio::fs::read(self.dir.join(name)).await {
+            Ok(bytes) => Ok(bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::debug!("No recorded {} in {}", name, self.dir.display());
+                Err(AppError::upstream(context, reqwest::StatusCode::NOT_FOUND))
+            }
+            Err(e) => Err(anyhow::anyhow!("Failed to read {}: {}", name, e).into()),
+        }
+    }
+
+    async fn replay<T: DeserializeOwned>(&self, name: String, context: &'static str) -> Result<T> {
+        let bytes = self.read(&name, context).await?;
+        serde_json::from_slice(&bytes).map_err(|e| anyhow::anyhow!("Invalid recording {}: {}", name, e).into())
+    }
+}
+
+impl AbsClient for ReplayClient {}
+
+#[async_trait]
+impl AuthClient for ReplayClient {
+    async fn login(&self, username: &str, _password: &str) -> Result<InternalUser> {
+        Ok(InternalUser { name: username.to_string(), api_key: REPLAY_TOKEN.to_string(), password: None })
+    }
+
+    async fn verify_token(&self, _token: &str) -> Result<InternalUser> {
+        Err(anyhow::anyhow!("ABS tokens can't be checked while replaying").into())
+    }
+}
+
+#[async_trait]
+impl CatalogClient for ReplayClient {
+    async fn get_libraries(&self, _user: &InternalUser) -> Result<Vec<AbsLibrary>> {
+        self.replay(file_name("libraries", None, "json"), "Failed to fetch libraries").await
+    }
+
+    async fn get_library(&self, _user: &InternalUser, library_id: &str) -> Result<AbsLibrary> {
+        self.replay(file_name("library", Some(library_id), "json"), "Failed to fetch library details").await
+    }
+
+    async fn get_items(&self, _user: &InternalUser, library_id: &str) -> Result<AbsItemsResponse> {
+        self.replay(file_name("items", Some(library_id), "json"), "Failed to fetch library items").await
+    }
+
+    async fn get_item(&self, _user: &InternalUser, item_id: &str) -> Result<AbsItemResult> {
+        self.replay(file_name("item", Some(item_id), "json"), "Failed to fetch library item").await
+    }
+
+    async fn get_cover(&self, _user: &InternalUser, item_id: &str) -> Result<Option<Vec<u8>>> {
+        match self.read(&file_name("cover", Some(item_id), "bin"), "Failed to fetch cover").await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(AppError::Upstream { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
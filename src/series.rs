@@ -0,0 +1,68 @@
+//! Series names as ABS sends them, such as `"Mistborn #1, Cosmere #3"`: one entry per
+//! series, each with an optional position that `SERIES_PATTERN` picks out.
+
+use regex::Regex;
+use std::borrow::Cow;
+
+use crate::models::AppConfig;
+
+/// Where the position sits in `"Mistborn #1"`: everything after the `#`.
+pub const DEFAULT_SERIES_PATTERN: &str = r"\s*#\s*(?P<sequence>.*)$";
+
+/// Splits series entries into their names and positions.
+#[derive(Debug, Clone)]
+pub struct SeriesParser {
+    /// The part of an entry that holds its position; `None` keeps entries as they are.
+    pattern: Option<Regex>,
+    /// Leaves the matched position in the series name, e.g. `"Mistborn #1"`.
+    keep_sequence: bool,
+}
+
+impl Default for SeriesParser {
+    fn default() -> Self {
+        Self::new(DEFAULT_SERIES_PATTERN, false).expect("the default series pattern is valid")
+    }
+}
+
+impl SeriesParser {
+    /// A parser for `pattern`, whose `sequence` group (or, without one, the whole match) is
+    /// the position. An empty pattern turns the cleanup off.
+    pub fn new(pattern: &str, keep_sequence: bool) -> Result<Self, regex::Error> {
+        let pattern = if pattern.is_empty() { None } else { Some(Regex::new(pattern)?) };
+        Ok(Self { pattern, keep_sequence })
+    }
+
+    /// The parser for `SERIES_PATTERN` and `SERIES_KEEP_SEQUENCE`. `AppConfig::validate`
+    /// rejects invalid patterns, so one that slipped through disables the cleanup.
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self::new(&config.series_pattern, config.series_keep_sequence).unwrap_or_else(|e| {
+            tracing::warn!("Invalid SERIES_PATTERN, keeping series names as they are: {}", e);
+            Self { pattern: None, keep_sequence: config.series_keep_sequence }
+        })
+    }
+
+    /// The name and position of one entry: `"Mistborn #1"` becomes `("Mistborn", Some("1"))`.
+    pub fn parse<'a>(&self, entry: &'a str) -> (Cow<'a, str>, Option<&'a str>) {
+        let entry = entry.trim();
+        let Some(captures) = self.pattern.as_ref().and_then(|pattern| pattern.captures(entry)) else {
+            return (Cow::Borrowed(entry), None);
+        };
+        let matched = captures.get(0).expect("a match has a whole group");
+        let sequence = captures.name("sequence").unwrap_or(matched).as_str().trim();
+        let sequence = (!sequence.is_empty()).then_some(sequence);
+        if self.keep_sequence {
+            return (Cow::Borrowed(entry), sequence);
+        }
+        let name = if matched.end() == entry.len() {
+            Cow::Borrowed(entry[..matched.start()].trim_end())
+        } else {
+            Cow::Owned(format!("{}{}", &entry[..matched.start()], &entry[matched.end()..]).trim().to_string())
+        };
+        (name, sequence)
+    }
+
+    /// Every entry of a comma-separated series name, parsed.
+    pub fn entries<'a>(&'a self, series_name: &'a str) -> impl Iterator<Item = (Cow<'a, str>, Option<&'a str>)> + 'a {
+        series_name.split(',').map(|entry| self.parse(entry)).filter(|(name, _)| !name.is_empty())
+    }
+}
@@ -1,11 +1,15 @@
 use crate::api::AbsClient;
-use crate::models::{Library, LibraryItem, InternalUser, ItemType, AppConfig};
+use crate::models::{Library, LibraryItem, InternalUser, ItemType, AppConfig, MediaType};
 use crate::i18n::I18n;
-use crate::xml::OpdsBuilder;
+use crate::query::{canonical_identifier, fold, SearchQuery, ShelfField, ShelfQuery, SortField, SortOrder};
+use crate::opds::model::{Entry, Facet, Feed, FeedKind};
 use std::sync::Arc;
+use std::borrow::Cow;
 use std::collections::{HashSet, HashMap};
 use unicode_normalization::UnicodeNormalization;
-use anyhow::Result;
+use crate::error::Result;
+use crate::metrics::PhaseTimer;
+use crate::series::SeriesParser;
 use rayon::prelude::*;
 
 #[cfg(test)]
@@ -24,15 +28,40 @@ pub enum CategoriesResult {
     },
 }
 
+/// What is behind the cards of a library: the books of "All books" and the entries of
+/// each category.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LibraryCounts {
+    pub books: usize,
+    pub authors: usize,
+    pub narrators: usize,
+    pub genres: usize,
+    pub series: usize,
+}
+
+impl LibraryCounts {
+    /// The categories without any entries, whose cards would lead to empty feeds, e.g.
+    /// narrators in an ebook-only library.
+    pub fn empty_categories(&self) -> Vec<&'static str> {
+        [("authors", self.authors), ("narrators", self.narrators), ("genres", self.genres), ("series", self.series)]
+            .into_iter()
+            .filter(|(_, count)| *count == 0)
+            .map(|(type_, _)| type_)
+            .collect()
+    }
+}
+
 pub struct LibraryService<C: AbsClient + ?Sized> {
     pub client: Arc<C>,
     pub config: AppConfig,
     pub i18n: I18n,
+    pub series: SeriesParser,
 }
 
 impl<C: AbsClient + ?Sized> LibraryService<C> {
     pub fn new(client: Arc<C>, config: AppConfig, i18n: I18n) -> Self {
-        Self { client, config, i18n }
+        let series = SeriesParser::from_config(&config);
+        Self { client, config, i18n, series }
     }
 
     pub async fn get_libraries(&self, user: &InternalUser) -> Result<Vec<Library>> {
@@ -41,45 +70,160 @@ pub async fn get_libraries(&self, user: &InternalUser) -> Result<Vec<Library>> {
             id: l.id,
             name: l.name,
             icon: l.icon,
+            media_type: l.media_type,
         }).collect())
     }
 
+    /// The library the root feed goes straight into: the only one, or the user's
+    /// `default_library` if it is among `libraries`.
+    pub fn root_library<'a>(&self, user: &InternalUser, libraries: &'a [Library]) -> Option<&'a Library> {
+        if let [library] = libraries {
+            return Some(library);
+        }
+        let default = self.config.user_options(&user.name).default_library?;
+        libraries.iter().find(|library| library.id == default || library.name.eq_ignore_ascii_case(&default))
+    }
+
     pub async fn get_library(&self, user: &InternalUser, library_id: &str) -> Result<Library> {
         let lib = self.client.get_library(user, library_id).await?;
         Ok(Library {
             id: lib.id,
             name: lib.name,
             icon: lib.icon,
+            media_type: lib.media_type,
         })
     }
 
+    /// What `library_id` holds, or books when ABS can't tell us.
+    pub async fn media_type(&self, user: &InternalUser, library_id: &str) -> MediaType {
+        match self.get_library(user, library_id).await {
+            Ok(library) => library.media_type,
+            Err(e) => {
+                tracing::debug!("No media type for library {}: {}", library_id, e);
+                MediaType::Book
+            }
+        }
+    }
+
     pub async fn get_filtered_items(
         &self,
         user: &InternalUser,
         library_id: &str,
         query: &crate::handlers::LibraryQuery,
     ) -> Result<(Vec<LibraryItem>, usize)> {
+        let mut timer = PhaseTimer::start("get_filtered_items");
         let items_data = self.client.get_items(user, library_id).await?;
+        self.warn_if_slow("fetch", library_id, items_data.results.len(), timer.phase("fetch"));
+
+        // A collection lists its books in the order they were arranged in, and continue
+        // reading the books in progress, most recently read first
+        let listed: Option<Vec<String>> = if let Some(id) = &query.collection {
+            Some(self.collection(user, library_id, id).await?.books.into_iter().map(|book| book.id).collect())
+        } else if query.in_progress {
+            Some(self.progress_client()?.get_items_in_progress(user).await?)
+        } else {
+            None
+        };
+        let positions: Option<HashMap<&str, usize>> =
+            listed.as_ref().map(|ids| ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect());
 
         let results = &items_data.results;
-        let filtered_items: Vec<&crate::models::AbsItemResult> = if results.len() > 2000 {
-            results.par_iter().filter(|item| self.filter_item(item, query)).collect()
+        let search = SearchQuery::parse(query.q.as_deref().unwrap_or(""));
+        let hidden = HiddenItems::for_user(&self.config, user);
+        let since = query.updated_since.as_deref().and_then(crate::query::parse_timestamp);
+        // Items ABS didn't date can't be told apart from old ones, so a delta leaves them out
+        let changed = |item: &crate::models::AbsItemResult| since.is_none_or(|since| item.updated_at.max(item.added_at).is_some_and(|at| at > since));
+        let is_listed = |item: &crate::models::AbsItemResult| positions.as_ref().is_none_or(|p| p.contains_key(item.id.as_str()));
+        let keep = |item: &&crate::models::AbsItemResult| {
+            changed(item) && is_listed(item) && !hidden.hides(&item.media.metadata) && self.filter_item(item, query, &search)
+        };
+        let mut filtered_items: Vec<&crate::models::AbsItemResult> = if results.len() > 2000 {
+            results.par_iter().filter(keep).collect()
+        } else {
+            results.iter().filter(keep).collect()
+        };
+
+        // Recently added keeps the newest items, newest first unless the reader picks an order
+        if query.recently_added {
+            self.sort_items(&mut filtered_items, SortOrder::RECENTLY_ADDED);
+            if self.config.opds_recent_limit > 0 {
+                filtered_items.truncate(self.config.opds_recent_limit);
+            }
+        }
+
+        // An order the reader picked replaces the one of the feed
+        let requested = query.sort.as_deref().and_then(|sort| SortOrder::parse(sort).ok());
+        if let Some(order) = requested {
+            self.sort_items(&mut filtered_items, order);
+        }
+
+        // A–Z browsing lists the books of a letter alphabetically
+        let letter_browsing = query.start.is_some() && query.type_.is_none();
+        if letter_browsing && requested.is_none() {
+            filtered_items.sort_by_cached_key(|item| fold(item.media.metadata.title.as_deref().unwrap_or("")));
+        }
+
+        // Recently updated lists the newest changes first; items ABS didn't date go last
+        if query.recently_updated && requested.is_none() {
+            self.sort_items(&mut filtered_items, SortOrder::RECENTLY_UPDATED);
+        }
+
+        if let Some(positions) = positions.as_ref().filter(|_| requested.is_none()) {
+            filtered_items.sort_by_key(|item| positions.get(item.id.as_str()).copied());
+        }
+
+        // Other feeds without an order of their own take the configured one
+        let series_filter = query.name.as_deref().filter(|_| query.type_ == Some(ItemType::Series)).map(fold);
+        let own_order = letter_browsing || query.recently_updated || query.recently_added || positions.is_some() || series_filter.is_some();
+        if !own_order && requested.is_none() {
+            if let Some(order) = self.config.sort_for(library_id) {
+                self.sort_items(&mut filtered_items, order);
+            }
+        }
+
+        // Browsing a series lists it in reading order; books without a position go last
+        if let Some(series) = series_filter.as_ref().filter(|_| requested.is_none()) {
+            filtered_items.sort_by(|a, b| {
+                let a = series_position(&self.series, a.media.metadata.series_name.as_deref(), Some(series)).and_then(|(_, seq)| sequence_number(&seq));
+                let b = series_position(&self.series, b.media.metadata.series_name.as_deref(), Some(series)).and_then(|(_, seq)| sequence_number(&seq));
+                match (a, b) {
+                    (Some(a), Some(b)) => a.total_cmp(&b),
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (None, None) => std::cmp::Ordering::Equal,
+                }
+            });
+        }
+
+        let entries: Vec<(&crate::models::AbsItemResult, Vec<&crate::models::AbsItemResult>)> = if self.config.merge_editions {
+            merge_editions(filtered_items)
         } else {
-            results.iter().filter(|item| self.filter_item(item, query)).collect()
+            filtered_items.into_iter().map(|item| (item, Vec::new())).collect()
         };
 
-        let total_items = filtered_items.len();
-        let page_size = self.config.opds_page_size;
+        let total_items = entries.len();
+        self.warn_if_slow("filter", library_id, results.len(), timer.phase("filter"));
+        let page_size = self.config.page_size_for(query.limit);
         let start_index = query.page * page_size;
 
         if start_index < total_items {
              let end_index = std::cmp::min(start_index + page_size, total_items);
-             let paginated_refs = &filtered_items[start_index..end_index];
-             let mapped_items: Vec<LibraryItem> = paginated_refs.iter().map(|item| {
+             let paginated_refs = &entries[start_index..end_index];
+             let mut mapped_items: Vec<LibraryItem> = paginated_refs.iter().map(|(item, editions)| {
                  let format = item.media.ebook_format.clone();
+                 let position = series_position(&self.series, item.media.metadata.series_name.as_deref(), series_filter.as_deref());
+                 let mut series: Vec<String> = item.media.metadata.series_name.as_deref().map(|s| {
+                     self.series.entries(s).map(|(name, _)| name.into_owned()).collect()
+                 }).unwrap_or_default();
+                 // The browsed series comes first, so `series_sequence` refers to it
+                 if let Some((name, _)) = &position {
+                     if let Some(idx) = series.iter().position(|s| s == name) {
+                         series[..=idx].rotate_right(1);
+                     }
+                 }
                  LibraryItem {
                      id: item.id.clone(),
-                     title: item.media.metadata.title.clone(),
+                     title: self.entry_title(item.media.metadata.title.as_deref(), position.as_ref()),
                      subtitle: item.media.metadata.subtitle.clone(),
                      description: item.media.metadata.description.clone(),
                      genres: item.media.metadata.genres.clone().unwrap_or_default(),
@@ -89,30 +233,133 @@ pub async fn get_filtered_items(
                      language: item.media.metadata.language.clone(),
                      published_year: item.media.metadata.published_year.clone(),
                      authors: item.media.metadata.author_name.as_deref().map(|s| {
-                         s.split(',').map(|n| crate::models::Author { name: n.trim().to_string() }).collect()
+                         split_names(s).into_iter().map(|n| crate::models::Author { name: n.into_owned() }).collect()
                      }).unwrap_or_default(),
                      narrators: item.media.metadata.narrator_name.as_deref().map(|s| {
-                         s.split(',').map(|n| crate::models::Author { name: n.trim().to_string() }).collect()
-                     }).unwrap_or_default(),
-                     series: item.media.metadata.series_name.as_deref().map(|s| {
-                         s.split(',').map(|n| {
-                             let cleaned = if let Some(idx) = n.find('#') {
-                                 n[..idx].trim()
-                             } else {
-                                 n.trim()
-                             };
-                             cleaned.to_string()
-                         }).collect()
+                         split_names(s).into_iter().map(|n| crate::models::Author { name: n.into_owned() }).collect()
                      }).unwrap_or_default(),
+                     series,
+                     series_sequence: position.map(|(_, seq)| seq),
                      format,
+                     audio_file_count: item.media.num_audio_files.unwrap_or(0),
+                     editions: editions.iter().map(|e| crate::models::Edition {
+                         id: e.id.clone(),
+                         format: e.media.ebook_format.clone(),
+                         audio_file_count: e.media.num_audio_files.unwrap_or(0),
+                     }).collect(),
+                     updated: item
+                         .updated_at
+                         .and_then(chrono::DateTime::from_timestamp_millis)
+                         .map(|updated| updated.to_rfc3339()),
                  }
              }).collect();
+             self.add_progress(user, &mut mapped_items).await;
              Ok((mapped_items, total_items))
         } else {
              Ok((vec![], total_items))
         }
     }
 
+    /// Sorts `items` stably by `order`, with the items that lack its field last.
+    fn sort_items(&self, items: &mut Vec<&crate::models::AbsItemResult>, order: SortOrder) {
+        enum Key {
+            Text(String),
+            Number(i64),
+            /// A series name and the position in it, unnumbered books last.
+            Series(String, f64),
+        }
+        let key = |item: &crate::models::AbsItemResult| {
+            let metadata = &item.media.metadata;
+            match order.field {
+                SortField::Title => metadata.title.as_deref().map(|title| Key::Text(fold(title))),
+                SortField::Author => metadata.author_name.as_deref().and_then(|names| {
+                    let first = split_names(names).into_iter().next()?;
+                    Some(Key::Text(fold(&if self.config.authors_by_surname { surname_first(&first) } else { first.into_owned() })))
+                }),
+                SortField::Added => item.added_at.map(Key::Number),
+                SortField::Updated => item.updated_at.map(Key::Number),
+                SortField::Published => metadata.published_year.as_deref().and_then(|year| year.trim().parse().ok()).map(Key::Number),
+                SortField::Series => metadata.series_name.as_deref().and_then(|names| {
+                    let (name, sequence) = self.series.entries(names).next()?;
+                    Some(Key::Series(fold(&name), sequence.and_then(sequence_number).unwrap_or(f64::INFINITY)))
+                }),
+            }
+        };
+        let mut keyed: Vec<_> = items.drain(..).map(|item| (key(item), item)).collect();
+        keyed.sort_by(|(a, _), (b, _)| {
+            let ordering = match (a, b) {
+                (Some(Key::Text(a)), Some(Key::Text(b))) => a.cmp(b),
+                (Some(Key::Number(a)), Some(Key::Number(b))) => a.cmp(b),
+                (Some(Key::Series(a, x)), Some(Key::Series(b, y))) => a.cmp(b).then(x.total_cmp(y)),
+                (Some(_), None) => return std::cmp::Ordering::Less,
+                (None, Some(_)) => return std::cmp::Ordering::Greater,
+                _ => std::cmp::Ordering::Equal,
+            };
+            if order.descending { ordering.reverse() } else { ordering }
+        });
+        items.extend(keyed.into_iter().map(|(_, item)| item));
+    }
+
+    /// Appends the reading progress to the titles of started books, for users with
+    /// `show_progress`; titles stay as they are if ABS can't tell.
+    async fn add_progress(&self, user: &InternalUser, items: &mut [LibraryItem]) {
+        if items.is_empty() || !self.config.user_options(&user.name).show_progress {
+            return;
+        }
+        let Some(client) = self.client.progress() else {
+            return;
+        };
+        let progress = match client.get_progress(user).await {
+            Ok(progress) => progress,
+            Err(e) => {
+                tracing::debug!("No progress for {}: {}", user.name, e);
+                return;
+            }
+        };
+        for item in items {
+            let Some(title) = item.title.as_mut() else { continue };
+            let percent = progress.get(&item.id).map_or(0, |p| (p * 100.0).round() as u32);
+            if percent > 0 {
+                title.push_str(&format!(" — {}%", percent));
+            }
+        }
+    }
+
+    /// The title of a book's entry: `TITLE_TEMPLATE` filled in for books with a series
+    /// position, the plain title otherwise.
+    fn entry_title(&self, title: Option<&str>, position: Option<&(String, String)>) -> Option<String> {
+        let title = title?;
+        match position {
+            Some((series, sequence)) if !self.config.title_template.is_empty() => Some(
+                self.config
+                    .title_template
+                    .replace("{series}", series)
+                    .replace("{position}", sequence)
+                    .replace("{title}", title),
+            ),
+            _ => Some(title.to_string()),
+        }
+    }
+
+    /// Warns when fetching or filtering a library took longer than `SLOW_UPSTREAM_MS`,
+    /// a sign that it has outgrown fetching all items per request.
+    fn warn_if_slow(&self, phase: &str, library_id: &str, item_count: usize, elapsed: std::time::Duration) {
+        let threshold = self.config.slow_upstream_ms;
+        if threshold > 0 && elapsed.as_millis() >= u128::from(threshold) {
+            tracing::warn!(
+                library_id,
+                item_count,
+                duration_ms = elapsed.as_millis() as u64,
+                phase,
+                "Slow library {} for {}: {} items took {} ms",
+                phase,
+                library_id,
+                item_count,
+                elapsed.as_millis()
+            );
+        }
+    }
+
     pub async fn get_categories_data(
         &self,
         user: &InternalUser,
@@ -120,57 +367,49 @@ pub async fn get_categories_data(
         type_: &str,
         query: &crate::handlers::LibraryQuery,
     ) -> Result<CategoriesResult> {
+         let mut timer = PhaseTimer::start("get_categories");
          let items_data = self.client.get_items(user, library_id).await?;
+         let item_count = items_data.results.len();
+         self.warn_if_slow("fetch", library_id, item_count, timer.phase("fetch"));
+         let hidden = HiddenItems::for_user(&self.config, user);
+
+         // Titles are always split by letter; each letter opens the book list
+         if type_ == "titles" {
+             let mut count_by_start: HashMap<char, usize> = HashMap::new();
+             for item in &items_data.results {
+                 if hidden.hides(&item.media.metadata) || (item.media.ebook_format.is_none() && !self.config.show_audiobooks) {
+                     continue;
+                 }
+                 if let Some(letter) = item.media.metadata.title.as_deref().and_then(initial_letter) {
+                     *count_by_start.entry(letter).or_insert(0) += 1;
+                 }
+             }
+             let mut letters: Vec<(String, usize)> = count_by_start.into_iter().map(|(l, c)| (l.to_string(), c)).collect();
+             letters.sort();
+             self.warn_if_slow("filter", library_id, item_count, timer.phase("filter"));
+             return Ok(CategoriesResult::Letters(letters));
+         }
+
+         // Authors can be filed by surname; the cards keep linking to the names as ABS has them
+         let by_surname = type_ == "authors" && self.config.authors_by_surname;
+         let filed_as = |name: &str| if by_surname { surname_first(name) } else { name.to_string() };
 
          let mut distinct_type = HashSet::new();
-         for item in items_data.results {
-             match type_ {
-                 "authors" => {
-                     if let Some(names) = item.media.metadata.author_name {
-                         for name in names.split(',') {
-                             distinct_type.insert(name.trim().to_string());
-                         }
-                     }
-                 },
-                 "narrators" => {
-                      if let Some(names) = item.media.metadata.narrator_name {
-                         for name in names.split(',') {
-                             distinct_type.insert(name.trim().to_string());
-                         }
-                     }
-                 },
-                 "genres" => {
-                     if let Some(genres) = item.media.metadata.genres {
-                         for g in genres {
-                             distinct_type.insert(g.trim().to_string());
-                         }
-                     }
-                     if let Some(tags) = item.media.metadata.tags {
-                         for t in tags {
-                             distinct_type.insert(t.trim().to_string());
-                         }
-                     }
-                 },
-                 "series" => {
-                      if let Some(series) = item.media.metadata.series_name {
-                         for s in series.split(',') {
-                             distinct_type.insert(s.trim().to_string());
-                         }
-                     }
-                 },
-                 _ => {}
+         for item in &items_data.results {
+             if hidden.hides(&item.media.metadata) {
+                 continue;
              }
+             distinct_type.extend(category_names(&item.media.metadata, type_, &self.series).into_iter().map(Cow::into_owned));
          }
 
          if query.start.is_none() && self.config.show_char_cards {
-                let mut distinct_type_array: Vec<String> = distinct_type.into_iter().collect();
-                distinct_type_array.sort_unstable();
+                let distinct_type_array: Vec<String> = distinct_type.iter().map(|name| filed_as(name)).collect();
 
                 let mut count_by_start: HashMap<String, usize> = HashMap::new();
                 for item in &distinct_type_array {
                     let start_char = item.chars().next().unwrap_or(' ').to_uppercase().to_string();
                     let normalized = start_char.nfd().filter(|c| !crate::xml::is_combining_mark(*c)).collect::<String>();
-                    let key = if normalized >= "A".to_string() && normalized <= "Z".to_string() { normalized } else { String::new() };
+                    let key = if normalized.as_str() >= "A" && normalized.as_str() <= "Z" { normalized } else { String::new() };
                     if !key.is_empty() {
                          *count_by_start.entry(key).or_insert(0) += 1;
                     }
@@ -184,12 +423,13 @@ pub async fn get_categories_data(
                     (letter, count)
                 }).collect();
 
+                self.warn_if_slow("filter", library_id, item_count, timer.phase("filter"));
                 Ok(CategoriesResult::Letters(letters))
          } else {
              let mut distinct_type_array: Vec<String> = if let Some(start) = &query.start {
                  distinct_type.into_iter()
                      .filter(|item| {
-                          let start_char = item.chars().next().unwrap_or(' ').to_lowercase().to_string();
+                          let start_char = filed_as(item).chars().next().unwrap_or(' ').to_lowercase().to_string();
                           let normalized = start_char.nfd().filter(|c| !crate::xml::is_combining_mark(*c)).collect::<String>();
                           normalized == *start
                      })
@@ -197,11 +437,15 @@ pub async fn get_categories_data(
              } else {
                  distinct_type.into_iter().collect()
              };
-             distinct_type_array.sort_unstable();
+             if by_surname {
+                 distinct_type_array.sort_by_cached_key(|name| fold(&surname_first(name)));
+             } else {
+                 distinct_type_array.sort_unstable();
+             }
 
              let total_items = distinct_type_array.len();
-             let page_size = self.config.opds_page_size;
-             let total_pages = (total_items + page_size - 1) / page_size;
+             let page_size = self.config.page_size_for(query.limit);
+             let total_pages = total_items.div_ceil(page_size);
              let start_index = query.page * page_size;
 
              let (paginated_items, page_info) = if start_index < total_items {
@@ -211,6 +455,7 @@ pub async fn get_categories_data(
                  (vec![], Some((query.page, page_size, total_items, total_pages)))
              };
 
+             self.warn_if_slow("filter", library_id, item_count, timer.phase("filter"));
              Ok(CategoriesResult::Items {
                  items: paginated_items,
                  page_info,
@@ -224,72 +469,378 @@ pub async fn get_categories(
         library_id: &str,
         type_: &str,
         query: &crate::handlers::LibraryQuery,
-    ) -> Result<String> {
+        lang: Option<&str>,
+    ) -> Result<Feed> {
          let updated_time = chrono::Utc::now().to_rfc3339();
          let lib_data = self.client.get_library(user, library_id).await?;
          let library = Library {
              id: lib_data.id,
              name: lib_data.name,
              icon: lib_data.icon,
+             media_type: lib_data.media_type,
          };
 
-         match self.get_categories_data(user, library_id, type_, query).await? {
+         let feed = match self.get_categories_data(user, library_id, type_, query).await? {
              CategoriesResult::Letters(letters) => {
-                  OpdsBuilder::build_opds_skeleton(
-                        &format!("urn:uuid:{}", library_id),
-                        &library.name,
-                        |writer| {
-                            let mut url_buf = String::with_capacity(256);
-                            for (letter, count) in letters {
-                                let title = format!("{} ({})", letter, count);
-                                let link = format!("/opds/libraries/{}/{}?start={}", library_id, type_, letter.to_lowercase());
-                                OpdsBuilder::build_custom_card_entry(writer, &title, &link, &updated_time, &mut url_buf)?;
-                            }
-                            Ok(())
-                        },
-                        None,
-                        None,
-                        None,
-                        &format!("/opds/libraries/{}/{}", library_id, type_),
-                        false,
-                    ).map_err(|e| e.into())
+                 // All letters fit on one page
+                 let count = letters.len();
+                 // Title letters open the books themselves, other letters their names
+                 let letter_kind = if type_ == "titles" { FeedKind::Acquisition } else { FeedKind::Navigation };
+                 let entries = letters
+                     .into_iter()
+                     .map(|(letter, count)| {
+                         let title = self.i18n.localize_with("category.letter", lang, &[("letter", &letter), ("count", &count.to_string())]);
+                         Entry::card(&title, crate::xml::letter_href(library_id, type_, &letter), letter_kind, &updated_time)
+                     })
+                     .collect();
+                 self.feed(format!("urn:uuid:{}", library_id), &library.name, FeedKind::Navigation, &format!("/opds/libraries/{}/{}", library_id, type_), lang)
+                     .with_library_links(&library, &self.i18n, lang)
+                     .with_pagination(Some((0, count.max(1), count, 1)))
+                     .with_entries(entries)
              }
              CategoriesResult::Items { items, page_info } => {
-                  let mut url_base = format!("/opds/libraries/{}/{}", library_id, type_);
-                  if let Some(start) = &query.start {
-                      url_base.push_str(&format!("?start={}", start));
-                  }
-
-                  OpdsBuilder::build_opds_skeleton(
-                     &format!("urn:uuid:{}", library_id),
-                     &library.name,
-                     |writer| {
-                         let mut url_buf = String::with_capacity(256);
-                         for item in items {
-                             OpdsBuilder::build_card_entry(writer, &item, &type_, &library_id, &updated_time, &mut url_buf)?;
-                         }
-                         Ok(())
-                     },
-                    Some(&library),
-                    Some(user),
-                    page_info,
-                    &url_base,
-                    false,
-                ).map_err(|e| e.into())
+                 let url_base = crate::xml::category_href(library_id, type_, query.start.as_deref(), query.limit);
+                 let entries = items
+                     .iter()
+                     .map(|item| {
+                         let mut entry = Entry::category_card(item, type_, library_id, &updated_time);
+                         entry.title = Some(self.category_title(type_, item));
+                         entry
+                     })
+                     .collect();
+                 self.feed(format!("urn:uuid:{}", library_id), &library.name, FeedKind::Navigation, &url_base, lang)
+                     .with_library_links(&library, &self.i18n, lang)
+                     .with_pagination(page_info)
+                     .with_entries(entries)
              }
-         }
+         };
+         Ok(feed)
+    }
+
+    /// The root feed of a user with access to several libraries.
+    /// How a category entry is shown: authors as "Sanderson, Brandon" with
+    /// `AUTHORS_BY_SURNAME`, everything else as named in ABS.
+    pub fn category_title(&self, type_: &str, name: &str) -> String {
+        if type_ == "authors" && self.config.authors_by_surname {
+            surname_first(name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Counts the books and category entries of a library the way its feeds list them.
+    pub async fn library_counts(&self, user: &InternalUser, library_id: &str) -> Result<LibraryCounts> {
+        let items_data = self.client.get_items(user, library_id).await?;
+        let hidden = HiddenItems::for_user(&self.config, user);
+        let visible: Vec<&crate::models::AbsItemResult> =
+            items_data.results.iter().filter(|item| !hidden.hides(&item.media.metadata)).collect();
+
+        let query = crate::handlers::LibraryQuery::default();
+        let search = SearchQuery::parse("");
+        let books: Vec<&crate::models::AbsItemResult> = visible.iter().copied().filter(|item| self.filter_item(item, &query, &search)).collect();
+        let distinct = |type_: &str| {
+            visible.iter().flat_map(|item| category_names(&item.media.metadata, type_, &self.series)).collect::<HashSet<Cow<str>>>().len()
+        };
+        Ok(LibraryCounts {
+            books: if self.config.merge_editions { merge_editions(books).len() } else { books.len() },
+            authors: distinct("authors"),
+            narrators: distinct("narrators"),
+            genres: distinct("genres"),
+            series: distinct("series"),
+        })
+    }
+
+    /// `library_counts` when `SHOW_COUNTS` is on; `None` if it is off or the items can't
+    /// be read, leaving the cards without counts.
+    async fn counts_if_shown(&self, user: &InternalUser, library_id: &str) -> Option<LibraryCounts> {
+        if !self.config.show_counts {
+            return None;
+        }
+        self.try_library_counts(user, library_id).await
+    }
+
+    /// The categories of a library to leave out of its categories feed; none if the
+    /// items can't be read.
+    pub async fn empty_categories(&self, user: &InternalUser, library_id: &str) -> Vec<&'static str> {
+        self.hidden_categories(self.try_library_counts(user, library_id).await.as_ref())
+    }
+
+    /// The categories without entries by `counts`, and those backends such as the demo
+    /// library can't fill: books in progress and collections.
+    fn hidden_categories(&self, counts: Option<&LibraryCounts>) -> Vec<&'static str> {
+        let mut hidden = counts.map(LibraryCounts::empty_categories).unwrap_or_default();
+        if self.client.progress().is_none() {
+            hidden.push("continue");
+        }
+        if self.client.collections().is_none() {
+            hidden.push("collections");
+        }
+        hidden
+    }
+
+    async fn try_library_counts(&self, user: &InternalUser, library_id: &str) -> Option<LibraryCounts> {
+        match self.library_counts(user, library_id).await {
+            Ok(counts) => Some(counts),
+            Err(e) => {
+                tracing::debug!("No counts for library {}: {}", library_id, e);
+                None
+            }
+        }
     }
 
-    fn filter_item(&self, item: &crate::models::AbsItemResult, query: &crate::handlers::LibraryQuery) -> bool {
+    pub async fn libraries_feed(&self, user: &InternalUser, libraries: &[Library], lang: Option<&str>) -> Feed {
+        let updated_time = chrono::Utc::now().to_rfc3339();
+        let mut hasher = sha1_smol::Sha1::new();
+        hasher.update(user.name.as_bytes());
+        let user_hash = hasher.digest().to_string();
+
+        let counts = futures_util::future::join_all(libraries.iter().map(|lib| self.counts_if_shown(user, &lib.id))).await;
+        let entries = libraries
+            .iter()
+            .zip(counts)
+            .map(|(lib, counts)| {
+                let mut entry = Entry::library(lib, &updated_time);
+                if let Some(counts) = counts {
+                    let count = self.i18n.format_count(counts.books, lang);
+                    let args = [("name", lib.name.as_str()), ("count", count.as_str())];
+                    entry.title = Some(if counts.books == 1 {
+                        self.i18n.localize_with("library.count_one", lang, &args)
+                    } else {
+                        self.i18n.localize_with("library.count", lang, &args)
+                    });
+                }
+                entry
+            })
+            .collect();
+
+        let title = self.i18n.localize_with("feed.libraries", lang, &[("user", &user.name)]);
+        self.feed(user_hash, title, FeedKind::Navigation, "/opds", lang)
+            .with_entries(entries)
+    }
+
+    /// The categories of a library: for books all books, authors, narrators, genres,
+    /// series and titles; podcasts and music get their own set.
+    pub async fn categories_root_feed(
+        &self,
+        user: &InternalUser,
+        library_id: &str,
+        media_type: MediaType,
+        lang: Option<&str>,
+    ) -> Feed {
+        let updated_time = chrono::Utc::now().to_rfc3339();
+        let mut entries = Entry::categories(library_id, media_type, &self.i18n, lang, &updated_time);
+        let counts = self.try_library_counts(user, library_id).await;
+        let hidden = self.hidden_categories(counts.as_ref());
+        entries.retain(|entry| !hidden.contains(&entry.id.as_str()));
+        if let Some(counts) = counts.filter(|_| self.config.show_counts) {
+            for entry in &mut entries {
+                let count = match entry.id.as_str() {
+                    "authors" => counts.authors,
+                    "narrators" => counts.narrators,
+                    "genres" => counts.genres,
+                    "series" => counts.series,
+                    // The same books as all books, in another order, and lists we don't count
+                    "recent" | "updated" | "episodes" | "collections" | "continue" => continue,
+                    // All books and titles A–Z
+                    _ => counts.books,
+                };
+                let title = entry.title.take().unwrap_or_default();
+                let count = self.i18n.format_count(count, lang);
+                entry.title = Some(self.i18n.localize_with("category.count", lang, &[("title", &title), ("count", &count)]));
+            }
+        }
+        entries.extend(self.config.shelves.iter().map(|shelf| {
+            Entry::card(&shelf.name, format!("/opds/libraries/{}?shelf={}", library_id, shelf.name), FeedKind::Acquisition, &updated_time)
+        }));
+        self.feed(
+            format!("urn:uuid:{}", library_id),
+            self.i18n.localize("feed.categories", lang),
+            FeedKind::Navigation,
+            &format!("/opds/libraries/{}", library_id),
+            lang,
+        )
+        .with_entries(entries)
+    }
+
+    /// One page of a podcast library's episodes, newest first.
+    pub async fn episodes_feed(&self, user: &InternalUser, library_id: &str, page: usize, lang: Option<&str>) -> Result<Feed> {
+        let podcasts = self.client.podcasts().ok_or(crate::error::AppError::Upstream {
+            context: "This server doesn't list episodes",
+            status: axum::http::StatusCode::NOT_FOUND,
+        })?;
+        let limit = self.config.opds_page_size;
+        let response = podcasts.get_recent_episodes(user, library_id, limit, page).await?;
+        let full = response.episodes.len() == limit;
+        let total = response.total.unwrap_or(page * limit + response.episodes.len() + usize::from(full));
+
+        let updated_time = chrono::Utc::now().to_rfc3339();
+        let link_url = if self.config.use_proxy { crate::opds::model::PROXY_PATH } else { &self.config.abs_url };
+        let entries = response.episodes.iter().map(|episode| Entry::episode(episode, user, link_url, &updated_time)).collect();
+        Ok(self
+            .feed(
+                format!("urn:uuid:{}:episodes", library_id),
+                self.i18n.localize("category.episodes", lang),
+                FeedKind::Acquisition,
+                &format!("/opds/libraries/{}/episodes", library_id),
+                lang,
+            )
+            .with_pagination(Some((page, limit, total, total.div_ceil(limit))))
+            .with_entries(entries))
+    }
+
+    /// The collections of a library, each a card leading to its books.
+    pub async fn collections_feed(&self, user: &InternalUser, library_id: &str, lang: Option<&str>) -> Result<Feed> {
+        let collections = self.collections_client()?.get_collections(user, library_id).await?;
+        let updated_time = chrono::Utc::now().to_rfc3339();
+        let entries = collections
+            .into_iter()
+            .map(|collection| {
+                let href = format!("/opds/libraries/{}/collections/{}", library_id, collection.id);
+                let mut entry = Entry::navigation(collection.id, collection.name, href, FeedKind::Acquisition, &updated_time);
+                entry.content = collection.description.filter(|d| !d.is_empty());
+                entry
+            })
+            .collect();
+        Ok(self
+            .feed(
+                format!("urn:uuid:{}:collections", library_id),
+                self.i18n.localize("category.collections", lang),
+                FeedKind::Navigation,
+                &format!("/opds/libraries/{}/collections", library_id),
+                lang,
+            )
+            .with_entries(entries))
+    }
+
+    /// The collection `id` of a library.
+    async fn collection(&self, user: &InternalUser, library_id: &str, id: &str) -> Result<crate::models::AbsCollection> {
+        let collections = self.collections_client()?.get_collections(user, library_id).await?;
+        collections.into_iter().find(|collection| collection.id == id).ok_or(crate::error::AppError::Upstream {
+            context: "Unknown collection",
+            status: axum::http::StatusCode::NOT_FOUND,
+        })
+    }
+
+    fn progress_client(&self) -> Result<&dyn crate::api::ProgressClient> {
+        self.client.progress().ok_or(crate::error::AppError::Upstream {
+            context: "This server doesn't know what you are reading",
+            status: axum::http::StatusCode::NOT_FOUND,
+        })
+    }
+
+    fn collections_client(&self) -> Result<&dyn crate::api::CollectionClient> {
+        self.client.collections().ok_or(crate::error::AppError::Upstream {
+            context: "This server doesn't list collections",
+            status: axum::http::StatusCode::NOT_FOUND,
+        })
+    }
+
+    /// A feed with the authentication block `OPDS_AUTH_BLOCK` asks for.
+    fn feed(&self, id: impl Into<String>, title: impl Into<String>, kind: FeedKind, self_href: &str, lang: Option<&str>) -> Feed {
+        Feed::new(id, title, kind, self_href, &self.i18n, lang).with_auth_block(self.config.auth_block())
+    }
+
+    /// One page of publications from `get_filtered_items`.
+    pub fn items_feed(
+        &self,
+        user: &InternalUser,
+        library: &Library,
+        items: &[LibraryItem],
+        page_info: Option<(usize, usize, usize, usize)>,
+        url_base: &str,
+        lang: Option<&str>,
+    ) -> Feed {
+        let updated_time = chrono::Utc::now().to_rfc3339();
+        let link_url = if self.config.use_proxy { crate::opds::model::PROXY_PATH } else { &self.config.abs_url };
+        let acquisition_links = self.config.acquisition_links();
+        let entries = items
+            .iter()
+            .map(|item| {
+                let mut entry = Entry::publication(item, user, &library.id, link_url, &acquisition_links, &updated_time);
+                if let Some(url) = self.config.web_item_url(&item.id) {
+                    entry.links.push(crate::opds::model::web_link(url));
+                }
+                entry
+            })
+            .collect();
+        self.feed(format!("urn:uuid:{}", library.id), &library.name, FeedKind::Acquisition, url_base, lang)
+            .with_library_links(library, &self.i18n, lang)
+            .with_pagination(page_info)
+            .with_entries(entries)
+    }
+
+    /// Links to the items at `base` in all formats, as EPUB, as PDF and as audiobooks,
+    /// marking the `active` one.
+    pub fn format_facets(&self, base: &str, active: Option<&str>, lang: Option<&str>) -> Vec<Facet> {
+        let group = self.i18n.localize("facet.format", lang);
+        let separator = if base.contains('?') { '&' } else { '?' };
+        let mut formats = vec![
+            (None, self.i18n.localize("format.all", lang)),
+            (Some("epub"), "EPUB".to_string()),
+            (Some("pdf"), "PDF".to_string()),
+        ];
+        if self.config.show_audiobooks {
+            formats.push((Some("audiobook"), self.i18n.localize("format.audiobooks", lang)));
+        }
+        let active = active.map(fold);
+        formats
+            .into_iter()
+            .map(|(format, title)| Facet {
+                group: group.clone(),
+                title,
+                href: format.map_or_else(|| base.to_string(), |format| format!("{}{}format={}", base, separator, format)),
+                active: active.as_deref() == format,
+                count: None,
+            })
+            .collect()
+    }
+
+    /// Links to the items at `base` in each order of `SortOrder::FACETS`, marking `active`.
+    pub fn sort_facets(&self, base: &str, active: Option<SortOrder>, lang: Option<&str>) -> Vec<Facet> {
+        let group = self.i18n.localize("facet.sort", lang);
+        let separator = if base.contains('?') { '&' } else { '?' };
+        SortOrder::FACETS
+            .iter()
+            .map(|(order, key)| Facet {
+                group: group.clone(),
+                title: self.i18n.localize(key, lang),
+                href: format!("{}{}sort={}", base, separator, order),
+                active: active == Some(*order),
+                count: None,
+            })
+            .collect()
+    }
+
+    /// Whether `item` meets every condition of a shelf's saved filter.
+    pub fn matches_shelf(&self, item: &crate::models::AbsItemResult, shelf: &ShelfQuery) -> bool {
+        let metadata = &item.media.metadata;
+        let any_contains = |values: &Option<Vec<String>>, value: &str| {
+            values.as_ref().is_some_and(|values| values.iter().any(|v| contains_case_insensitive(v, value)))
+        };
+        let contains = |field: &Option<String>, value: &str| field.as_deref().is_some_and(|f| contains_case_insensitive(f, value));
+        shelf.conditions().iter().all(|(field, value)| match field {
+            ShelfField::Any => matches_search_abs(metadata, value),
+            ShelfField::Title => contains(&metadata.title, value) || contains(&metadata.subtitle, value),
+            ShelfField::Author => author_matches(metadata.author_name.as_deref(), value),
+            ShelfField::Narrator => author_matches(metadata.narrator_name.as_deref(), value),
+            ShelfField::Series => clean_series(&self.series, metadata.series_name.as_deref(), value),
+            ShelfField::Genre => any_contains(&metadata.genres, value),
+            ShelfField::Tag => any_contains(&metadata.tags, value),
+            ShelfField::Publisher => contains(&metadata.publisher, value),
+            ShelfField::Language => metadata.language.as_deref().is_some_and(|l| fold(l) == *value),
+            ShelfField::Format => matches_format(item, value),
+        })
+    }
+
+    fn filter_item(&self, item: &crate::models::AbsItemResult, query: &crate::handlers::LibraryQuery, search: &SearchQuery) -> bool {
          let format = item.media.ebook_format.as_deref();
          if format.is_none() && !self.config.show_audiobooks {
              return false;
          }
 
          if query.q.is_some() || query.type_.is_some() {
-             let search_term_lower = query.q.as_deref().unwrap_or("").to_lowercase();
              let type_query = query.type_.as_ref();
-             let name_query_lower = query.name.as_deref().map(|n| n.to_lowercase());
+             let name_query_lower = query.name.as_deref().map(fold);
 
              let matches = if type_query == Some(&ItemType::Authors) {
                  if let Some(n_lower) = &name_query_lower {
@@ -305,11 +856,11 @@ fn filter_item(&self, item: &crate::models::AbsItemResult, query: &crate::handle
                  }
              } else if type_query == Some(&ItemType::Genres) {
                  if let Some(n_lower) = &name_query_lower {
-                     let g_match = item.media.metadata.genres.as_ref().map_or(false, |genres| {
-                         genres.iter().any(|g| g.to_lowercase().contains(n_lower))
+                     let g_match = item.media.metadata.genres.as_ref().is_some_and(|genres| {
+                         genres.iter().any(|g| contains_case_insensitive(g, n_lower))
                      });
-                     let t_match = item.media.metadata.tags.as_ref().map_or(false, |tags| {
-                         tags.iter().any(|t| t.to_lowercase().contains(n_lower))
+                     let t_match = item.media.metadata.tags.as_ref().is_some_and(|tags| {
+                         tags.iter().any(|t| contains_case_insensitive(t, n_lower))
                      });
                      g_match || t_match
                  } else {
@@ -317,16 +868,19 @@ fn filter_item(&self, item: &crate::models::AbsItemResult, query: &crate::handle
                  }
              } else if type_query == Some(&ItemType::Series) {
                  if let Some(n_lower) = &name_query_lower {
-                     clean_series(item.media.metadata.series_name.as_deref(), n_lower)
+                     clean_series(&self.series, item.media.metadata.series_name.as_deref(), n_lower)
                  } else {
                      true
                  }
              } else {
-                 if !search_term_lower.is_empty() {
-                     matches_search_abs(&item.media.metadata, &search_term_lower)
-                 } else {
-                     true
-                 }
+                 true
+             };
+
+             // `q` narrows category feeds as well, e.g. searching within an author's books
+             let matches = matches && if let Some(id) = search.identifier() {
+                 matches_identifier(&item.media.metadata, id)
+             } else {
+                 search.matches(|term| matches_search_abs(&item.media.metadata, term))
              };
 
              if !matches {
@@ -334,17 +888,43 @@ fn filter_item(&self, item: &crate::models::AbsItemResult, query: &crate::handle
              }
          }
 
+         if let Some(name) = &query.shelf {
+             if !self.config.shelf(name).is_some_and(|shelf| self.matches_shelf(item, &shelf.query)) {
+                 return false;
+             }
+         }
+
+         if let Some(format) = &query.format {
+             if !matches_format(item, &fold(format)) {
+                 return false;
+             }
+         }
+
          if let Some(author) = &query.author {
-             let author_lower = author.to_lowercase();
+             let author_lower = fold(author);
              if !author_matches(item.media.metadata.author_name.as_deref(), &author_lower) {
                  return false;
              }
          }
 
+         if let Some(start) = query.start.as_deref().filter(|_| query.type_.is_none()) {
+             let wanted = start.chars().next().map(|c| c.to_ascii_uppercase());
+             if item.media.metadata.title.as_deref().and_then(initial_letter).is_none_or(|l| Some(l) != wanted) {
+                 return false;
+             }
+         }
+
+         if let Some(narrator) = &query.narrator {
+             let narrator_lower = fold(narrator);
+             if !author_matches(item.media.metadata.narrator_name.as_deref(), &narrator_lower) {
+                 return false;
+             }
+         }
+
          if let Some(title) = &query.title {
-             let title_lower = title.to_lowercase();
-             let title_match = item.media.metadata.title.as_deref().map_or(false, |t| contains_case_insensitive(t, &title_lower)) ||
-                 item.media.metadata.subtitle.as_deref().map_or(false, |t| contains_case_insensitive(t, &title_lower));
+             let title_lower = fold(title);
+             let title_match = item.media.metadata.title.as_deref().is_some_and(|t| contains_case_insensitive(t, &title_lower)) ||
+                 item.media.metadata.subtitle.as_deref().is_some_and(|t| contains_case_insensitive(t, &title_lower));
              if !title_match {
                  return false;
              }
@@ -354,47 +934,217 @@ fn filter_item(&self, item: &crate::models::AbsItemResult, query: &crate::handle
     }
 }
 
+/// Whether `item` is in the folded `format`: its ebook format, or `audiobook` for items
+/// with audio files or without an ebook.
+fn matches_format(item: &crate::models::AbsItemResult, format: &str) -> bool {
+    if format == "audiobook" {
+        return item.media.num_audio_files.unwrap_or(0) > 0 || item.media.ebook_format.is_none();
+    }
+    item.media.ebook_format.as_deref().is_some_and(|ebook| fold(ebook) == format)
+}
+
 fn author_matches(author_name: Option<&str>, term_lower: &str) -> bool {
-    author_name.map_or(false, |s| {
-        s.split(',').any(|n| contains_case_insensitive(n.trim(), term_lower))
+    author_name.is_some_and(|s| {
+        split_names(s).iter().any(|n| contains_case_insensitive(n, term_lower))
     })
 }
 
-fn clean_series(series_name: Option<&str>, term_lower: &str) -> bool {
-    series_name.map_or(false, |s| {
-        s.split(',').any(|n| {
-            let cleaned = if let Some(idx) = n.find('#') {
-                n[..idx].trim()
-            } else {
-                n.trim()
-            };
-            contains_case_insensitive(cleaned, term_lower)
-        })
-    })
+fn clean_series(parser: &SeriesParser, series_name: Option<&str>, term_lower: &str) -> bool {
+    series_name.is_some_and(|s| parser.entries(s).any(|(name, _)| contains_case_insensitive(&name, term_lower)))
+}
+
+/// Generational and academic suffixes that belong to the name before them.
+const NAME_SUFFIXES: [&str; 8] = ["jr", "jr.", "sr", "sr.", "ii", "iii", "iv", "phd"];
+
+fn is_name_suffix(word: &str) -> bool {
+    NAME_SUFFIXES.contains(&word.to_lowercase().as_str())
+}
+
+/// The people in an ABS author or narrator field, which separates them by commas, or by
+/// semicolons when they are written "Last, First". A suffix such as "Jr." stays with its
+/// name, and a lone surname before given names (`"Tolkien, J.R.R."`) is one person,
+/// turned around to `"J.R.R. Tolkien"`.
+pub fn split_names(names: &str) -> Vec<Cow<'_, str>> {
+    if names.contains(';') {
+        return names.split(';').map(str::trim).filter(|n| !n.is_empty()).map(given_name_first).collect();
+    }
+    let parts: Vec<&str> = names.split(',').map(str::trim).filter(|p| !p.is_empty()).collect();
+    if let [surname, given] = parts.as_slice() {
+        if !surname.contains(char::is_whitespace) && !is_name_suffix(given) {
+            return vec![Cow::Owned(format!("{} {}", given, surname))];
+        }
+    }
+    let mut people: Vec<Cow<str>> = Vec::with_capacity(parts.len());
+    for part in parts {
+        match people.last_mut() {
+            Some(person) if is_name_suffix(part) => *person = Cow::Owned(format!("{} {}", person, part)),
+            _ => people.push(Cow::Borrowed(part)),
+        }
+    }
+    people
+}
+
+/// `"Le Guin, Ursula K."` as `"Ursula K. Le Guin"`; other names are kept.
+fn given_name_first(name: &str) -> Cow<'_, str> {
+    match name.split_once(',') {
+        Some((surname, given)) if !given.contains(',') && !is_name_suffix(given.trim()) => {
+            Cow::Owned(format!("{} {}", given.trim(), surname.trim()))
+        }
+        _ => Cow::Borrowed(name),
+    }
+}
+
+/// A personal name with the surname first, as in library catalogs: `"Brandon Sanderson"`
+/// becomes `"Sanderson, Brandon"`, `"Martin Luther King Jr."` `"King, Martin Luther, Jr."`.
+/// Single names are kept.
+pub fn surname_first(name: &str) -> String {
+    let mut words: Vec<&str> = name.split_whitespace().collect();
+    let suffix = match words.last() {
+        Some(last) if words.len() > 2 && is_name_suffix(last) => words.pop(),
+        _ => None,
+    };
+    let Some((surname, given)) = words.split_last().filter(|(_, given)| !given.is_empty()) else {
+        return name.trim().to_string();
+    };
+    let mut filed = format!("{}, {}", surname, given.join(" "));
+    if let Some(suffix) = suffix {
+        filed.push_str(", ");
+        filed.push_str(suffix);
+    }
+    filed
+}
+
+/// The A–Z letter a title is filed under, ignoring case and accents.
+fn initial_letter(title: &str) -> Option<char> {
+    let first = title.trim_start().chars().next()?;
+    let base = first.to_string().nfd().next()?.to_ascii_uppercase();
+    base.is_ascii_uppercase().then_some(base)
+}
+
+/// The names an item is listed under in a category: its authors, narrators, genres and
+/// tags, or series.
+fn category_names<'a>(metadata: &'a crate::models::AbsMetadata, type_: &str, series: &'a SeriesParser) -> Vec<Cow<'a, str>> {
+    let split = |names: &'a Option<String>| names.as_deref().map_or(Vec::new(), split_names);
+    match type_ {
+        "authors" => split(&metadata.author_name),
+        "narrators" => split(&metadata.narrator_name),
+        "genres" => metadata.genres.iter().chain(metadata.tags.iter()).flatten().map(|g| Cow::Borrowed(g.trim())).collect(),
+        "series" => metadata.series_name.as_deref().map_or(Vec::new(), |names| series.entries(names).map(|(name, _)| name).collect()),
+        _ => Vec::new(),
+    }
+}
+
+/// Groups items with the same title and author, keeping the position of the first one.
+/// An ebook edition is preferred as the entry itself, so its metadata and format are shown.
+fn merge_editions(
+    items: Vec<&crate::models::AbsItemResult>,
+) -> Vec<(&crate::models::AbsItemResult, Vec<&crate::models::AbsItemResult>)> {
+    let mut entries: Vec<(&crate::models::AbsItemResult, Vec<&crate::models::AbsItemResult>)> = Vec::with_capacity(items.len());
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+    for item in items {
+        let metadata = &item.media.metadata;
+        let Some(title) = metadata.title.as_deref().map(|t| fold(t.trim())).filter(|t| !t.is_empty()) else {
+            entries.push((item, Vec::new()));
+            continue;
+        };
+        let author = fold(metadata.author_name.as_deref().unwrap_or("").trim());
+        match index.get(&(title.clone(), author.clone())) {
+            Some(&i) => {
+                let (primary, editions) = &mut entries[i];
+                if primary.media.ebook_format.is_none() && item.media.ebook_format.is_some() {
+                    editions.insert(0, std::mem::replace(primary, item));
+                } else {
+                    editions.push(item);
+                }
+            }
+            None => {
+                index.insert((title, author), entries.len());
+                entries.push((item, Vec::new()));
+            }
+        }
+    }
+    entries
+}
+
+/// Tags and genres whose items are left out of every feed for a user: the global
+/// `HIDDEN_TAGS`/`HIDDEN_GENRES` plus that user's own `OPDS_USER_OPTIONS`.
+struct HiddenItems {
+    tags: HashSet<String>,
+    genres: HashSet<String>,
+}
+
+impl HiddenItems {
+    fn for_user(config: &AppConfig, user: &InternalUser) -> Self {
+        let options = config.user_options(&user.name);
+        let collect = |global: &str, own: &[String]| -> HashSet<String> {
+            global
+                .split(',')
+                .chain(own.iter().map(String::as_str))
+                .map(|s| fold(s.trim()))
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        Self {
+            tags: collect(&config.hidden_tags, &options.hidden_tags),
+            genres: collect(&config.hidden_genres, &options.hidden_genres),
+        }
+    }
+
+    fn hides(&self, metadata: &crate::models::AbsMetadata) -> bool {
+        let any_in = |values: &Option<Vec<String>>, hidden: &HashSet<String>| {
+            !hidden.is_empty() && values.iter().flatten().any(|v| hidden.contains(&fold(v.trim())))
+        };
+        any_in(&metadata.tags, &self.tags) || any_in(&metadata.genres, &self.genres)
+    }
+}
+
+/// The series name and position (`"Mistborn #1"` → `("Mistborn", "1")`) of the entry
+/// matching `name_lower`, or of the first positioned entry if no name is given.
+fn series_position(parser: &SeriesParser, series_name: Option<&str>, name_lower: Option<&str>) -> Option<(String, String)> {
+    parser
+        .entries(series_name?)
+        .filter_map(|(name, seq)| Some((name, seq?)))
+        .find(|(name, _)| name_lower.is_none_or(|n| contains_case_insensitive(name, n)))
+        .map(|(name, seq)| (name.into_owned(), seq.to_string()))
+}
+
+/// Parses the leading number of a sequence such as `"1"`, `"2.5"` or `"3-4"`.
+fn sequence_number(seq: &str) -> Option<f64> {
+    let end = seq.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(seq.len());
+    seq[..end].parse().ok()
+}
+
+fn matches_identifier(metadata: &crate::models::AbsMetadata, id: &str) -> bool {
+    [metadata.isbn.as_deref(), metadata.asin.as_deref()]
+        .into_iter()
+        .flatten()
+        .any(|value| canonical_identifier(value) == id)
 }
 
 fn matches_search_abs(metadata: &crate::models::AbsMetadata, term_lower: &str) -> bool {
     if term_lower.is_empty() {
         return true;
     }
-    metadata.title.as_deref().map_or(false, |s| contains_case_insensitive(s, term_lower)) ||
-    metadata.subtitle.as_deref().map_or(false, |s| contains_case_insensitive(s, term_lower)) ||
-    metadata.description.as_deref().map_or(false, |s| contains_case_insensitive(s, term_lower)) ||
-    metadata.publisher.as_deref().map_or(false, |s| contains_case_insensitive(s, term_lower)) ||
-    metadata.isbn.as_deref().map_or(false, |s| contains_case_insensitive(s, term_lower)) ||
-    metadata.language.as_deref().map_or(false, |s| contains_case_insensitive(s, term_lower)) ||
-    metadata.published_year.as_deref().map_or(false, |s| contains_case_insensitive(s, term_lower)) ||
-    metadata.author_name.as_deref().map_or(false, |s| {
+    metadata.title.as_deref().is_some_and(|s| contains_case_insensitive(s, term_lower)) ||
+    metadata.subtitle.as_deref().is_some_and(|s| contains_case_insensitive(s, term_lower)) ||
+    metadata.description.as_deref().is_some_and(|s| contains_case_insensitive(s, term_lower)) ||
+    metadata.publisher.as_deref().is_some_and(|s| contains_case_insensitive(s, term_lower)) ||
+    metadata.isbn.as_deref().is_some_and(|s| contains_case_insensitive(s, term_lower)) ||
+    metadata.asin.as_deref().is_some_and(|s| contains_case_insensitive(s, term_lower)) ||
+    metadata.language.as_deref().is_some_and(|s| contains_case_insensitive(s, term_lower)) ||
+    metadata.published_year.as_deref().is_some_and(|s| contains_case_insensitive(s, term_lower)) ||
+    metadata.author_name.as_deref().is_some_and(|s| {
         s.split(',').any(|n| contains_case_insensitive(n.trim(), term_lower))
     }) ||
-    metadata.genres.as_ref().map_or(false, |genres| {
+    metadata.genres.as_ref().is_some_and(|genres| {
         genres.iter().any(|g| contains_case_insensitive(g, term_lower))
     }) ||
-    metadata.tags.as_ref().map_or(false, |tags| {
+    metadata.tags.as_ref().is_some_and(|tags| {
         tags.iter().any(|t| contains_case_insensitive(t, term_lower))
     })
 }
 
+/// Case- and diacritic-insensitive substring match.
 pub(crate) fn contains_case_insensitive(haystack: &str, needle_lower: &str) -> bool {
     if needle_lower.is_empty() {
         return true;
@@ -404,6 +1154,6 @@ pub(crate) fn contains_case_insensitive(haystack: &str, needle_lower: &str) -> b
             window.eq_ignore_ascii_case(needle_lower.as_bytes())
         })
     } else {
-        haystack.to_lowercase().contains(needle_lower)
+        fold(haystack).contains(&fold(needle_lower))
     }
 }
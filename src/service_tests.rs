@@ -1,47 +1,20 @@
 #[cfg(test)]
 mod tests {
-    use crate::api::AbsClient;
-    use crate::models::{AbsItemsResponse, AbsLibrary, AbsItemResult, AbsMedia, AbsMetadata, InternalUser, AppConfig};
+    use crate::models::{AbsItemsResponse, AbsItemResult, AppConfig, MediaType};
     use crate::service::LibraryService;
     use crate::i18n::I18n;
     use crate::handlers::LibraryQuery;
-    use mockall::mock;
+    use crate::test_util::{create_item, mock_user, MockAbsClient};
     use std::sync::Arc;
-    use async_trait::async_trait;
-
-    mock! {
-        pub AbsClient {}
-        #[async_trait]
-        impl AbsClient for AbsClient {
-            async fn login(&self, username: &str, password: &str) -> anyhow::Result<InternalUser>;
-            async fn get_libraries(&self, user: &InternalUser) -> anyhow::Result<Vec<AbsLibrary>>;
-            async fn get_library(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsLibrary>;
-            async fn get_items(&self, user: &InternalUser, library_id: &str) -> anyhow::Result<AbsItemsResponse>;
-        }
-    }
-
-    fn mock_user() -> InternalUser {
-        InternalUser {
-            name: "test_user".to_string(),
-            api_key: "test_token".to_string(),
-            password: None,
-        }
-    }
 
     fn mock_config() -> AppConfig {
-        AppConfig {
-            port: 3000,
-            use_proxy: false,
-            abs_url: "http://localhost:3000".to_string(),
-            opds_users: "user:token:pass".to_string(),
-            internal_users: vec![],
-            show_audiobooks: true,
-            show_char_cards: false,
-            opds_no_auth: false,
-            abs_noauth_username: "".to_string(),
-            abs_noauth_password: "".to_string(),
-            opds_page_size: 10,
-        }
+        AppConfig::builder()
+            .port(3000)
+            .user("user", "token", "pass")
+            .show_audiobooks(true)
+            .page_size(10)
+            .build()
+            .unwrap()
     }
 
     fn mock_i18n() -> I18n {
@@ -52,27 +25,9 @@ fn mock_items_response(items: Vec<AbsItemResult>) -> AbsItemsResponse {
         AbsItemsResponse { results: items }
     }
 
-    fn create_item(id: &str, title: &str, author: Option<&str>, genre: Option<&str>) -> AbsItemResult {
-        AbsItemResult {
-            id: id.to_string(),
-            media: AbsMedia {
-                ebook_format: Some("epub".to_string()),
-                metadata: AbsMetadata {
-                    title: Some(title.to_string()),
-                    subtitle: None,
-                    description: None,
-                    genres: genre.map(|g| vec![g.to_string()]),
-                    tags: None,
-                    publisher: None,
-                    isbn: None,
-                    language: Some("en".to_string()),
-                    published_year: None,
-                    author_name: author.map(|a| a.to_string()),
-                    narrator_name: None,
-                    series_name: None,
-                },
-            },
-        }
+    /// A search for `q` over the whole library.
+    fn search(q: &str) -> LibraryQuery {
+        LibraryQuery { q: Some(q.to_string()), ..LibraryQuery::default() }
     }
 
     #[tokio::test]
@@ -98,10 +53,20 @@ async fn test_get_filtered_items_search() {
             page: 0,
             categories: None,
             author: None,
+            narrator: None,
             title: None,
             name: None,
             type_: None,
             start: None,
+            limit: None,
+            shelf: None,
+            updated_since: None,
+            sort: None,
+            format: None,
+            recently_updated: false,
+            recently_added: false,
+            in_progress: false,
+            collection: None,
         };
 
         let (filtered, total) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
@@ -134,10 +99,20 @@ async fn test_get_filtered_items_author() {
             page: 0,
             categories: None,
             author: Some("Tolkien".to_string()),
+            narrator: None,
             title: None,
             name: None,
             type_: None,
             start: None,
+            limit: None,
+            shelf: None,
+            updated_since: None,
+            sort: None,
+            format: None,
+            recently_updated: false,
+            recently_added: false,
+            in_progress: false,
+            collection: None,
         };
 
         let (filtered, total) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
@@ -171,10 +146,20 @@ async fn test_pagination() {
             page: 0,
             categories: None,
             author: None,
+            narrator: None,
             title: None,
             name: None,
             type_: None,
             start: None,
+            limit: None,
+            shelf: None,
+            updated_since: None,
+            sort: None,
+            format: None,
+            recently_updated: false,
+            recently_added: false,
+            in_progress: false,
+            collection: None,
         };
         let (filtered, total) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
         assert_eq!(filtered.len(), 10);
@@ -187,10 +172,20 @@ async fn test_pagination() {
             page: 2,
             categories: None,
             author: None,
+            narrator: None,
             title: None,
             name: None,
             type_: None,
             start: None,
+            limit: None,
+            shelf: None,
+            updated_since: None,
+            sort: None,
+            format: None,
+            recently_updated: false,
+            recently_added: false,
+            in_progress: false,
+            collection: None,
         };
         // We need to recreate service or mock because mock expectations are consumed? No, .times(1) consumes.
         // But we can't easily reuse the same service with mockall in this setup without `clone` on client which is Arc.
@@ -224,14 +219,740 @@ async fn test_pagination_page_2() {
             page: 2,
             categories: None,
             author: None,
+            narrator: None,
             title: None,
             name: None,
             type_: None,
             start: None,
+            limit: None,
+            shelf: None,
+            updated_since: None,
+            sort: None,
+            format: None,
+            recently_updated: false,
+            recently_added: false,
+            in_progress: false,
+            collection: None,
         };
         let (filtered, total) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
         assert_eq!(filtered.len(), 5);
         assert_eq!(total, 25);
         assert_eq!(filtered[0].title, Some("Book 20".to_string()));
     }
+
+    #[tokio::test]
+    async fn test_get_filtered_items_multi_term_search() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let items = vec![
+            create_item("1", "Mistborn: The Final Empire", Some("Brandon Sanderson"), Some("Fantasy")),
+            create_item("2", "Elantris", Some("Brandon Sanderson"), Some("Fantasy")),
+            create_item("3", "The Final Empire of Brandon", Some("Someone Else"), Some("History")),
+        ];
+
+        mock_client
+            .expect_get_items()
+            .times(2)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
+
+        let query = search("brandon sanderson mistborn");
+        let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["1"]);
+
+        let query = search("\"final empire\" brandon");
+        let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["1", "3"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_filtered_items_ignores_diacritics() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let items = vec![
+            create_item("1", "Osudy dobrého vojáka Švejka", Some("Jaroslav Hašek"), Some("Satira")),
+            create_item("2", "Válka s mloky", Some("Karel Čapek"), Some("Sci-Fi")),
+        ];
+
+        mock_client
+            .expect_get_items()
+            .times(3)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
+
+        let query = search("Hasek");
+        let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["1"]);
+
+        let query = LibraryQuery { author: Some("capek".to_string()), ..LibraryQuery::default() };
+        let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["2"]);
+
+        let query = LibraryQuery { title: Some("svejka".to_string()), ..LibraryQuery::default() };
+        let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["1"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_filtered_items_identifier_search() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let mut emma = create_item("1", "Emma", Some("Jane Austen"), None);
+        emma.media.metadata.isbn = Some("978-0-14-143958-7".to_string());
+        let mut audiobook = create_item("2", "Mistborn", Some("Brandon Sanderson"), None);
+        audiobook.media.metadata.asin = Some("B002UZMLXM".to_string());
+        // Contains the ISBN digits in its description, but is a different book
+        let mut other = create_item("3", "Catalogue", None, None);
+        other.media.metadata.description = Some("Includes 9780141439587 and more".to_string());
+        let items = vec![emma, audiobook, other];
+
+        mock_client
+            .expect_get_items()
+            .times(3)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
+
+        for (q, expected) in [("9780141439587", "1"), ("0-14-143958-0", "1"), ("b002uzmlxm", "2")] {
+            let query = search(q);
+            let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+            assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec![expected], "query {}", q);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_filtered_items_narrator() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let mut hobbit = create_item("1", "The Hobbit", Some("J.R.R. Tolkien"), None);
+        hobbit.media.metadata.narrator_name = Some("Andy Serkis".to_string());
+        let mut silmarillion = create_item("2", "The Silmarillion", Some("J.R.R. Tolkien"), None);
+        silmarillion.media.metadata.narrator_name = Some("Martin Shaw, Andy Serkis".to_string());
+        let mut dune = create_item("3", "Dune", Some("Frank Herbert"), None);
+        dune.media.metadata.narrator_name = Some("Scott Brick".to_string());
+        let items = vec![hobbit, silmarillion, dune];
+
+        mock_client
+            .expect_get_items()
+            .times(1)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
+
+        let query = LibraryQuery { narrator: Some("serkis".to_string()), ..LibraryQuery::default() };
+        let (filtered, total) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["1", "2"]);
+        assert_eq!(total, 2);
+    }
+
+    #[tokio::test]
+    async fn test_series_items_ordered_by_sequence() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let items: Vec<AbsItemResult> = [
+            ("1", "Book Ten", Some("Cosmere #7, Stormlight #10")),
+            ("2", "Novella", Some("Stormlight")),
+            ("3", "Book Two", Some("Stormlight #2")),
+            ("4", "Book One", Some("Stormlight #1")),
+            ("5", "Interlude", Some("Stormlight #2.5")),
+        ]
+        .into_iter()
+        .map(|(id, title, series)| {
+            let mut item = create_item(id, title, None, None);
+            item.media.metadata.series_name = series.map(str::to_string);
+            item
+        })
+        .collect();
+
+        mock_client
+            .expect_get_items()
+            .times(1)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
+
+        let query = LibraryQuery {
+            name: Some("Stormlight".to_string()),
+            type_: Some(crate::models::ItemType::Series),
+            ..LibraryQuery::default()
+        };
+        let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["4", "3", "5", "1", "2"]);
+        assert_eq!(filtered[3].series, vec!["Stormlight".to_string(), "Cosmere".to_string()]);
+        assert_eq!(filtered[3].series_sequence.as_deref(), Some("10"));
+        assert_eq!(filtered[4].series_sequence, None);
+    }
+
+    #[tokio::test]
+    async fn test_hidden_tags_and_genres() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let mut arc = create_item("1", "Advance Copy", Some("Jane Doe"), Some("Fantasy"));
+        arc.media.metadata.tags = Some(vec!["arc".to_string()]);
+        let scary = create_item("2", "It", Some("Stephen King"), Some("Horror"));
+        let hobbit = create_item("3", "The Hobbit", Some("J.R.R. Tolkien"), Some("Fantasy"));
+        let items = vec![arc, scary, hobbit];
+
+        mock_client
+            .expect_get_items()
+            .times(2)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let mut config = mock_config();
+        config.hidden_tags = "ARC, DNF".to_string();
+        config.opds_user_options = r#"{"test_user": {"hidden_genres": ["horror"]}, "other": {"hidden_tags": ["x"]}}"#.to_string();
+        config.parse_users().unwrap();
+        assert_eq!(config.user_options("other").hidden_tags, vec!["x".to_string()]);
+        let service = LibraryService::new(Arc::new(mock_client), config, mock_i18n());
+
+        let query = LibraryQuery::default();
+        let (filtered, total) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["3"]);
+        assert_eq!(total, 1);
+
+        match service.get_categories_data(&user, "lib1", "authors", &query).await.unwrap() {
+            crate::service::CategoriesResult::Items { items, .. } => assert_eq!(items, vec!["J.R.R. Tolkien".to_string()]),
+            other => panic!("unexpected categories: {:?}", other),
+        }
+
+        let mut config = mock_config();
+        config.opds_user_options = r#"{"test_user": {"hiden_tags": []}}"#.to_string();
+        assert!(config.parse_users().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_merge_editions() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let mut audiobook = create_item("1", "Dune", Some("Frank Herbert"), None);
+        audiobook.media.ebook_format = None;
+        audiobook.media.num_audio_files = Some(3);
+        let ebook = create_item("2", "DUNE ", Some("Frank Herbert"), None);
+        let other = create_item("3", "Dune", Some("Someone Else"), None);
+        let items = vec![audiobook, ebook, other];
+
+        mock_client
+            .expect_get_items()
+            .times(1)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let mut config = mock_config();
+        config.merge_editions = true;
+        let service = LibraryService::new(Arc::new(mock_client), config, mock_i18n());
+
+        let query = LibraryQuery::default();
+        let (filtered, total) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(filtered[0].id, "2");
+        assert_eq!(filtered[0].editions.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), vec!["1"]);
+        assert_eq!(filtered[0].editions[0].audio_file_count, 3);
+        assert!(filtered[1].editions.is_empty());
+
+        let entry = crate::opds::model::Entry::publication(&filtered[0], &user, "lib1", "/opds/proxy", &crate::models::AcquisitionLink::DEFAULT_ORDER, "2026-06-02T12:00:00Z");
+        let hrefs: Vec<&str> = entry.links.iter().filter(|l| l.rel == crate::opds::model::ACQUISITION_REL).map(|l| l.href.as_str()).collect();
+        assert!(hrefs.contains(&"/opds/proxy/api/items/2/ebook"));
+        assert!(hrefs.contains(&"/opds/proxy/api/items/1/download"));
+        assert!(hrefs.contains(&"/opds/items/1/audiobook.zip"));
+
+        let streams: Vec<(&str, &str)> = entry.links.iter()
+            .filter(|l| l.rel == crate::opds::model::STREAM_REL)
+            .map(|l| (l.href.as_str(), l.title.as_str()))
+            .collect();
+        assert_eq!(streams, vec![("/opds/items/1/tracks/1", "1/3"), ("/opds/items/1/tracks/2", "2/3"), ("/opds/items/1/tracks/3", "3/3")]);
+    }
+
+    #[tokio::test]
+    async fn test_search_within_category() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let items = vec![
+            create_item("1", "The Hobbit", Some("J.R.R. Tolkien"), Some("Fantasy")),
+            create_item("2", "The Silmarillion", Some("J.R.R. Tolkien"), Some("Fantasy")),
+            create_item("3", "The Hobbit Companion", Some("David Day"), Some("Reference")),
+        ];
+
+        mock_client
+            .expect_get_items()
+            .times(2)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
+
+        let query = LibraryQuery {
+            q: Some("hobbit".to_string()),
+            name: Some("Tolkien".to_string()),
+            type_: Some(crate::models::ItemType::Authors),
+            ..LibraryQuery::default()
+        };
+        let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["1"]);
+
+        let query = LibraryQuery {
+            q: Some("the".to_string()),
+            name: Some("Reference".to_string()),
+            type_: Some(crate::models::ItemType::Genres),
+            ..LibraryQuery::default()
+        };
+        let (filtered, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["3"]);
+    }
+
+    #[tokio::test]
+    async fn test_titles_a_to_z() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+
+        let items = vec![
+            create_item("1", "Zorro", None, None),
+            create_item("2", "Émile", None, None),
+            create_item("3", "empire", None, None),
+            create_item("4", "1984", None, None),
+            create_item("5", "Eagle", None, None),
+        ];
+
+        mock_client
+            .expect_get_items()
+            .times(2)
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+
+        let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
+
+        let query = LibraryQuery::default();
+        match service.get_categories_data(&user, "lib1", "titles", &query).await.unwrap() {
+            crate::service::CategoriesResult::Letters(letters) => {
+                assert_eq!(letters, vec![("E".to_string(), 3), ("Z".to_string(), 1)]);
+            }
+            other => panic!("unexpected categories: {:?}", other),
+        }
+
+        let query = LibraryQuery { start: Some("e".to_string()), ..query };
+        let (filtered, total) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(filtered.iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["5", "2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn test_slow_upstream_warning() {
+        use std::io::Write;
+        use std::sync::Mutex;
+
+        #[derive(Clone, Default)]
+        struct Logs(Arc<Mutex<Vec<u8>>>);
+        impl Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+        let items = vec![create_item("1", "Book", None, None), create_item("2", "Other", None, None)];
+        mock_client.expect_get_items().returning(move |_, _| {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            Ok(mock_items_response(items.clone()))
+        });
+        let query = LibraryQuery::default();
+        let mock_client = Arc::new(mock_client);
+
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt().with_writer(move || writer.clone()).with_ansi(false).finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut config = mock_config();
+        config.slow_upstream_ms = 0;
+        let service = LibraryService::new(mock_client.clone(), config, mock_i18n());
+        service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        assert!(logs.0.lock().unwrap().is_empty());
+
+        let mut config = mock_config();
+        config.slow_upstream_ms = 10;
+        let service = LibraryService::new(mock_client, config, mock_i18n());
+        service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("WARN"));
+        assert!(output.contains("Slow library fetch for lib1: 2 items"));
+        assert!(output.contains("library_id=\"lib1\" item_count=2"));
+        assert!(!output.contains("Slow library filter"));
+    }
+
+    #[tokio::test]
+    async fn test_category_pagination_links() {
+        use crate::models::AbsLibrary;
+
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+        let items = crate::test_util::create_items(50, 25, 1);
+        mock_client
+            .expect_get_items()
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+        mock_client
+            .expect_get_library()
+            .returning(|_, id| Ok(AbsLibrary { id: id.to_string(), name: "Books".to_string(), icon: None, media_type: Default::default() }));
+        let service = LibraryService::new(Arc::new(mock_client), mock_config(), mock_i18n());
+
+        // 25 authors in pages of 10, on the middle page
+        let query = LibraryQuery { page: 1, ..LibraryQuery::default() };
+        let feed = service.get_categories(&user, "lib1", "authors", &query, None).await.unwrap();
+        assert_eq!(feed.entries.len(), 10);
+        let pagination = feed.pagination.unwrap();
+        assert_eq!((pagination.total_results, pagination.start_index, pagination.items_per_page), (25, 11, 10));
+        let links: Vec<(&str, &str)> = pagination.links.iter().map(|l| (l.rel.as_str(), l.href.as_str())).collect();
+        assert_eq!(links, vec![
+            ("start", "/opds/libraries/lib1/authors"),
+            ("first", "/opds/libraries/lib1/authors"),
+            ("previous", "/opds/libraries/lib1/authors"),
+            ("next", "/opds/libraries/lib1/authors?page=2"),
+            ("last", "/opds/libraries/lib1/authors?page=2"),
+        ]);
+
+        // The letter and page size carry over
+        let query = LibraryQuery { start: Some("a".to_string()), limit: Some(4), page: 2, ..query };
+        let feed = service.get_categories(&user, "lib1", "authors", &query, None).await.unwrap();
+        assert_eq!(feed.entries.len(), 4);
+        let pagination = feed.pagination.unwrap();
+        let links: Vec<(&str, &str)> = pagination.links.iter().map(|l| (l.rel.as_str(), l.href.as_str())).collect();
+        assert!(links.contains(&("previous", "/opds/libraries/lib1/authors?start=a&limit=4&page=1")));
+        assert!(links.contains(&("next", "/opds/libraries/lib1/authors?start=a&limit=4&page=3")));
+        assert!(links.contains(&("last", "/opds/libraries/lib1/authors?start=a&limit=4&page=6")));
+    }
+
+    #[tokio::test]
+    async fn test_library_and_category_counts() {
+        let mut mock_client = MockAbsClient::new();
+        let user = mock_user();
+        let mut items = crate::test_util::create_items(1200, 7, 3);
+        items[0].media.metadata.narrator_name = Some("Reader A, Reader B".to_string());
+        items[1].media.metadata.series_name = Some("Saga #1".to_string());
+        items[2].media.metadata.tags = Some(vec!["Hidden".to_string()]);
+        items[3].media.ebook_format = None;
+        mock_client
+            .expect_get_items()
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+        let mut config = mock_config();
+        config.show_audiobooks = false;
+        config.hidden_tags = "Hidden".to_string();
+        let service = LibraryService::new(Arc::new(mock_client), config, mock_i18n());
+
+        let counts = service.library_counts(&user, "lib1").await.unwrap();
+        assert_eq!(counts, crate::service::LibraryCounts { books: 1198, authors: 7, narrators: 2, genres: 3, series: 1 });
+
+        let libraries = vec![crate::models::Library { id: "lib1".to_string(), name: "Main Library".to_string(), icon: None, media_type: Default::default() }];
+        let feed = service.libraries_feed(&user, &libraries, None).await;
+        assert_eq!(feed.entries[0].title.as_deref(), Some("Main Library — 1,198 books"));
+        let feed = service.libraries_feed(&user, &libraries, Some("de")).await;
+        assert_eq!(feed.entries[0].title.as_deref(), Some("Main Library — 1.198 Bücher"));
+
+        let feed = service.categories_root_feed(&user, "lib1", MediaType::Book, None).await;
+        let titles: Vec<&str> = feed.entries.iter().filter_map(|e| e.title.as_deref()).collect();
+        assert_eq!(titles, vec!["All books (1,198)", "Continue reading", "Authors (7)", "Narrators (2)", "Tags/Genres (3)", "Series (1)", "Collections", "Titles A–Z (1,198)", "New books", "Recently updated"]);
+    }
+
+    #[tokio::test]
+    async fn test_authors_by_surname() {
+        use crate::service::surname_first;
+
+        assert_eq!(surname_first("Brandon Sanderson"), "Sanderson, Brandon");
+        assert_eq!(surname_first("Ludwig van Beethoven"), "Beethoven, Ludwig van");
+        assert_eq!(surname_first("Martin Luther King Jr."), "King, Martin Luther, Jr.");
+        assert_eq!(surname_first("Homer"), "Homer");
+
+        let user = mock_user();
+        let service = |char_cards: bool| {
+            let items = vec![
+                create_item("1", "Mistborn", Some("Brandon Sanderson"), None),
+                create_item("2", "Dune", Some("Frank Herbert"), None),
+                create_item("3", "The Hobbit", Some("J.R.R. Tolkien"), None),
+                create_item("4", "Iliad", Some("Homer"), None),
+                create_item("5", "Emma", Some("Jane Austen"), None),
+            ];
+            let mut mock_client = MockAbsClient::new();
+            mock_client
+                .expect_get_items()
+                .returning(move |_, _| Ok(mock_items_response(items.clone())));
+            mock_client
+                .expect_get_library()
+                .returning(|_, id| Ok(crate::models::AbsLibrary { id: id.to_string(), name: "Books".to_string(), icon: None, media_type: Default::default() }));
+            let mut config = mock_config();
+            config.authors_by_surname = true;
+            config.show_char_cards = char_cards;
+            LibraryService::new(Arc::new(mock_client), config, mock_i18n())
+        };
+
+        let feed = service(false).get_categories(&user, "lib1", "authors", &LibraryQuery::default(), None).await.unwrap();
+        let titles: Vec<&str> = feed.entries.iter().filter_map(|e| e.title.as_deref()).collect();
+        assert_eq!(titles, vec!["Austen, Jane", "Herbert, Frank", "Homer", "Sanderson, Brandon", "Tolkien, J.R.R."]);
+        // The cards still filter by the name as ABS has it
+        assert!(feed.entries[0].links[0].href.ends_with("?name=Jane Austen&type=authors"));
+
+        // Letter cards group by surname too
+        match service(true).get_categories_data(&user, "lib1", "authors", &LibraryQuery::default()).await.unwrap() {
+            crate::service::CategoriesResult::Letters(letters) => {
+                assert_eq!(letters, vec![("A".to_string(), 1), ("H".to_string(), 2), ("S".to_string(), 1), ("T".to_string(), 1)]);
+            }
+            other => panic!("Expected letters, got {:?}", other),
+        }
+        let query = LibraryQuery { start: Some("h".to_string()), ..LibraryQuery::default() };
+        match service(true).get_categories_data(&user, "lib1", "authors", &query).await.unwrap() {
+            crate::service::CategoriesResult::Items { items, .. } => assert_eq!(items, vec!["Frank Herbert".to_string(), "Homer".to_string()]),
+            other => panic!("Expected items, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_title_template() {
+        let user = mock_user();
+        let service = |template: &str| {
+            let mut first = create_item("1", "The Final Empire", Some("Brandon Sanderson"), None);
+            first.media.metadata.series_name = Some("Mistborn #1".to_string());
+            let mut standalone = create_item("2", "Elantris", Some("Brandon Sanderson"), None);
+            standalone.media.metadata.series_name = Some("Cosmere".to_string());
+            let items = vec![first, standalone, create_item("3", "Warbreaker", Some("Brandon Sanderson"), None)];
+            let mut mock_client = MockAbsClient::new();
+            mock_client
+                .expect_get_items()
+                .returning(move |_, _| Ok(mock_items_response(items.clone())));
+            let mut config = mock_config();
+            config.title_template = template.to_string();
+            LibraryService::new(Arc::new(mock_client), config, mock_i18n())
+        };
+        let titles = |items: Vec<crate::models::LibraryItem>| {
+            let mut titles: Vec<String> = items.into_iter().filter_map(|item| item.title).collect();
+            titles.sort();
+            titles
+        };
+
+        let (items, _) = service("{title} ({series} #{position})")
+            .get_filtered_items(&user, "lib1", &LibraryQuery::default())
+            .await
+            .unwrap();
+        // Only books with a series position are templated
+        assert_eq!(titles(items), vec!["Elantris", "The Final Empire (Mistborn #1)", "Warbreaker"]);
+
+        let (items, _) = service("").get_filtered_items(&user, "lib1", &LibraryQuery::default()).await.unwrap();
+        assert_eq!(titles(items), vec!["Elantris", "The Final Empire", "Warbreaker"]);
+
+        assert!(AppConfig::builder().title_template("{series} #{position}").build().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_empty_categories_hidden() {
+        let user = mock_user();
+        let items = vec![
+            create_item("1", "Dune", Some("Frank Herbert"), Some("Science Fiction")),
+            create_item("2", "Emma", Some("Jane Austen"), None),
+        ];
+        let mut mock_client = MockAbsClient::new();
+        mock_client
+            .expect_get_items()
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+        let mut config = mock_config();
+        config.show_counts = false;
+        let service = LibraryService::new(Arc::new(mock_client), config, mock_i18n());
+
+        assert_eq!(service.empty_categories(&user, "lib1").await, vec!["narrators", "series"]);
+        let feed = service.categories_root_feed(&user, "lib1", MediaType::Book, None).await;
+        let ids: Vec<&str> = feed.entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["lib1", "continue", "authors", "genres", "collections", "titles", "recent", "updated"]);
+
+        // Without items to count, every card stays
+        let mut failing = MockAbsClient::new();
+        failing
+            .expect_get_items()
+            .returning(|_, _| Err(crate::error::AppError::upstream("Failed to fetch items", reqwest::StatusCode::SERVICE_UNAVAILABLE)));
+        let service = LibraryService::new(Arc::new(failing), mock_config(), mock_i18n());
+        assert_eq!(service.categories_root_feed(&user, "lib1", MediaType::Book, None).await.entries.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_series_pattern() {
+        use crate::series::SeriesParser;
+        use std::borrow::Cow;
+
+        let default = SeriesParser::default();
+        assert_eq!(default.parse("Mistborn #1"), (Cow::Borrowed("Mistborn"), Some("1")));
+        assert_eq!(default.parse(" Mistborn # 2.5 "), (Cow::Borrowed("Mistborn"), Some("2.5")));
+        assert_eq!(default.parse("Mistborn #"), (Cow::Borrowed("Mistborn"), None));
+        assert_eq!(default.parse("Cosmere"), (Cow::Borrowed("Cosmere"), None));
+
+        let kept = SeriesParser::new(crate::series::DEFAULT_SERIES_PATTERN, true).unwrap();
+        assert_eq!(kept.parse("Mistborn #1"), (Cow::Borrowed("Mistborn #1"), Some("1")));
+        let off = SeriesParser::new("", false).unwrap();
+        assert_eq!(off.parse("Mistborn #1"), (Cow::Borrowed("Mistborn #1"), None));
+        let bracketed = SeriesParser::new(r"\s*\((?:Book\s+)?(?P<sequence>[\d.]+)\)", false).unwrap();
+        assert_eq!(bracketed.parse("Discworld (Book 3)"), (Cow::Borrowed("Discworld"), Some("3")));
+        assert_eq!(bracketed.parse("Discworld (3) Omnibus"), (Cow::Owned("Discworld Omnibus".to_string()), Some("3")));
+        assert!(AppConfig::builder().series_pattern("(unclosed").build().is_err());
+
+        // A custom separator drives series ordering, names and the series list
+        let user = mock_user();
+        let items: Vec<AbsItemResult> = [("1", "Guards! Guards!", "Discworld - 8"), ("2", "The Colour of Magic", "Discworld - 1")]
+            .into_iter()
+            .map(|(id, title, series)| {
+                let mut item = create_item(id, title, None, None);
+                item.media.metadata.series_name = Some(series.to_string());
+                item
+            })
+            .collect();
+        let mut mock_client = MockAbsClient::new();
+        mock_client
+            .expect_get_items()
+            .returning(move |_, _| Ok(mock_items_response(items.clone())));
+        mock_client
+            .expect_get_library()
+            .returning(|_, id| Ok(crate::models::AbsLibrary { id: id.to_string(), name: "Books".to_string(), icon: None, media_type: Default::default() }));
+        let mut config = mock_config();
+        config.series_pattern = r"\s+-\s+(?P<sequence>\d+)$".to_string();
+        let service = LibraryService::new(Arc::new(mock_client), config, mock_i18n());
+
+        let query = LibraryQuery { type_: Some(crate::models::ItemType::Series), name: Some("Discworld".to_string()), ..LibraryQuery::default() };
+        let (items, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+        let titles: Vec<&str> = items.iter().filter_map(|item| item.title.as_deref()).collect();
+        assert_eq!(titles, vec!["The Colour of Magic", "Guards! Guards!"]);
+        assert_eq!(items[0].series, vec!["Discworld".to_string()]);
+        assert_eq!(items[0].series_sequence.as_deref(), Some("1"));
+
+        let feed = service.get_categories(&user, "lib1", "series", &LibraryQuery::default(), None).await.unwrap();
+        let names: Vec<&str> = feed.entries.iter().filter_map(|e| e.title.as_deref()).collect();
+        assert_eq!(names, vec!["Discworld"]);
+    }
+
+    #[tokio::test]
+    async fn test_progress_in_titles() {
+        use crate::models::UserOptions;
+
+        let user = mock_user();
+        let service = |show_progress: bool| {
+            let items = vec![
+                create_item("1", "Dune", None, None),
+                create_item("2", "Emma", None, None),
+                create_item("3", "Iliad", None, None),
+            ];
+            let mut mock_client = MockAbsClient::new();
+            mock_client
+                .expect_get_items()
+                .returning(move |_, _| Ok(mock_items_response(items.clone())));
+            mock_client
+                .expect_get_progress()
+                .returning(|_| Ok([("1".to_string(), 0.426), ("2".to_string(), 1.0)].into_iter().collect()));
+            let config = AppConfig::builder()
+                .user("test_user", "test_token", "pass")
+                .user_options("test_user", UserOptions { show_progress, ..UserOptions::default() })
+                .build()
+                .unwrap();
+            LibraryService::new(Arc::new(mock_client), config, mock_i18n())
+        };
+
+        let (items, _) = service(true).get_filtered_items(&user, "lib1", &LibraryQuery::default()).await.unwrap();
+        let titles: Vec<&str> = items.iter().filter_map(|item| item.title.as_deref()).collect();
+        assert_eq!(titles, vec!["Dune — 43%", "Emma — 100%", "Iliad"]);
+
+        let (items, _) = service(false).get_filtered_items(&user, "lib1", &LibraryQuery::default()).await.unwrap();
+        let titles: Vec<&str> = items.iter().filter_map(|item| item.title.as_deref()).collect();
+        assert_eq!(titles, vec!["Dune", "Emma", "Iliad"]);
+    }
+
+    #[tokio::test]
+    async fn test_default_sort_order() {
+        let user = mock_user();
+        let service = |config: AppConfig| {
+            let added = |id: &str, title: &str, author: &str, added_at: Option<i64>| {
+                let mut item = create_item(id, title, Some(author), None);
+                item.added_at = added_at;
+                item
+            };
+            let items = vec![
+                added("1", "Dune", "Frank Herbert", Some(2)),
+                added("2", "Anathem", "Neal Stephenson", None),
+                added("3", "Cryptonomicon", "Neal Stephenson", Some(3)),
+                added("4", "Brave New World", "Aldous Huxley", Some(1)),
+            ];
+            let mut mock_client = MockAbsClient::new();
+            mock_client
+                .expect_get_items()
+                .returning(move |_, _| Ok(mock_items_response(items.clone())));
+            LibraryService::new(Arc::new(mock_client), config, mock_i18n())
+        };
+        let ids = |service: LibraryService<MockAbsClient>, library_id: &'static str| {
+            let user = user.clone();
+            async move {
+                let (items, _) = service.get_filtered_items(&user, library_id, &LibraryQuery::default()).await.unwrap();
+                items.into_iter().map(|item| item.id).collect::<Vec<_>>()
+            }
+        };
+        let builder = || AppConfig::builder().user("user", "token", "pass").show_audiobooks(true);
+
+        // Without a configured order, items stay in ABS order
+        assert_eq!(ids(service(builder().build().unwrap()), "lib1").await, vec!["1", "2", "3", "4"]);
+
+        // Newest first, with undated items last
+        let config = builder().sort("added:desc").build().unwrap();
+        assert_eq!(ids(service(config), "lib1").await, vec!["3", "1", "4", "2"]);
+
+        // A library's own order wins over the global one
+        let config = builder().sort("added:desc").library_sorts(&[("lib2", "author"), ("lib3", "title:asc")]).build().unwrap();
+        assert_eq!(ids(service(config.clone()), "lib2").await, vec!["4", "1", "2", "3"]);
+        assert_eq!(ids(service(config.clone()), "lib3").await, vec!["2", "4", "3", "1"]);
+        assert_eq!(ids(service(config), "lib1").await, vec!["3", "1", "4", "2"]);
+
+        let config = builder().sort("added:desc").library_sorts(&[("lib2", "author")]).authors_by_surname(true).build().unwrap();
+        assert_eq!(ids(service(config), "lib2").await, vec!["1", "4", "2", "3"]);
+
+        assert!(builder().sort("popularity").build().is_err());
+        assert!(builder().library_sorts(&[("lib1", "title:up")]).build().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_requested_sort_order() {
+        let user = mock_user();
+        let item = |id: &str, title: &str, series: Option<&str>, year: Option<&str>| {
+            let mut item = create_item(id, title, Some("Someone"), None);
+            item.media.metadata.series_name = series.map(str::to_string);
+            item.media.metadata.published_year = year.map(str::to_string);
+            item
+        };
+        let items = vec![
+            item("1", "Mistborn", Some("Mistborn #1"), Some("2006")),
+            item("2", "The Hero of Ages", Some("Mistborn #3"), Some("2008")),
+            item("3", "Arcanum Unbounded", None, Some("2016")),
+            item("4", "The Well of Ascension", Some("Mistborn #2"), None),
+            item("5", "Elantris", Some("Elantris"), Some("2005")),
+        ];
+        let mut mock_client = MockAbsClient::new();
+        mock_client.expect_get_items().returning(move |_, _| Ok(mock_items_response(items.clone())));
+        let config = AppConfig::builder().user("user", "token", "pass").show_audiobooks(true).sort("added:desc").build().unwrap();
+        let service = LibraryService::new(Arc::new(mock_client), config, mock_i18n());
+        let ids = |query: LibraryQuery| {
+            let service = &service;
+            let user = user.clone();
+            async move {
+                let (items, _) = service.get_filtered_items(&user, "lib1", &query).await.unwrap();
+                items.into_iter().map(|item| item.id).collect::<Vec<_>>()
+            }
+        };
+        let sorted = |sort: &str| LibraryQuery { sort: Some(sort.to_string()), ..Default::default() };
+
+        assert_eq!(ids(sorted("title")).await, vec!["3", "5", "1", "2", "4"]);
+        assert_eq!(ids(sorted("published:desc")).await, vec!["3", "2", "1", "5", "4"]);
+        // By series, then in reading order; books outside a series go last
+        assert_eq!(ids(sorted("series")).await, vec!["5", "1", "4", "2", "3"]);
+        assert_eq!(ids(sorted("series:desc")).await, vec!["2", "4", "1", "5", "3"]);
+
+        // It replaces the order of letters and series too
+        let query = LibraryQuery { start: Some("t".to_string()), sort: Some("published".to_string()), ..Default::default() };
+        assert_eq!(ids(query).await, vec!["2", "4"]);
+    }
 }
@@ -0,0 +1,68 @@
+//! Share links (`OPDS_SHARE_SECRET`): a signed token in the URL that opens the catalog,
+//! or one library or shelf of it, read-only and without an account, until it expires.
+//! Tokens carry their grant, so nothing is stored; changing the secret revokes them all.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// What a share link opens.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareGrant {
+    /// The only library it reaches; `None` for every library the share user can see.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub library: Option<String>,
+    /// A shelf from `OPDS_SHELVES` of that library, whose books are the only ones listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shelf: Option<String>,
+    /// When it stops working, in seconds since the epoch; `None` never.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ShareError {
+    #[error("Invalid share link")]
+    Invalid,
+    #[error("This share link has expired")]
+    Expired,
+}
+
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// The token of a link opening `grant`, signed with `secret`.
+pub fn mint(secret: &str, grant: &ShareGrant) -> String {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(grant).expect("a grant serializes"));
+    let signature = URL_SAFE_NO_PAD.encode(mac(secret, &payload).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+}
+
+/// The grant of `token` if `secret` signed it and it hasn't expired at `now`, in seconds
+/// since the epoch.
+pub fn verify(secret: &str, token: &str, now: i64) -> Result<ShareGrant, ShareError> {
+    let (payload, signature) = token.split_once('.').ok_or(ShareError::Invalid)?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| ShareError::Invalid)?;
+    mac(secret, payload).verify_slice(&signature).map_err(|_| ShareError::Invalid)?;
+    let grant: ShareGrant = URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or(ShareError::Invalid)?;
+    if grant.expires.is_some_and(|expires| expires <= now) {
+        return Err(ShareError::Expired);
+    }
+    Ok(grant)
+}
+
+/// `value` percent-encoded for a query string.
+pub(crate) fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| if b.is_ascii_alphanumeric() || b"-._~".contains(&b) { (b as char).to_string() } else { format!("%{:02X}", b) })
+        .collect()
+}
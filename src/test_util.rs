@@ -0,0 +1,206 @@
+//! A mock ABS client, a wiremock ABS server and fixtures for testing the catalog, in this
+//! crate's tests and benches, in applications embedding it and against deployment images.
+//! Enabled by the `test-util` feature.
+
+use async_trait::async_trait;
+use mockall::mock;
+use serde_json::json;
+use std::sync::Arc;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::api::AbsClient;
+use crate::models::{AbsItemResult, AbsItemsResponse, AbsLibrary, AbsMedia, AbsMetadata, AppConfig, AppConfigBuilder, InternalUser};
+use crate::AppState;
+
+mock! {
+    pub AbsClient {}
+    #[async_trait]
+    impl crate::api::AuthClient for AbsClient {
+        async fn login(&self, username: &str, password: &str) -> crate::error::Result<InternalUser>;
+        async fn verify_token(&self, token: &str) -> crate::error::Result<InternalUser>;
+        async fn forget_login(&self, username: &str) -> bool;
+        async fn refresh(&self, token: &str) -> Option<String>;
+    }
+    #[async_trait]
+    impl crate::api::CatalogClient for AbsClient {
+        async fn get_libraries(&self, user: &InternalUser) -> crate::error::Result<Vec<AbsLibrary>>;
+        async fn get_library(&self, user: &InternalUser, library_id: &str) -> crate::error::Result<AbsLibrary>;
+        async fn get_items(&self, user: &InternalUser, library_id: &str) -> crate::error::Result<AbsItemsResponse>;
+        async fn get_item(&self, user: &InternalUser, item_id: &str) -> crate::error::Result<AbsItemResult>;
+        async fn get_cover(&self, user: &InternalUser, item_id: &str) -> crate::error::Result<Option<Vec<u8>>>;
+        async fn forget_items(&self, user: &InternalUser, library_id: &str);
+    }
+    #[async_trait]
+    impl crate::api::ProgressClient for AbsClient {
+        async fn report_download(&self, user: &InternalUser, item: &AbsItemResult) -> crate::error::Result<()>;
+        async fn get_progress(&self, user: &InternalUser) -> crate::error::Result<std::collections::HashMap<String, f64>>;
+        async fn get_items_in_progress(&self, user: &InternalUser) -> crate::error::Result<Vec<String>>;
+    }
+    #[async_trait]
+    impl crate::api::PodcastClient for AbsClient {
+        async fn get_recent_episodes(&self, user: &InternalUser, library_id: &str, limit: usize, page: usize) -> crate::error::Result<crate::models::AbsEpisodesResponse>;
+    }
+    #[async_trait]
+    impl crate::api::CollectionClient for AbsClient {
+        async fn get_collections(&self, user: &InternalUser, library_id: &str) -> crate::error::Result<Vec<crate::models::AbsCollection>>;
+    }
+}
+
+impl AbsClient for MockAbsClient {
+    fn progress(&self) -> Option<&dyn crate::api::ProgressClient> {
+        Some(self)
+    }
+
+    fn podcasts(&self) -> Option<&dyn crate::api::PodcastClient> {
+        Some(self)
+    }
+
+    fn collections(&self) -> Option<&dyn crate::api::CollectionClient> {
+        Some(self)
+    }
+}
+
+/// The state the server would build from `config`, reading ABS through `mock_client`.
+pub async fn build_app_state_with_mock(
+    config: AppConfig,
+    mock_client: Arc<dyn AbsClient + Send + Sync>
+) -> Arc<AppState> {
+    AppState::new(config, mock_client, crate::http_client())
+}
+
+pub fn mock_user() -> InternalUser {
+    InternalUser {
+        name: "test_user".to_string(),
+        api_key: "test_token".to_string(),
+        password: None,
+    }
+}
+
+/// An English epub with an optional author and genre.
+pub fn create_item(id: &str, title: &str, author: Option<&str>, genre: Option<&str>) -> AbsItemResult {
+    AbsItemResult {
+        id: id.to_string(),
+        media: AbsMedia {
+            ebook_format: Some("epub".to_string()),
+            num_audio_files: None,
+            audio_files: vec![],
+            metadata: AbsMetadata {
+                title: Some(title.to_string()),
+                subtitle: None,
+                description: None,
+                genres: genre.map(|g| vec![g.to_string()]),
+                tags: None,
+                publisher: None,
+                isbn: None,
+                asin: None,
+                language: Some("en".to_string()),
+                published_year: None,
+                author_name: author.map(|a| a.to_string()),
+                narrator_name: None,
+                series_name: None,
+            },
+        },
+        updated_at: None,
+        added_at: None,
+        library_id: None,
+    }
+}
+
+/// `count` items spread round-robin over `authors` authors and `genres` genres.
+pub fn create_items(count: usize, authors: usize, genres: usize) -> Vec<AbsItemResult> {
+    (0..count)
+        .map(|i| {
+            create_item(
+                &i.to_string(),
+                &format!("Book Title {}", i),
+                Some(&format!("Author {}", i % authors)),
+                Some(&format!("Genre {}", i % genres)),
+            )
+        })
+        .collect()
+}
+
+/// The password of [`mock_user`] on a [`MockAbs`].
+pub const MOCK_PASSWORD: &str = "pass";
+
+/// An ABS server on a local port with canned responses: `/login` accepts [`mock_user`]
+/// with [`MOCK_PASSWORD`] and rejects anything else, and `/api/libraries`, the libraries'
+/// details, their `/items` and each item are served from the given libraries. Mount
+/// further mocks on [`MockAbs::server`].
+pub struct MockAbs {
+    pub server: MockServer,
+}
+
+impl MockAbs {
+    pub async fn start(libraries: &[(AbsLibrary, Vec<AbsItemResult>)]) -> Self {
+        Self::mount(MockServer::start().await, libraries).await
+    }
+
+    /// Like [`MockAbs::start`], on the given listener, e.g. one bound to `0.0.0.0` for a
+    /// server in a container.
+    pub async fn start_on(listener: std::net::TcpListener, libraries: &[(AbsLibrary, Vec<AbsItemResult>)]) -> Self {
+        Self::mount(MockServer::builder().listener(listener).start().await, libraries).await
+    }
+
+    async fn mount(server: MockServer, libraries: &[(AbsLibrary, Vec<AbsItemResult>)]) -> Self {
+        let user = mock_user();
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .and(body_json(json!({ "username": user.name, "password": MOCK_PASSWORD })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "user": { "username": user.name, "accessToken": user.api_key }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .respond_with(ResponseTemplate::new(401))
+            .with_priority(10)
+            .mount(&server)
+            .await;
+
+        let list: Vec<&AbsLibrary> = libraries.iter().map(|(library, _)| library).collect();
+        Mock::given(method("GET"))
+            .and(path("/api/libraries"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "libraries": list })))
+            .mount(&server)
+            .await;
+        for (library, items) in libraries {
+            Mock::given(method("GET"))
+                .and(path(format!("/api/libraries/{}", library.id)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(library))
+                .mount(&server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(format!("/api/libraries/{}/items", library.id)))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "results": items })))
+                .mount(&server)
+                .await;
+            for item in items {
+                Mock::given(method("GET"))
+                    .and(path(format!("/api/items/{}", item.id)))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(item))
+                    .mount(&server)
+                    .await;
+            }
+        }
+        Self { server }
+    }
+
+    pub fn uri(&self) -> String {
+        self.server.uri()
+    }
+
+    /// A configuration reading from this server, with [`mock_user`] and [`MOCK_PASSWORD`]
+    /// as the one user.
+    pub fn config(&self) -> AppConfigBuilder {
+        let user = mock_user();
+        AppConfig::builder().abs_url(self.uri()).user(&user.name, &user.api_key, MOCK_PASSWORD)
+    }
+}
+
+/// A library with the given ID and name.
+pub fn create_library(id: &str, name: &str) -> AbsLibrary {
+    AbsLibrary { id: id.to_string(), name: name.to_string(), icon: None, media_type: Default::default() }
+}
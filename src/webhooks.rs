@@ -0,0 +1,134 @@
+//! New-item notifications (`OPDS_WEBHOOKS`): a background task lists the libraries every
+//! `WEBHOOK_INTERVAL_SECS` through the same client the feeds read with, and calls the
+//! webhooks of a library for each item that wasn't there the time before. The first
+//! listing after startup only learns what is there, so restarts don't announce the
+//! whole library again.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::models::{AbsItemResult, AbsLibrary, InternalUser, Webhook, WebhookFormat};
+use crate::AppState;
+
+/// Remembers the items of each library and tells the webhooks about new ones.
+pub struct Watcher {
+    state: Arc<AppState>,
+    user: InternalUser,
+    /// Item ids per library id, as of the last listing.
+    seen: HashMap<String, HashSet<String>>,
+}
+
+impl Watcher {
+    /// A watcher for the configured webhooks, or `None` if there are none.
+    pub fn new(state: Arc<AppState>) -> Option<Self> {
+        if state.config.webhooks.is_empty() {
+            return None;
+        }
+        let user = state.config.webhook_user()?;
+        Some(Self { state, user, seen: HashMap::new() })
+    }
+
+    /// Checks the libraries once, returning how many new items were announced.
+    pub async fn poll(&mut self) -> anyhow::Result<usize> {
+        let libraries = self.state.api_client.get_libraries(&self.user).await?;
+        let mut announced = 0;
+        for library in &libraries {
+            let hooks: Vec<&Webhook> = self.state.config.webhooks.iter().filter(|hook| hears_about(hook, library)).collect();
+            if hooks.is_empty() {
+                continue;
+            }
+            let items = match self.state.api_client.get_items(&self.user, &library.id).await {
+                Ok(items) => items.results,
+                Err(e) => {
+                    tracing::warn!("Failed to list library {} for webhooks: {}", library.id, e);
+                    continue;
+                }
+            };
+            let ids: HashSet<String> = items.iter().map(|item| item.id.clone()).collect();
+            let Some(seen) = self.seen.insert(library.id.clone(), ids) else {
+                continue;
+            };
+            let mut added: Vec<&AbsItemResult> = items.iter().filter(|item| !seen.contains(&item.id)).collect();
+            added.sort_by_key(|item| item.added_at);
+            for item in added {
+                for hook in &hooks {
+                    if let Err(e) = self.send(hook, library, item).await {
+                        tracing::warn!("Webhook {} failed: {}", hook.url, e);
+                    }
+                }
+                announced += 1;
+            }
+        }
+        Ok(announced)
+    }
+
+    /// Polls forever, every `WEBHOOK_INTERVAL_SECS`.
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.state.config.webhook_interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match self.poll().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Announced {} new items to webhooks", count),
+                Err(e) => tracing::warn!("Failed to check libraries for new items: {}", e),
+            }
+        }
+    }
+
+    async fn send(&self, hook: &Webhook, library: &AbsLibrary, item: &AbsItemResult) -> anyhow::Result<()> {
+        let metadata = &item.media.metadata;
+        let title = metadata.title.as_deref().unwrap_or(&item.id);
+        let url = self.state.config.web_item_url(&item.id);
+        let message = self.state.i18n.localize_with("webhook.added", None, &[("library", &library.name), ("title", title)]);
+        let message = match &metadata.author_name {
+            Some(author) if !author.is_empty() => format!("{} — {}", message, author),
+            _ => message,
+        };
+
+        let client = &self.state.api_client_raw;
+        let request = match hook.format {
+            WebhookFormat::Json => client.post(&hook.url).json(&serde_json::json!({
+                "event": "item_added",
+                "library": { "id": library.id, "name": library.name },
+                "item": {
+                    "id": item.id,
+                    "title": metadata.title,
+                    "author": metadata.author_name,
+                    "series": metadata.series_name,
+                    "addedAt": item.added_at,
+                    "url": url,
+                },
+            })),
+            WebhookFormat::Ntfy => {
+                let request = client.post(&hook.url).header("Tags", "books").body(message);
+                match &url {
+                    Some(url) => request.header("Click", url),
+                    None => request,
+                }
+            }
+            WebhookFormat::Discord => {
+                let content = match &url {
+                    Some(url) => format!("{}\n{}", message, url),
+                    None => message,
+                };
+                client.post(&hook.url).json(&serde_json::json!({ "content": content }))
+            }
+        };
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Whether `hook` is told about items added to `library`.
+fn hears_about(hook: &Webhook, library: &AbsLibrary) -> bool {
+    hook.library.as_deref().is_none_or(|wanted| wanted == library.id || wanted == library.name)
+}
+
+/// Starts watching for new items in the background, if webhooks are configured.
+pub fn spawn(state: Arc<AppState>) {
+    if let Some(watcher) = Watcher::new(state) {
+        tokio::spawn(watcher.run());
+    }
+}
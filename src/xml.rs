@@ -1,8 +1,11 @@
-use crate::models::{Library, LibraryItem};
+use crate::opds::model::{Entry, Feed, Link, Person, FACET_REL};
 use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
 use quick_xml::Writer;
-use std::io::Cursor;
-use crate::models::InternalUser;
+use std::io::{Cursor, Write};
+use crate::i18n::I18n;
+
+/// Roughly how much of a streamed feed is sent at a time.
+const CHUNK_SIZE: usize = 16 * 1024;
 
 pub struct OpdsBuilder;
 
@@ -10,123 +13,211 @@ pub fn is_combining_mark(c: char) -> bool {
     unicode_normalization::char::is_combining_mark(c)
 }
 
+/// Where a letter card leads: titles go straight to the filtered book list, other
+/// categories to the names starting with that letter.
+pub fn letter_href(library_id: &str, type_: &str, letter: &str) -> String {
+    if type_ == "titles" {
+        format!("/opds/libraries/{}?start={}", library_id, letter.to_lowercase())
+    } else {
+        format!("/opds/libraries/{}/{}?start={}", library_id, type_, letter.to_lowercase())
+    }
+}
+
+/// The href of a category feed, keeping the letter and page size it was opened with so
+/// its pagination links do too.
+pub fn category_href(library_id: &str, type_: &str, start: Option<&str>, limit: Option<usize>) -> String {
+    let mut params = Vec::new();
+    if let Some(start) = start {
+        params.push(format!("start={}", start));
+    }
+    if let Some(limit) = limit {
+        params.push(format!("limit={}", limit));
+    }
+    let href = format!("/opds/libraries/{}/{}", library_id, type_);
+    if params.is_empty() { href } else { format!("{}?{}", href, params.join("&")) }
+}
+
+/// A search template limited to the category or filter of `url_base`, e.g.
+/// `/opds/libraries/1?type=authors&name=Tolkien&q={searchTerms}`. `None` if the feed is
+/// not filtered.
+pub fn scoped_search_href(url_base: &str, placeholder: &str) -> Option<String> {
+    let (path, query) = url_base.split_once('?')?;
+    let scope: Vec<&str> = query
+        .split('&')
+        .filter(|param| {
+            let key = param.split_once('=').map_or(*param, |(k, _)| k);
+            matches!(key, "type" | "name" | "author" | "narrator" | "title")
+        })
+        .collect();
+    if scope.is_empty() {
+        return None;
+    }
+    Some(format!("{}?{}&q={}", path, scope.join("&"), placeholder))
+}
+
+/// Feeds a document written to it into a SHA-1 hash, counting its bytes.
+struct HashWriter {
+    hasher: sha1_smol::Sha1,
+    length: u64,
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.hasher.update(buf);
+        self.length += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 impl OpdsBuilder {
-    pub fn build_opds_skeleton<F>(
-        id: &str,
-        title: &str,
-        write_entries: F,
-        library: Option<&Library>,
-        _user: Option<&InternalUser>,
-        page_info: Option<(usize, usize, usize, usize)>,
-        url_base: &str,
-        is_acquisition: bool,
-    ) -> Result<String, quick_xml::Error>
-    where
-        F: FnOnce(&mut Writer<Cursor<Vec<u8>>>) -> Result<(), quick_xml::Error>,
-    {
-        let mut writer = Writer::new(Cursor::new(Vec::new()));
+    /// Serializes `feed` as an OPDS 1.2 Atom document.
+    pub fn build_feed(feed: &Feed) -> Result<String, quick_xml::Error> {
+        let bytes = Self::write_feed(feed, Vec::new())?;
+        String::from_utf8(bytes).map_err(|e| {
+            quick_xml::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
+        })
+    }
+
+    /// Writes `feed` as an OPDS 1.2 Atom document to `out`, returning it.
+    pub fn write_feed<W: Write>(feed: &Feed, out: W) -> Result<W, quick_xml::Error> {
+        let mut writer = Writer::new(out);
+        Self::write_feed_start(&mut writer, feed)?;
+        for entry in &feed.entries {
+            Self::write_entry(&mut writer, entry)?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("feed")))?;
+        Ok(writer.into_inner())
+    }
+
+    /// The weak ETag and the length in bytes of `feed` as [`build_feed`](Self::build_feed)
+    /// writes it, measured as it is written rather than from a copy of the document.
+    pub fn measure_feed(feed: &Feed) -> Result<(String, u64), quick_xml::Error> {
+        let measured = Self::write_feed(feed, HashWriter { hasher: sha1_smol::Sha1::new(), length: 0 })?;
+        Ok((format!("W/\"{}\"", measured.hasher.digest()), measured.length))
+    }
+
+    /// The document of `feed` in chunks of about [`CHUNK_SIZE`], each written when the
+    /// body asks for it, so a large page is never held in memory as a whole.
+    pub fn stream_feed(feed: Feed) -> axum::body::Body {
+        let mut writer = Writer::new(Vec::with_capacity(CHUNK_SIZE));
+        let mut failed = Self::write_feed_start(&mut writer, &feed).err();
+        let mut finished = failed.is_some();
+        let mut entries = feed.entries.into_iter();
+        let chunks = std::iter::from_fn(move || {
+            if let Some(e) = failed.take() {
+                return Some(Err(e));
+            }
+            if finished {
+                return None;
+            }
+            while !finished && writer.get_ref().len() < CHUNK_SIZE {
+                let written = match entries.next() {
+                    Some(entry) => Self::write_entry(&mut writer, &entry),
+                    None => {
+                        finished = true;
+                        writer.write_event(Event::End(BytesEnd::new("feed"))).map_err(quick_xml::Error::from)
+                    }
+                };
+                if let Err(e) = written {
+                    finished = true;
+                    return Some(Err(e));
+                }
+            }
+            Some(Ok(std::mem::replace(writer.get_mut(), Vec::with_capacity(CHUNK_SIZE))))
+        });
+        axum::body::Body::from_stream(futures_util::stream::iter(chunks))
+    }
+
+    /// Everything of `feed` before its entries.
+    fn write_feed_start<W: Write>(writer: &mut Writer<W>, feed: &Feed) -> Result<(), quick_xml::Error> {
         writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
 
-        let mut feed = BytesStart::new("feed");
-        feed.push_attribute(("xmlns", "http://www.w3.org/2005/Atom"));
-        feed.push_attribute(("xmlns:opds", "http://opds-spec.org/2010/catalog"));
-        feed.push_attribute(("xmlns:dcterms", "http://purl.org/dc/terms/"));
-        feed.push_attribute(("xmlns:opensearch", "http://a9.com/-/spec/opensearch/1.1/"));
+        let mut root = BytesStart::new("feed");
+        root.push_attribute(("xmlns", "http://www.w3.org/2005/Atom"));
+        root.push_attribute(("xmlns:opds", "http://opds-spec.org/2010/catalog"));
+        root.push_attribute(("xmlns:dcterms", "http://purl.org/dc/terms/"));
+        root.push_attribute(("xmlns:opensearch", "http://a9.com/-/spec/opensearch/1.1/"));
+        root.push_attribute(("xmlns:calibre", "http://calibre.kovidgoyal.net/2009/metadata"));
+        if feed.facets.iter().any(|f| f.count.is_some()) {
+            root.push_attribute(("xmlns:thr", "http://purl.org/syndication/thread/1.0"));
+        }
+        if feed.entries.iter().any(|e| e.contributors.iter().any(|c| c.role.is_some())) {
+            root.push_attribute(("xmlns:opf", "http://www.idpf.org/2007/opf"));
+        }
 
-        writer.write_event(Event::Start(feed))?;
+        writer.write_event(Event::Start(root))?;
 
-        Self::write_elem(&mut writer, "id", id)?;
-        Self::write_elem(&mut writer, "title", title)?;
+        Self::write_elem(writer, "id", &feed.id)?;
+        Self::write_elem(writer, "title", &feed.title)?;
 
         // Feed-level author is required by Atom when entries might lack one
         writer.write_event(Event::Start(BytesStart::new("author")))?;
-        Self::write_elem(&mut writer, "name", "ABS-OPDS")?;
+        Self::write_elem(writer, "name", "ABS-OPDS")?;
         writer.write_event(Event::End(BytesEnd::new("author")))?;
 
-        writer.write_event(Event::Start(BytesStart::new("authentication")))?;
-        Self::write_elem(&mut writer, "type", "http://opds-spec.org/auth/basic")?;
-        writer.write_event(Event::Start(BytesStart::new("labels")))?;
-        Self::write_elem(&mut writer, "login", "Card")?;
-        Self::write_elem(&mut writer, "password", "PW")?;
-        writer.write_event(Event::End(BytesEnd::new("labels")))?;
-        writer.write_event(Event::End(BytesEnd::new("authentication")))?;
-
-        Self::write_elem(&mut writer, "updated", &chrono::Utc::now().to_rfc3339())?;
-
-        let feed_kind = if is_acquisition { "acquisition" } else { "navigation" };
-        let feed_profile = format!("application/atom+xml;profile=opds-catalog;kind={}", feed_kind);
-        Self::write_link(&mut writer, "self", &feed_profile, "", url_base)?;
-
-        if let Some(lib) = library {
-            Self::write_link(&mut writer, "alternate", "text/html", "Web Interface", &format!("/library/{}", lib.id))?;
-            Self::write_link(&mut writer, "search", "application/opensearchdescription+xml", "Search this library", &format!("/opds/libraries/{}/search-definition", lib.id))?;
-            Self::write_link(&mut writer, "search", "application/atom+xml;profile=opds-catalog;kind=acquisition", "Search this library", &format!("/opds/libraries/{}?q={{searchTerms}}", lib.id))?;
-
-             if let Some((page, page_size, total_items, total_pages)) = page_info {
-                let start_index = page * page_size + 1;
-                Self::write_elem_ns(&mut writer, "opensearch:totalResults", &total_items.to_string())?;
-                Self::write_elem_ns(&mut writer, "opensearch:startIndex", &start_index.to_string())?;
-                Self::write_elem_ns(&mut writer, "opensearch:itemsPerPage", &page_size.to_string())?;
-
-                 static PAGE_REGEX: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
-                 let regex = PAGE_REGEX.get_or_init(|| {
-                     regex::Regex::new(r"[?&]page=\d+").expect("Failed to compile regex")
-                 });
-                 let clean_url = if url_base.contains("?page=") || url_base.contains("&page=") {
-                     regex.replace(url_base, "").to_string()
-                 } else {
-                     url_base.to_string()
-                 };
-
-                 let separator = if clean_url.contains('?') { "&" } else { "?" };
-
-                Self::write_link(&mut writer, "start", &feed_profile, "", &clean_url)?;
-                Self::write_link(&mut writer, "first", &feed_profile, "", &clean_url)?;
-
-                if page > 0 {
-                     let prev_page = page - 1;
-                     let href = if prev_page > 0 { format!("{}{}{}{}", clean_url, separator, "page=", prev_page) } else { clean_url.clone() };
-                     Self::write_link(&mut writer, "previous", &feed_profile, "", &href)?;
-                }
-
-                if page + 1 < total_pages {
-                    let next_page = page + 1;
-                     let href = format!("{}{}{}{}", clean_url, separator, "page=", next_page);
-                     Self::write_link(&mut writer, "next", &feed_profile, "", &href)?;
-                }
+        if let Some(auth) = &feed.authentication {
+            writer.write_event(Event::Start(BytesStart::new("authentication")))?;
+            Self::write_elem(writer, "type", "http://opds-spec.org/auth/basic")?;
+            writer.write_event(Event::Start(BytesStart::new("labels")))?;
+            Self::write_elem(writer, "login", &auth.login)?;
+            Self::write_elem(writer, "password", &auth.password)?;
+            writer.write_event(Event::End(BytesEnd::new("labels")))?;
+            writer.write_event(Event::End(BytesEnd::new("authentication")))?;
+        }
 
-                if total_pages > 1 {
-                     let last_page = total_pages - 1;
-                      let href = format!("{}{}{}{}", clean_url, separator, "page=", last_page);
-                      Self::write_link(&mut writer, "last", &feed_profile, "", &href)?;
-                }
+        Self::write_elem(writer, "updated", &feed.updated)?;
 
-             }
+        for link in &feed.links {
+            Self::write_model_link(writer, link)?;
         }
 
-        write_entries(&mut writer)?;
+        if let Some(pagination) = &feed.pagination {
+            Self::write_elem_ns(writer, "opensearch:totalResults", &pagination.total_results.to_string())?;
+            Self::write_elem_ns(writer, "opensearch:startIndex", &pagination.start_index.to_string())?;
+            Self::write_elem_ns(writer, "opensearch:itemsPerPage", &pagination.items_per_page.to_string())?;
+            for link in &pagination.links {
+                Self::write_model_link(writer, link)?;
+            }
+        }
 
-        writer.write_event(Event::End(BytesEnd::new("feed")))?;
-        String::from_utf8(writer.into_inner().into_inner()).map_err(|e| {
-            quick_xml::Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
-        })
+        for facet in &feed.facets {
+            let mut link = BytesStart::new("link");
+            link.push_attribute(("rel", FACET_REL));
+            link.push_attribute(("type", feed.kind.content_type()));
+            link.push_attribute(("title", facet.title.as_str()));
+            link.push_attribute(("href", facet.href.as_str()));
+            link.push_attribute(("opds:facetGroup", facet.group.as_str()));
+            if facet.active {
+                link.push_attribute(("opds:activeFacet", "true"));
+            }
+            let count = facet.count.map(|c| c.to_string());
+            if let Some(count) = &count {
+                link.push_attribute(("thr:count", count.as_str()));
+            }
+            writer.write_event(Event::Empty(link))?;
+        }
+        Ok(())
     }
 
-    fn write_elem(writer: &mut Writer<Cursor<Vec<u8>>>, name: &str, value: &str) -> Result<(), quick_xml::Error> {
+    fn write_elem<W: Write>(writer: &mut Writer<W>, name: &str, value: &str) -> Result<(), quick_xml::Error> {
         writer.write_event(Event::Start(BytesStart::new(name)))?;
         writer.write_event(Event::Text(quick_xml::events::BytesText::from_escaped(quick_xml::escape::escape(value))))?;
         writer.write_event(Event::End(BytesEnd::new(name)))?;
         Ok(())
     }
 
-     fn write_elem_ns(writer: &mut Writer<Cursor<Vec<u8>>>, name: &str, value: &str) -> Result<(), quick_xml::Error> {
+     fn write_elem_ns<W: Write>(writer: &mut Writer<W>, name: &str, value: &str) -> Result<(), quick_xml::Error> {
         writer.write_event(Event::Start(BytesStart::new(name)))?;
         writer.write_event(Event::Text(quick_xml::events::BytesText::from_escaped(quick_xml::escape::escape(value))))?;
         writer.write_event(Event::End(BytesEnd::new(name)))?;
         Ok(())
     }
 
-    pub(crate) fn write_link(writer: &mut Writer<Cursor<Vec<u8>>>, rel: &str, type_: &str, title: &str, href: &str) -> Result<(), quick_xml::Error> {
+    pub(crate) fn write_link<W: Write>(writer: &mut Writer<W>, rel: &str, type_: &str, title: &str, href: &str) -> Result<(), quick_xml::Error> {
         let mut link = BytesStart::new("link");
         if !rel.is_empty() { link.push_attribute(("rel", rel)); }
         if !type_.is_empty() { link.push_attribute(("type", type_)); }
@@ -136,141 +227,19 @@ pub(crate) fn write_link(writer: &mut Writer<Cursor<Vec<u8>>>, rel: &str, type_:
         Ok(())
     }
 
-    pub fn build_library_entry_list<'a>(libraries: &'a [Library], updated_time: &'a str) -> impl FnOnce(&mut Writer<Cursor<Vec<u8>>>) -> Result<(), quick_xml::Error> + 'a {
-        move |writer| {
-            for lib in libraries {
-                Self::build_library_entry(writer, lib, updated_time)?;
-            }
-            Ok(())
-        }
-    }
-
-    pub fn build_library_entry(writer: &mut Writer<Cursor<Vec<u8>>>, library: &Library, updated_time: &str) -> Result<(), quick_xml::Error> {
-        let entry = BytesStart::new("entry");
-        writer.write_event(Event::Start(entry))?;
-
-        Self::write_elem(writer, "id", &library.id)?;
-        Self::write_elem(writer, "title", &library.name)?;
-        Self::write_elem(writer, "updated", updated_time)?;
-
-        Self::write_link(writer, "subsection", "application/atom+xml;profile=opds-catalog", "", &format!("/opds/libraries/{}?categories=true", library.id))?;
-
-        writer.write_event(Event::End(BytesEnd::new("entry")))?;
-        Ok(())
-    }
-
-    pub fn build_category_entries<'a>(library_id: &'a str, i18n: &'a crate::i18n::I18n, lang: Option<&'a str>, updated_time: &'a str) -> impl FnOnce(&mut Writer<Cursor<Vec<u8>>>) -> Result<(), quick_xml::Error> + 'a {
-        move |writer| {
-            let categories = vec![
-                (library_id.to_string(), i18n.localize("category.all", lang)),
-                ("authors".to_string(), i18n.localize("category.authors", lang)),
-                ("narrators".to_string(), i18n.localize("category.narrators", lang)),
-                ("genres".to_string(), i18n.localize("category.genres", lang)),
-                ("series".to_string(), i18n.localize("category.series", lang)),
-            ];
-
-            for (id, title) in categories {
-                writer.write_event(Event::Start(BytesStart::new("entry")))?;
-                Self::write_elem(writer, "id", &id)?;
-                Self::write_elem(writer, "title", &title)?;
-                Self::write_elem(writer, "updated", updated_time)?;
-
-                let href = if id == library_id {
-                     format!("/opds/libraries/{}", library_id)
-                } else {
-                     format!("/opds/libraries/{}/{}", library_id, id)
-                };
-
-                Self::write_link(writer, "subsection", "application/atom+xml;profile=opds-catalog", "", &href)?;
-
-                writer.write_event(Event::End(BytesEnd::new("entry")))?;
-            }
-            Ok(())
-        }
+    fn write_model_link<W: Write>(writer: &mut Writer<W>, link: &Link) -> Result<(), quick_xml::Error> {
+        Self::write_link(writer, &link.rel, &link.type_, &link.title, &link.href)
     }
 
-    pub fn build_card_entry(
-        writer: &mut Writer<Cursor<Vec<u8>>>,
-        item: &str,
-        type_: &str,
-        library_id: &str,
-        updated_time: &str,
-        url_buf: &mut String,
-    ) -> Result<(), quick_xml::Error> {
+    pub fn write_entry<W: Write>(writer: &mut Writer<W>, entry: &Entry) -> Result<(), quick_xml::Error> {
         writer.write_event(Event::Start(BytesStart::new("entry")))?;
 
-        url_buf.clear();
-        for c in item.chars() {
-            if c == ' ' {
-                url_buf.push('-');
-            } else {
-                for lc in c.to_lowercase() {
-                    url_buf.push(lc);
-                }
-            }
-        }
-        Self::write_elem(writer, "id", url_buf)?;
-        Self::write_elem(writer, "title", item)?;
-        Self::write_elem(writer, "updated", updated_time)?;
-
-        url_buf.clear();
-        use std::fmt::Write as _;
-        let _ = write!(url_buf, "/opds/libraries/{}?name={}&type={}", library_id, item, type_);
-        Self::write_link(writer, "subsection", "application/atom+xml;profile=opds-catalog", "", url_buf)?;
+        Self::write_elem(writer, "id", &entry.id)?;
+        if let Some(t) = &entry.title { Self::write_elem(writer, "title", t)?; }
+        if let Some(s) = &entry.subtitle { Self::write_elem(writer, "subtitle", s)?; }
+        Self::write_elem(writer, "updated", &entry.updated)?;
 
-        writer.write_event(Event::End(BytesEnd::new("entry")))?;
-        Ok(())
-    }
-
-    pub fn build_custom_card_entry(
-        writer: &mut Writer<Cursor<Vec<u8>>>,
-        item: &str,
-        link: &str,
-        updated_time: &str,
-        url_buf: &mut String,
-    ) -> Result<(), quick_xml::Error> {
-        writer.write_event(Event::Start(BytesStart::new("entry")))?;
-
-        url_buf.clear();
-        for c in item.chars() {
-            if c == ' ' {
-                url_buf.push('-');
-            } else {
-                for lc in c.to_lowercase() {
-                    url_buf.push(lc);
-                }
-            }
-        }
-        Self::write_elem(writer, "id", url_buf)?;
-        Self::write_elem(writer, "title", item)?;
-        Self::write_elem(writer, "updated", updated_time)?;
-
-        Self::write_link(writer, "subsection", "application/atom+xml;profile=opds-catalog", "", link)?;
-
-        writer.write_event(Event::End(BytesEnd::new("entry")))?;
-        Ok(())
-    }
-
-    pub fn build_item_entry(
-        writer: &mut Writer<Cursor<Vec<u8>>>,
-        item: &LibraryItem,
-        user: &InternalUser,
-        link_url: &str,
-        updated_time: &str,
-        url_buf: &mut String,
-    ) -> Result<(), quick_xml::Error> {
-        writer.write_event(Event::Start(BytesStart::new("entry")))?;
-
-        url_buf.clear();
-        use std::fmt::Write as _;
-        let _ = write!(url_buf, "urn:uuid:{}", item.id);
-        Self::write_elem(writer, "id", url_buf)?;
-
-        if let Some(t) = &item.title { Self::write_elem(writer, "title", t)?; }
-        if let Some(s) = &item.subtitle { Self::write_elem(writer, "subtitle", s)?; }
-        Self::write_elem(writer, "updated", updated_time)?;
-
-        if let Some(desc) = &item.description {
+        if let Some(desc) = &entry.content {
              let mut content = BytesStart::new("content");
              content.push_attribute(("type", "text"));
              writer.write_event(Event::Start(content))?;
@@ -278,54 +247,30 @@ pub fn build_item_entry(
              writer.write_event(Event::End(BytesEnd::new("content")))?;
         }
 
-        if let Some(publ) = &item.publisher { Self::write_elem(writer, "dcterms:publisher", publ)?; }
-        if let Some(isbn) = &item.isbn {
-            let id_val = if isbn.starts_with("urn:isbn:") {
-                isbn.to_string()
-            } else {
-                format!("urn:isbn:{}", isbn)
-            };
-            Self::write_elem(writer, "dcterms:identifier", &id_val)?;
+        if let Some(publ) = &entry.publisher { Self::write_elem(writer, "dcterms:publisher", publ)?; }
+        if let Some(id) = &entry.identifier { Self::write_elem(writer, "dcterms:identifier", id)?; }
+        if let Some(year) = &entry.issued { Self::write_elem(writer, "dcterms:issued", year)?; }
+        if let Some(lang) = &entry.language { Self::write_elem(writer, "dcterms:language", lang)?; }
+
+        if let Some(series) = &entry.series {
+            Self::write_elem(writer, "calibre:series", series)?;
+            if let Some(index) = &entry.series_index {
+                Self::write_elem(writer, "calibre:series_index", index)?;
+            }
         }
-        if let Some(year) = &item.published_year { Self::write_elem(writer, "dcterms:issued", year)?; }
-        if let Some(lang) = &item.language { Self::write_elem(writer, "dcterms:language", lang)?; }
 
-        for narrator in &item.narrators {
-            Self::write_elem(writer, "dcterms:contributor", &narrator.name)?;
+        for link in &entry.links {
+            Self::write_model_link(writer, link)?;
         }
 
-        let format = item.format.as_deref().unwrap_or("");
-        let mime_type = match format {
-            "audiobook" => "audio/mpeg",
-            "epub" => "application/epub+zip",
-            "pdf" => "application/pdf",
-            "mobi" => "application/x-mobipocket-ebook",
-             _ => "application/octet-stream"
-        };
-
-        url_buf.clear();
-        let _ = write!(url_buf, "{}/api/items/{}/download?token={}", link_url, item.id, user.api_key);
-        Self::write_link(writer, "http://opds-spec.org/acquisition", "application/octet-stream", "", url_buf)?;
-
-        url_buf.clear();
-        let _ = write!(url_buf, "{}/api/items/{}/ebook?token={}", link_url, item.id, user.api_key);
-        Self::write_link(writer, "http://opds-spec.org/acquisition", mime_type, "", url_buf)?;
-
-        url_buf.clear();
-        let _ = write!(url_buf, "{}/api/items/{}/cover?token={}", link_url, item.id, user.api_key);
-        Self::write_link(writer, "http://opds-spec.org/image", "image/webp", "", url_buf)?;
-
-        url_buf.clear();
-        let _ = write!(url_buf, "{}/api/items/{}/cover?token={}", link_url, item.id, user.api_key);
-        Self::write_link(writer, "http://opds-spec.org/image", "image/png", "", url_buf)?;
-
-        for author in &item.authors {
-             writer.write_event(Event::Start(BytesStart::new("author")))?;
-             Self::write_elem(writer, "name", &author.name)?;
-             writer.write_event(Event::End(BytesEnd::new("author")))?;
+        for author in &entry.authors {
+            Self::write_person(writer, "author", author)?;
+        }
+        for contributor in &entry.contributors {
+            Self::write_person(writer, "contributor", contributor)?;
         }
 
-        for tag in item.genres.iter().chain(item.tags.iter()) {
+        for tag in &entry.categories {
             let mut cat = BytesStart::new("category");
             cat.push_attribute(("label", tag.as_str()));
             cat.push_attribute(("term", tag.as_str()));
@@ -336,7 +281,21 @@ pub fn build_item_entry(
         Ok(())
     }
 
-     pub fn build_search_definition(id: &str) -> Result<String, quick_xml::Error> {
+    fn write_person<W: Write>(writer: &mut Writer<W>, tag: &str, person: &Person) -> Result<(), quick_xml::Error> {
+        let mut start = BytesStart::new(tag);
+        if let Some(role) = &person.role {
+            start.push_attribute(("opf:role", role.as_str()));
+        }
+        writer.write_event(Event::Start(start))?;
+        Self::write_elem(writer, "name", &person.name)?;
+        if let Some(uri) = &person.uri {
+            Self::write_elem(writer, "uri", uri)?;
+        }
+        writer.write_event(Event::End(BytesEnd::new(tag)))?;
+        Ok(())
+    }
+
+     pub fn build_search_definition(id: &str, i18n: &I18n, lang: Option<&str>) -> Result<String, quick_xml::Error> {
         let mut writer = Writer::new(Cursor::new(Vec::new()));
         writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
 
@@ -347,13 +306,13 @@ pub fn build_search_definition(id: &str) -> Result<String, quick_xml::Error> {
 
         Self::write_elem(&mut writer, "ShortName", "ABS")?;
         Self::write_elem(&mut writer, "LongName", "Audiobookshelf")?;
-        Self::write_elem(&mut writer, "Description", "Search for books in Audiobookshelf")?;
+        Self::write_elem(&mut writer, "Description", &i18n.localize("search.description", lang))?;
 
         let mut url = BytesStart::new("Url");
         url.push_attribute(("type", "application/atom+xml;profile=opds-catalog;kind=acquisition"));
 
         // Fix formatting of template string attribute
-        let template = format!("/opds/libraries/{}?q={{searchTerms}}&author={{atom:author}}&title={{atom:title}}", id);
+        let template = format!("/opds/libraries/{}?q={{searchTerms}}&author={{atom:author}}&narrator={{atom:contributor}}&title={{atom:title}}", id);
         url.push_attribute(("template", template.as_str()));
 
         writer.write_event(Event::Empty(url))?;
@@ -364,7 +323,7 @@ pub fn build_search_definition(id: &str) -> Result<String, quick_xml::Error> {
          })
       }
 
-     pub fn build_error_feed(error_msg: &str) -> Result<String, quick_xml::Error> {
+     pub fn build_error_feed(error_msg: &str, i18n: &I18n, lang: Option<&str>) -> Result<String, quick_xml::Error> {
         let mut writer = Writer::new(Cursor::new(Vec::new()));
         writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
 
@@ -373,7 +332,7 @@ pub fn build_error_feed(error_msg: &str) -> Result<String, quick_xml::Error> {
         writer.write_event(Event::Start(feed))?;
 
         Self::write_elem(&mut writer, "id", "urn:abs-opds:error")?;
-        Self::write_elem(&mut writer, "title", &format!("Error: {}", error_msg))?;
+        Self::write_elem(&mut writer, "title", &i18n.localize_with("feed.error", lang, &[("message", error_msg)]))?;
 
         // Feed author
         writer.write_event(Event::Start(BytesStart::new("author")))?;